@@ -0,0 +1,96 @@
+//! Working out which file an inode or a name is, and listing the top of the
+//! mount, which every FUSE operation does before anything else
+//!
+//! The filesystem lives in the binary, so `File` is pulled in from its source.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+#[allow(dead_code)]
+#[path = "../src/fs/file.rs"]
+mod file;
+
+use file::File;
+
+/// Around as many as xkcd has, and then some
+const COMICS: u32 = 5000;
+
+/// Every file for every comic
+fn all_files() -> Vec<File> {
+    let mut files = vec![File::Root, File::Refresh, File::Credits, File::Montage];
+
+    for num in 1..=COMICS {
+        files.push(File::Image(num));
+        files.push(File::MetaFolder(num));
+
+        let folder = File::MetaFolder(num);
+        let mut index = 2;
+
+        while let Some((ino, _, _)) = folder.child_by_index(index, u64::from(COMICS)) {
+            files.push(File::from_inode(ino).unwrap());
+            index += 1;
+        }
+    }
+
+    files
+}
+
+fn from_inode(c: &mut Criterion) {
+    let inodes: Vec<u64> = all_files().iter().map(File::inode).collect();
+
+    c.bench_function("from_inode, every file", |b| {
+        b.iter(|| {
+            for &ino in &inodes {
+                black_box(File::from_inode(black_box(ino)));
+            }
+        })
+    });
+}
+
+fn from_filename(c: &mut Criterion) {
+    let root: Vec<String> = (1..=COMICS)
+        .flat_map(|num| {
+            vec![
+                File::Image(num).filename(),
+                File::MetaFolder(num).filename(),
+            ]
+        })
+        .collect();
+
+    c.bench_function("from_filename, top of the mount", |b| {
+        b.iter(|| {
+            for name in &root {
+                black_box(File::from_filename(&File::Root, black_box(name)));
+            }
+        })
+    });
+
+    let folder = File::MetaFolder(614);
+    let meta: Vec<String> = (2..)
+        .map(|index| folder.child_by_index(index, u64::from(COMICS)))
+        .take_while(Option::is_some)
+        .map(|child| child.unwrap().2)
+        .collect();
+
+    c.bench_function("from_filename, comic folder", |b| {
+        b.iter(|| {
+            for name in &meta {
+                black_box(File::from_filename(&folder, black_box(name)));
+            }
+        })
+    });
+}
+
+fn readdir(c: &mut Criterion) {
+    let comics = u64::from(COMICS);
+
+    c.bench_function("readdir, top of the mount", |b| {
+        b.iter(|| {
+            (0..File::root_len(comics))
+                .filter_map(|index| File::Root.child_by_index(index, black_box(comics)))
+                .count()
+        })
+    });
+}
+
+criterion_group!(files, from_inode, from_filename, readdir);
+criterion_main!(files);
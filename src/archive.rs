@@ -0,0 +1,195 @@
+//! Archives: a directory of comic images, mounted without the network
+//!
+//! With `--archive DIR`, images come from the files under DIR instead of
+//! xkcd.com. A file named after a comic's number, like `614.png` or
+//! `0614 - Woodpecker.png`, is that comic's image. Any others are matched
+//! against the names in comics' image URLs, like `woodpecker.png`.
+//!
+//! Metadata comes from `comics.json` at the top of DIR, a JSON array of comics
+//! as the xkcd API gives them, if there is one. Without it, only the comics
+//! already in the cache can be listed.
+
+use crate::requests::parse_comics;
+use crate::Comic;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use xkcdfs_core::ComicSource;
+
+/// The metadata's name, at the top of the archive
+pub const METADATA: &str = "comics.json";
+
+const EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "webp"];
+
+/// The images in an archive, found when it's opened
+#[derive(Clone)]
+pub struct Archive {
+    /// Images named after their comics' numbers
+    by_num: Arc<BTreeMap<u32, PathBuf>>,
+    /// Every other image, by file name
+    by_name: Arc<HashMap<OsString, PathBuf>>,
+    /// From `comics.json`, if there is one
+    comics: Arc<BTreeMap<u32, Comic>>,
+}
+
+impl Archive {
+    pub fn open(dir: &Path) -> Result<Self, String> {
+        let mut by_num = BTreeMap::new();
+        let mut by_name = HashMap::new();
+
+        scan(dir, &mut by_num, &mut by_name)?;
+
+        let comics = match std::fs::read(dir.join(METADATA)) {
+            Ok(json) => parse_comics(&json)
+                .map_err(|e| format!("Invalid {}: {}", METADATA, e))?
+                .into_iter()
+                .map(|c| (c.num, c))
+                .collect(),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => {
+                info!("No {}, so comics will only be listed if cached", METADATA);
+                BTreeMap::new()
+            }
+            Err(e) => return Err(format!("Could not read {}: {}", METADATA, e)),
+        };
+
+        info!(
+            "Found {} images and {} comics in {}",
+            by_num.len() + by_name.len(),
+            comics.len(),
+            dir.display()
+        );
+
+        Ok(Self {
+            by_num: Arc::new(by_num),
+            by_name: Arc::new(by_name),
+            comics: Arc::new(comics),
+        })
+    }
+
+    /// Where `comic`'s image is, by its number or failing that its URL
+    fn image_path(&self, comic: &Comic) -> Option<&PathBuf> {
+        self.by_num.get(&comic.num).or_else(|| {
+            let name = comic.img_url.rsplit('/').next()?;
+            self.by_name.get(&OsString::from(name))
+        })
+    }
+}
+
+impl ComicSource for Archive {
+    fn latest(&self) -> Result<Comic, String> {
+        self.comics
+            .values()
+            .next_back()
+            .cloned()
+            .ok_or_else(|| format!("No comics in the archive's {}", METADATA))
+    }
+
+    fn comic(&self, num: u32) -> Result<Comic, String> {
+        self.comics
+            .get(&num)
+            .cloned()
+            .ok_or_else(|| format!("Comic {} is not in the archive's {}", num, METADATA))
+    }
+
+    fn image(&self, comic: &Comic) -> Result<Vec<u8>, String> {
+        let path = self
+            .image_path(comic)
+            .ok_or_else(|| format!("No image for {} in the archive", comic))?;
+
+        std::fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))
+    }
+}
+
+/// Note every image under `dir`
+fn scan(
+    dir: &Path,
+    by_num: &mut BTreeMap<u32, PathBuf>,
+    by_name: &mut HashMap<OsString, PathBuf>,
+) -> Result<(), String> {
+    let entries =
+        std::fs::read_dir(dir).map_err(|e| format!("Could not read {}: {}", dir.display(), e))?;
+
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        let name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(n) if !n.starts_with('.') => n.to_string(),
+            _ => continue,
+        };
+
+        if path.is_dir() {
+            scan(&path, by_num, by_name)?;
+            continue;
+        }
+
+        let image = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()));
+
+        if !image {
+            continue;
+        }
+
+        match number(&name) {
+            Some(num) => by_num.insert(num, path),
+            None => by_name.insert(OsString::from(name), path),
+        };
+    }
+
+    Ok(())
+}
+
+/// The comic number a file name starts with, if it does
+fn number(name: &str) -> Option<u32> {
+    let end = name
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(name.len());
+
+    name[..end].parse().ok().filter(|&n| n > 0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_numbers_from_names() {
+        assert_eq!(number("614.png"), Some(614));
+        assert_eq!(number("0614 - Woodpecker.png"), Some(614));
+        assert_eq!(number("woodpecker.png"), None);
+        assert_eq!(number("0.png"), None);
+    }
+
+    #[test]
+    fn finds_images_by_number_and_name() {
+        let dir = std::env::temp_dir().join(format!("xkcdfs-archive-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("old")).unwrap();
+        std::fs::write(dir.join("0614 - Woodpecker.png"), b"woodpecker").unwrap();
+        std::fs::write(dir.join("old").join("barrel_cropped_(1).jpg"), b"barrel").unwrap();
+        std::fs::write(dir.join("notes.txt"), b"not an image").unwrap();
+        std::fs::write(
+            dir.join(METADATA),
+            r#"[
+                {"num": 1, "title": "Barrel - Part 1",
+                 "img": "https://imgs.xkcd.com/comics/barrel_cropped_(1).jpg"},
+                {"num": 614, "title": "Woodpecker", "year": "2009", "month": "7", "day": "24",
+                 "img": "https://imgs.xkcd.com/comics/woodpecker.png"}
+            ]"#,
+        )
+        .unwrap();
+
+        let archive = Archive::open(&dir).unwrap();
+
+        let latest = archive.latest().unwrap();
+        assert_eq!((latest.num, latest.title.as_str()), (614, "Woodpecker"));
+        assert_eq!(archive.image(&latest), Ok(b"woodpecker".to_vec()));
+
+        let barrel = archive.comic(1).unwrap();
+        assert_eq!(archive.image(&barrel), Ok(b"barrel".to_vec()));
+        assert!(archive.comic(404).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
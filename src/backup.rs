@@ -0,0 +1,266 @@
+//! Backing up the cache every so often while mounted
+//!
+//! With `--backup-dir`, the cache is copied there every `--backup-interval`,
+//! using SQLite's online backup so the mount carries on as usual while it's
+//! copied. That's the main database and each `[[source]]`'s and
+//! translation's, which all go in one directory named for when they were
+//! copied, like `xkcdfs-20090724-120000`, under their own file names. Only the
+//! newest `--backup-keep` directories are kept.
+//!
+//! A copy is made under another name and only renamed once every database is
+//! in it, so an interrupted backup never takes the place of a good one. To
+//! restore one, unmount and copy the files in it over the databases.
+
+use crate::logging;
+use crate::requests;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const PREFIX: &str = "xkcdfs-";
+
+/// Tacked onto a copy's name until it's complete
+const PARTIAL: &str = ".partial";
+
+/// How long to wait after a backup fails before trying again
+const RETRY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+pub const DEFAULT_KEEP: usize = 7;
+
+/// Where backups go, and how often
+#[derive(Clone, Debug, PartialEq)]
+pub struct Backups {
+    /// Absolute, since the daemon changes directory
+    pub dir: PathBuf,
+    pub interval: Duration,
+    /// How many copies to keep
+    pub keep: usize,
+}
+
+impl Backups {
+    /// Every complete copy's directory, oldest first
+    fn generations(&self) -> Result<Vec<PathBuf>, String> {
+        let entries = std::fs::read_dir(&self.dir)
+            .map_err(|e| format!("Could not read {}: {}", self.dir.display(), e))?;
+
+        let mut generations = Vec::new();
+
+        for entry in entries {
+            let path = entry.map_err(|e| e.to_string())?.path();
+
+            if path.is_dir()
+                && path
+                    .file_name()
+                    .and_then(OsStr::to_str)
+                    .is_some_and(is_generation)
+            {
+                generations.push(path);
+            }
+        }
+
+        // The names sort by when they were made
+        generations.sort();
+
+        Ok(generations)
+    }
+
+    /// How long ago the newest copy was made, if there is one
+    fn newest_age(&self) -> Option<Duration> {
+        let generations = self.generations().ok()?;
+        let modified = std::fs::metadata(generations.last()?)
+            .ok()?
+            .modified()
+            .ok()?;
+
+        // A copy from the future is as good as new
+        Some(modified.elapsed().unwrap_or_default())
+    }
+
+    /// Copy every one of `databases` into a new generation, returning its
+    /// path
+    fn make(&self, databases: &[OsString]) -> Result<PathBuf, String> {
+        let name = format!(
+            "{}{}",
+            PREFIX,
+            time::now_utc().strftime("%Y%m%d-%H%M%S").unwrap()
+        );
+        let path = self.dir.join(&name);
+        let partial = self.dir.join(name + PARTIAL);
+
+        std::fs::create_dir(&partial)
+            .map_err(|e| format!("Could not create {}: {}", partial.display(), e))?;
+
+        let copied = databases.iter().try_for_each(|database| {
+            let file_name = Path::new(database)
+                .file_name()
+                .ok_or_else(|| format!("{} is not a file", database.to_string_lossy()))?;
+
+            requests::backup_database(database, &partial.join(file_name))
+                .map_err(|e| format!("{}: {}", database.to_string_lossy(), e))
+        });
+
+        if let Err(e) = copied {
+            std::fs::remove_dir_all(&partial).ok();
+            return Err(e);
+        }
+
+        std::fs::rename(&partial, &path)
+            .map_err(|e| format!("Could not rename {}: {}", partial.display(), e))?;
+
+        Ok(path)
+    }
+
+    /// Delete all but the newest `keep` copies, returning how many went
+    fn prune(&self) -> Result<usize, String> {
+        let generations = self.generations()?;
+        let extra = generations.len().saturating_sub(self.keep);
+
+        for old in &generations[..extra] {
+            std::fs::remove_dir_all(old)
+                .map_err(|e| format!("Could not delete {}: {}", old.display(), e))?;
+        }
+
+        Ok(extra)
+    }
+}
+
+/// Whether a directory in the backup directory is a complete copy
+fn is_generation(name: &str) -> bool {
+    name.starts_with(PREFIX) && !name.ends_with(PARTIAL)
+}
+
+/// Start backing up all of `databases` every so often
+pub fn spawn(databases: Vec<OsString>, backups: Backups) -> Result<JoinHandle<()>, String> {
+    std::fs::create_dir_all(&backups.dir)
+        .map_err(|e| format!("Could not create {}: {}", backups.dir.display(), e))?;
+
+    thread::Builder::new()
+        .name("backup".to_string())
+        .spawn(move || run(&databases, &backups))
+        .map_err(|e| e.to_string())
+}
+
+fn run(databases: &[OsString], backups: &Backups) {
+    // Carry on from the newest copy, so remounting doesn't make another
+    // straight away, or put the next one off
+    let mut wait = backups
+        .newest_age()
+        .and_then(|age| backups.interval.checked_sub(age))
+        .unwrap_or_default();
+
+    loop {
+        thread::sleep(wait);
+
+        let _op = logging::operation_scope("backup", None);
+
+        wait = match backups.make(databases) {
+            Ok(path) => {
+                info!("Backed up the cache to {}", path.display());
+
+                match backups.prune() {
+                    Ok(0) => {}
+                    Ok(n) => debug!("Deleted {} old backups", n),
+                    Err(e) => warn!("Could not delete old backups: {}", e),
+                }
+
+                backups.interval
+            }
+            Err(e) => {
+                logging::set_outcome("error");
+                warn!("Could not back up the cache: {}", e);
+
+                RETRY_INTERVAL.min(backups.interval)
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use xkcdfs_core::SqliteStore;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("xkcdfs-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    #[test]
+    fn keeps_the_newest_generations() {
+        let dir = temp_dir("backup-test");
+
+        for name in &[
+            "xkcdfs-20090722-120000",
+            "xkcdfs-20090724-120000",
+            "xkcdfs-20090723-120000",
+            "xkcdfs-20090725-120000.partial",
+        ] {
+            std::fs::create_dir(dir.join(name)).unwrap();
+            std::fs::write(dir.join(name).join("cache.db"), b"").unwrap();
+        }
+
+        std::fs::write(dir.join("xkcdfs-notes.txt"), b"").unwrap();
+
+        let backups = Backups {
+            dir: dir.clone(),
+            interval: DEFAULT_INTERVAL,
+            keep: 2,
+        };
+
+        assert_eq!(backups.generations().unwrap().len(), 3);
+        assert_eq!(backups.prune(), Ok(1));
+        assert_eq!(
+            backups.generations().unwrap(),
+            vec![
+                dir.join("xkcdfs-20090723-120000"),
+                dir.join("xkcdfs-20090724-120000"),
+            ]
+        );
+        assert!(dir.join("xkcdfs-notes.txt").exists());
+        assert!(backups.newest_age().unwrap() < Duration::from_secs(60));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn copies_every_database() {
+        let dir = temp_dir("backup-databases");
+        let databases: Vec<OsString> = ["cache.db", "cache.smbc.db"]
+            .iter()
+            .map(|name| dir.join(name).into_os_string())
+            .collect();
+
+        for database in &databases {
+            SqliteStore::open(database).unwrap();
+        }
+
+        let backups = Backups {
+            dir: dir.join("backups"),
+            interval: DEFAULT_INTERVAL,
+            keep: 2,
+        };
+        std::fs::create_dir(&backups.dir).unwrap();
+
+        let generation = backups.make(&databases).unwrap();
+
+        assert!(generation.join("cache.db").is_file());
+        assert!(generation.join("cache.smbc.db").is_file());
+        assert_eq!(backups.generations().unwrap(), vec![generation.clone()]);
+
+        // One that can't be copied leaves nothing behind
+        std::fs::remove_dir_all(&generation).unwrap();
+        let missing = vec![
+            databases[0].clone(),
+            dir.join("missing/cache.db").into_os_string(),
+        ];
+
+        assert!(backups.make(&missing).is_err());
+        assert_eq!(std::fs::read_dir(&backups.dir).unwrap().count(), 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,61 @@
+//! Send one command to a mount's `--control-socket`, and print the reply
+//!
+//! The reply is printed as the line of JSON the mount sent. The exit status
+//! is 1 if the command failed, and 2 if the mount couldn't be reached.
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::process::exit;
+
+const USAGE: &str = "Usage: xkcdfsctl SOCKET refresh|prefetch RANGE|stats|clear-cache";
+
+fn main() {
+    let mut args = std::env::args_os().skip(1);
+
+    let socket = match args.next() {
+        Some(s) => s,
+        None => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    };
+
+    let command = args
+        .map(|a| a.into_string())
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap_or_else(|a| {
+            eprintln!("Invalid argument {:?}", a);
+            exit(2);
+        })
+        .join(" ");
+
+    if command.is_empty() {
+        eprintln!("{}", USAGE);
+        exit(2);
+    }
+
+    let reply = send(&socket, &command).unwrap_or_else(|e| {
+        eprintln!("Could not reach {}: {}", socket.to_string_lossy(), e);
+        exit(2);
+    });
+
+    println!("{}", reply);
+
+    let ok = serde_json::from_str::<serde_json::Value>(&reply)
+        .map(|r| r["ok"] == true)
+        .unwrap_or(false);
+
+    if !ok {
+        exit(1);
+    }
+}
+
+fn send(socket: &std::ffi::OsStr, command: &str) -> std::io::Result<String> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.write_all(format!("{}\n", command).as_bytes())?;
+
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+
+    Ok(reply.trim_end().to_string())
+}
@@ -0,0 +1,149 @@
+//! `--check`: make sure everything needed to mount is in place, without
+//! mounting anything
+//!
+//! Each check prints one line, so a broken setup shows everything that's
+//! wrong with it at once instead of panicking at the first problem.
+
+use crate::cli::Config;
+use crate::fs::mount;
+use crate::{image, requests};
+use std::path::Path;
+
+enum Status {
+    Ok(String),
+    /// Mounting will work, but maybe not as expected
+    Warn(String),
+    Fail(String),
+    Skip(String),
+}
+
+#[derive(Default)]
+struct Report {
+    failures: usize,
+    warnings: usize,
+}
+
+impl Report {
+    fn add(&mut self, what: &str, status: Status) {
+        let (label, detail) = match status {
+            Status::Ok(d) => ("  OK", d),
+            Status::Warn(d) => {
+                self.warnings += 1;
+                ("WARN", d)
+            }
+            Status::Fail(d) => {
+                self.failures += 1;
+                ("FAIL", d)
+            }
+            Status::Skip(d) => ("SKIP", d),
+        };
+
+        println!("[{}] {}: {}", label, what, detail);
+    }
+}
+
+fn check_mountpoint(path: &Path, nonempty: bool) -> Status {
+    if mount::is_stale(path) {
+        return Status::Warn(format!(
+            "{} is a stale mount, and will be cleaned up when mounting",
+            path.display()
+        ));
+    }
+
+    let metadata = match std::fs::metadata(path) {
+        Ok(m) => m,
+        Err(e) => return Status::Fail(format!("{}: {}", path.display(), e)),
+    };
+
+    if !metadata.is_dir() {
+        return Status::Fail(format!("{} is not a directory", path.display()));
+    }
+
+    match mount::is_mounted(path) {
+        Ok(true) => {
+            return Status::Fail(format!(
+                "Something is already mounted at {}",
+                path.display()
+            ))
+        }
+        Ok(false) => {}
+        Err(e) => return Status::Fail(format!("{}: {}", path.display(), e)),
+    }
+
+    let empty = match std::fs::read_dir(path) {
+        Ok(mut entries) => entries.next().is_none(),
+        Err(e) => return Status::Fail(format!("Could not list {}: {}", path.display(), e)),
+    };
+
+    match (empty, nonempty) {
+        (true, _) => Status::Ok(format!("{} is an empty directory", path.display())),
+        (false, true) => Status::Warn(format!(
+            "{} is not empty, and its contents will be hidden while mounted",
+            path.display()
+        )),
+        (false, false) => Status::Fail(format!(
+            "{} is not empty (mount with -o nonempty to allow it)",
+            path.display()
+        )),
+    }
+}
+
+/// Run every check and print a report
+///
+/// Returns whether mounting should work.
+pub fn run(conf: &Config, mountpoint: &Path, network: bool) -> bool {
+    let mut report = Report::default();
+
+    report.add(
+        "Mountpoint",
+        check_mountpoint(mountpoint, conf.mount_options.has("nonempty")),
+    );
+
+    report.add(
+        "Database",
+        match requests::check_database(&conf.database) {
+            Ok(count) => Status::Ok(format!(
+                "{} ({} comics cached)",
+                conf.database.to_string_lossy(),
+                count
+            )),
+            Err(e) => Status::Fail(format!("{}: {}", conf.database.to_string_lossy(), e)),
+        },
+    );
+
+    report.add(
+        "FUSE",
+        match mount::check_fuse() {
+            Ok(unmounter) => Status::Ok(format!("available, unmounting with {}", unmounter)),
+            Err(e) => Status::Fail(e),
+        },
+    );
+
+    report.add(
+        "Font",
+        if conf.no_render {
+            Status::Skip("nothing is rendered with --no-render".to_string())
+        } else {
+            match image::check_font() {
+                Ok(font) => Status::Ok(format!("{} is installed", font)),
+                Err(e) => Status::Warn(e),
+            }
+        },
+    );
+
+    report.add(
+        "Network",
+        if network {
+            match requests::probe_network(conf.timeout, &conf.user_agent, conf.headers.clone()) {
+                Ok(latest) => Status::Ok(format!("the latest comic is {}", latest)),
+                Err(e) => Status::Fail(format!("could not reach xkcd.com: {}", e)),
+            }
+        } else {
+            Status::Skip("use --check-network to try reaching xkcd.com".to_string())
+        },
+    );
+
+    println!("{} failed, {} warnings", report.failures, report.warnings);
+
+    report.failures == 0
+}
@@ -1,21 +1,848 @@
-use clap::{App, Arg};
+use crate::backup::{self, Backups};
+use crate::config::{ConfigFile, SourceSettings};
+use crate::desktop;
+use crate::export::ExportFormat;
+use crate::feed::UpdateSource;
+use crate::fs::file::{File, MAX_THUMBNAIL_WIDTH};
+use crate::fs::options::MountOptions;
+use crate::fstab;
+use crate::image::wallpaper::{self, WallpaperSpec};
+use crate::image::{ascii, Color, Contrast, RenderOptions, ScaleFilter};
+use crate::logging::{Destination, Filter, Format, Rotation};
+use crate::units::{parse_duration, parse_size};
+use clap::{App, AppSettings, Arg, ArgMatches, Shell, SubCommand};
 use log::LevelFilter;
-use std::ffi::OsString;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::ffi::{OsStr, OsString};
+use std::path::PathBuf;
 use std::time::Duration;
 
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Checking more often than this would just be rude to xkcd.com
+pub const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Backing up more often than this would spend more time copying than serving
+const MIN_BACKUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What xkcdfs was asked to do
+pub enum Command {
+    /// Mount the filesystem (the default)
+    Mount { mountpoint: OsString },
+    /// Check that mounting would work, without mounting
+    Check { mountpoint: OsString, network: bool },
+    /// Write a range of comics to a file
+    Export {
+        format: ExportFormat,
+        output: OsString,
+        first: u32,
+        last: Option<u32>,
+    },
+    /// Copy the comics in a pack into the cache
+    ImportPack { input: OsString },
+    /// Download every comic, picking up where the last run left off
+    Mirror {
+        /// Cache rendered images too
+        rendered: bool,
+        /// Wait this long after each comic, to go easy on xkcd.com
+        delay: Option<Duration>,
+    },
+    /// Print the fetch log's record of network use in the last `since`
+    CacheHistory { since: Duration },
+    /// Delete cached data that can be made again without the network
+    CacheClear {
+        /// Rendered images, and everything made from them
+        rendered: bool,
+    },
+    /// Serve the tree over WebDAV instead of mounting it
+    ServeDav { listen: String },
+    /// Serve a gallery for web browsers instead of mounting
+    ServeHttp { listen: String },
+}
+
 pub struct Config {
     pub timeout: Duration,
-    pub mountpoint: OsString,
+    /// How often to check for new comics while mounted
+    pub refresh_interval: Option<Duration>,
+    /// Where to find out about new comics
+    pub update_source: UpdateSource,
+    /// Where to copy the cache to every so often while mounted
+    pub backups: Option<Backups>,
+    pub command: Command,
     pub database: OsString,
+    pub max_cache_size: Option<u64>,
     pub log_level: LevelFilter,
+    pub log_filter: Filter,
+    pub log_destination: Destination,
+    pub log_format: Format,
     pub user_agent: String,
+    /// Sent with every request, on top of the user agent
+    pub headers: HeaderMap,
+    /// Somewhere other than https://xkcd.com with the same API, for testing
+    pub api_url: Option<String>,
+    /// A pack to mount instead of fetching from xkcd.com
+    pub pack: Option<OsString>,
+    /// A directory of images to mount instead of fetching from xkcd.com
+    pub archive: Option<OsString>,
+    /// Widths of each comic's thumbnails, the first used by the gallery
+    pub thumbnail_sizes: Vec<u32>,
+    pub thumbnail_filter: ScaleFilter,
+    pub ascii_width: u32,
+    /// Serve transcripts with the API's markup, instead of cleaned up
+    pub raw_transcripts: bool,
+    /// Size and background of `wallpaper.png`
+    pub wallpaper: WallpaperSpec,
+    pub render_threads: usize,
+    pub fuse_threads: usize,
+    /// Most bytes renders and open files' snapshots can use at once
+    pub max_memory: Option<u64>,
+    pub keep_animations: bool,
+    pub no_render: bool,
+    pub render_options: RenderOptions,
+    pub mount_options: MountOptions,
+    /// Files left out of the top of the mount
+    pub hidden: Vec<File>,
+    /// Note when comics are read, for their atimes and eviction
+    pub track_atime: bool,
+    /// Run with `sh -c` for each new comic the refresh finds
+    pub on_new_comic: Option<String>,
+    /// Show a desktop notification for each new comic the refresh finds
+    pub notify: bool,
+    pub foreground: bool,
+    pub pidfile: Option<OsString>,
+    /// Socket to take commands from `xkcdfsctl` on while mounted
+    pub control_socket: Option<OsString>,
+    /// User to switch to once mounted, when started as root
+    pub run_as: Option<String>,
+    /// Comics to download in the background once mounted, from the first to
+    /// the last (or the latest)
+    pub prefetch: Option<(u32, Option<u32>)>,
+    /// Other webcomics, each mounted in its own directory
+    pub sources: Vec<SourceSettings>,
+    pub reload: Reload,
+}
+
+impl Config {
+    /// Every source's database, and then the main one
+    pub fn databases(&self) -> Vec<OsString> {
+        self.sources
+            .iter()
+            .map(|s| s.database(&self.database))
+            .chain(std::iter::once(self.database.clone()))
+            .collect()
+    }
+}
+
+/// What's needed to build the config again after the config file changes
+#[derive(Clone)]
+pub struct Reload {
+    matches: ArgMatches<'static>,
+    /// Absolute, since the daemon changes directory
+    config_path: Option<PathBuf>,
+}
+
+/// Parse the command line, or return None if there's nothing left to do
+pub fn get_args() -> Result<Option<Config>, String> {
+    let mut args = std::env::args_os().peekable();
+
+    let matches = if args.peek().is_some_and(|a| fstab::is_mount_helper(a)) {
+        app().get_matches_from(fstab::translate(args)?)
+    } else {
+        app().get_matches_from(args)
+    };
+
+    if let ("completions", Some(completions)) = matches.subcommand() {
+        let shell = match completions.value_of("shell").map(str::parse::<Shell>) {
+            None => {
+                return Err("Could not determine shell".to_string());
+            }
+            Some(Err(e)) => {
+                return Err(e);
+            }
+            Some(Ok(s)) => s,
+        };
+
+        app().gen_completions_to(env!("CARGO_PKG_NAME"), shell, &mut std::io::stdout());
+
+        return Ok(None);
+    }
+
+    let config_path = match matches.value_of_os("config") {
+        None => None,
+        Some(path) => match std::env::current_dir() {
+            Err(e) => {
+                return Err(format!("Could not find the current directory: {}", e));
+            }
+            Ok(dir) => Some(dir.join(path)),
+        },
+    };
+
+    Reload {
+        matches,
+        config_path,
+    }
+    .config()
+    .map(Some)
+}
+
+impl Reload {
+    /// Whether there's a config file to reload
+    pub fn has_config_file(&self) -> bool {
+        self.config_path.is_some()
+    }
+
+    /// Build the config from the command line and the current config file
+    pub fn config(&self) -> Result<Config, String> {
+        let matches = &self.matches;
+
+        let config_file = match &self.config_path {
+            None => ConfigFile::default(),
+            Some(path) => match ConfigFile::load(path) {
+                Err(e) => {
+                    return Err(e);
+                }
+                Ok(c) => c,
+            },
+        };
+
+        // Pull out command-line arguments
+        let timeout = match matches.value_of("timeout").map(parse_duration) {
+            None => match config_file.network.timeout() {
+                Err(e) => {
+                    return Err(format!("Invalid [network] config: {}", e));
+                }
+                Ok(t) => t.unwrap_or(DEFAULT_TIMEOUT),
+            },
+            Some(Err(e)) => {
+                return Err(e);
+            }
+            Some(Ok(t)) => t,
+        };
+        if timeout == Duration::from_secs(0) {
+            return Err("Timeout must be more than zero".to_string());
+        }
+        let refresh_interval = match matches.value_of("refresh-interval").map(parse_duration) {
+            None => match config_file.network.refresh_interval() {
+                Err(e) => {
+                    return Err(format!("Invalid [network] config: {}", e));
+                }
+                Ok(i) => i,
+            },
+            Some(Err(e)) => {
+                return Err(e);
+            }
+            Some(Ok(i)) => Some(i),
+        };
+        if refresh_interval.is_some_and(|i| i < MIN_REFRESH_INTERVAL) {
+            return Err("Refresh interval must be at least a minute".to_string());
+        }
+        let update_source = match matches
+            .value_of("update-source")
+            .map(str::parse::<UpdateSource>)
+        {
+            None => {
+                return Err("Could not determine update source".to_string());
+            }
+            Some(Err(e)) => {
+                return Err(e);
+            }
+            Some(Ok(s)) => s,
+        };
+        let backup_interval = match matches.value_of("backup-interval").map(parse_duration) {
+            None => backup::DEFAULT_INTERVAL,
+            Some(Err(e)) => {
+                return Err(e);
+            }
+            Some(Ok(i)) if i < MIN_BACKUP_INTERVAL => {
+                return Err("Backup interval must be at least a minute".to_string());
+            }
+            Some(Ok(i)) => i,
+        };
+        let backup_keep = match matches.value_of("backup-keep").map(str::parse::<usize>) {
+            None => backup::DEFAULT_KEEP,
+            Some(Err(e)) => {
+                return Err(format!("Could not parse backup count as an integer: {}", e));
+            }
+            Some(Ok(0)) => {
+                return Err("At least one backup has to be kept".to_string());
+            }
+            Some(Ok(n)) => n,
+        };
+        let backups = match matches.value_of_os("backup-dir") {
+            None => None,
+            Some(dir) => match std::env::current_dir() {
+                Err(e) => {
+                    return Err(format!("Could not find the current directory: {}", e));
+                }
+                Ok(current) => Some(Backups {
+                    dir: current.join(dir),
+                    interval: backup_interval,
+                    keep: backup_keep,
+                }),
+            },
+        };
+        if matches.is_present("notify") && !desktop::AVAILABLE {
+            return Err(
+                "--notify needs xkcdfs built with --features desktop-notifications".to_string(),
+            );
+        }
+        let sources = match config_file.sources() {
+            Err(e) => {
+                return Err(format!("Invalid [[source]] config: {}", e));
+            }
+            Ok(s) => s,
+        };
+        let command = match matches.subcommand() {
+            (name @ "export-epub", Some(export))
+            | (name @ "export-cbz", Some(export))
+            | (name @ "export-pack", Some(export)) => {
+                let format = match name {
+                    "export-epub" => ExportFormat::Epub,
+                    "export-cbz" => ExportFormat::Cbz,
+                    _ => ExportFormat::Pack,
+                };
+                let output = match export.value_of_os("output") {
+                    None => {
+                        return Err("Could not determine export file".to_string());
+                    }
+                    Some(o) => o,
+                };
+                let first = match export.value_of("from").map(str::parse::<u32>) {
+                    None => {
+                        return Err("Could not determine first comic to export".to_string());
+                    }
+                    Some(Err(e)) => {
+                        return Err(format!("Could not parse first comic as an integer: {}", e));
+                    }
+                    Some(Ok(n)) => n,
+                };
+                let last = match export.value_of("to").map(str::parse::<u32>) {
+                    None => None,
+                    Some(Err(e)) => {
+                        return Err(format!("Could not parse last comic as an integer: {}", e));
+                    }
+                    Some(Ok(n)) => Some(n),
+                };
+
+                Command::Export {
+                    format,
+                    output: output.to_owned(),
+                    first,
+                    last,
+                }
+            }
+            ("import-pack", Some(import)) => match import.value_of_os("input") {
+                None => {
+                    return Err("Could not determine pack to import".to_string());
+                }
+                Some(i) => Command::ImportPack {
+                    input: i.to_owned(),
+                },
+            },
+            ("mirror", Some(mirror)) => {
+                let delay = match mirror.value_of("delay").map(parse_duration) {
+                    None => None,
+                    Some(Err(e)) => {
+                        return Err(e);
+                    }
+                    Some(Ok(d)) => Some(d),
+                };
+
+                Command::Mirror {
+                    rendered: mirror.is_present("rendered"),
+                    delay,
+                }
+            }
+            ("cache", Some(cache)) => match cache.subcommand() {
+                ("history", Some(history)) => {
+                    let since = match history.value_of("since").map(parse_duration) {
+                        None => {
+                            return Err("Could not determine how far back to go".to_string());
+                        }
+                        Some(Err(e)) => {
+                            return Err(e);
+                        }
+                        Some(Ok(d)) => d,
+                    };
+
+                    Command::CacheHistory { since }
+                }
+                ("clear", Some(clear)) => Command::CacheClear {
+                    rendered: clear.is_present("rendered"),
+                },
+                _ => return Err("Could not determine what to do with the cache".to_string()),
+            },
+            (name @ "serve-dav", Some(serve)) | (name @ "serve-http", Some(serve)) => {
+                let listen = match serve.value_of("listen") {
+                    None => {
+                        return Err("Could not determine address to listen on".to_string());
+                    }
+                    Some(l) => l.to_string(),
+                };
+
+                match name {
+                    "serve-dav" => Command::ServeDav { listen },
+                    _ => Command::ServeHttp { listen },
+                }
+            }
+            _ => match matches.value_of_os("path") {
+                None => {
+                    return Err("Could not determine mount path".to_string());
+                }
+                Some(p) if matches.is_present("check") => Command::Check {
+                    mountpoint: p.to_owned(),
+                    network: matches.is_present("check-network"),
+                },
+                Some(p) => Command::Mount {
+                    mountpoint: p.to_owned(),
+                },
+            },
+        };
+        let mut mount_options = MountOptions::default();
+        if matches.is_present("no-auto-unmount") {
+            mount_options.disable_auto_unmount();
+        }
+        for arg in matches.values_of("fuse-opt").into_iter().flatten() {
+            mount_options.add(arg)?;
+        }
+        for name in &["fsname", "subtype"] {
+            if let Some(value) = matches.value_of(name) {
+                mount_options.set(name, value)?;
+            }
+        }
+        // Nothing written to a pack or archive's mount could go anywhere
+        let read_only = matches.is_present("read-only")
+            || matches.is_present("pack")
+            || matches.is_present("archive");
+        if read_only && !mount_options.has("ro") {
+            mount_options.add("ro")?;
+        }
+        let mut hidden = Vec::new();
+        for name in matches.values_of("hide").into_iter().flatten() {
+            hidden.push(parse_hidden(name)?);
+        }
+        let database = match matches.value_of_os("database") {
+            None => default_database()?,
+            Some(d) => d.to_owned(),
+        };
+        let max_cache_size = match matches.value_of("max-cache-size").map(parse_size) {
+            None => match config_file.cache.max_size() {
+                Err(e) => {
+                    return Err(format!("Invalid [cache] config: {}", e));
+                }
+                Ok(s) => s,
+            },
+            Some(Err(e)) => {
+                return Err(e);
+            }
+            Some(Ok(s)) => Some(s),
+        };
+        let user_agent = matches.value_of("user-agent").unwrap();
+        let mut headers = HeaderMap::new();
+        for header in matches.values_of("header").into_iter().flatten() {
+            let (name, value) = parse_header(header)?;
+            headers.append(name, value);
+        }
+        let mut thumbnail_sizes = Vec::new();
+        for size in matches.values_of("thumbnail-sizes").into_iter().flatten() {
+            let size = parse_thumbnail_size(size)?;
+            if !thumbnail_sizes.contains(&size) {
+                thumbnail_sizes.push(size);
+            }
+        }
+        let thumbnail_filter = match matches
+            .value_of("thumbnail-filter")
+            .map(str::parse::<ScaleFilter>)
+        {
+            None => {
+                return Err("Could not determine thumbnail filter".to_string());
+            }
+            Some(Err(e)) => {
+                return Err(e);
+            }
+            Some(Ok(f)) => f,
+        };
+        let (wallpaper_width, wallpaper_height) = match matches
+            .value_of("wallpaper-size")
+            .map(wallpaper::parse_size)
+        {
+            None => {
+                return Err("Could not determine wallpaper size".to_string());
+            }
+            Some(Err(e)) => {
+                return Err(e);
+            }
+            Some(Ok(s)) => s,
+        };
+        let wallpaper_background = match matches
+            .value_of("wallpaper-background")
+            .map(str::parse::<Color>)
+        {
+            None => {
+                return Err("Could not determine wallpaper background".to_string());
+            }
+            Some(Err(e)) => {
+                return Err(e);
+            }
+            Some(Ok(c)) => c,
+        };
+        let ascii_width = match matches.value_of("ascii-width").map(str::parse::<u32>) {
+            None => {
+                return Err("Could not determine ASCII art width".to_string());
+            }
+            Some(Err(e)) => {
+                return Err(format!(
+                    "Could not parse ASCII art width as an integer: {}",
+                    e
+                ));
+            }
+            Some(Ok(w)) if !(ascii::MIN_WIDTH..=ascii::MAX_WIDTH).contains(&w) => {
+                return Err(format!(
+                    "ASCII art width must be between {} and {} columns",
+                    ascii::MIN_WIDTH,
+                    ascii::MAX_WIDTH
+                ));
+            }
+            Some(Ok(w)) => w,
+        };
+        let render_threads = match matches.value_of("render-threads").map(str::parse::<usize>) {
+            None => {
+                return Err("Could not determine render thread count".to_string());
+            }
+            Some(Err(e)) => {
+                return Err(format!(
+                    "Could not parse render thread count as an integer: {}",
+                    e
+                ));
+            }
+            Some(Ok(0)) => {
+                return Err("At least one render thread is needed".to_string());
+            }
+            Some(Ok(n)) => n,
+        };
+        let fuse_threads = match matches.value_of("fuse-threads").map(str::parse::<usize>) {
+            None => {
+                return Err("Could not determine filesystem thread count".to_string());
+            }
+            Some(Err(e)) => {
+                return Err(format!(
+                    "Could not parse filesystem thread count as an integer: {}",
+                    e
+                ));
+            }
+            Some(Ok(0)) => {
+                return Err("At least one filesystem thread is needed".to_string());
+            }
+            Some(Ok(n)) => n,
+        };
+        let max_memory = match matches.value_of("max-memory").map(parse_size) {
+            None => None,
+            Some(Err(e)) => {
+                return Err(e);
+            }
+            Some(Ok(0)) => {
+                return Err("The memory budget can't be 0".to_string());
+            }
+            Some(Ok(s)) => Some(s),
+        };
+
+        let max_render_width = match matches.value_of("max-render-width").map(str::parse::<u32>) {
+            None => None,
+            Some(Err(e)) => {
+                return Err(format!(
+                    "Could not parse maximum render width as an integer: {}",
+                    e
+                ));
+            }
+            Some(Ok(0)) => {
+                return Err("The maximum render width must be at least 1 pixel".to_string());
+            }
+            Some(Ok(w)) => Some(w),
+        };
+        let max_render_height = match matches.value_of("max-render-height").map(str::parse::<u32>) {
+            None => None,
+            Some(Err(e)) => {
+                return Err(format!(
+                    "Could not parse maximum render height as an integer: {}",
+                    e
+                ));
+            }
+            Some(Ok(0)) => {
+                return Err("The maximum render height must be at least 1 pixel".to_string());
+            }
+            Some(Ok(h)) => Some(h),
+        };
+
+        let pixels = |name: &str| match matches.value_of(name).map(str::parse::<f64>) {
+            None => Ok(None),
+            Some(Err(e)) => Err(format!("Could not parse {} as a number: {}", name, e)),
+            Some(Ok(p)) => Ok(Some(p)),
+        };
+        let alt_background = match matches.value_of("alt-background").map(str::parse::<Color>) {
+            None => None,
+            Some(Err(e)) => {
+                return Err(e);
+            }
+            Some(Ok(c)) => Some(c),
+        };
+
+        let contrast = match matches.value_of("contrast").map(str::parse::<Contrast>) {
+            None => {
+                return Err("Could not determine contrast".to_string());
+            }
+            Some(Err(e)) => {
+                return Err(e);
+            }
+            Some(Ok(c)) => c,
+        };
+
+        // The command line wins over the config file
+        let theme = match config_file.render.options() {
+            Err(e) => {
+                return Err(format!("Invalid [render] config: {}", e));
+            }
+            Ok(t) => t,
+        };
+
+        let render_options = RenderOptions {
+            max_width: max_render_width,
+            max_height: max_render_height,
+            outer_margin: pixels("margin")?.unwrap_or(theme.outer_margin),
+            alt_width: pixels("alt-width")?.unwrap_or(theme.alt_width),
+            alt_padding: pixels("alt-padding")?.unwrap_or(theme.alt_padding),
+            alt_background: alt_background.unwrap_or(theme.alt_background),
+            contrast,
+            ..theme
+        };
+
+        if let Err(e) = render_options.validate() {
+            return Err(format!("Invalid render options: {}", e));
+        }
+
+        let log_rotation = match matches.value_of("log-rotate").map(str::parse::<Rotation>) {
+            None => {
+                return Err("Could not determine log rotation".to_string());
+            }
+            Some(Err(e)) => {
+                return Err(e);
+            }
+            Some(Ok(r)) => r,
+        };
+        let log_destination = match (
+            matches.value_of_os("log-file"),
+            matches.value_of("log-target"),
+        ) {
+            (Some(path), _) => Destination::File {
+                path: path.into(),
+                rotation: log_rotation,
+            },
+            (None, None) | (None, Some("stderr")) => Destination::Stderr,
+            (None, Some("syslog")) => Destination::Syslog,
+            (None, Some("journald")) => Destination::Journald,
+            (None, Some(other)) => {
+                return Err(format!("Unknown log target: {}", other));
+            }
+        };
+
+        let log_format = match matches.value_of("log-format").map(str::parse::<Format>) {
+            None => {
+                return Err("Could not determine log format".to_string());
+            }
+            Some(Err(e)) => {
+                return Err(e);
+            }
+            Some(Ok(f)) => f,
+        };
+
+        let prefetch = match matches.value_of("prefetch-on-mount").map(parse_range) {
+            None if matches.is_present("prefetch-on-mount") => Some((1, None)),
+            None => None,
+            Some(Err(e)) => {
+                return Err(e);
+            }
+            Some(Ok(r)) => Some(r),
+        };
+
+        let log_filter = match matches.value_of("log-filter").map(str::parse::<Filter>) {
+            None => match config_file.log.filter() {
+                Err(e) => {
+                    return Err(format!("Invalid [log] config: {}", e));
+                }
+                Ok(f) => f.unwrap_or_default(),
+            },
+            Some(Err(e)) => {
+                return Err(e);
+            }
+            Some(Ok(f)) => f,
+        };
+
+        let quiet = matches.occurrences_of("quiet") as i64;
+        let verbose = matches.occurrences_of("verbose") as i64;
+        let verbosity_level: i64 = 3 - quiet + verbose;
+
+        use LevelFilter::*;
+        let log_level = match verbosity_level {
+            std::i64::MIN..=0 => Off,
+            1 => Error,
+            2 => Warn,
+            3 => Info,
+            4 => Debug,
+            5..=std::i64::MAX => Trace,
+        };
+
+        // -q and -v win over the config file
+        let log_level = match config_file.log.level() {
+            Err(e) => {
+                return Err(format!("Invalid [log] config: {}", e));
+            }
+            Ok(Some(level)) if quiet == 0 && verbose == 0 => level,
+            Ok(_) => log_level,
+        };
+
+        Ok(Config {
+            timeout,
+            refresh_interval,
+            update_source,
+            backups,
+            command,
+            database,
+            max_cache_size,
+            log_level,
+            log_filter,
+            log_destination,
+            log_format,
+            user_agent: user_agent.to_owned(),
+            headers,
+            api_url: matches.value_of("api-url").map(str::to_string),
+            pack: matches.value_of_os("pack").map(OsStr::to_owned),
+            archive: matches.value_of_os("archive").map(OsStr::to_owned),
+            thumbnail_sizes,
+            thumbnail_filter,
+            ascii_width,
+            raw_transcripts: matches.is_present("raw-transcripts"),
+            wallpaper: WallpaperSpec {
+                width: wallpaper_width,
+                height: wallpaper_height,
+                background: wallpaper_background,
+            },
+            render_threads,
+            fuse_threads,
+            max_memory,
+            keep_animations: matches.is_present("keep-animations"),
+            no_render: matches.is_present("no-render"),
+            render_options,
+            mount_options,
+            hidden,
+            track_atime: matches.is_present("track-atime"),
+            on_new_comic: matches.value_of("on-new-comic").map(str::to_string),
+            notify: matches.is_present("notify"),
+            foreground: matches.is_present("foreground"),
+            pidfile: matches.value_of_os("pidfile").map(OsString::from),
+            control_socket: matches.value_of_os("control-socket").map(OsString::from),
+            run_as: matches.value_of("run-as").map(str::to_string),
+            prefetch,
+            sources,
+            reload: self.clone(),
+        })
+    }
+}
+
+/// A file at the top of the mount that can be hidden, like `refresh`
+fn parse_hidden(name: &str) -> Result<File, String> {
+    File::from_filename(&File::Root, name)
+        .filter(|f| f.comic_num().is_none())
+        .ok_or_else(|| format!("{:?} isn't a file at the top of the mount", name))
+}
+
+/// The width of one size of thumbnail, in pixels
+fn parse_thumbnail_size(size: &str) -> Result<u32, String> {
+    match size.parse::<u32>() {
+        Err(e) => Err(format!(
+            "Could not parse thumbnail size {:?} as an integer: {}",
+            size, e
+        )),
+        Ok(0) => Err("Thumbnail sizes must be at least 1 pixel".to_string()),
+        Ok(w) if w > MAX_THUMBNAIL_WIDTH => Err(format!(
+            "Thumbnail sizes can be at most {} pixels",
+            MAX_THUMBNAIL_WIDTH
+        )),
+        Ok(w) => Ok(w),
+    }
 }
 
-pub fn get_args() -> Option<Config> {
-    let matches = App::new(env!("CARGO_PKG_NAME"))
+/// Parse a range of comics like `100-200`, `100-` or `-200`
+///
+/// Missing ends mean the first or latest comic, and a single number is a
+/// range of one.
+pub fn parse_range(range: &str) -> Result<(u32, Option<u32>), String> {
+    let parse = |n: &str| {
+        n.parse::<u32>()
+            .map_err(|e| format!("Invalid comic number {:?} in range: {}", n, e))
+    };
+
+    let (first, last) = match range.find('-') {
+        None => (parse(range)?, Some(parse(range)?)),
+        Some(i) => (
+            match &range[..i] {
+                "" => 1,
+                n => parse(n)?,
+            },
+            match &range[i + 1..] {
+                "" => None,
+                n => Some(parse(n)?),
+            },
+        ),
+    };
+
+    if first == 0 || last.is_some_and(|last| first > last) {
+        return Err(format!("{} is not a valid range of comics", range));
+    }
+
+    Ok((first, last))
+}
+
+/// Parse a header like `Name: value`
+fn parse_header(header: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let colon = match header.find(':') {
+        Some(i) => i,
+        None => {
+            return Err(format!(
+                "Header {:?} should look like \"Name: value\"",
+                header
+            ))
+        }
+    };
+
+    let name = HeaderName::from_bytes(header[..colon].trim().as_bytes())
+        .map_err(|e| format!("Invalid header name in {:?}: {}", header, e))?;
+    let value = HeaderValue::from_str(header[colon + 1..].trim())
+        .map_err(|e| format!("Invalid header value in {:?}: {}", header, e))?;
+
+    Ok((name, value))
+}
+
+/// Everything xkcdfs accepts on the command line
+fn app<'a, 'b>() -> App<'a, 'b> {
+    App::new(env!("CARGO_PKG_NAME"))
         .version(env!("CARGO_PKG_VERSION"))
         .author(env!("CARGO_PKG_AUTHORS"))
         .about(env!("CARGO_PKG_DESCRIPTION"))
+        .after_help(concat!(
+            "EXIT CODES:\n",
+            "    1    Any other failure\n",
+            "    2    Invalid command line or config file\n",
+            "    3    The database could not be opened, or is in use\n",
+            "    4    The mountpoint is missing or unusable\n",
+            "    5    xkcd.com could not be reached, and nothing is cached\n",
+            "    6    FUSE could not mount the filesystem, or failed while mounted\n",
+            "\n",
+            "SIGNALS:\n",
+            "    SIGHUP     Re-read the config file and reopen the log file. The config\n",
+            "               is applied when the mount next handles a request, so an idle\n",
+            "               mount doesn't pick it up (or log that it did) until it's used\n",
+            "    SIGUSR1    Log statistics",
+        ))
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("path")
                 .help("Path where the filesystem will be mounted")
@@ -23,22 +850,194 @@ pub fn get_args() -> Option<Config> {
                 .required(true)
                 .index(1),
         )
+        .arg(
+            Arg::with_name("config")
+                .help("Config file location (re-read on SIGHUP)")
+                .short("c")
+                .long("config")
+                .value_name("FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("fuse-opt")
+                .help(concat!(
+                    "Mount options to pass to FUSE, like allow_other or ro ",
+                    "(allow_other needs user_allow_other in /etc/fuse.conf)"
+                ))
+                .short("o")
+                .long("fuse-opt")
+                .value_name("OPTIONS")
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("fsname")
+                .help("Name to show as the mount's source in mount and findmnt [default: xkcdfs]")
+                .long("fsname")
+                .value_name("NAME")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("subtype")
+                .help("Mount type to show, after fuse. [default: xkcdfs]")
+                .long("subtype")
+                .value_name("NAME")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("read-only")
+                .help("Mount read-only, so the refresh file can't be written to")
+                .long("read-only"),
+        )
+        .arg(
+            Arg::with_name("hide")
+                .help("Leave files out of the top of the mount, like refresh,credits")
+                .long("hide")
+                .value_name("NAMES")
+                .use_delimiter(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("track-atime")
+                .help("Note when comics are read, for ls -ltu and to evict unread comics first")
+                .long("track-atime"),
+        )
+        .arg(
+            Arg::with_name("on-new-comic")
+                .help(concat!(
+                    "Run this shell command for each new comic found while mounted, ",
+                    "with XKCDFS_NUM, XKCDFS_TITLE and XKCDFS_PATH set"
+                ))
+                .long("on-new-comic")
+                .value_name("CMD")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("notify")
+                .help("Show a desktop notification for each new comic found while mounted")
+                .long("notify"),
+        )
+        .arg(
+            Arg::with_name("no-auto-unmount")
+                .help("Leave the filesystem mounted if xkcdfs exits without unmounting it")
+                .long("no-auto-unmount"),
+        )
+        .arg(
+            Arg::with_name("foreground")
+                .help("Stay in the foreground instead of running as a daemon once mounted")
+                .short("f")
+                .long("foreground"),
+        )
+        .arg(
+            Arg::with_name("pidfile")
+                .help("Write the process ID to this file while mounted")
+                .long("pidfile")
+                .value_name("FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("control-socket")
+                .help("Take commands from xkcdfsctl on a Unix socket here while mounted")
+                .long("control-socket")
+                .value_name("PATH")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("run-as")
+                .help(concat!(
+                    "When started as root, switch to this user once mounted ",
+                    "(the database must be somewhere they can write to)"
+                ))
+                .long("run-as")
+                .value_name("USER")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("check")
+                .help("Check that everything needed to mount is in place, then exit")
+                .long("check")
+                .alias("dry-run"),
+        )
+        .arg(
+            Arg::with_name("check-network")
+                .help("Also make sure xkcd.com can be reached when checking")
+                .long("check-network")
+                .requires("check"),
+        )
+        .arg(
+            Arg::with_name("prefetch-on-mount")
+                .help(concat!(
+                    "Download every comic in the background once mounted, ",
+                    "or just a range like 1-500"
+                ))
+                .long("prefetch-on-mount")
+                .value_name("RANGE")
+                .min_values(0)
+                .require_equals(true),
+        )
         .arg(
             Arg::with_name("database")
-                .help("Database file location")
+                .help(concat!(
+                    "Database file location, or :memory: to keep nothing between mounts ",
+                    "[default: $XDG_CACHE_HOME/xkcdfs/cache.db]"
+                ))
                 .short("d")
                 .long("database")
                 .value_name("FILE")
-                .default_value(":memory:")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-cache-size")
+                .help("Evict cached images and renders to keep them under this size, like 2G")
+                .long("max-cache-size")
+                .value_name("SIZE")
                 .takes_value(true),
         )
         .arg(
             Arg::with_name("timeout")
-                .help("Timeout for web requests")
-                .value_name("SECONDS")
+                .help("Timeout for web requests, like 10s or 500ms [default: 5s]")
+                .value_name("DURATION")
                 .short("t")
                 .long("timeout")
-                .default_value("5"),
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("refresh-interval")
+                .help("How often to check for new comics while mounted, like 30m or 6h")
+                .long("refresh-interval")
+                .value_name("DURATION")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("update-source")
+                .help("Whether to check xkcd's Atom feed or its JSON for new comics")
+                .long("update-source")
+                .possible_values(&["api", "feed"])
+                .default_value("api"),
+        )
+        .arg(
+            Arg::with_name("backup-dir")
+                .help("Back up every source's cache to this directory every so often while mounted")
+                .long("backup-dir")
+                .value_name("DIR")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("backup-interval")
+                .help("How often to back up the cache, like 12h [default: 1d]")
+                .long("backup-interval")
+                .value_name("DURATION")
+                .takes_value(true)
+                .requires("backup-dir"),
+        )
+        .arg(
+            Arg::with_name("backup-keep")
+                .help("How many backups to keep, deleting the oldest [default: 7]")
+                .long("backup-keep")
+                .value_name("COUNT")
+                .takes_value(true)
+                .requires("backup-dir"),
         )
         .arg(
             Arg::with_name("quiet")
@@ -54,6 +1053,44 @@ pub fn get_args() -> Option<Config> {
                 .long("verbose")
                 .multiple(true),
         )
+        .arg(
+            Arg::with_name("log-filter")
+                .help(concat!(
+                    "Log levels for particular modules, ",
+                    "like xkcdfs_core::requests=trace,xkcdfs::fs=warn"
+                ))
+                .long("log-filter")
+                .value_name("FILTER")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-target")
+                .help("Where to send logs when there's no log file [default: stderr]")
+                .long("log-target")
+                .possible_values(&["stderr", "syslog", "journald"])
+                .conflicts_with("log-file"),
+        )
+        .arg(
+            Arg::with_name("log-file")
+                .help("Write logs to this file instead of stderr (reopened on SIGHUP)")
+                .long("log-file")
+                .value_name("FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-rotate")
+                .help("When to start a new log file: never, daily, or at a size like 10M")
+                .long("log-rotate")
+                .value_name("WHEN")
+                .default_value("10M"),
+        )
+        .arg(
+            Arg::with_name("log-format")
+                .help("How to write logs to stderr or a log file")
+                .long("log-format")
+                .possible_values(&["text", "json"])
+                .default_value("text"),
+        )
         .arg(
             Arg::with_name("user-agent")
                 .help("User agent string to send on API requests")
@@ -65,50 +1102,301 @@ pub fn get_args() -> Option<Config> {
                     env!("CARGO_PKG_VERSION")
                 )),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("header")
+                .help("Extra header to send on every request, like \"X-Api-Key: secret\"")
+                .short("H")
+                .long("header")
+                .value_name("HEADER")
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("api-url")
+                .help("Get xkcd from a stand-in with the same API, for testing")
+                .long("api-url")
+                .value_name("URL")
+                .takes_value(true)
+                .hidden(true),
+        )
+        .arg(
+            Arg::with_name("pack")
+                .help("Mount the comics in a pack, read-only and without the network")
+                .long("pack")
+                .value_name("FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("archive")
+                .help("Mount a directory of comic images, read-only and without the network")
+                .long("archive")
+                .value_name("DIR")
+                .takes_value(true)
+                .conflicts_with("pack"),
+        )
+        .arg(
+            Arg::with_name("thumbnail-sizes")
+                .help("Widths of comic thumbnails, each a thumbnail_WIDTH.png file, like 64,256")
+                .long("thumbnail-sizes")
+                .value_name("PIXELS")
+                .use_delimiter(true)
+                .multiple(true)
+                .number_of_values(1)
+                .default_value("200"),
+        )
+        .arg(
+            Arg::with_name("thumbnail-filter")
+                .help("Filter used when scaling thumbnails")
+                .long("thumbnail-filter")
+                .possible_values(&["box", "lanczos"])
+                .default_value("box"),
+        )
+        .arg(
+            Arg::with_name("wallpaper-size")
+                .help("Size of wallpaper.png, the latest comic centered for a desktop background")
+                .long("wallpaper-size")
+                .value_name("WIDTHxHEIGHT")
+                .default_value("1920x1080"),
+        )
+        .arg(
+            Arg::with_name("wallpaper-background")
+                .help("Color around the comic in wallpaper.png")
+                .long("wallpaper-background")
+                .value_name("COLOR")
+                .default_value("#ffffff"),
+        )
+        .arg(
+            Arg::with_name("ascii-width")
+                .help("Width of ASCII art renderings of comics")
+                .long("ascii-width")
+                .value_name("COLUMNS")
+                .default_value("80"),
+        )
+        .arg(
+            Arg::with_name("raw-transcripts")
+                .help("Serve transcripts as xkcd has them, [[markup]] and all")
+                .long("raw-transcripts"),
+        )
+        .arg(
+            Arg::with_name("render-threads")
+                .help("Number of threads rendering comics in the background")
+                .long("render-threads")
+                .value_name("COUNT")
+                .default_value("2"),
+        )
+        .arg(
+            Arg::with_name("fuse-threads")
+                .help("Number of threads fetching comics for filesystem requests")
+                .long("fuse-threads")
+                .value_name("COUNT")
+                .default_value("4"),
+        )
+        .arg(
+            Arg::with_name("max-memory")
+                .help("Keep renders and open files under this much memory, like 256M")
+                .long("max-memory")
+                .value_name("SIZE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("keep-animations")
+                .help("Serve animated comics as the original GIF instead of rendering them")
+                .long("keep-animations"),
+        )
+        .arg(
+            Arg::with_name("no-render")
+                .help("Serve the original images, and leave out everything that needs rendering")
+                .long("no-render"),
+        )
+        .arg(
+            Arg::with_name("max-render-width")
+                .help("Scale comics down so renders are at most this wide")
+                .long("max-render-width")
+                .value_name("PIXELS")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-render-height")
+                .help("Scale comics down so renders are at most this tall")
+                .long("max-render-height")
+                .value_name("PIXELS")
+                .takes_value(true),
+        )
+        // These have no default_value so they don't hide the config file's
+        // [render] section when they aren't given
+        .arg(
+            Arg::with_name("margin")
+                .help("Space around the edge of rendered comics [default: 40]")
+                .long("margin")
+                .value_name("PIXELS")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("alt-width")
+                .help("Width to wrap alt text to in rendered comics [default: 500]")
+                .long("alt-width")
+                .value_name("PIXELS")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("alt-padding")
+                .help("Space between the alt text and the edge of its box [default: 10]")
+                .long("alt-padding")
+                .value_name("PIXELS")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("alt-background")
+                .help("Background color of the alt text box [default: #fff9bd]")
+                .long("alt-background")
+                .value_name("COLOR")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("contrast")
+                .help("Render with larger text and a high-contrast, inverted comic")
+                .long("contrast")
+                .possible_values(&["normal", "high"])
+                .default_value("normal"),
+        )
+        .subcommand(export_subcommand(
+            "export-epub",
+            "Export a range of comics as an EPUB book",
+        ))
+        .subcommand(export_subcommand(
+            "export-cbz",
+            "Export a range of rendered comics as a CBZ comic book archive",
+        ))
+        .subcommand(export_subcommand(
+            "export-pack",
+            "Export a range of comics as a pack, to import or mount elsewhere",
+        ))
+        .subcommand(
+            SubCommand::with_name("import-pack")
+                .about("Copy the comics in a pack into the cache, without the network")
+                .arg(
+                    Arg::with_name("input")
+                        .help("Pack to import")
+                        .value_name("FILE")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("mirror")
+                .about("Download every comic, resuming if interrupted, and check them")
+                .arg(
+                    Arg::with_name("rendered")
+                        .help("Cache rendered images too")
+                        .long("rendered"),
+                )
+                .arg(
+                    Arg::with_name("delay")
+                        .help("Time to wait after each comic, like 500ms")
+                        .long("delay")
+                        .value_name("DURATION")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("cache")
+                .about("Look into the cache")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("history")
+                        .about("Show how many requests were made and how much was downloaded")
+                        .arg(
+                            Arg::with_name("since")
+                                .help("How far back to go, like 12h")
+                                .long("since")
+                                .value_name("DURATION")
+                                .default_value("1d"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("clear")
+                        .about("Delete cached data that can be made again without the network")
+                        .arg(
+                            Arg::with_name("rendered")
+                                .help("Rendered images, so they're rendered again when next read")
+                                .long("rendered")
+                                .required(true),
+                        ),
+                ),
+        )
+        .subcommand(serve_subcommand(
+            "serve-dav",
+            "Serve the comics over WebDAV instead of mounting them",
+        ))
+        .subcommand(serve_subcommand(
+            "serve-http",
+            "Serve a gallery of the comics for web browsers",
+        ))
+        .subcommand(
+            SubCommand::with_name("completions")
+                .about("Print a shell completion script")
+                .arg(
+                    Arg::with_name("shell")
+                        .help("Shell to write completions for")
+                        .possible_values(&["bash", "zsh", "fish"])
+                        .required(true)
+                        .index(1),
+                ),
+        )
+}
 
-    // Pull out command-line arguments
-    let timeout = match matches.value_of("timeout").map(str::parse::<u64>) {
-        None => {
-            panic!("Could not determine timeout value");
-        }
-        Some(Err(e)) => {
-            panic!("Could not parse timeout as an integer: {}", e);
-        }
-        Some(Ok(t)) => t,
-    };
-    let path = match matches.value_of_os("path") {
+/// The database location when none is given, creating its directory
+fn default_database() -> Result<OsString, String> {
+    let dir = match dirs::cache_dir() {
         None => {
-            panic!("Could not determine mount path");
+            return Err(
+                "Could not find a cache directory, use --database to choose a location".to_string(),
+            );
         }
-        Some(p) => p,
-    };
-    let database = match matches.value_of_os("database") {
-        None => {
-            panic!("Could not determine database location");
-        }
-        Some(d) => d,
-    };
-    let user_agent = matches.value_of("user-agent").unwrap();
-
-    let verbosity_level: i64 =
-        3 - matches.occurrences_of("quiet") as i64 + matches.occurrences_of("verbose") as i64;
-
-    use LevelFilter::*;
-    let log_level = match verbosity_level {
-        std::i64::MIN..=0 => Off,
-        1 => Error,
-        2 => Warn,
-        3 => Info,
-        4 => Debug,
-        5..=std::i64::MAX => Trace,
+        Some(d) => d.join(env!("CARGO_PKG_NAME")),
     };
 
-    Some(Config {
-        timeout: Duration::from_secs(timeout),
-        mountpoint: path.to_owned(),
-        database: database.to_owned(),
-        log_level,
-        user_agent: user_agent.to_owned(),
-    })
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        return Err(format!("Could not create {}: {}", dir.display(), e));
+    }
+
+    Ok(dir.join("cache.db").into_os_string())
+}
+
+/// Subcommand for serving the comics some way other than FUSE
+fn serve_subcommand<'a, 'b>(name: &'a str, about: &'b str) -> App<'a, 'b> {
+    SubCommand::with_name(name).about(about).arg(
+        Arg::with_name("listen")
+            .help("Address and port to listen on")
+            .long("listen")
+            .value_name("ADDR")
+            .default_value("127.0.0.1:8080"),
+    )
+}
+
+/// Subcommand for exporting a range of comics to a file
+fn export_subcommand<'a, 'b>(name: &'a str, about: &'b str) -> App<'a, 'b> {
+    SubCommand::with_name(name)
+        .about(about)
+        .arg(
+            Arg::with_name("output")
+                .help("File to write")
+                .value_name("FILE")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::with_name("from")
+                .help("First comic to export")
+                .long("from")
+                .value_name("NUM")
+                .default_value("1"),
+        )
+        .arg(
+            Arg::with_name("to")
+                .help("Last comic to export [default: the latest comic]")
+                .long("to")
+                .value_name("NUM")
+                .takes_value(true),
+        )
 }
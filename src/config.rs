@@ -0,0 +1,535 @@
+//! The optional TOML config file
+//!
+//! Everything in here can be left out, in which case the defaults (or the
+//! command line) decide.
+
+use crate::cli::{parse_range, MIN_REFRESH_INTERVAL};
+use crate::image::{Color, Preset, RenderOptions};
+use crate::logging::Filter;
+use crate::units::{parse_duration, parse_size};
+use crate::File;
+use log::LevelFilter;
+use serde::Deserialize;
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+use std::time::Duration;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    pub render: RenderTheme,
+    pub cache: CacheSettings,
+    pub network: NetworkSettings,
+    pub log: LogSettings,
+    #[serde(rename = "source")]
+    pub sources: Vec<SourceSettings>,
+}
+
+/// A `[[source]]`: another webcomic with an xkcd-style JSON API
+///
+/// Translations of xkcd, like es.xkcd.com, are sources too.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SourceSettings {
+    /// The directory its comics are in, at the top of the mount
+    ///
+    /// For a translation, this is the language, like `es`.
+    pub name: String,
+    /// Where the latest comic's JSON is
+    pub latest: String,
+    /// Where any comic's JSON is, with `{num}` for the comic number
+    pub comic: String,
+    /// Listed in `translations/<name>/` instead of at the top
+    #[serde(default)]
+    pub translation: bool,
+    /// How often to check this source for new comics, like `6h`
+    #[serde(default)]
+    pub refresh_interval: Option<String>,
+    /// Comics to fetch in the background after mounting, like `1-100` or `1-`
+    #[serde(default)]
+    pub prefetch: Option<String>,
+}
+
+impl SourceSettings {
+    fn check(&self) -> Result<(), String> {
+        let name_ok = !self.name.is_empty()
+            && !self.name.starts_with('.')
+            && self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+
+        if !name_ok {
+            return Err(format!(
+                "Source name {:?} should be letters, numbers, -, _ and .",
+                self.name
+            ));
+        }
+
+        if !self.translation && File::from_filename(&File::Root, &self.name).is_some() {
+            return Err(format!(
+                "Source name {:?} is already a file in the mount",
+                self.name
+            ));
+        }
+
+        if !self.comic.contains("{num}") {
+            return Err(format!(
+                "The comic URL for {} needs {{num}} where the number goes",
+                self.name
+            ));
+        }
+
+        let in_source = |e: String| format!("{} for {}", e, self.name);
+
+        if self
+            .refresh_interval()
+            .map_err(in_source)?
+            .is_some_and(|i| i < MIN_REFRESH_INTERVAL)
+        {
+            return Err(format!(
+                "The refresh interval for {} must be at least a minute",
+                self.name
+            ));
+        }
+
+        self.prefetch().map_err(in_source)?;
+
+        Ok(())
+    }
+
+    pub fn refresh_interval(&self) -> Result<Option<Duration>, String> {
+        self.refresh_interval
+            .as_deref()
+            .map(parse_duration)
+            .transpose()
+    }
+
+    /// The first comic to prefetch, and the last if it isn't the latest
+    pub fn prefetch(&self) -> Result<Option<(u32, Option<u32>)>, String> {
+        self.prefetch.as_deref().map(parse_range).transpose()
+    }
+
+    /// Where this source is cached, next to the main `database`
+    ///
+    /// `xkcd.db` becomes `xkcd.smbc.db`, so each source has its own cache.
+    pub fn database(&self, database: &OsStr) -> OsString {
+        let path = Path::new(database);
+        let mut name = path.file_stem().unwrap_or_default().to_owned();
+
+        name.push(".");
+        name.push(&self.name);
+
+        if let Some(extension) = path.extension() {
+            name.push(".");
+            name.push(extension);
+        }
+
+        path.with_file_name(name).into_os_string()
+    }
+}
+
+/// The `[log]` section
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogSettings {
+    /// off, error, warn, info, debug, or trace
+    pub level: Option<String>,
+    /// Levels for particular modules, like `xkcdfs_core::requests=trace`
+    pub filter: Option<String>,
+}
+
+impl LogSettings {
+    pub fn level(&self) -> Result<Option<LevelFilter>, String> {
+        self.level
+            .as_deref()
+            .map(|l| l.parse().map_err(|_| format!("Invalid log level {:?}", l)))
+            .transpose()
+    }
+
+    pub fn filter(&self) -> Result<Option<Filter>, String> {
+        self.filter.as_deref().map(str::parse).transpose()
+    }
+}
+
+/// The `[cache]` section
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheSettings {
+    /// Most space images and renders can take up, like `2G`
+    pub max_size: Option<String>,
+}
+
+impl CacheSettings {
+    pub fn max_size(&self) -> Result<Option<u64>, String> {
+        self.max_size.as_deref().map(parse_size).transpose()
+    }
+}
+
+/// The `[network]` section
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkSettings {
+    /// How long to wait for xkcd.com, like `5s` or `500ms`
+    pub timeout: Option<String>,
+    /// How often to check for new comics while mounted, like `6h`
+    pub refresh_interval: Option<String>,
+}
+
+impl NetworkSettings {
+    pub fn timeout(&self) -> Result<Option<Duration>, String> {
+        self.timeout.as_deref().map(parse_duration).transpose()
+    }
+
+    pub fn refresh_interval(&self) -> Result<Option<Duration>, String> {
+        self.refresh_interval
+            .as_deref()
+            .map(parse_duration)
+            .transpose()
+    }
+}
+
+/// The `[render]` section: how rendered comics look
+///
+/// Settings start from the named preset, and anything given here replaces
+/// the preset's value. Colors are hex, like `#fff9bd`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderTheme {
+    /// classic, dark, or print
+    pub preset: Option<String>,
+    pub margin: Option<f64>,
+    pub title_font_size: Option<f64>,
+    pub title_spacing: Option<f64>,
+    pub alt_width: Option<f64>,
+    pub alt_padding: Option<f64>,
+    pub alt_font_size: Option<f64>,
+    pub alt_leading: Option<f64>,
+    pub alt_spacing: Option<f64>,
+    pub alt_border_width: Option<f64>,
+    pub foreground: Option<String>,
+    /// A color, or `transparent`
+    pub background: Option<String>,
+    pub alt_background: Option<String>,
+    pub alt_border: Option<String>,
+}
+
+impl RenderTheme {
+    /// Render options described by this section
+    pub fn options(&self) -> Result<RenderOptions, String> {
+        let preset = match self.preset {
+            None => Preset::Classic,
+            Some(ref p) => p.parse()?,
+        };
+
+        let mut options = RenderOptions::preset(preset);
+
+        fn set<T: Copy>(target: &mut T, value: Option<T>) {
+            if let Some(v) = value {
+                *target = v;
+            }
+        }
+
+        fn color(name: &str, value: &Option<String>) -> Result<Option<Color>, String> {
+            value
+                .as_ref()
+                .map(|v| v.parse().map_err(|e| format!("{}: {}", name, e)))
+                .transpose()
+        }
+
+        set(&mut options.outer_margin, self.margin);
+        set(&mut options.header_font_size, self.title_font_size);
+        set(&mut options.header_spacing, self.title_spacing);
+        set(&mut options.alt_width, self.alt_width);
+        set(&mut options.alt_padding, self.alt_padding);
+        set(&mut options.alt_font_size, self.alt_font_size);
+        set(&mut options.alt_leading, self.alt_leading);
+        set(&mut options.alt_spacing, self.alt_spacing);
+        set(&mut options.alt_border_width, self.alt_border_width);
+        set(
+            &mut options.foreground,
+            color("foreground", &self.foreground)?,
+        );
+        set(
+            &mut options.alt_background,
+            color("alt_background", &self.alt_background)?,
+        );
+        set(
+            &mut options.alt_border,
+            color("alt_border", &self.alt_border)?,
+        );
+
+        match self.background.as_deref() {
+            None => {}
+            Some("transparent") => options.background = None,
+            Some(_) => options.background = color("background", &self.background)?,
+        }
+
+        Ok(options)
+    }
+}
+
+impl ConfigFile {
+    /// The `[[source]]`s, if they all make sense
+    pub fn sources(&self) -> Result<Vec<SourceSettings>, String> {
+        for (i, source) in self.sources.iter().enumerate() {
+            source.check()?;
+
+            if self.sources[..i].iter().any(|s| s.name == source.name) {
+                return Err(format!(
+                    "There's more than one source called {}",
+                    source.name
+                ));
+            }
+        }
+
+        Ok(self.sources.clone())
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+
+        Self::parse(&text).map_err(|e| format!("Could not parse {}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn empty_file_is_classic() {
+        let config = ConfigFile::parse("").unwrap();
+
+        assert_eq!(config.render.options().unwrap(), RenderOptions::default());
+    }
+
+    #[test]
+    fn settings_override_the_preset() {
+        let config = ConfigFile::parse(
+            r##"
+            [render]
+            preset = "dark"
+            margin = 10
+            alt_font_size = 18.5
+            alt_background = "#102030"
+            "##,
+        )
+        .unwrap();
+        let options = config.render.options().unwrap();
+        let dark = RenderOptions::preset(Preset::Dark);
+
+        assert_eq!(options.outer_margin, 10.0);
+        assert_eq!(options.alt_font_size, 18.5);
+        assert_eq!(options.alt_background, "#102030".parse().unwrap());
+        assert_eq!(options.foreground, dark.foreground);
+        assert_eq!(options.background, dark.background);
+    }
+
+    #[test]
+    fn background_can_be_transparent() {
+        let config = ConfigFile::parse(
+            r#"
+            [render]
+            preset = "print"
+            background = "transparent"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.render.options().unwrap().background, None);
+    }
+
+    #[test]
+    fn parses_cache_size() {
+        let config = ConfigFile::parse("[cache]\nmax_size = \"2G\"").unwrap();
+        assert_eq!(config.cache.max_size(), Ok(Some(2 << 30)));
+
+        let config = ConfigFile::parse("[cache]\nmax_size = \"lots\"").unwrap();
+        assert!(config.cache.max_size().is_err());
+    }
+
+    #[test]
+    fn parses_network_durations() {
+        let config = ConfigFile::parse(
+            r#"
+            [network]
+            timeout = "500ms"
+            refresh_interval = "6h"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.network.timeout(),
+            Ok(Some(Duration::from_millis(500)))
+        );
+        assert_eq!(
+            config.network.refresh_interval(),
+            Ok(Some(Duration::from_secs(6 * 60 * 60)))
+        );
+        assert_eq!(ConfigFile::default().network.refresh_interval(), Ok(None));
+    }
+
+    #[test]
+    fn parses_log_settings() {
+        let config = ConfigFile::parse(
+            r#"
+            [log]
+            level = "debug"
+            filter = "xkcdfs::fs=warn"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.log.level(), Ok(Some(LevelFilter::Debug)));
+        assert_eq!(
+            config.log.filter(),
+            Ok(Some("xkcdfs::fs=warn".parse().unwrap()))
+        );
+
+        let config = ConfigFile::parse("[log]\nlevel = \"chatty\"").unwrap();
+        assert!(config.log.level().is_err());
+    }
+
+    #[test]
+    fn parses_sources() {
+        let config = ConfigFile::parse(
+            r#"
+            [[source]]
+            name = "smbc"
+            latest = "https://example.com/info.0.json"
+            comic = "https://example.com/{num}/info.0.json"
+            "#,
+        )
+        .unwrap();
+        let sources = config.sources().unwrap();
+
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].name, "smbc");
+        assert_eq!(
+            sources[0].database(OsStr::new("/var/cache/xkcd.db")),
+            OsString::from("/var/cache/xkcd.smbc.db")
+        );
+        assert_eq!(
+            sources[0].database(OsStr::new("cache")),
+            OsString::from("cache.smbc")
+        );
+        assert!(ConfigFile::default().sources().unwrap().is_empty());
+    }
+
+    #[test]
+    fn sources_refresh_and_prefetch() {
+        let source = |settings: &str| {
+            ConfigFile::parse(&format!(
+                "[[source]]\nname = \"smbc\"\nlatest = \"https://example.com\"\n\
+                 comic = \"https://example.com/{{num}}\"\n{}",
+                settings
+            ))
+            .unwrap()
+            .sources()
+            .map(|s| s[0].clone())
+        };
+
+        let both = source("refresh_interval = \"6h\"\nprefetch = \"10-\"").unwrap();
+        assert_eq!(
+            both.refresh_interval(),
+            Ok(Some(Duration::from_secs(6 * 3600)))
+        );
+        assert_eq!(both.prefetch(), Ok(Some((10, None))));
+
+        let neither = source("").unwrap();
+        assert_eq!(neither.refresh_interval(), Ok(None));
+        assert_eq!(neither.prefetch(), Ok(None));
+
+        assert!(source("refresh_interval = \"10s\"").is_err());
+        assert!(source("prefetch = \"5-1\"").is_err());
+    }
+
+    #[test]
+    fn parses_translations() {
+        let config = ConfigFile::parse(
+            r#"
+            [[source]]
+            name = "es"
+            latest = "https://es.example.com/info.0.json"
+            comic = "https://es.example.com/{num}/info.0.json"
+            translation = true
+
+            [[source]]
+            name = "credits"
+            latest = "https://credits.example.com/info.0.json"
+            comic = "https://credits.example.com/{num}/info.0.json"
+            translation = true
+            "#,
+        )
+        .unwrap();
+        let sources = config.sources().unwrap();
+
+        assert!(sources.iter().all(|s| s.translation));
+
+        // Translations have their own directory, so only the top is off limits
+        assert_eq!(sources[1].name, "credits");
+        assert_eq!(
+            sources[0].database(OsStr::new("/var/cache/xkcd.db")),
+            OsString::from("/var/cache/xkcd.es.db")
+        );
+    }
+
+    #[test]
+    fn rejects_bad_sources() {
+        let source = |name: &str, comic: &str| {
+            ConfigFile::parse(&format!(
+                "[[source]]\nname = {:?}\nlatest = \"https://example.com\"\ncomic = {:?}",
+                name, comic
+            ))
+            .unwrap()
+            .sources()
+        };
+
+        assert!(source("smbc", "https://example.com/{num}").is_ok());
+        assert!(source("smbc", "https://example.com/").is_err());
+
+        for bad in &[
+            "",
+            "a/b",
+            "..",
+            "refresh",
+            "montage.png",
+            "info_0001",
+            "translations",
+        ] {
+            assert!(source(bad, "https://example.com/{num}").is_err(), "{}", bad);
+        }
+
+        let twice = r#"
+            [[source]]
+            name = "a"
+            latest = "https://example.com"
+            comic = "https://example.com/{num}"
+
+            [[source]]
+            name = "a"
+            latest = "https://example.org"
+            comic = "https://example.org/{num}"
+            "#;
+        assert!(ConfigFile::parse(twice).unwrap().sources().is_err());
+    }
+
+    #[test]
+    fn rejects_bad_settings() {
+        assert!(ConfigFile::parse("[render]\nmargn = 10").is_err());
+        assert!(ConfigFile::parse("[render]\nmargin = \"wide\"").is_err());
+
+        for bad in &["preset = \"neon\"", "foreground = \"black\""] {
+            let config = ConfigFile::parse(&format!("[render]\n{}", bad)).unwrap();
+
+            assert!(config.render.options().is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,366 @@
+//! A Unix socket for driving a mount from scripts, and `xkcdfsctl`
+//!
+//! The fuse crate has no ioctl callback, so commands come in over a socket
+//! beside the mount instead. Each line written to it is one command, and each
+//! gets one line of JSON back, with `"ok": false` and an `"error"` if it
+//! failed:
+//!
+//! - `refresh` checks for a new latest comic, like writing to `refresh`
+//! - `prefetch RANGE` downloads comics in the background, like
+//!   `--prefetch-on-mount`
+//! - `stats` gives the counters SIGUSR1 logs
+//! - `clear-cache` deletes rendered images, so they're rendered again
+//!
+//! Like the stats thread, this has its own database connection, so it still
+//! answers when the filesystem thread is stuck.
+
+use crate::cli;
+use crate::logging;
+use crate::prefetch;
+use crate::requests::RequestMode::BustCache;
+use crate::XkcdClient;
+use serde::Serialize;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How long a connection can sit without sending a command
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The counters from a `stats` command
+#[derive(Serialize, Debug, PartialEq)]
+pub struct StatsReply {
+    pub uptime_secs: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub network_requests: u64,
+    pub network_failures: u64,
+    pub in_flight: u64,
+    pub downloaded: u64,
+    pub renders: u64,
+    pub renders_pending: u64,
+    pub prefetch_remaining: u64,
+    pub open_handles: u64,
+    pub memory_reserved: u64,
+    pub cached_comics: usize,
+    /// Bytes of images and renders, if the database could say
+    pub cache_bytes: Option<u64>,
+}
+
+/// The answer to one command, sent back as a line of JSON
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct Reply {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The latest comic, after a refresh
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latest: Option<u32>,
+    /// The first and last comics being prefetched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefetching: Option<(u32, u32)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<StatsReply>,
+    /// Bytes of rendered images deleted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cleared: Option<u64>,
+}
+
+impl Reply {
+    fn failed(error: String) -> Self {
+        Self {
+            error: Some(error),
+            ..Self::default()
+        }
+    }
+
+    fn to_line(&self) -> String {
+        // Nothing in there can fail to serialize
+        serde_json::to_string(self).unwrap() + "\n"
+    }
+}
+
+/// Carry out one command
+pub fn answer(client: &XkcdClient, line: &str) -> Reply {
+    let mut words = line.split_whitespace();
+    let command = words.next().unwrap_or("");
+    let args = words.collect::<Vec<_>>();
+
+    let _op = logging::operation_scope("control", None);
+    debug!("Control command {:?}", line.trim());
+
+    let reply = match (command, args.as_slice()) {
+        ("refresh", []) => client
+            .request_latest_comic(None, BustCache)
+            .map(|latest| Reply {
+                latest: Some(latest.num),
+                ..Reply::default()
+            })
+            .ok_or_else(|| "Could not get the latest comic".to_string()),
+        ("prefetch", [range]) => prefetch_range(client, range).map(|range| Reply {
+            prefetching: Some(range),
+            ..Reply::default()
+        }),
+        ("stats", []) => Ok(Reply {
+            stats: Some(stats(client)),
+            ..Reply::default()
+        }),
+        ("clear-cache", []) => client.clear_rendered().map(|cleared| Reply {
+            cleared: Some(cleared),
+            ..Reply::default()
+        }),
+        ("refresh", _) | ("stats", _) | ("clear-cache", _) => {
+            Err(format!("{} takes no arguments", command))
+        }
+        ("prefetch", _) => Err("prefetch takes a range of comics, like 1-100".to_string()),
+        ("", _) => Err("No command given".to_string()),
+        _ => Err(format!("Unknown command {:?}", command)),
+    };
+
+    match reply {
+        Ok(reply) => Reply { ok: true, ..reply },
+        Err(e) => {
+            logging::set_outcome("error");
+            Reply::failed(e)
+        }
+    }
+}
+
+/// Start prefetching `range`, up to the latest cached comic if it's open
+fn prefetch_range(client: &XkcdClient, range: &str) -> Result<(u32, u32), String> {
+    let (first, last) = cli::parse_range(range)?;
+    let last = last.unwrap_or(client.get_cached_count() as u32);
+
+    prefetch::spawn("prefetch".to_string(), client.try_clone()?, first, last)?;
+
+    Ok((first, last))
+}
+
+fn stats(client: &XkcdClient) -> StatsReply {
+    let stats = client.stats().snapshot();
+
+    StatsReply {
+        uptime_secs: stats.uptime.as_secs(),
+        cache_hits: stats.cache_hits,
+        cache_misses: stats.cache_misses,
+        network_requests: stats.network_requests,
+        network_failures: stats.network_failures,
+        in_flight: stats.in_flight,
+        downloaded: stats.downloaded,
+        renders: stats.renders,
+        renders_pending: stats.renders_pending,
+        prefetch_remaining: stats.prefetch_remaining,
+        open_handles: stats.open_handles,
+        memory_reserved: stats.memory_reserved,
+        cached_comics: client.get_cached_count(),
+        cache_bytes: client.cache_size().ok(),
+    }
+}
+
+/// Answer every command on one connection, until it's closed
+fn serve(client: &XkcdClient, stream: UnixStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+
+        if !line.trim().is_empty() {
+            writer.write_all(answer(client, &line).to_line().as_bytes())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The listening socket, removed again when this is dropped
+pub struct Socket {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl Socket {
+    /// Listen at `path`, only for this user
+    ///
+    /// A socket left behind by a mount that didn't exit cleanly is replaced,
+    /// but not one something is still listening on.
+    pub fn bind(path: &Path) -> Result<Self, String> {
+        // Absolute, so it can still be removed after the working directory
+        // changes
+        let path = std::env::current_dir()
+            .map_err(|e| e.to_string())?
+            .join(path);
+
+        if let Ok(meta) = std::fs::symlink_metadata(&path) {
+            if !meta.file_type().is_socket() {
+                return Err(format!("{} is not a socket", path.display()));
+            }
+            if UnixStream::connect(&path).is_ok() {
+                return Err(format!("{} is already in use", path.display()));
+            }
+
+            std::fs::remove_file(&path)
+                .map_err(|e| format!("Could not remove {}: {}", path.display(), e))?;
+        }
+
+        let listener = UnixListener::bind(&path)
+            .map_err(|e| format!("Could not listen at {}: {}", path.display(), e))?;
+
+        // Removes the socket again if this fails
+        let socket = Self { listener, path };
+        let private = std::fs::Permissions::from_mode(0o600);
+
+        std::fs::set_permissions(&socket.path, private)
+            .map_err(|e| format!("Could not make {} private: {}", socket.path.display(), e))?;
+
+        Ok(socket)
+    }
+
+    /// Answer commands on their own thread, one connection at a time
+    pub fn spawn(&self, client: XkcdClient) -> Result<JoinHandle<()>, String> {
+        let listener = self.listener.try_clone().map_err(|e| e.to_string())?;
+
+        info!("Listening for commands at {}", self.path.display());
+
+        thread::Builder::new()
+            .name("control".to_string())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    let result = stream.and_then(|s| serve(&client, s));
+
+                    if let Err(e) = result {
+                        warn!("Control connection failed: {}", e);
+                    }
+                }
+            })
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("Could not remove {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::image::RenderOptions;
+    use crate::source::Source;
+    use crate::Comic;
+    use reqwest::header::HeaderMap;
+    use std::ffi::OsStr;
+    use xkcdfs_core::{CacheStore, XkcdApi};
+
+    fn client() -> XkcdClient {
+        let api = XkcdApi::new(Duration::from_secs(1), "test".to_string(), HeaderMap::new());
+        let client = XkcdClient::with_source(
+            Source::Api(api.unwrap()),
+            OsStr::new(":memory:"),
+            false,
+            false,
+            RenderOptions::default(),
+            None,
+        )
+        .unwrap();
+
+        let comic = Comic {
+            num: 614,
+            day: 24,
+            month: 7,
+            year: 2009,
+            link: None,
+            news: None,
+            alt: String::new(),
+            title: "Woodpecker".to_string(),
+            safe_title: "Woodpecker".to_string(),
+            transcript: None,
+            img_url: "https://imgs.xkcd.com/comics/woodpecker.png".to_string(),
+            img_len: None,
+            extra_parts: None,
+        };
+
+        client.store().put_comic(&comic).unwrap();
+        client
+            .store()
+            .put_rendered_image(614, "key", &[0; 100])
+            .unwrap();
+
+        client
+    }
+
+    #[test]
+    fn gives_stats() {
+        let reply = answer(&client(), "stats");
+
+        assert!(reply.ok);
+        assert_eq!(reply.stats.unwrap().cached_comics, 614);
+    }
+
+    #[test]
+    fn clears_renders() {
+        let client = client();
+        let reply = answer(&client, "clear-cache\n");
+
+        assert_eq!(
+            reply,
+            Reply {
+                ok: true,
+                cleared: Some(100),
+                ..Reply::default()
+            }
+        );
+        assert_eq!(client.cache_size().unwrap(), 0);
+    }
+
+    #[test]
+    fn rejects_bad_commands() {
+        let client = client();
+
+        for line in &["", "evict 614", "stats now", "prefetch", "prefetch 5-2"] {
+            let reply = answer(&client, line);
+
+            assert!(!reply.ok, "{:?} worked", line);
+            assert!(reply.error.is_some());
+        }
+    }
+
+    #[test]
+    fn replies_are_json_lines() {
+        let line = Reply::failed("No command given".to_string()).to_line();
+
+        assert_eq!(line, "{\"ok\":false,\"error\":\"No command given\"}\n");
+    }
+
+    #[test]
+    fn answers_over_the_socket() {
+        let path = std::env::temp_dir().join(format!("xkcdfs-control-{}", std::process::id()));
+        let socket = Socket::bind(&path).unwrap();
+        socket.spawn(client()).unwrap();
+
+        // A second mount can't take it over
+        assert!(Socket::bind(&path).is_err());
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream.write_all(b"stats\nbogus\n").unwrap();
+        let mut lines = BufReader::new(stream).lines();
+
+        let stats: serde_json::Value =
+            serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!(stats["ok"], true);
+        assert_eq!(stats["stats"]["cached_comics"], 614);
+
+        let bogus: serde_json::Value =
+            serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!(bogus["ok"], false);
+
+        drop(socket);
+        assert!(!path.exists());
+    }
+}
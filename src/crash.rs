@@ -0,0 +1,97 @@
+//! Going down cleanly when something panics
+//!
+//! Panics in filesystem workers and render threads are caught there, and the
+//! request just fails. A panic on the thread running the FUSE session can't
+//! be, and without help it would leave a mountpoint that hangs anything that
+//! touches it until someone runs `fusermount -u`.
+//!
+//! The hook logs every panic, since stderr is often nowhere useful. When the
+//! session's thread panics, it also checkpoints the databases, so nothing is
+//! left only in a write-ahead log, and unmounts before the process goes away.
+
+use std::any::Any;
+use std::ffi::OsString;
+use std::panic::{self, PanicHookInfo};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use crate::fs;
+use crate::requests;
+
+/// What a panic said, if it said anything readable
+fn payload_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "(no message)"
+    }
+}
+
+fn describe(info: &PanicHookInfo) -> String {
+    let message = payload_message(info.payload());
+
+    match info.location() {
+        Some(l) => format!("{} at {}:{}", message, l.file(), l.line()),
+        None => message.to_string(),
+    }
+}
+
+/// Log panics from now on, and clean up if the current thread panics
+///
+/// This is the thread that runs the session for the filesystem at
+/// `mountpoint`, caching in `databases`.
+pub fn install(mountpoint: PathBuf, databases: Vec<OsString>) {
+    let session = thread::current().id();
+    let previous = panic::take_hook();
+
+    // Only the first panic cleans up, in case cleaning up panics too
+    let cleaned_up = AtomicBool::new(false);
+
+    panic::set_hook(Box::new(move |info| {
+        let thread = thread::current();
+
+        error!(
+            "Thread {} panicked: {}",
+            thread.name().unwrap_or("(unnamed)"),
+            describe(info)
+        );
+
+        if thread.id() == session && !cleaned_up.swap(true, Ordering::SeqCst) {
+            error!(
+                "The filesystem can't carry on, unmounting {}",
+                mountpoint.display()
+            );
+
+            for database in &databases {
+                if let Err(e) = requests::checkpoint_database(database) {
+                    error!("Could not checkpoint {}: {}", database.to_string_lossy(), e);
+                }
+            }
+
+            if let Err(e) = fs::mount::unmount(&mountpoint) {
+                error!("Could not unmount {}: {}", mountpoint.display(), e);
+            }
+        }
+
+        log::logger().flush();
+
+        previous(info);
+    }));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_panic_messages() {
+        let message = |payload: Box<dyn Any + Send>| payload_message(&*payload).to_string();
+
+        assert_eq!(message(Box::new("oops")), "oops");
+        assert_eq!(message(Box::new(format!("comic {}", 404))), "comic 404");
+        assert_eq!(message(Box::new(404)), "(no message)");
+    }
+}
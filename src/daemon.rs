@@ -0,0 +1,122 @@
+//! Detaching from the terminal once the filesystem is mounted
+//!
+//! The fork has to happen early, before any threads exist (the HTTP client
+//! starts one as soon as it's created), but the original process shouldn't
+//! exit until the mount has worked. So the daemon is forked first and reports
+//! back over a pipe once it's ready, and the original process exits with the
+//! exit code it hears.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+
+/// The running daemon, which still needs to tell its parent it's ready
+pub struct Daemon {
+    ready: File,
+}
+
+fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+/// Fork into the background
+///
+/// Only returns in the daemon. The original process waits to hear whether
+/// the daemon started, and exits accordingly.
+pub fn daemonize() -> io::Result<Daemon> {
+    let mut fds = [0; 2];
+    check(unsafe { libc::pipe(fds.as_mut_ptr()) })?;
+
+    let (mut waiting, ready) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    if check(unsafe { libc::fork() })? != 0 {
+        drop(ready);
+
+        // The daemon writes its exit code, 0 once it's ready. If it dies
+        // first, the pipe closes with nothing written.
+        let mut status = [0];
+        let code = match waiting.read(&mut status) {
+            Ok(1) => libc::c_int::from(status[0]),
+            _ => crate::exit::FAILURE,
+        };
+
+        // Skip destructors, since they belong to the daemon now
+        unsafe { libc::_exit(code) };
+    }
+
+    drop(waiting);
+
+    // Leave the terminal's session, then fork again so the daemon isn't a
+    // session leader and can never pick up a controlling terminal
+    check(unsafe { libc::setsid() })?;
+
+    if check(unsafe { libc::fork() })? != 0 {
+        unsafe { libc::_exit(0) };
+    }
+
+    debug!("Daemon started with PID {}", std::process::id());
+
+    Ok(Daemon { ready })
+}
+
+impl Daemon {
+    /// Let go of the terminal and tell the original process to exit
+    ///
+    /// Call this once everything that might fail on startup has worked. Any
+    /// relative paths must already have been used, since the working
+    /// directory changes to `/`.
+    pub fn detach(self) -> io::Result<()> {
+        // Don't keep whatever directory we were started from busy
+        std::env::set_current_dir("/")?;
+
+        let null = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/null")?;
+
+        for fd in &[libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+            check(unsafe { libc::dup2(null.as_raw_fd(), *fd) })?;
+        }
+
+        let mut ready = self.ready;
+        ready.write_all(&[0])
+    }
+
+    /// Tell the original process to exit with `code`, since starting failed
+    pub fn fail(self, code: i32) {
+        let mut ready = self.ready;
+
+        // Exit codes only go up to 255 anyway
+        ready.write_all(&[code as u8]).ok();
+    }
+}
+
+/// A file holding our PID, removed when dropped
+pub struct Pidfile {
+    path: PathBuf,
+}
+
+impl Pidfile {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        // Make the path absolute so it can still be removed after the working
+        // directory changes
+        let path = std::env::current_dir()?.join(path);
+
+        std::fs::write(&path, format!("{}\n", std::process::id()))?;
+
+        Ok(Self { path })
+    }
+}
+
+impl Drop for Pidfile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("Could not remove pidfile {}: {}", self.path.display(), e);
+        }
+    }
+}
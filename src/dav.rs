@@ -0,0 +1,309 @@
+//! `serve-dav`: the mounted tree, over WebDAV instead of FUSE
+//!
+//! For systems without FUSE, or for reading comics from another machine. The
+//! tree is the same one the filesystem shows, minus the files that only do
+//! anything when written to. It's read-only, so only what's needed to browse
+//! and download is supported: OPTIONS, PROPFIND, GET and HEAD.
+
+use crate::fs::{self, file::File};
+use crate::http::{self, content_type, escape, header, Handler};
+use crate::image::ScaleFilter;
+use crate::requests::RequestMode::*;
+use crate::XkcdClient;
+use tiny_http::{Method, Request, Response};
+
+const ALLOW: &str = "OPTIONS, PROPFIND, GET, HEAD";
+
+/// What a thread needs to answer requests
+struct DavHandler {
+    client: XkcdClient,
+    thumbnail_sizes: Vec<u32>,
+    thumbnail_filter: ScaleFilter,
+    ascii_width: u32,
+    raw_transcripts: bool,
+}
+
+/// Serve the tree on `listen` until the process is killed
+pub fn serve(
+    client: XkcdClient,
+    listen: &str,
+    thumbnail_sizes: Vec<u32>,
+    thumbnail_filter: ScaleFilter,
+    ascii_width: u32,
+    raw_transcripts: bool,
+) -> Result<(), String> {
+    http::serve(listen, "WebDAV", || {
+        Ok(DavHandler {
+            client: client.try_clone()?,
+            thumbnail_sizes: thumbnail_sizes.clone(),
+            thumbnail_filter,
+            ascii_width,
+            raw_transcripts,
+        })
+    })
+}
+
+/// The file at a URL path, like `/info_0001/alt`
+fn resolve(path: &str) -> Option<File> {
+    path.split('?')
+        .next()
+        .unwrap_or("")
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .try_fold(File::Root, |parent, name| {
+            File::from_filename(&parent, name)
+        })
+}
+
+/// Whether a file is shown over WebDAV
+///
+/// Refreshing and montages both work by writing to a file, so they're left out.
+/// Only xkcd itself is served, so translations are too, and `.status`, the
+/// wallpaper and history are about the mount. WebDAV has no symlinks, so
+/// `latest` and `random` can't be served, and `by_year`, `by_date` and
+/// `by_title` are only filled in by the filesystem. Thumbnails are there in the
+/// sizes asked for.
+fn served(client: &XkcdClient, thumbnail_sizes: &[u32], file: &File) -> bool {
+    match file {
+        File::Refresh
+        | File::Montage
+        | File::Translations
+        | File::Status
+        | File::Wallpaper
+        | File::History
+        | File::LatestLink
+        | File::RandomLink
+        | File::ByYear
+        | File::Year(_)
+        | File::ByDate
+        | File::Day(_)
+        | File::ByTitle
+        | File::Error(_) => false,
+        File::Thumbnail(_, width) if !thumbnail_sizes.contains(width) => false,
+        f => client.rendering_enabled() || !f.is_rendered(),
+    }
+}
+
+fn is_directory(file: &File) -> bool {
+    matches!(file, File::Root | File::MetaFolder(_))
+}
+
+/// The URL path of a file listed as `name`
+fn href(file: &File, name: &str) -> String {
+    match file {
+        File::Root => "/".to_string(),
+        File::MetaFolder(_) => format!("/{}/", name),
+        f => match f.comic_num() {
+            Some(num) if *f != File::Image(num) => {
+                format!("/{}/{}", File::MetaFolder(num).filename(), name)
+            }
+            _ => format!("/{}", name),
+        },
+    }
+}
+
+impl Handler for DavHandler {
+    fn handle(&self, mut request: Request) {
+        let method = request.method().clone();
+        let path = request.url().split('?').next().unwrap_or("");
+        let file = resolve(path).filter(|f| {
+            served(&self.client, &self.thumbnail_sizes, f) && fs::listed_with(&self.client, f, path)
+        });
+
+        debug!("WebDAV {} for {}", method, request.url());
+
+        // Nothing here needs a body, but it has to be read before the
+        // connection can be reused
+        std::io::copy(request.as_reader(), &mut std::io::sink()).ok();
+
+        let not_allowed = || Response::empty(405).with_header(header("Allow", ALLOW));
+
+        let result = match (method, file) {
+            (Method::Options, _) => request.respond(
+                Response::empty(200)
+                    .with_header(header("DAV", "1"))
+                    .with_header(header("Allow", ALLOW)),
+            ),
+            (_, None) => request.respond(Response::empty(404)),
+            (Method::Get, Some(ref f)) | (Method::Head, Some(ref f)) if is_directory(f) => {
+                request.respond(not_allowed())
+            }
+            (Method::Get, Some(f)) | (Method::Head, Some(f)) => match self.contents(&f) {
+                Some(data) => {
+                    let name = fs::entry_name(&self.client, f.inode(), f.filename());
+
+                    request.respond(
+                        Response::from_data(data)
+                            .with_header(header("Content-Type", content_type(&name))),
+                    )
+                }
+                None => request.respond(Response::empty(502)),
+            },
+            (ref m, Some(ref f)) if m.as_str() == "PROPFIND" => {
+                // Infinite depth is treated as 1, since the whole tree would
+                // mean fetching every comic
+                let depth_zero = request
+                    .headers()
+                    .iter()
+                    .any(|h| h.field.equiv("Depth") && h.value.as_str().trim() == "0");
+
+                request.respond(
+                    Response::from_string(self.propfind(f, !depth_zero))
+                        .with_status_code(207)
+                        .with_header(header("Content-Type", "application/xml; charset=utf-8")),
+                )
+            }
+            _ => request.respond(not_allowed()),
+        };
+
+        if let Err(e) = result {
+            debug!("Could not send a WebDAV response: {}", e);
+        }
+    }
+}
+
+impl DavHandler {
+    /// What a GET of a file returns
+    fn contents(&self, file: &File) -> Option<Vec<u8>> {
+        match file {
+            File::Credits => Some(fs::CREDITS_DATA.as_bytes().to_vec()),
+            File::Image(num) => {
+                let comic = self.client.request_comic(*num, None, Normal)?;
+
+                self.client.request_rendered_image(&comic, None, Normal)
+            }
+            f => fs::file_contents(
+                &self.client,
+                f,
+                self.thumbnail_filter,
+                self.ascii_width,
+                self.raw_transcripts,
+            ),
+        }
+    }
+
+    /// A multistatus response describing `file`, and its children if asked
+    fn propfind(&self, file: &File, children: bool) -> String {
+        let mut body = String::from(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n",
+        );
+
+        let name = fs::entry_name(&self.client, file.inode(), file.filename());
+        self.describe(&mut body, file, &name, true);
+
+        if children && is_directory(file) {
+            let count = self.client.get_cached_count() as u64;
+
+            // Index 0 and 1 are . and ..
+            let mut index = 2;
+
+            while let Some((ino, _, name)) = file.child_by_index(index, count) {
+                index += 1;
+
+                match File::from_inode(ino) {
+                    Some(ref child) if served(&self.client, &self.thumbnail_sizes, child) => {
+                        let name = fs::entry_name(&self.client, ino, name);
+                        self.describe(&mut body, child, &name, false);
+                    }
+                    _ => {}
+                }
+            }
+
+            // Thumbnails aren't listed by `child_by_index`
+            if let File::MetaFolder(num) = *file {
+                for &width in &self.thumbnail_sizes {
+                    let child = File::Thumbnail(num, width);
+
+                    if served(&self.client, &self.thumbnail_sizes, &child) {
+                        self.describe(&mut body, &child, &child.filename(), false);
+                    }
+                }
+            }
+        }
+
+        body.push_str("</D:multistatus>\n");
+        body
+    }
+
+    /// Add a `<D:response>` for `file`, listed as `name`
+    ///
+    /// Working out a file's size can mean fetching or rendering it, so that's
+    /// only done when the file itself is asked about, not when it's listed.
+    fn describe(&self, body: &mut String, file: &File, name: &str, with_length: bool) {
+        body.push_str("<D:response>\n");
+        body.push_str(&format!("<D:href>{}</D:href>\n", escape(&href(file, name))));
+        body.push_str("<D:propstat><D:prop>\n");
+        body.push_str(&format!(
+            "<D:displayname>{}</D:displayname>\n",
+            escape(name)
+        ));
+
+        if is_directory(file) {
+            body.push_str("<D:resourcetype><D:collection/></D:resourcetype>\n");
+        } else {
+            body.push_str("<D:resourcetype/>\n");
+            body.push_str(&format!(
+                "<D:getcontenttype>{}</D:getcontenttype>\n",
+                content_type(name)
+            ));
+
+            let data = if with_length {
+                self.contents(file)
+            } else {
+                None
+            };
+
+            if let Some(data) = data {
+                body.push_str(&format!(
+                    "<D:getcontentlength>{}</D:getcontentlength>\n",
+                    data.len()
+                ));
+            }
+        }
+
+        let comic = file
+            .comic_num()
+            .and_then(|num| self.client.request_comic(num, None, VeryFast));
+
+        if let Some(comic) = comic {
+            body.push_str(&format!(
+                "<D:getlastmodified>{}</D:getlastmodified>\n",
+                time::at_utc(comic.time()).rfc822()
+            ));
+        }
+
+        body.push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat>\n");
+        body.push_str("</D:response>\n");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn resolves_paths() {
+        assert_eq!(resolve("/"), Some(File::Root));
+        assert_eq!(resolve(""), Some(File::Root));
+        assert_eq!(resolve("/comic_0001.png"), Some(File::Image(1)));
+        assert_eq!(resolve("/comic_0001.gif"), Some(File::Image(1)));
+        assert_eq!(resolve("/info_0012/"), Some(File::MetaFolder(12)));
+        assert_eq!(resolve("/info_0012/alt?x=1"), Some(File::AltText(12)));
+        assert_eq!(resolve("/credits"), Some(File::Credits));
+        assert_eq!(resolve("/info_0012/nope"), None);
+        assert_eq!(resolve("/credits/alt"), None);
+    }
+
+    #[test]
+    fn hrefs_round_trip() {
+        for file in &[
+            File::Root,
+            File::Credits,
+            File::Image(3),
+            File::MetaFolder(3),
+            File::Date(3),
+        ] {
+            assert_eq!(resolve(&href(file, &file.filename())).as_ref(), Some(file));
+        }
+    }
+}
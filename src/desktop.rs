@@ -0,0 +1,82 @@
+//! `--notify`: a desktop notification for each new comic
+//!
+//! Only builds with the `desktop-notifications` feature can show them, since
+//! notify-rust brings D-Bus along with it. Other builds refuse `--notify`.
+
+use crate::Comic;
+
+/// Whether this build can show notifications at all
+pub const AVAILABLE: bool = cfg!(feature = "desktop-notifications");
+
+/// Most notification servers read the body as a little HTML
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// The notification's summary and body
+fn message(comic: &Comic) -> (String, String) {
+    (
+        format!("xkcd {}: {}", comic.num, comic.title),
+        escape(&comic.alt),
+    )
+}
+
+/// Show a notification for `comic`
+#[cfg(feature = "desktop-notifications")]
+pub fn notify(comic: &Comic) {
+    let (summary, body) = message(comic);
+
+    let shown = notify_rust::Notification::new()
+        .appname("xkcdfs")
+        .summary(&summary)
+        .body(&body)
+        .show();
+
+    if let Err(e) = shown {
+        warn!(
+            "Could not show a notification for comic {}: {}",
+            comic.num, e
+        );
+    }
+}
+
+#[cfg(not(feature = "desktop-notifications"))]
+pub fn notify(comic: &Comic) {
+    let (summary, _) = message(comic);
+
+    debug!("Built without notifications, not showing {:?}", summary);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn describes_the_comic() {
+        let comic = Comic {
+            num: 1000,
+            day: 6,
+            month: 1,
+            year: 2012,
+            link: None,
+            news: None,
+            alt: "Thanks for reading & <3".to_string(),
+            title: "1000 Comics".to_string(),
+            safe_title: "1000 Comics".to_string(),
+            transcript: None,
+            img_url: String::new(),
+            img_len: None,
+            extra_parts: None,
+        };
+
+        assert_eq!(
+            message(&comic),
+            (
+                "xkcd 1000: 1000 Comics".to_string(),
+                "Thanks for reading &amp; &lt;3".to_string()
+            )
+        );
+    }
+}
@@ -0,0 +1,22 @@
+//! Process exit codes
+//!
+//! Each kind of failure gets its own code, so scripts can tell them apart.
+//! These are also listed at the end of `--help`.
+
+/// Something went wrong that doesn't have its own code
+pub const FAILURE: i32 = 1;
+
+/// The command line or config file is invalid
+pub const CONFIG: i32 = 2;
+
+/// The database couldn't be opened or set up, or another xkcdfs is using it
+pub const DATABASE: i32 = 3;
+
+/// The mountpoint doesn't exist, or a stale mount there couldn't be cleaned up
+pub const MOUNTPOINT: i32 = 4;
+
+/// xkcd.com couldn't be reached at startup, and the cache is empty
+pub const NETWORK: i32 = 5;
+
+/// FUSE couldn't mount the filesystem, or failed while it was mounted
+pub const FUSE: i32 = 6;
@@ -0,0 +1,115 @@
+//! Comic book archives: rendered comics in a ZIP, plus a ComicInfo.xml
+
+use super::{add_zip_file, escape_xml, image_type, Exporter, ImageKind, AUTHOR};
+use crate::Comic;
+use std::io::{Seek, Write};
+use zip::{CompressionMethod, ZipWriter};
+
+/// A comic that has been written to the archive
+struct Page {
+    num: u32,
+    title: String,
+    date: (i32, i32, i32),
+}
+
+pub struct CbzExporter<W: Write + Seek> {
+    zip: ZipWriter<W>,
+    title: String,
+    pages: Vec<Page>,
+}
+
+impl<W: Write + Seek> CbzExporter<W> {
+    pub fn new(output: W, title: String) -> Self {
+        Self {
+            zip: ZipWriter::new(output),
+            title,
+            pages: Vec::new(),
+        }
+    }
+
+    /// Metadata in the ComicRack format, which most readers understand
+    fn comic_info(&self) -> String {
+        let mut pages = String::new();
+
+        for (index, page) in self.pages.iter().enumerate() {
+            pages.push_str(&format!(
+                "    <Page Image=\"{}\" Bookmark=\"{}: {}\"/>\n",
+                index,
+                page.num,
+                escape_xml(&page.title)
+            ));
+        }
+
+        let (year, month, day) = self.pages.first().map(|p| p.date).unwrap_or((0, 0, 0));
+
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<ComicInfo xmlns:xsd="http://www.w3.org/2001/XMLSchema" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance">
+  <Title>{title}</Title>
+  <Series>xkcd</Series>
+  <Year>{year}</Year>
+  <Month>{month}</Month>
+  <Day>{day}</Day>
+  <Writer>{author}</Writer>
+  <Penciller>{author}</Penciller>
+  <Web>https://xkcd.com/</Web>
+  <PageCount>{count}</PageCount>
+  <LanguageISO>en</LanguageISO>
+  <Pages>
+{pages}  </Pages>
+</ComicInfo>
+"#,
+            title = escape_xml(&self.title),
+            year = year,
+            month = month,
+            day = day,
+            author = AUTHOR,
+            count = self.pages.len(),
+            pages = pages,
+        )
+    }
+}
+
+impl<W: Write + Seek> Exporter for CbzExporter<W> {
+    fn image_kind(&self) -> ImageKind {
+        // Readers only show the images, so the title and alt text need to be
+        // drawn in
+        ImageKind::Rendered
+    }
+
+    fn add(&mut self, comic: &Comic, image: &[u8]) -> Result<(), String> {
+        let (_, extension) = image_type(image)
+            .ok_or_else(|| format!("The image for {} is not a PNG, JPEG or GIF", comic))?;
+
+        // Readers order pages by filename, so keep the numbers padded
+        add_zip_file(
+            &mut self.zip,
+            &format!("comic_{:04}.{}", comic.num, extension),
+            image,
+            CompressionMethod::Stored,
+        )?;
+
+        self.pages.push(Page {
+            num: comic.num,
+            title: comic.safe_title.clone(),
+            date: (comic.year, comic.month, comic.day),
+        });
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        let comic_info = self.comic_info();
+
+        add_zip_file(
+            &mut self.zip,
+            "ComicInfo.xml",
+            comic_info.as_bytes(),
+            CompressionMethod::Deflated,
+        )?;
+
+        self.zip.finish().map_err(|e| e.to_string())?;
+
+        Ok(())
+    }
+}
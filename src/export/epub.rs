@@ -0,0 +1,313 @@
+//! EPUB 3 export, one comic per page
+
+use super::{add_zip_file, escape_xml, image_type, Exporter, ImageKind, AUTHOR};
+use crate::Comic;
+use std::io::{Seek, Write};
+use zip::{CompressionMethod, ZipWriter};
+
+const CONTAINER_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#;
+
+const STYLESHEET: &str = "\
+body { margin: 0; padding: 0.5em; text-align: center; }
+h1 { font-size: 1.2em; margin: 0 0 0.5em 0; }
+figure { margin: 0; }
+img { max-width: 100%; max-height: 80vh; }
+figcaption { font-style: italic; margin-top: 0.5em; text-align: left; }
+";
+
+/// A comic that has been written to the book
+struct Page {
+    num: u32,
+    title: String,
+    image_name: String,
+    media_type: &'static str,
+}
+
+pub struct EpubExporter<W: Write + Seek> {
+    zip: ZipWriter<W>,
+    title: String,
+    modified: String,
+    pages: Vec<Page>,
+}
+
+impl<W: Write + Seek> EpubExporter<W> {
+    /// Start a new book, writing the parts that don't depend on its contents
+    pub fn new(output: W, title: String) -> Result<Self, String> {
+        let mut zip = ZipWriter::new(output);
+
+        // The mimetype has to come first, uncompressed, so the file can be
+        // recognized by its first few bytes
+        add_zip_file(
+            &mut zip,
+            "mimetype",
+            b"application/epub+zip",
+            CompressionMethod::Stored,
+        )?;
+
+        add_zip_file(
+            &mut zip,
+            "META-INF/container.xml",
+            CONTAINER_XML.as_bytes(),
+            CompressionMethod::Deflated,
+        )?;
+        add_zip_file(
+            &mut zip,
+            "OEBPS/style.css",
+            STYLESHEET.as_bytes(),
+            CompressionMethod::Deflated,
+        )?;
+
+        Ok(Self {
+            zip,
+            title,
+            modified: time::now_utc()
+                .strftime("%Y-%m-%dT%H:%M:%SZ")
+                .map(|t| t.to_string())
+                .map_err(|e| e.to_string())?,
+            pages: Vec::new(),
+        })
+    }
+
+    fn package(&self) -> String {
+        let mut manifest = String::new();
+        let mut spine = String::new();
+
+        for page in self.pages.iter() {
+            manifest.push_str(&format!(
+                "    <item id=\"page-{0}\" href=\"{1}\" media-type=\"application/xhtml+xml\"/>\n\
+                 \x20   <item id=\"image-{0}\" href=\"{2}\" media-type=\"{3}\"/>\n",
+                page.num,
+                page_name(page.num),
+                page.image_name,
+                page.media_type
+            ));
+            spine.push_str(&format!("    <itemref idref=\"page-{}\"/>\n", page.num));
+        }
+
+        let first = self.pages.first().map(|p| p.num).unwrap_or(0);
+        let last = self.pages.last().map(|p| p.num).unwrap_or(0);
+
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="book-id">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="book-id">https://xkcd.com/#{first}-{last}</dc:identifier>
+    <dc:title>{title}</dc:title>
+    <dc:creator>{author}</dc:creator>
+    <dc:language>en</dc:language>
+    <meta property="dcterms:modified">{modified}</meta>
+  </metadata>
+  <manifest>
+    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+    <item id="style" href="style.css" media-type="text/css"/>
+{manifest}  </manifest>
+  <spine>
+{spine}  </spine>
+</package>
+"#,
+            first = first,
+            last = last,
+            title = escape_xml(&self.title),
+            author = AUTHOR,
+            modified = self.modified,
+            manifest = manifest,
+            spine = spine,
+        )
+    }
+
+    fn navigation(&self) -> String {
+        let mut entries = String::new();
+
+        for page in self.pages.iter() {
+            entries.push_str(&format!(
+                "      <li><a href=\"{}\">{}: {}</a></li>\n",
+                page_name(page.num),
+                page.num,
+                escape_xml(&page.title)
+            ));
+        }
+
+        xhtml(
+            &self.title,
+            &format!(
+                "  <nav epub:type=\"toc\">\n    <h1>{}</h1>\n    <ol>\n{}    </ol>\n  </nav>\n",
+                escape_xml(&self.title),
+                entries
+            ),
+        )
+    }
+}
+
+impl<W: Write + Seek> Exporter for EpubExporter<W> {
+    fn image_kind(&self) -> ImageKind {
+        // The title and alt text are part of the page, so they don't need to
+        // be drawn in
+        ImageKind::Raw
+    }
+
+    fn add(&mut self, comic: &Comic, image: &[u8]) -> Result<(), String> {
+        let (media_type, extension) = image_type(image)
+            .ok_or_else(|| format!("The image for {} is not a PNG, JPEG or GIF", comic))?;
+        let image_name = format!("images/comic_{:04}.{}", comic.num, extension);
+
+        // Images are already compressed, so don't bother deflating them
+        add_zip_file(
+            &mut self.zip,
+            &format!("OEBPS/{}", image_name),
+            image,
+            CompressionMethod::Stored,
+        )?;
+
+        let body = format!(
+            concat!(
+                "  <h1>{num}: {title}</h1>\n",
+                "  <figure>\n",
+                "    <img src=\"{image}\" alt=\"{title}\"/>\n",
+                "    <figcaption>{alt}</figcaption>\n",
+                "  </figure>\n",
+            ),
+            num = comic.num,
+            title = escape_xml(&comic.safe_title),
+            image = image_name,
+            alt = escape_xml(&comic.alt),
+        );
+
+        add_zip_file(
+            &mut self.zip,
+            &format!("OEBPS/{}", page_name(comic.num)),
+            xhtml(&comic.safe_title, &body).as_bytes(),
+            CompressionMethod::Deflated,
+        )?;
+
+        self.pages.push(Page {
+            num: comic.num,
+            title: comic.safe_title.clone(),
+            image_name,
+            media_type,
+        });
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        let navigation = self.navigation();
+        let package = self.package();
+
+        add_zip_file(
+            &mut self.zip,
+            "OEBPS/nav.xhtml",
+            navigation.as_bytes(),
+            CompressionMethod::Deflated,
+        )?;
+        add_zip_file(
+            &mut self.zip,
+            "OEBPS/content.opf",
+            package.as_bytes(),
+            CompressionMethod::Deflated,
+        )?;
+
+        self.zip.finish().map_err(|e| e.to_string())?;
+
+        Ok(())
+    }
+}
+
+fn page_name(num: u32) -> String {
+    format!("comic_{:04}.xhtml", num)
+}
+
+/// Wrap a body in an XHTML page
+fn xhtml(title: &str, body: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops" xml:lang="en" lang="en">
+<head>
+  <meta charset="UTF-8"/>
+  <title>{}</title>
+  <link rel="stylesheet" type="text/css" href="style.css"/>
+</head>
+<body>
+{}</body>
+</html>
+"#,
+        escape_xml(title),
+        body
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Cursor, Read};
+
+    fn comic(num: u32, title: &str, alt: &str) -> Comic {
+        Comic {
+            num,
+            day: 1,
+            month: 1,
+            year: 2006,
+            link: None,
+            news: None,
+            alt: alt.to_string(),
+            title: title.to_string(),
+            safe_title: title.to_string(),
+            transcript: None,
+            img_url: format!("https://imgs.xkcd.com/comics/{}.png", num),
+            img_len: None,
+            extra_parts: None,
+        }
+    }
+
+    #[test]
+    fn writes_a_book() {
+        let png = b"\x89PNG\r\n\x1a\nnot really a png";
+        let mut output = Cursor::new(Vec::new());
+
+        {
+            let mut exporter = EpubExporter::new(&mut output, "xkcd 1-2".to_string()).unwrap();
+
+            exporter
+                .add(&comic(1, "Barrel - Part 1", "Don't we all."), png)
+                .unwrap();
+            exporter
+                .add(&comic(2, "Petit Trees (sketch)", "'Petit' <small>"), png)
+                .unwrap();
+            exporter.finish().unwrap();
+        }
+
+        let data = output.into_inner();
+
+        // The mimetype must be the first file, stored, with no extra field
+        assert_eq!(&data[30..38], b"mimetype");
+        assert_eq!(&data[38..58], b"application/epub+zip");
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(data)).unwrap();
+        let mut read = |name: &str| {
+            let mut contents = String::new();
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            contents
+        };
+
+        let package = read("OEBPS/content.opf");
+        assert!(package.contains("<itemref idref=\"page-1\"/>"));
+        assert!(package.contains("href=\"images/comic_0002.png\" media-type=\"image/png\""));
+
+        let page = read("OEBPS/comic_0002.xhtml");
+        assert!(page.contains("<h1>2: Petit Trees (sketch)</h1>"));
+        assert!(page.contains("<figcaption>&apos;Petit&apos; &lt;small&gt;</figcaption>"));
+
+        let nav = read("OEBPS/nav.xhtml");
+        assert!(nav.contains("<a href=\"comic_0001.xhtml\">1: Barrel - Part 1</a>"));
+    }
+}
@@ -0,0 +1,187 @@
+//! Exporting ranges of comics to files outside the filesystem
+
+pub mod cbz;
+pub mod epub;
+pub mod pack;
+
+use crate::logging;
+use crate::requests::RequestMode::*;
+use crate::{Comic, XkcdClient};
+use std::io::{Seek, Write};
+use std::time::Duration;
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+const AUTHOR: &str = "Randall Munroe";
+
+/// Formats that `run` can export to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Epub,
+    Cbz,
+    Pack,
+}
+
+impl ExportFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Epub => "EPUB",
+            Self::Cbz => "CBZ",
+            Self::Pack => "Pack",
+        }
+    }
+}
+
+/// Which image an exporter wants for each comic
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageKind {
+    /// The original image from xkcd.com
+    Raw,
+    /// The image with title and alt text drawn in, as served by the filesystem
+    Rendered,
+}
+
+/// A file format that comics can be exported to
+///
+/// Comics are added one at a time in order, so exporters should write them
+/// out as they go instead of holding on to every image.
+pub trait Exporter {
+    fn image_kind(&self) -> ImageKind;
+
+    fn add(&mut self, comic: &Comic, image: &[u8]) -> Result<(), String>;
+
+    fn finish(&mut self) -> Result<(), String>;
+}
+
+/// Feed every comic from `first` to `last` into an exporter
+///
+/// Comics that can't be fetched are skipped with a warning (there is no
+/// comic 404, for one). Returns the number of comics exported.
+pub fn run(
+    client: &XkcdClient,
+    first: u32,
+    last: u32,
+    timeout: Option<Duration>,
+    exporter: &mut dyn Exporter,
+) -> Result<usize, String> {
+    if first == 0 || first > last {
+        return Err(format!("{}-{} is not a valid range of comics", first, last));
+    }
+
+    let mut count = 0;
+
+    for num in first..=last {
+        let _comic = logging::comic_scope(Some(num));
+
+        let comic = match client.request_comic(num, timeout, Normal) {
+            Some(c) => c,
+            None => {
+                warn!("Could not get comic {}, skipping it", num);
+                continue;
+            }
+        };
+
+        let image = match exporter.image_kind() {
+            ImageKind::Raw => client.request_raw_image(&comic, timeout, Normal),
+            ImageKind::Rendered => client.request_rendered_image(&comic, timeout, Normal),
+        };
+
+        let image = match image {
+            Some(i) => i,
+            None => {
+                warn!("Could not get the image for {}, skipping it", comic);
+                continue;
+            }
+        };
+
+        info!("Exporting {}", comic);
+
+        exporter.add(&comic, &image)?;
+        count += 1;
+    }
+
+    if count == 0 {
+        return Err(format!(
+            "None of comics {}-{} could be exported",
+            first, last
+        ));
+    }
+
+    exporter.finish()?;
+
+    Ok(count)
+}
+
+/// Write one whole file into a ZIP archive
+fn add_zip_file<W: Write + Seek>(
+    zip: &mut ZipWriter<W>,
+    name: &str,
+    data: &[u8],
+    method: CompressionMethod,
+) -> Result<(), String> {
+    zip.start_file(name, FileOptions::default().compression_method(method))
+        .map_err(|e| e.to_string())?;
+    zip.write_all(data).map_err(|e| e.to_string())
+}
+
+/// Guess an image's media type and extension from its first few bytes
+pub fn image_type(data: &[u8]) -> Option<(&'static str, &'static str)> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(("image/png", "png"))
+    } else if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some(("image/jpeg", "jpg"))
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some(("image/gif", "gif"))
+    } else {
+        None
+    }
+}
+
+/// Escape text for use in XML content or attribute values
+pub fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters other than whitespace aren't allowed in XML
+            c if c.is_control() && !c.is_whitespace() => {}
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn escapes_xml() {
+        assert_eq!(escape_xml("plain"), "plain");
+        assert_eq!(
+            escape_xml("<a href=\"x\">Tom & Jerry's</a>"),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&apos;s&lt;/a&gt;"
+        );
+        assert_eq!(escape_xml("bell\u{7}\nline"), "bell\nline");
+    }
+
+    #[test]
+    fn detects_image_types() {
+        assert_eq!(
+            image_type(b"\x89PNG\r\n\x1a\n...."),
+            Some(("image/png", "png"))
+        );
+        assert_eq!(
+            image_type(&[0xFF, 0xD8, 0xFF, 0xE0]),
+            Some(("image/jpeg", "jpg"))
+        );
+        assert_eq!(image_type(b"GIF89a...."), Some(("image/gif", "gif")));
+        assert_eq!(image_type(b"<html>"), None);
+        assert_eq!(image_type(b""), None);
+    }
+}
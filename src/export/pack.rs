@@ -0,0 +1,60 @@
+//! Packs, for taking comics to another machine's cache (see `crate::pack`)
+
+use super::{add_zip_file, Exporter, ImageKind};
+use crate::image::format::ImageFormat;
+use crate::pack::{Entry, Index, PackComic, INDEX, VERSION};
+use crate::Comic;
+use std::io::{Seek, Write};
+use zip::{CompressionMethod, ZipWriter};
+
+pub struct PackExporter<W: Write + Seek> {
+    zip: ZipWriter<W>,
+    comics: Vec<Entry>,
+}
+
+impl<W: Write + Seek> PackExporter<W> {
+    pub fn new(output: W) -> Self {
+        Self {
+            zip: ZipWriter::new(output),
+            comics: Vec::new(),
+        }
+    }
+}
+
+impl<W: Write + Seek> Exporter for PackExporter<W> {
+    fn image_kind(&self) -> ImageKind {
+        // Anything else can be made again from these
+        ImageKind::Raw
+    }
+
+    fn add(&mut self, comic: &Comic, image: &[u8]) -> Result<(), String> {
+        let name = format!("images/{:04}", comic.num);
+
+        // Images are compressed already
+        add_zip_file(&mut self.zip, &name, image, CompressionMethod::Stored)?;
+
+        self.comics.push(Entry {
+            comic: PackComic::from(comic),
+            image: name,
+            content_type: ImageFormat::sniff(image).map(|f| f.content_type().to_string()),
+            size: image.len() as u64,
+            crc32: crc32fast::hash(image),
+        });
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        let index = Index {
+            version: VERSION,
+            comics: std::mem::take(&mut self.comics),
+        };
+        let json = serde_json::to_vec_pretty(&index).map_err(|e| e.to_string())?;
+
+        add_zip_file(&mut self.zip, INDEX, &json, CompressionMethod::Deflated)?;
+
+        self.zip.finish().map_err(|e| e.to_string())?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+//! xkcd's Atom feed, as a cheaper way to find new comics
+//!
+//! With `--update-source feed`, the refresh asks for the feed instead of the
+//! latest comic's JSON. It's asked for conditionally, so most checks get an
+//! empty 304 back, and the JSON API is only asked once the feed has a comic
+//! newer than any known. The feed also says when each comic was published.
+
+use reqwest::header::{
+    HeaderMap, HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, USER_AGENT,
+};
+use reqwest::StatusCode;
+use std::str::FromStr;
+use std::time::Duration;
+
+const FEED_URL: &str = "https://xkcd.com/atom.xml";
+
+/// Where the refresh finds out about new comics
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UpdateSource {
+    /// The latest comic's JSON
+    Api,
+    /// The Atom feed
+    Feed,
+}
+
+impl FromStr for UpdateSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "api" => Ok(Self::Api),
+            "feed" => Ok(Self::Feed),
+            _ => Err(format!(
+                "Invalid update source {:?}, expected feed or api",
+                s
+            )),
+        }
+    }
+}
+
+/// The newest comic in the feed
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub num: u32,
+    /// When it was published, as the feed has it, like `2009-07-24T00:00:00Z`
+    pub updated: String,
+}
+
+pub struct Feed {
+    client: reqwest::Client,
+    user_agent: String,
+    /// From the last full response, so the same feed isn't sent again
+    etag: Option<HeaderValue>,
+    last_modified: Option<HeaderValue>,
+}
+
+impl Feed {
+    /// `headers` are sent with every request, on top of `user_agent`
+    pub fn new(timeout: Duration, user_agent: String, headers: HeaderMap) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .default_headers(headers)
+            .build()
+            .map_err(|e| format!("Could not set up the HTTP client: {}", e))?;
+
+        Ok(Self {
+            client,
+            user_agent,
+            etag: None,
+            last_modified: None,
+        })
+    }
+
+    /// The newest comic in the feed, or `None` if it hasn't changed since the
+    /// last check
+    pub fn check(&mut self) -> Result<Option<Entry>, String> {
+        let mut request = self
+            .client
+            .get(FEED_URL)
+            .header(USER_AGENT, self.user_agent.as_str());
+
+        if let Some(etag) = &self.etag {
+            request = request.header(IF_NONE_MATCH, etag.clone());
+        }
+
+        if let Some(modified) = &self.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, modified.clone());
+        }
+
+        let response = request.send().map_err(|e| e.to_string())?;
+
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+
+        let mut response = response.error_for_status().map_err(|e| e.to_string())?;
+        let text = response.text().map_err(|e| e.to_string())?;
+        let entry = newest(&text).ok_or_else(|| "No comics in the feed".to_string())?;
+
+        // Only kept once the feed's been read, so a bad one is sent in full
+        // next time
+        self.etag = response.headers().get(ETAG).cloned();
+        self.last_modified = response.headers().get(LAST_MODIFIED).cloned();
+
+        Ok(Some(entry))
+    }
+}
+
+/// The highest-numbered comic in a feed
+///
+/// This is nowhere near a full XML parser, but xkcd's feed is simple and
+/// doesn't change shape.
+fn newest(feed: &str) -> Option<Entry> {
+    feed.split("<entry>")
+        .skip(1)
+        .filter_map(entry)
+        .max_by_key(|e| e.num)
+}
+
+/// The comic in an `<entry>`, linked like `https://xkcd.com/614/`
+fn entry(xml: &str) -> Option<Entry> {
+    let link = between(&xml[xml.find("<link")?..], "href=\"", "\"")?;
+    let num = link
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()?
+        .parse()
+        .ok()?;
+    let updated = between(xml, "<updated>", "</updated>")?;
+
+    Some(Entry {
+        num,
+        updated: updated.trim().to_string(),
+    })
+}
+
+/// The text between the first `start` and the `end` after it
+fn between<'a>(text: &'a str, start: &str, end: &str) -> Option<&'a str> {
+    let from = text.find(start)? + start.len();
+    let len = text[from..].find(end)?;
+
+    Some(&text[from..from + len])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn finds_the_newest_comic() {
+        let feed = concat!(
+            r#"<?xml version="1.0" encoding="utf-8"?>"#,
+            r#"<feed xml:lang="en" xmlns="http://www.w3.org/2005/Atom"><title>xkcd.com</title>"#,
+            r#"<link href="https://xkcd.com/" rel="alternate"></link>"#,
+            r#"<updated>2009-07-24T00:00:00Z</updated>"#,
+            r#"<entry><title>Woodpecker</title>"#,
+            r#"<link href="https://xkcd.com/614/" rel="alternate"></link>"#,
+            r#"<updated>2009-07-24T00:00:00Z</updated></entry>"#,
+            r#"<entry><title>Avoidance</title>"#,
+            r#"<link href="https://xkcd.com/613/" rel="alternate"></link>"#,
+            r#"<updated>2009-07-22T00:00:00Z</updated></entry></feed>"#,
+        );
+
+        assert_eq!(
+            newest(feed),
+            Some(Entry {
+                num: 614,
+                updated: "2009-07-24T00:00:00Z".to_string(),
+            })
+        );
+        assert_eq!(newest("<feed></feed>"), None);
+        assert_eq!(
+            newest(r#"<entry><link href="https://xkcd.com/about/"/></entry>"#),
+            None
+        );
+    }
+}
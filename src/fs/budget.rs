@@ -0,0 +1,259 @@
+//! Keeping renders under a memory budget
+//!
+//! Rendering decodes the whole comic and draws a bigger image around it, all
+//! in memory, so a few big comics rendering at once can need more than a small
+//! machine has. Each render reserves what it's expected to need before it
+//! starts, and waits while that would take the total over the budget.
+//!
+//! A render can always go ahead when nothing else is reserved, however big it
+//! is. Big renders end up one at a time instead of failing.
+//!
+//! What open files are kept with comes out of the same budget, but opening a
+//! file can't wait for renders to finish. Small files are always let in, so a
+//! big render can't stop `.status` or a directory from opening, and only big
+//! ones fail when there's no room.
+//!
+//! That's all the budget covers. Rendered images aren't kept in memory once
+//! they're done: they go to the cache database, which `--max-cache-size`
+//! keeps in check by evicting what was read longest ago, so there's nothing
+//! else in memory to evict when the budget is tight.
+
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::image::dimensions::dimensions;
+use crate::stats::Stats;
+
+/// Copies of the decoded comic a render has around at once: the comic itself,
+/// the image it's drawn onto, and the encoded result
+const RENDER_COPIES: u64 = 3;
+
+/// How much bigger an image is decoded, when its size can't be found
+const UNKNOWN_EXPANSION: u64 = 16;
+
+/// About how many bytes rendering `raw_image` will take
+pub fn render_estimate(raw_image: &[u8]) -> u64 {
+    let raw = raw_image.len() as u64;
+
+    match dimensions(raw_image) {
+        Some((width, height)) => raw + u64::from(width) * u64::from(height) * 4 * RENDER_COPIES,
+        None => raw * UNKNOWN_EXPANSION,
+    }
+}
+
+struct Budget {
+    max: Option<u64>,
+    reserved: Mutex<u64>,
+    released: Condvar,
+    stats: Arc<Stats>,
+}
+
+/// Shared between every render, and whatever they hand their images to
+///
+/// Clones share the same budget.
+#[derive(Clone)]
+pub struct MemoryBudget(Arc<Budget>);
+
+/// Bytes taken out of a budget, until dropped
+pub struct Reservation {
+    budget: MemoryBudget,
+    bytes: u64,
+}
+
+impl MemoryBudget {
+    /// A budget of `max` bytes, or a limitless one that just keeps count
+    pub fn new(max: Option<u64>, stats: Arc<Stats>) -> Self {
+        Self(Arc::new(Budget {
+            max,
+            reserved: Mutex::new(0),
+            released: Condvar::new(),
+            stats,
+        }))
+    }
+
+    /// The most that can be reserved at once, if there's a limit
+    pub fn max(&self) -> Option<u64> {
+        self.0.max
+    }
+
+    /// Take `bytes` out of the budget, waiting until there's room
+    pub fn reserve(&self, bytes: u64) -> Reservation {
+        let budget = &self.0;
+        let mut reserved = budget.reserved.lock().unwrap();
+
+        if let Some(max) = budget.max {
+            if *reserved > 0 && *reserved + bytes > max {
+                debug!(
+                    "Waiting for {} bytes of memory ({} of {} reserved)",
+                    bytes, *reserved, max
+                );
+            }
+
+            while *reserved > 0 && *reserved + bytes > max {
+                reserved = budget.released.wait(reserved).unwrap();
+            }
+        }
+
+        *reserved += bytes;
+        budget.stats.set_memory_reserved(*reserved);
+
+        Reservation {
+            budget: self.clone(),
+            bytes,
+        }
+    }
+
+    /// Take `bytes` out of the budget if there's room for them now
+    ///
+    /// Like `reserve`, anything fits when nothing else is reserved.
+    pub fn try_reserve(&self, bytes: u64) -> Option<Reservation> {
+        let budget = &self.0;
+        let mut reserved = budget.reserved.lock().unwrap();
+
+        if let Some(max) = budget.max {
+            if *reserved > 0 && *reserved + bytes > max {
+                debug!(
+                    "No room for {} bytes of memory ({} of {} reserved)",
+                    bytes, *reserved, max
+                );
+                return None;
+            }
+        }
+
+        *reserved += bytes;
+        budget.stats.set_memory_reserved(*reserved);
+
+        Some(Reservation {
+            budget: self.clone(),
+            bytes,
+        })
+    }
+
+    /// Take `bytes` out of the budget whether there's room or not
+    ///
+    /// They still count, so renders wait for them to be given back.
+    pub fn take(&self, bytes: u64) -> Reservation {
+        let budget = &self.0;
+        let mut reserved = budget.reserved.lock().unwrap();
+
+        *reserved += bytes;
+        budget.stats.set_memory_reserved(*reserved);
+
+        Reservation {
+            budget: self.clone(),
+            bytes,
+        }
+    }
+
+    fn release(&self, bytes: u64) {
+        let budget = &self.0;
+        let mut reserved = budget.reserved.lock().unwrap();
+
+        *reserved -= bytes;
+        budget.stats.set_memory_reserved(*reserved);
+
+        budget.released.notify_all();
+    }
+}
+
+impl Reservation {
+    /// Give back all but `bytes`, once it's clear how much is really needed
+    pub fn shrink_to(&mut self, bytes: u64) {
+        if bytes < self.bytes {
+            self.budget.release(self.bytes - bytes);
+            self.bytes = bytes;
+        }
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.budget.release(self.bytes);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn waits_for_room() {
+        let stats = Arc::new(Stats::default());
+        let budget = MemoryBudget::new(Some(100), Arc::clone(&stats));
+
+        let mut first = budget.reserve(60);
+        assert_eq!(stats.snapshot().memory_reserved, 60);
+
+        let (tx, rx) = channel();
+        let waiting = budget.clone();
+
+        thread::spawn(move || tx.send(waiting.reserve(60)).unwrap());
+
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+
+        // Shrinking makes just enough room
+        first.shrink_to(40);
+        let second = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(stats.snapshot().memory_reserved, 100);
+
+        drop(first);
+        drop(second);
+        assert_eq!(stats.snapshot().memory_reserved, 0);
+    }
+
+    #[test]
+    fn oversized_reservations_go_alone() {
+        let budget = MemoryBudget::new(Some(100), Arc::new(Stats::default()));
+
+        // Nothing else is reserved, so this doesn't wait forever
+        let big = budget.reserve(1000);
+        drop(big);
+
+        let _small = budget.reserve(10);
+    }
+
+    #[test]
+    fn tries_without_waiting() {
+        let stats = Arc::new(Stats::default());
+        let budget = MemoryBudget::new(Some(100), Arc::clone(&stats));
+
+        let first = budget.try_reserve(60).unwrap();
+        assert!(budget.try_reserve(60).is_none());
+        assert_eq!(stats.snapshot().memory_reserved, 60);
+
+        drop(first);
+        let big = budget.try_reserve(1000).unwrap();
+        assert!(budget.try_reserve(1).is_none());
+
+        drop(big);
+        assert!(MemoryBudget::new(None, stats).try_reserve(1000).is_some());
+    }
+
+    #[test]
+    fn takes_without_room() {
+        let stats = Arc::new(Stats::default());
+        let budget = MemoryBudget::new(Some(100), Arc::clone(&stats));
+
+        let first = budget.reserve(100);
+        let taken = budget.take(10);
+        assert_eq!(stats.snapshot().memory_reserved, 110);
+
+        drop(first);
+        assert!(budget.try_reserve(95).is_none());
+
+        drop(taken);
+        assert_eq!(stats.snapshot().memory_reserved, 0);
+    }
+
+    #[test]
+    fn estimates_from_dimensions() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend_from_slice(&100u32.to_be_bytes());
+        png.extend_from_slice(&50u32.to_be_bytes());
+
+        assert_eq!(render_estimate(&png), 24 + 100 * 50 * 4 * RENDER_COPIES);
+        assert_eq!(render_estimate(b"mystery"), 7 * UNKNOWN_EXPANSION);
+    }
+}
@@ -0,0 +1,87 @@
+//! `metadata.csv`, a table of every cached comic
+//!
+//! One row per comic, for loading into pandas, R or a spreadsheet straight
+//! from the mount. The rows come from one query over the whole cache, and are
+//! written out as they're read.
+
+use crate::{Comic, XkcdClient};
+
+const HEADER: &str = "num,date,title,alt_length,has_transcript,image_size\r\n";
+
+/// Quote a field if it needs it, doubling any quotes inside
+fn field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// A comic's row, with the image size left empty if its image isn't cached
+fn row(comic: &Comic) -> String {
+    let has_transcript = comic.transcript.as_ref().is_some_and(|t| !t.is_empty());
+
+    format!(
+        "{},{},{},{},{},{}\r\n",
+        comic.num,
+        comic.isodate(),
+        field(&comic.title),
+        comic.alt.chars().count(),
+        has_transcript,
+        comic.img_len.map(|l| l.to_string()).unwrap_or_default()
+    )
+}
+
+/// The whole file
+pub fn metadata_csv(client: &XkcdClient) -> Result<Vec<u8>, String> {
+    let mut csv = HEADER.as_bytes().to_vec();
+
+    client.each_cached_comic(&mut |comic| csv.extend_from_slice(row(&comic).as_bytes()))?;
+
+    Ok(csv)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn quotes_fields_that_need_it() {
+        assert_eq!(field("Petit Trees (sketch)"), "Petit Trees (sketch)");
+        assert_eq!(field("Hello, world"), "\"Hello, world\"");
+        assert_eq!(field("\"Quote\""), "\"\"\"Quote\"\"\"");
+        assert_eq!(field("Two\nlines"), "\"Two\nlines\"");
+    }
+
+    #[test]
+    fn writes_rows() {
+        let comic = Comic {
+            num: 614,
+            day: 24,
+            month: 7,
+            year: 2009,
+            link: None,
+            news: None,
+            alt: "Naïve".to_string(),
+            title: "Woodpecker, \"sort of\"".to_string(),
+            safe_title: String::new(),
+            transcript: Some(String::new()),
+            img_url: String::new(),
+            img_len: None,
+            extra_parts: None,
+        };
+
+        assert_eq!(
+            row(&comic),
+            "614,2009-07-24,\"Woodpecker, \"\"sort of\"\"\",5,false,\r\n"
+        );
+
+        let comic = Comic {
+            transcript: Some("[[A woodpecker]]".to_string()),
+            img_len: Some(31_337),
+            ..comic
+        };
+
+        assert!(row(&comic).ends_with(",5,true,31337\r\n"));
+    }
+}
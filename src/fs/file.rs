@@ -1,5 +1,100 @@
 use fuse::FileType;
+use std::convert::TryFrom;
 use std::ffi::OsStr;
+use time::{Timespec, Tm};
+
+/// Extensions comic images can be listed under
+pub const IMAGE_EXTENSIONS: &[&str] = &[".png", ".gif", ".jpg"];
+
+/// Where the comic source goes in the lower half of an inode
+const SOURCE_SHIFT: u64 = 16;
+const SOURCE_MASK: u64 = 0xFFFF << SOURCE_SHIFT;
+
+/// Split an inode into the source it's from and the file's inode within it
+///
+/// Source 0 is xkcd itself, so its inodes are just the file's. Other sources
+/// put their index in bits 16 to 31, which `File::inode` never uses.
+pub fn split_inode(ino: u64) -> (usize, u64) {
+    (
+        ((ino & SOURCE_MASK) >> SOURCE_SHIFT) as usize,
+        ino & !SOURCE_MASK,
+    )
+}
+
+/// The inode of the file with inode `ino` in source `source`
+pub fn source_inode(source: usize, ino: u64) -> u64 {
+    ino | ((source as u64) << SOURCE_SHIFT)
+}
+
+/// Year folders go in the lower half of the top-level inodes, after this
+///
+/// Years have to stay under it too, so they're never in the source's bits.
+const YEAR_BASE: u32 = 0x8000;
+
+/// Day folders go in the lower half of the top-level inodes, from this up to
+/// the year folders
+const DAY_BASE: u32 = 0x100;
+
+/// Thumbnails go in the lower half of a comic's inodes, after this, with
+/// their width added
+const THUMBNAIL_BASE: u32 = 0x1000;
+
+/// The widest thumbnail that has an inode
+pub const MAX_THUMBNAIL_WIDTH: u32 = 0xFFF;
+
+/// The day day folders count from, 2000-01-01, in seconds since 1970
+const FIRST_DAY: i64 = 946_684_800;
+const DAY_SECS: i64 = 24 * 60 * 60;
+
+/// The comic number in a filename, with or without padding
+///
+/// Only digits count, and there's no comic 0: its inodes would be the ones at
+/// the top of the mount.
+fn comic_num(digits: &str) -> Option<u32> {
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    digits.parse().ok().filter(|&num| num != 0)
+}
+
+/// The year a year folder is named for
+fn year(digits: &str) -> Option<u32> {
+    comic_num(digits).filter(|&year| year < YEAR_BASE)
+}
+
+/// The day a day folder is named for, like `2009-07-24`, in days since 2000
+fn day(name: &str) -> Option<u32> {
+    let bytes = name.as_bytes();
+
+    if !name.is_ascii() || bytes.len() != 10 || bytes[4] != b'-' || bytes[7] != b'-' {
+        return None;
+    }
+
+    let part = |range: std::ops::Range<usize>| comic_num(&name[range]).map(|n| n as i32);
+    let tm = Tm {
+        tm_year: part(0..4)? - 1900,
+        tm_mon: part(5..7)? - 1,
+        tm_mday: part(8..10)?,
+        ..time::empty_tm()
+    };
+    let secs = u32::try_from(tm.to_timespec().sec - FIRST_DAY).ok()?;
+
+    // Days past the end of a month roll over into the next, so they won't
+    // have the same name
+    Some(secs / DAY_SECS as u32)
+        .filter(|&days| days < YEAR_BASE - DAY_BASE)
+        .filter(|&days| File::Day(days).filename() == name)
+}
+
+/// The width of a thumbnail, from its name, like `thumbnail_256.png`
+fn thumbnail_width(name: &str) -> Option<u32> {
+    let digits = name.strip_prefix("thumbnail_")?.strip_suffix(".png")?;
+
+    comic_num(digits)
+        .filter(|&width| width <= MAX_THUMBNAIL_WIDTH)
+        .filter(|width| width.to_string() == digits)
+}
 
 /// Like an inode, but fancier
 ///
@@ -11,6 +106,26 @@ pub enum File {
     Root,
     Refresh,
     Credits,
+    Montage,
+    MetadataCsv,
+    Translations,
+    Status,
+    Wallpaper,
+    History,
+    /// A symlink to the latest comic's image
+    LatestLink,
+    /// A symlink to a different comic's image every time it's read
+    RandomLink,
+    /// A folder for each year comics were published in
+    ByYear,
+    /// The comics published in a year
+    Year(u32),
+    /// A folder for each day comics were published on, only found by name
+    ByDate,
+    /// The comics published on a day, in days since 2000-01-01
+    Day(u32),
+    /// Every comic, named for its title
+    ByTitle,
     Image(u32),
     MetaFolder(u32),
     AltText(u32),
@@ -18,6 +133,14 @@ pub enum File {
     Transcript(u32),
     Date(u32),
     RawImage(u32),
+    /// A thumbnail of a comic, and its width
+    Thumbnail(u32, u32),
+    Pdf(u32),
+    Svg(u32),
+    Ascii(u32),
+    Described(u32),
+    Error(u32),
+    ExtraParts(u32),
 }
 
 impl File {
@@ -33,6 +156,19 @@ impl File {
             (0, 1) => Some(Self::Root),
             (0, 2) => Some(Self::Refresh),
             (0, 3) => Some(Self::Credits),
+            (0, 4) => Some(Self::Montage),
+            (0, 5) => Some(Self::MetadataCsv),
+            (0, 6) => Some(Self::Translations),
+            (0, 7) => Some(Self::Status),
+            (0, 8) => Some(Self::Wallpaper),
+            (0, 9) => Some(Self::History),
+            (0, 10) => Some(Self::LatestLink),
+            (0, 11) => Some(Self::RandomLink),
+            (0, 12) => Some(Self::ByYear),
+            (0, 13) => Some(Self::ByDate),
+            (0, 14) => Some(Self::ByTitle),
+            (0, low) if (DAY_BASE..YEAR_BASE).contains(&low) => Some(Self::Day(low - DAY_BASE)),
+            (0, low) if low > YEAR_BASE && low < 2 * YEAR_BASE => Some(Self::Year(low - YEAR_BASE)),
             (0, _) => None,
             (num, 0) => Some(Self::Image(num)),
             (num, 1) => Some(Self::MetaFolder(num)),
@@ -41,6 +177,15 @@ impl File {
             (num, 4) => Some(Self::Transcript(num)),
             (num, 5) => Some(Self::Date(num)),
             (num, 6) => Some(Self::RawImage(num)),
+            (num, 8) => Some(Self::Pdf(num)),
+            (num, 9) => Some(Self::Svg(num)),
+            (num, 10) => Some(Self::Ascii(num)),
+            (num, 11) => Some(Self::Described(num)),
+            (num, 12) => Some(Self::Error(num)),
+            (num, 13) => Some(Self::ExtraParts(num)),
+            (num, low) if low > THUMBNAIL_BASE && low <= THUMBNAIL_BASE + MAX_THUMBNAIL_WIDTH => {
+                Some(Self::Thumbnail(num, low - THUMBNAIL_BASE))
+            }
             _ => None,
         }
     }
@@ -54,6 +199,19 @@ impl File {
     /// |  0  | 1 | Root folder |
     /// |  0  | 2 | Refresh file |
     /// |  0  | 3 | Credits file |
+    /// |  0  | 4 | Montage file |
+    /// |  0  | 5 | Metadata CSV file |
+    /// |  0  | 6 | Translations folder |
+    /// |  0  | 7 | Status file |
+    /// |  0  | 8 | Wallpaper of the latest comic |
+    /// |  0  | 9 | History folder |
+    /// |  0  | 10 | Symlink to the latest comic |
+    /// |  0  | 11 | Symlink to a random comic |
+    /// |  0  | 12 | Folder of years |
+    /// |  0  | 0x8000 + `y` | Folder of comics from year `y`, under 0x8000 |
+    /// |  0  | 13 | Folder of days |
+    /// |  0  | 0x100 + `d` | Folder of comics from `d` days after 2000-01-01, under 0x7F00 |
+    /// |  0  | 14 | Folder of comics by title |
     /// | `n` | 0 | Image file `n` |
     /// | `n` | 1 | Metadata folder for comic `n` |
     /// | `n` | 2 | Alt-text file for comic `n` |
@@ -61,6 +219,13 @@ impl File {
     /// | `n` | 4 | Transcription file for comic `n` |
     /// | `n` | 5 | Date file for comic `n` |
     /// | `n` | 6 | Raw image file for comic `n` |
+    /// | `n` | 8 | PDF rendering of comic `n` |
+    /// | `n` | 9 | SVG rendering of comic `n` |
+    /// | `n` | 10 | ASCII art rendering of comic `n` |
+    /// | `n` | 11 | Everything about comic `n`, for screen readers |
+    /// | `n` | 12 | Why fetching or rendering comic `n` failed |
+    /// | `n` | 13 | Extra content from the JSON of special comic `n` |
+    /// | `n` | 0x1000 + `w` | Thumbnail of comic `n`, `w` pixels wide, up to 0xFFF |
     pub fn inode(&self) -> u64 {
         fn from_halves(high: u32, low: u32) -> u64 {
             ((high as u64) << 32) + low as u64
@@ -70,6 +235,19 @@ impl File {
             Self::Root => 1,
             Self::Refresh => 2,
             Self::Credits => 3,
+            Self::Montage => 4,
+            Self::MetadataCsv => 5,
+            Self::Translations => 6,
+            Self::Status => 7,
+            Self::Wallpaper => 8,
+            Self::History => 9,
+            Self::LatestLink => 10,
+            Self::RandomLink => 11,
+            Self::ByYear => 12,
+            Self::Year(year) => u64::from(YEAR_BASE + year),
+            Self::ByDate => 13,
+            Self::Day(days) => u64::from(DAY_BASE + days),
+            Self::ByTitle => 14,
             Self::Image(i) => from_halves(*i, 0),
             Self::MetaFolder(i) => from_halves(*i, 1),
             Self::AltText(i) => from_halves(*i, 2),
@@ -77,6 +255,105 @@ impl File {
             Self::Transcript(i) => from_halves(*i, 4),
             Self::Date(i) => from_halves(*i, 5),
             Self::RawImage(i) => from_halves(*i, 6),
+            Self::Thumbnail(i, width) => from_halves(*i, THUMBNAIL_BASE + width),
+            Self::Pdf(i) => from_halves(*i, 8),
+            Self::Svg(i) => from_halves(*i, 9),
+            Self::Ascii(i) => from_halves(*i, 10),
+            Self::Described(i) => from_halves(*i, 11),
+            Self::Error(i) => from_halves(*i, 12),
+            Self::ExtraParts(i) => from_halves(*i, 13),
+        }
+    }
+
+    /// The folder for comics from `year`, if it can have one
+    pub fn year(year: i32) -> Option<Self> {
+        u32::try_from(year)
+            .ok()
+            .filter(|&year| year > 0 && year < YEAR_BASE)
+            .map(Self::Year)
+    }
+
+    /// The year, month and day of the day `days` after 2000-01-01
+    pub fn day_date(days: u32) -> (i32, i32, i32) {
+        let tm = time::at_utc(Timespec::new(FIRST_DAY + i64::from(days) * DAY_SECS, 0));
+
+        (tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday)
+    }
+
+    /// Whether this file only exists by rendering something
+    pub fn is_rendered(&self) -> bool {
+        match self {
+            Self::Montage
+            | Self::Wallpaper
+            | Self::Thumbnail(_, _)
+            | Self::Pdf(_)
+            | Self::Svg(_)
+            | Self::Ascii(_) => true,
+            Self::Root
+            | Self::Refresh
+            | Self::Credits
+            | Self::MetadataCsv
+            | Self::Translations
+            | Self::Status
+            | Self::History
+            | Self::LatestLink
+            | Self::RandomLink
+            | Self::ByYear
+            | Self::Year(_)
+            | Self::ByDate
+            | Self::Day(_)
+            | Self::ByTitle
+            | Self::Image(_)
+            | Self::MetaFolder(_)
+            | Self::AltText(_)
+            | Self::Title(_)
+            | Self::Transcript(_)
+            | Self::Date(_)
+            | Self::RawImage(_)
+            | Self::Described(_)
+            | Self::Error(_)
+            | Self::ExtraParts(_) => false,
+        }
+    }
+
+    /// Whether this file can be opened for writing
+    pub fn is_writable(&self) -> bool {
+        *self == Self::Refresh || *self == Self::Montage
+    }
+
+    /// The comic this file belongs to, if any
+    pub fn comic_num(&self) -> Option<u32> {
+        match self {
+            Self::Root
+            | Self::Refresh
+            | Self::Credits
+            | Self::Montage
+            | Self::MetadataCsv
+            | Self::Translations
+            | Self::Status
+            | Self::Wallpaper
+            | Self::History
+            | Self::LatestLink
+            | Self::RandomLink
+            | Self::ByYear
+            | Self::Year(_)
+            | Self::ByDate
+            | Self::Day(_)
+            | Self::ByTitle => None,
+            Self::Image(i)
+            | Self::MetaFolder(i)
+            | Self::AltText(i)
+            | Self::Title(i)
+            | Self::Transcript(i)
+            | Self::Date(i)
+            | Self::RawImage(i)
+            | Self::Thumbnail(i, _)
+            | Self::Pdf(i)
+            | Self::Svg(i)
+            | Self::Ascii(i)
+            | Self::Described(i)
+            | Self::Error(i)
+            | Self::ExtraParts(i) => Some(*i),
         }
     }
 
@@ -86,26 +363,75 @@ impl File {
         match parent {
             File::Refresh => None,
             File::Credits => None,
+            File::Montage => None,
+            File::MetadataCsv => None,
+            // Filled in by the filesystem, which knows the languages
+            File::Translations => None,
+            File::Status => None,
+            File::Wallpaper => None,
+            // Filled in by the filesystem, which knows what's been read
+            File::History => None,
+            File::LatestLink => None,
+            File::RandomLink => None,
+            File::ByYear => year(filename).map(Self::Year),
+            // Filled in by the filesystem, which knows when comics were published
+            File::Year(_) => None,
+            File::ByDate => day(filename).map(Self::Day),
+            // Filled in by the filesystem, like years
+            File::Day(_) => None,
+            // Filled in by the filesystem, which knows the titles
+            File::ByTitle => None,
             File::Image(_) => None,
             File::AltText(_) => None,
             File::Title(_) => None,
             File::Transcript(_) => None,
             File::Date(_) => None,
             File::RawImage(_) => None,
+            File::Thumbnail(_, _) => None,
+            File::Pdf(_) => None,
+            File::Svg(_) => None,
+            File::Ascii(_) => None,
+            File::Described(_) => None,
+            File::Error(_) => None,
+            File::ExtraParts(_) => None,
             File::Root => {
-                if filename.starts_with("comic_") && filename.ends_with(".png") {
+                if filename.starts_with("comic_")
+                    && IMAGE_EXTENSIONS.iter().any(|e| filename.ends_with(e))
+                {
                     let filename = filename.split_at("comic_".len()).1;
                     let filename = filename.split_at(filename.len() - ".png".len()).0;
 
-                    filename.parse().ok().map(Self::Image)
+                    comic_num(filename).map(Self::Image)
                 } else if filename.starts_with("info_") {
                     let filename = filename.split_at("info_".len()).1;
 
-                    filename.parse().ok().map(Self::MetaFolder)
+                    comic_num(filename).map(Self::MetaFolder)
                 } else if filename == "refresh" {
                     Some(Self::Refresh)
                 } else if filename == "credits" {
                     Some(Self::Credits)
+                } else if filename == "montage.png" {
+                    Some(Self::Montage)
+                } else if filename == "metadata.csv" {
+                    Some(Self::MetadataCsv)
+                } else if filename == "translations" {
+                    Some(Self::Translations)
+                } else if filename == ".status" {
+                    Some(Self::Status)
+                } else if filename == "wallpaper.png" {
+                    Some(Self::Wallpaper)
+                } else if filename == "history" {
+                    Some(Self::History)
+                } else if filename == "latest" {
+                    Some(Self::LatestLink)
+                } else if filename == "random" {
+                    Some(Self::RandomLink)
+                } else if filename == "by_year" {
+                    Some(Self::ByYear)
+                } else if filename == "by_date" {
+                    Some(Self::ByDate)
+                } else if filename == "by_title" {
+                    Some(Self::ByTitle)
                 } else {
                     None
                 }
@@ -116,31 +442,86 @@ impl File {
                 "transcript" => Some(Self::Transcript(*num)),
                 "date" => Some(Self::Date(*num)),
                 "raw_image" => Some(Self::RawImage(*num)),
-                _ => None,
+                "comic.pdf" => Some(Self::Pdf(*num)),
+                "comic.svg" => Some(Self::Svg(*num)),
+                "ascii.txt" => Some(Self::Ascii(*num)),
+                "described.txt" => Some(Self::Described(*num)),
+                "error" => Some(Self::Error(*num)),
+                "extra_parts.json" => Some(Self::ExtraParts(*num)),
+                _ => thumbnail_width(filename).map(|width| Self::Thumbnail(*num, width)),
             },
         }
     }
 
+    /// The file's own name, made from a comic number or fixed
+    ///
+    /// Comics are listed by title in `by_title` too, under names from
+    /// `titles` instead.
     pub fn filename(&self) -> String {
         match self {
             Self::Root => String::new(),
             Self::Refresh => String::from("refresh"),
             Self::Credits => String::from("credits"),
-            Self::Image(num) => format!("comic_{:04}.png", num),
+            Self::Montage => String::from("montage.png"),
+            Self::MetadataCsv => String::from("metadata.csv"),
+            Self::Translations => String::from("translations"),
+            Self::Status => String::from(".status"),
+            Self::Wallpaper => String::from("wallpaper.png"),
+            Self::History => String::from("history"),
+            Self::LatestLink => String::from("latest"),
+            Self::RandomLink => String::from("random"),
+            Self::ByYear => String::from("by_year"),
+            Self::Year(year) => year.to_string(),
+            Self::ByDate => String::from("by_date"),
+            Self::Day(days) => {
+                let (year, month, day) = Self::day_date(*days);
+
+                format!("{:04}-{:02}-{:02}", year, month, day)
+            }
+            Self::ByTitle => String::from("by_title"),
+            Self::Image(num) => Self::image_filename(*num, "png"),
             Self::MetaFolder(num) => format!("info_{:04}", num),
             Self::AltText(_) => String::from("alt"),
             Self::Title(_) => String::from("title"),
             Self::Transcript(_) => String::from("transcript"),
             Self::Date(_) => String::from("date"),
             Self::RawImage(_) => String::from("raw_image"),
+            Self::Thumbnail(_, width) => format!("thumbnail_{}.png", width),
+            Self::Pdf(_) => String::from("comic.pdf"),
+            Self::Svg(_) => String::from("comic.svg"),
+            Self::Ascii(_) => String::from("ascii.txt"),
+            Self::Described(_) => String::from("described.txt"),
+            Self::Error(_) => String::from("error"),
+            Self::ExtraParts(_) => String::from("extra_parts.json"),
         }
     }
 
+    /// Filename of a comic image with a particular extension
+    ///
+    /// Rendered images are always PNGs, but images passed through as-is keep
+    /// the extension of the original.
+    pub fn image_filename(num: u32, extension: &str) -> String {
+        format!("comic_{:04}.{}", num, extension)
+    }
+
     pub fn filetype(&self) -> FileType {
         match self {
             Self::Root => FileType::Directory,
             Self::Refresh => FileType::RegularFile,
             Self::Credits => FileType::RegularFile,
+            Self::Montage => FileType::RegularFile,
+            Self::MetadataCsv => FileType::RegularFile,
+            Self::Translations => FileType::Directory,
+            Self::Status => FileType::RegularFile,
+            Self::Wallpaper => FileType::RegularFile,
+            Self::History => FileType::Directory,
+            Self::LatestLink => FileType::Symlink,
+            Self::RandomLink => FileType::Symlink,
+            Self::ByYear => FileType::Directory,
+            Self::Year(_) => FileType::Directory,
+            Self::ByDate => FileType::Directory,
+            Self::Day(_) => FileType::Directory,
+            Self::ByTitle => FileType::Directory,
             Self::Image(_) => FileType::RegularFile,
             Self::MetaFolder(_) => FileType::Directory,
             Self::AltText(_) => FileType::RegularFile,
@@ -148,9 +529,24 @@ impl File {
             Self::Transcript(_) => FileType::RegularFile,
             Self::Date(_) => FileType::RegularFile,
             Self::RawImage(_) => FileType::RegularFile,
+            Self::Thumbnail(_, _) => FileType::RegularFile,
+            Self::Pdf(_) => FileType::RegularFile,
+            Self::Svg(_) => FileType::RegularFile,
+            Self::Ascii(_) => FileType::RegularFile,
+            Self::Described(_) => FileType::RegularFile,
+            Self::Error(_) => FileType::RegularFile,
+            Self::ExtraParts(_) => FileType::RegularFile,
         }
     }
 
+    /// Index of the first comic in the root folder, after the fixed files
+    const FIRST_COMIC_INDEX: u64 = 15;
+
+    /// How many entries `child_by_index` lists in the root folder
+    pub fn root_len(num_comics: u64) -> u64 {
+        Self::FIRST_COMIC_INDEX + 2 * num_comics
+    }
+
     pub fn child_by_index(&self, index: u64, num_comics: u64) -> Option<(u64, FileType, String)> {
         match self {
             Self::Root => match index {
@@ -166,13 +562,25 @@ impl File {
                     Self::Credits.filetype(),
                     Self::Credits.filename(),
                 )),
-                index if index <= (num_comics + 3) as u64 => {
-                    let file = File::Image((index - 3) as u32);
+                4 => Self::Montage.triple(),
+                5 => Self::MetadataCsv.triple(),
+                6 => Self::Translations.triple(),
+                7 => Self::Status.triple(),
+                8 => Self::Wallpaper.triple(),
+                9 => Self::History.triple(),
+                10 => Self::LatestLink.triple(),
+                11 => Self::RandomLink.triple(),
+                12 => Self::ByYear.triple(),
+                13 => Self::ByDate.triple(),
+                14 => Self::ByTitle.triple(),
+                index if index < Self::FIRST_COMIC_INDEX + num_comics => {
+                    let file = File::Image((index - Self::FIRST_COMIC_INDEX + 1) as u32);
 
                     Some((file.inode(), file.filetype(), file.filename()))
                 }
-                index if index <= (2 * num_comics + 3) as u64 => {
-                    let file = File::MetaFolder((index - 3 - num_comics) as u32);
+                index if index < Self::FIRST_COMIC_INDEX + 2 * num_comics => {
+                    let file =
+                        File::MetaFolder((index - Self::FIRST_COMIC_INDEX - num_comics + 1) as u32);
 
                     Some((file.inode(), file.filetype(), file.filename()))
                 }
@@ -180,6 +588,90 @@ impl File {
             },
             Self::Refresh => None,
             Self::Credits => None,
+            Self::Montage => None,
+            Self::MetadataCsv => None,
+            // Only . and .., the languages are added by the filesystem
+            Self::Translations => match index {
+                0 => Some((
+                    Self::Translations.inode(),
+                    Self::Translations.filetype(),
+                    ".".to_string(),
+                )),
+                1 => Some((Self::Root.inode(), Self::Root.filetype(), "..".to_string())),
+                _ => None,
+            },
+            Self::Status => None,
+            Self::Wallpaper => None,
+            Self::LatestLink => None,
+            Self::RandomLink => None,
+            // Only . and .., the comics are added by the filesystem
+            Self::History => match index {
+                0 => Some((
+                    Self::History.inode(),
+                    Self::History.filetype(),
+                    ".".to_string(),
+                )),
+                1 => Some((Self::Root.inode(), Self::Root.filetype(), "..".to_string())),
+                _ => None,
+            },
+            // Only . and .., the years are added by the filesystem
+            Self::ByYear => match index {
+                0 => Some((
+                    Self::ByYear.inode(),
+                    Self::ByYear.filetype(),
+                    ".".to_string(),
+                )),
+                1 => Some((Self::Root.inode(), Self::Root.filetype(), "..".to_string())),
+                _ => None,
+            },
+            // Only . and .., the comics are added by the filesystem
+            Self::Year(year) => match index {
+                0 => Some((
+                    Self::Year(*year).inode(),
+                    Self::Year(*year).filetype(),
+                    ".".to_string(),
+                )),
+                1 => Some((
+                    Self::ByYear.inode(),
+                    Self::ByYear.filetype(),
+                    "..".to_string(),
+                )),
+                _ => None,
+            },
+            // Only . and .., there are too many days to list
+            Self::ByDate => match index {
+                0 => Some((
+                    Self::ByDate.inode(),
+                    Self::ByDate.filetype(),
+                    ".".to_string(),
+                )),
+                1 => Some((Self::Root.inode(), Self::Root.filetype(), "..".to_string())),
+                _ => None,
+            },
+            // Only . and .., the comics are added by the filesystem
+            Self::Day(days) => match index {
+                0 => Some((
+                    Self::Day(*days).inode(),
+                    Self::Day(*days).filetype(),
+                    ".".to_string(),
+                )),
+                1 => Some((
+                    Self::ByDate.inode(),
+                    Self::ByDate.filetype(),
+                    "..".to_string(),
+                )),
+                _ => None,
+            },
+            // Only . and .., the comics are added by the filesystem
+            Self::ByTitle => match index {
+                0 => Some((
+                    Self::ByTitle.inode(),
+                    Self::ByTitle.filetype(),
+                    ".".to_string(),
+                )),
+                1 => Some((Self::Root.inode(), Self::Root.filetype(), "..".to_string())),
+                _ => None,
+            },
             Self::Image(_) => None,
             Self::MetaFolder(num) => {
                 if *num as u64 > num_comics {
@@ -198,6 +690,14 @@ impl File {
                     4 => File::Transcript(*num).triple(),
                     5 => File::Date(*num).triple(),
                     6 => File::RawImage(*num).triple(),
+                    7 => File::Pdf(*num).triple(),
+                    8 => File::Svg(*num).triple(),
+                    9 => File::Ascii(*num).triple(),
+                    10 => File::Described(*num).triple(),
+                    11 => File::Error(*num).triple(),
+                    12 => File::ExtraParts(*num).triple(),
+                    // Thumbnails are added by the filesystem, in the sizes
+                    // it makes
                     _ => None,
                 }
             }
@@ -206,6 +706,13 @@ impl File {
             Self::Transcript(_) => None,
             Self::Date(_) => None,
             Self::RawImage(_) => None,
+            Self::Thumbnail(_, _) => None,
+            Self::Pdf(_) => None,
+            Self::Svg(_) => None,
+            Self::Ascii(_) => None,
+            Self::Described(_) => None,
+            Self::Error(_) => None,
+            Self::ExtraParts(_) => None,
         }
     }
 
@@ -218,6 +725,22 @@ impl File {
 #[cfg(test)]
 mod test {
     use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn inodes_have_sources() {
+        let image = File::Image(1234).inode();
+        let refresh = File::Refresh.inode();
+
+        assert_eq!(split_inode(image), (0, image));
+        assert_eq!(split_inode(source_inode(0, refresh)), (0, refresh));
+        assert_eq!(split_inode(source_inode(3, image)), (3, image));
+        assert_eq!(
+            split_inode(source_inode(0xFFFF, refresh)),
+            (0xFFFF, refresh)
+        );
+        assert_ne!(source_inode(1, File::Root.inode()), File::Root.inode());
+    }
 
     #[test]
     fn file_from_inode() {
@@ -226,7 +749,35 @@ mod test {
         assert_eq!(File::from_inode(1), Some(File::Root));
         assert_eq!(File::from_inode(2), Some(File::Refresh));
         assert_eq!(File::from_inode(3), Some(File::Credits));
-        assert_eq!(File::from_inode(4), None);
+        assert_eq!(File::from_inode(4), Some(File::Montage));
+        assert_eq!(File::from_inode(5), Some(File::MetadataCsv));
+        assert_eq!(File::from_inode(6), Some(File::Translations));
+        assert_eq!(File::from_inode(7), Some(File::Status));
+        assert_eq!(File::from_inode(8), Some(File::Wallpaper));
+        assert_eq!(File::from_inode(9), Some(File::History));
+        assert_eq!(File::from_inode(10), Some(File::LatestLink));
+        assert_eq!(File::from_inode(11), Some(File::RandomLink));
+        assert_eq!(File::from_inode(12), Some(File::ByYear));
+        assert_eq!(File::from_inode(13), Some(File::ByDate));
+        assert_eq!(File::from_inode(14), Some(File::ByTitle));
+        assert_eq!(File::from_inode(15), None);
+        assert_eq!(File::from_inode(0xFF), None);
+
+        // Days
+        assert_eq!(File::from_inode(0x100), Some(File::Day(0)));
+        assert_eq!(File::from_inode(0x7FFF), Some(File::Day(0x7EFF)));
+
+        // Years
+        assert_eq!(File::from_inode(0x8000), None);
+        assert_eq!(File::from_inode(0x8000 + 2015), Some(File::Year(2015)));
+        assert_eq!(File::from_inode(0xFFFF), Some(File::Year(0x7FFF)));
+        assert_eq!(File::from_inode(0x10000), None);
+        assert_eq!(File::year(2015), Some(File::Year(2015)));
+        assert_eq!(File::year(0), None);
+        assert_eq!(File::year(-1), None);
+        assert_eq!(File::year(0x8000), None);
+        assert_eq!(File::day_date(0), (2000, 1, 1));
+        assert_eq!(File::day_date(3492), (2009, 7, 24));
 
         // Image 1
         assert_eq!(File::from_inode(0x00000001_00000000), Some(File::Image(1)));
@@ -249,6 +800,33 @@ mod test {
             Some(File::RawImage(1))
         );
         assert_eq!(File::from_inode(0x00000001_00000007), None);
+        assert_eq!(File::from_inode(0x00000001_00000008), Some(File::Pdf(1)));
+        assert_eq!(File::from_inode(0x00000001_00000009), Some(File::Svg(1)));
+        assert_eq!(File::from_inode(0x00000001_0000000A), Some(File::Ascii(1)));
+        assert_eq!(
+            File::from_inode(0x00000001_0000000B),
+            Some(File::Described(1))
+        );
+        assert_eq!(File::from_inode(0x00000001_0000000C), Some(File::Error(1)));
+        assert_eq!(
+            File::from_inode(0x00000001_0000000D),
+            Some(File::ExtraParts(1))
+        );
+        assert_eq!(File::from_inode(0x00000001_0000000E), None);
+        assert_eq!(File::from_inode(0x00000001_00001000), None);
+        assert_eq!(
+            File::from_inode(0x00000001_00001001),
+            Some(File::Thumbnail(1, 1))
+        );
+        assert_eq!(
+            File::from_inode(0x00000001_00001100),
+            Some(File::Thumbnail(1, 256))
+        );
+        assert_eq!(
+            File::from_inode(0x00000001_00001FFF),
+            Some(File::Thumbnail(1, 0xFFF))
+        );
+        assert_eq!(File::from_inode(0x00000001_00002000), None);
 
         // Image 0xFFFFFFFF
         assert_eq!(
@@ -279,7 +857,35 @@ mod test {
             File::from_inode(0xFFFFFFFF_00000006),
             Some(File::RawImage(0xFFFFFFFF))
         );
-        assert_eq!(File::from_inode(0xFFFFFFFF_00000007), None);
+        assert_eq!(
+            File::from_inode(0xFFFFFFFF_00001040),
+            Some(File::Thumbnail(0xFFFFFFFF, 64))
+        );
+        assert_eq!(
+            File::from_inode(0xFFFFFFFF_00000008),
+            Some(File::Pdf(0xFFFFFFFF))
+        );
+        assert_eq!(
+            File::from_inode(0xFFFFFFFF_00000009),
+            Some(File::Svg(0xFFFFFFFF))
+        );
+        assert_eq!(
+            File::from_inode(0xFFFFFFFF_0000000A),
+            Some(File::Ascii(0xFFFFFFFF))
+        );
+        assert_eq!(
+            File::from_inode(0xFFFFFFFF_0000000B),
+            Some(File::Described(0xFFFFFFFF))
+        );
+        assert_eq!(
+            File::from_inode(0xFFFFFFFF_0000000C),
+            Some(File::Error(0xFFFFFFFF))
+        );
+        assert_eq!(
+            File::from_inode(0xFFFFFFFF_0000000D),
+            Some(File::ExtraParts(0xFFFFFFFF))
+        );
+        assert_eq!(File::from_inode(0xFFFFFFFF_0000000E), None);
     }
 
     #[test]
@@ -300,9 +906,8 @@ mod test {
                     eprintln!("{:?} -> {:016x}", f, f.inode());
                 }
 
-                match file {
-                    Some(f) => assert_eq!(f.inode(), inode),
-                    None => {}
+                if let Some(f) = file {
+                    assert_eq!(f.inode(), inode)
                 }
             }
         }
@@ -343,6 +948,14 @@ mod test {
             Some(File::Image(123456)),
             File::from_filename(&File::Root, "comic_123456.png")
         );
+        assert_eq!(
+            Some(File::Image(1116)),
+            File::from_filename(&File::Root, "comic_1116.gif")
+        );
+        assert_eq!(
+            Some(File::Image(1)),
+            File::from_filename(&File::Root, "comic_1.jpg")
+        );
         assert_eq!(
             Some(File::MetaFolder(1)),
             File::from_filename(&File::Root, "info_1")
@@ -353,14 +966,99 @@ mod test {
         );
 
         // Failures: Parent is root
+        assert_eq!(
+            Some(File::Montage),
+            File::from_filename(&File::Root, "montage.png")
+        );
+        assert_eq!(
+            Some(File::MetadataCsv),
+            File::from_filename(&File::Root, "metadata.csv")
+        );
+        assert_eq!(
+            Some(File::Translations),
+            File::from_filename(&File::Root, "translations")
+        );
+        assert_eq!(None, File::from_filename(&File::Translations, "es"));
+        assert_eq!(
+            Some(File::Status),
+            File::from_filename(&File::Root, ".status")
+        );
+        assert_eq!(
+            Some(File::Wallpaper),
+            File::from_filename(&File::Root, "wallpaper.png")
+        );
+        assert_eq!(
+            Some(File::History),
+            File::from_filename(&File::Root, "history")
+        );
+        assert_eq!(
+            Some(File::LatestLink),
+            File::from_filename(&File::Root, "latest")
+        );
+        assert_eq!(
+            Some(File::RandomLink),
+            File::from_filename(&File::Root, "random")
+        );
+        assert_eq!(None, File::from_filename(&File::History, "comic_0001.png"));
+        assert_eq!(
+            Some(File::ByYear),
+            File::from_filename(&File::Root, "by_year")
+        );
+        assert_eq!(
+            Some(File::Year(2015)),
+            File::from_filename(&File::ByYear, "2015")
+        );
+        assert_eq!(None, File::from_filename(&File::ByYear, "0"));
+        assert_eq!(None, File::from_filename(&File::ByYear, "32768"));
+        assert_eq!(None, File::from_filename(&File::ByYear, "twenty"));
+        assert_eq!(None, File::from_filename(&File::Root, "2015"));
+        assert_eq!(
+            None,
+            File::from_filename(&File::Year(2015), "comic_1500.png")
+        );
+        assert_eq!(
+            Some(File::ByDate),
+            File::from_filename(&File::Root, "by_date")
+        );
+        assert_eq!(
+            Some(File::Day(3492)),
+            File::from_filename(&File::ByDate, "2009-07-24")
+        );
+        assert_eq!(
+            Some(File::Day(0)),
+            File::from_filename(&File::ByDate, "2000-01-01")
+        );
+        assert_eq!(None, File::from_filename(&File::ByDate, "1999-12-31"));
+        assert_eq!(None, File::from_filename(&File::ByDate, "2009-02-30"));
+        assert_eq!(None, File::from_filename(&File::ByDate, "2009-7-24"));
+        assert_eq!(None, File::from_filename(&File::ByDate, "2009-07-24x"));
+        assert_eq!(None, File::from_filename(&File::ByDate, "2009/07/24"));
+        assert_eq!(None, File::from_filename(&File::ByDate, "2009-07-é"));
+        assert_eq!(
+            None,
+            File::from_filename(&File::Day(3492), "comic_0614.png")
+        );
+        assert_eq!(
+            Some(File::ByTitle),
+            File::from_filename(&File::Root, "by_title")
+        );
+        assert_eq!(
+            None,
+            File::from_filename(&File::ByTitle, "Barrel - Part 1.png")
+        );
         assert_eq!(None, File::from_filename(&File::Root, "foobar.png"));
         assert_eq!(None, File::from_filename(&File::Root, "comic_asdf.png"));
+        assert_eq!(None, File::from_filename(&File::Root, "comic_1.bmp"));
         assert_eq!(None, File::from_filename(&File::Root, "info_baz"));
+        assert_eq!(None, File::from_filename(&File::Root, "info_0"));
+        assert_eq!(None, File::from_filename(&File::Root, "comic_0000.png"));
+        assert_eq!(None, File::from_filename(&File::Root, "comic_+1.png"));
         assert_eq!(None, File::from_filename(&File::Root, "alt"));
         assert_eq!(None, File::from_filename(&File::Root, "title"));
         assert_eq!(None, File::from_filename(&File::Root, "transcript"));
         assert_eq!(None, File::from_filename(&File::Root, "date"));
         assert_eq!(None, File::from_filename(&File::Root, "raw_image"));
+        assert_eq!(None, File::from_filename(&File::Root, "thumbnail.png"));
 
         // Successes: Parent is metafolder
         assert_eq!(
@@ -403,6 +1101,54 @@ mod test {
             Some(File::RawImage(123456)),
             File::from_filename(&File::MetaFolder(123456), "raw_image")
         );
+        assert_eq!(
+            Some(File::Thumbnail(1, 64)),
+            File::from_filename(&File::MetaFolder(1), "thumbnail_64.png")
+        );
+        assert_eq!(
+            Some(File::Thumbnail(123456, 256)),
+            File::from_filename(&File::MetaFolder(123456), "thumbnail_256.png")
+        );
+        assert_eq!(
+            None,
+            File::from_filename(&File::MetaFolder(1), "thumbnail.png")
+        );
+        assert_eq!(
+            None,
+            File::from_filename(&File::MetaFolder(1), "thumbnail_0.png")
+        );
+        assert_eq!(
+            None,
+            File::from_filename(&File::MetaFolder(1), "thumbnail_064.png")
+        );
+        assert_eq!(
+            None,
+            File::from_filename(&File::MetaFolder(1), "thumbnail_4096.png")
+        );
+        assert_eq!(
+            Some(File::Pdf(1)),
+            File::from_filename(&File::MetaFolder(1), "comic.pdf")
+        );
+        assert_eq!(
+            Some(File::Svg(1)),
+            File::from_filename(&File::MetaFolder(1), "comic.svg")
+        );
+        assert_eq!(
+            Some(File::Ascii(1)),
+            File::from_filename(&File::MetaFolder(1), "ascii.txt")
+        );
+        assert_eq!(
+            Some(File::Described(1)),
+            File::from_filename(&File::MetaFolder(1), "described.txt")
+        );
+        assert_eq!(
+            Some(File::Error(1)),
+            File::from_filename(&File::MetaFolder(1), "error")
+        );
+        assert_eq!(
+            Some(File::ExtraParts(1)),
+            File::from_filename(&File::MetaFolder(1), "extra_parts.json")
+        );
 
         // Failures: Parent is a metafolder but we request a root file
         assert_eq!(
@@ -430,6 +1176,16 @@ mod test {
 
         assert_eq!(None, File::from_filename(&File::RawImage(1), ""));
         assert_eq!(None, File::from_filename(&File::RawImage(123456), ""));
+
+        assert_eq!(None, File::from_filename(&File::Thumbnail(1, 64), ""));
+        assert_eq!(None, File::from_filename(&File::Thumbnail(123456, 64), ""));
+
+        assert_eq!(None, File::from_filename(&File::Pdf(1), ""));
+        assert_eq!(None, File::from_filename(&File::Svg(1), ""));
+        assert_eq!(None, File::from_filename(&File::Ascii(1), ""));
+        assert_eq!(None, File::from_filename(&File::Described(1), ""));
+        assert_eq!(None, File::from_filename(&File::Error(1), ""));
+        assert_eq!(None, File::from_filename(&File::ExtraParts(1), ""));
     }
 
     fn exp_child(f: File) -> Option<(u64, FileType, String)> {
@@ -448,12 +1204,35 @@ mod test {
         );
         assert_eq!(exp_child(File::Refresh), File::Root.child_by_index(2, 1));
         assert_eq!(exp_child(File::Credits), File::Root.child_by_index(3, 1));
-        assert_eq!(exp_child(File::Image(1)), File::Root.child_by_index(4, 1));
+        assert_eq!(exp_child(File::Montage), File::Root.child_by_index(4, 1));
         assert_eq!(
-            exp_child(File::MetaFolder(1)),
+            exp_child(File::MetadataCsv),
             File::Root.child_by_index(5, 1)
         );
-        assert_eq!(None, File::Root.child_by_index(6, 1));
+        assert_eq!(
+            exp_child(File::Translations),
+            File::Root.child_by_index(6, 1)
+        );
+        assert_eq!(exp_child(File::Status), File::Root.child_by_index(7, 1));
+        assert_eq!(exp_child(File::Wallpaper), File::Root.child_by_index(8, 1));
+        assert_eq!(exp_child(File::History), File::Root.child_by_index(9, 1));
+        assert_eq!(
+            exp_child(File::LatestLink),
+            File::Root.child_by_index(10, 1)
+        );
+        assert_eq!(
+            exp_child(File::RandomLink),
+            File::Root.child_by_index(11, 1)
+        );
+        assert_eq!(exp_child(File::ByYear), File::Root.child_by_index(12, 1));
+        assert_eq!(exp_child(File::ByDate), File::Root.child_by_index(13, 1));
+        assert_eq!(exp_child(File::ByTitle), File::Root.child_by_index(14, 1));
+        assert_eq!(exp_child(File::Image(1)), File::Root.child_by_index(15, 1));
+        assert_eq!(
+            exp_child(File::MetaFolder(1)),
+            File::Root.child_by_index(16, 1)
+        );
+        assert_eq!(None, File::Root.child_by_index(17, 1));
     }
 
     #[test]
@@ -474,22 +1253,66 @@ mod test {
             exp_child(File::Credits),
             File::Root.child_by_index(3, 10_000)
         );
+        assert_eq!(
+            exp_child(File::Montage),
+            File::Root.child_by_index(4, 10_000)
+        );
+        assert_eq!(
+            exp_child(File::MetadataCsv),
+            File::Root.child_by_index(5, 10_000)
+        );
+        assert_eq!(
+            exp_child(File::Translations),
+            File::Root.child_by_index(6, 10_000)
+        );
+        assert_eq!(
+            exp_child(File::Status),
+            File::Root.child_by_index(7, 10_000)
+        );
+        assert_eq!(
+            exp_child(File::Wallpaper),
+            File::Root.child_by_index(8, 10_000)
+        );
+        assert_eq!(
+            exp_child(File::History),
+            File::Root.child_by_index(9, 10_000)
+        );
+        assert_eq!(
+            exp_child(File::LatestLink),
+            File::Root.child_by_index(10, 10_000)
+        );
+        assert_eq!(
+            exp_child(File::RandomLink),
+            File::Root.child_by_index(11, 10_000)
+        );
+        assert_eq!(
+            exp_child(File::ByYear),
+            File::Root.child_by_index(12, 10_000)
+        );
+        assert_eq!(
+            exp_child(File::ByDate),
+            File::Root.child_by_index(13, 10_000)
+        );
+        assert_eq!(
+            exp_child(File::ByTitle),
+            File::Root.child_by_index(14, 10_000)
+        );
 
-        for i in 4..10_004 {
+        for i in 15..10_015 {
             assert_eq!(
-                exp_child(File::Image(i - 3)),
+                exp_child(File::Image(i - 14)),
                 File::Root.child_by_index(i as u64, 10_000)
             );
         }
 
-        for i in 10_004..20_004 {
+        for i in 10_015..20_015 {
             assert_eq!(
-                exp_child(File::MetaFolder(i - 10_003)),
+                exp_child(File::MetaFolder(i - 10_014)),
                 File::Root.child_by_index(i as u64, 10_000)
             );
         }
 
-        assert_eq!(None, File::Root.child_by_index(20_004, 10_000));
+        assert_eq!(None, File::Root.child_by_index(20_015, 10_000));
     }
 
     #[test]
@@ -557,8 +1380,188 @@ mod test {
             File::MetaFolder(1).child_by_index(6, 1)
         );
 
-        assert_eq!(None, File::MetaFolder(1).child_by_index(7, 1));
+        assert_eq!(
+            Some((
+                File::Pdf(1).inode(),
+                File::Pdf(1).filetype(),
+                "comic.pdf".to_string(),
+            )),
+            File::MetaFolder(1).child_by_index(7, 1)
+        );
+
+        assert_eq!(
+            Some((
+                File::Svg(1).inode(),
+                File::Svg(1).filetype(),
+                "comic.svg".to_string(),
+            )),
+            File::MetaFolder(1).child_by_index(8, 1)
+        );
+
+        assert_eq!(
+            Some((
+                File::Ascii(1).inode(),
+                File::Ascii(1).filetype(),
+                "ascii.txt".to_string(),
+            )),
+            File::MetaFolder(1).child_by_index(9, 1)
+        );
+
+        assert_eq!(
+            Some((
+                File::Described(1).inode(),
+                File::Described(1).filetype(),
+                "described.txt".to_string(),
+            )),
+            File::MetaFolder(1).child_by_index(10, 1)
+        );
+
+        assert_eq!(
+            Some((
+                File::Error(1).inode(),
+                File::Error(1).filetype(),
+                "error".to_string(),
+            )),
+            File::MetaFolder(1).child_by_index(11, 1)
+        );
+
+        assert_eq!(
+            Some((
+                File::ExtraParts(1).inode(),
+                File::ExtraParts(1).filetype(),
+                "extra_parts.json".to_string(),
+            )),
+            File::MetaFolder(1).child_by_index(12, 1)
+        );
+
+        assert_eq!(None, File::MetaFolder(1).child_by_index(13, 1));
 
         assert_eq!(None, File::MetaFolder(2).child_by_index(0, 1));
     }
+
+    /// The directory a file is listed in
+    fn parent_of(file: &File) -> File {
+        match file {
+            File::Root
+            | File::Refresh
+            | File::Credits
+            | File::Montage
+            | File::MetadataCsv
+            | File::Translations
+            | File::Status
+            | File::Wallpaper
+            | File::History
+            | File::LatestLink
+            | File::RandomLink
+            | File::ByYear
+            | File::ByDate
+            | File::ByTitle
+            | File::Image(_)
+            | File::MetaFolder(_) => File::Root,
+            File::Year(_) => File::ByYear,
+            File::Day(_) => File::ByDate,
+            File::AltText(num)
+            | File::Title(num)
+            | File::Transcript(num)
+            | File::Date(num)
+            | File::RawImage(num)
+            | File::Thumbnail(num, _)
+            | File::Pdf(num)
+            | File::Svg(num)
+            | File::Ascii(num)
+            | File::Described(num)
+            | File::Error(num)
+            | File::ExtraParts(num) => File::MetaFolder(*num),
+        }
+    }
+
+    /// Any file at all
+    ///
+    /// Built from inodes, so new kinds of file are included as soon as
+    /// `from_inode` knows about them.
+    fn any_file() -> impl Strategy<Value = File> {
+        prop_oneof![
+            (0..64u32).prop_filter_map("Not a file", |low| File::from_inode(low as u64)),
+            (1..YEAR_BASE).prop_map(File::Year),
+            (0..YEAR_BASE - DAY_BASE).prop_map(File::Day),
+            (1..=u32::MAX, 0..64u32).prop_filter_map("Not a file", |(high, low)| {
+                File::from_inode((high as u64) << 32 | low as u64)
+            }),
+            (1..=u32::MAX, 1..=MAX_THUMBNAIL_WIDTH)
+                .prop_map(|(num, width)| File::Thumbnail(num, width)),
+        ]
+    }
+
+    fn any_directory() -> impl Strategy<Value = File> {
+        prop_oneof![
+            (0..1u32).prop_map(|_| File::Root),
+            (0..1u32).prop_map(|_| File::Translations),
+            (0..1u32).prop_map(|_| File::History),
+            (0..1u32).prop_map(|_| File::ByYear),
+            (1..YEAR_BASE).prop_map(File::Year),
+            (0..1u32).prop_map(|_| File::ByDate),
+            (0..YEAR_BASE - DAY_BASE).prop_map(File::Day),
+            (0..1u32).prop_map(|_| File::ByTitle),
+            (1..=u32::MAX).prop_map(File::MetaFolder),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn inodes_round_trip(file in any_file()) {
+            prop_assert_eq!(File::from_inode(file.inode()), Some(file));
+        }
+
+        #[test]
+        fn inodes_keep_their_source(file in any_file(), source in 0..=0xFFFFusize) {
+            let ino = file.inode();
+
+            prop_assert_eq!(split_inode(source_inode(source, ino)), (source, ino));
+        }
+
+        #[test]
+        fn names_round_trip(file in any_file()) {
+            prop_assume!(file != File::Root);
+
+            let parent = parent_of(&file);
+
+            prop_assert_eq!(File::from_filename(&parent, file.filename()), Some(file));
+        }
+
+        #[test]
+        fn padding_is_optional(
+            num in 1..=u32::MAX,
+            zeros in 0..8usize,
+            extension in prop::sample::select(IMAGE_EXTENSIONS),
+        ) {
+            let digits = format!("{}{}", "0".repeat(zeros), num);
+
+            prop_assert_eq!(
+                File::from_filename(&File::Root, format!("comic_{}{}", digits, extension)),
+                Some(File::Image(num))
+            );
+            prop_assert_eq!(
+                File::from_filename(&File::Root, format!("info_{}", digits)),
+                Some(File::MetaFolder(num))
+            );
+        }
+
+        /// Whatever a name looks up has to be a real file, listed under a name
+        /// that looks up the same thing
+        #[test]
+        fn names_find_real_files(
+            parent in any_directory(),
+            name in "(comic_|info_)?[-+0-9a-z_.]{0,12}(\\.png|\\.gif|\\.jpg)?",
+        ) {
+            if let Some(file) = File::from_filename(&parent, &name) {
+                prop_assert_ne!(&file, &File::Root);
+                let by_inode = File::from_inode(file.inode());
+                prop_assert_eq!(by_inode.as_ref(), Some(&file));
+                prop_assert_eq!(parent_of(&file), parent);
+
+                let by_name = File::from_filename(&parent_of(&file), file.filename());
+                prop_assert_eq!(by_name.as_ref(), Some(&file));
+            }
+        }
+    }
 }
@@ -1,51 +1,574 @@
+mod budget;
+mod csv;
 pub mod file;
-
+pub mod mount;
+mod neighbors;
+pub mod options;
+mod random;
+mod render;
+mod snapshots;
+mod status;
+mod workers;
+
+use fuse::consts::{FOPEN_DIRECT_IO, FOPEN_KEEP_CACHE};
 use fuse::{
-    FileAttr, Filesystem, ReplyAttr, ReplyData, ReplyEntry, ReplyOpen, ReplyWrite, Request,
+    FileAttr, FileType, Filesystem, ReplyAttr, ReplyData, ReplyEmpty, ReplyEntry, ReplyOpen,
+    ReplyWrite, ReplyXattr, Request,
+};
+use libc::c_int;
+use libc::{
+    EACCES, EINVAL, EIO, EISDIR, ELOOP, ENODATA, ENOENT, ENOTDIR, EPERM, ERANGE, EREMOTEIO,
 };
-use libc::{EINVAL, EISDIR, ENOENT, ENOTDIR, EPERM, EREMOTEIO};
+use libc::{O_ACCMODE, O_DIRECTORY, O_RDONLY, O_TRUNC};
 use std::convert::TryInto;
 use std::ffi::OsStr;
+use std::mem;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use time::Timespec;
 
-use crate::{requests::RequestMode::*, Comic};
-use file::File;
+use crate::{
+    image::{
+        format::ImageFormat, montage, wallpaper::WallpaperSpec, RenderOptions, ScaleFilter,
+        ThumbnailSpec, VectorFormat,
+    },
+    logging,
+    notify::Watchdog,
+    prefetch, refresh,
+    reload::Reloader,
+    requests::RequestMode::*,
+    stats::Stats,
+    Comic, XkcdClient,
+};
+use budget::MemoryBudget;
+use file::{File, IMAGE_EXTENSIONS};
+use neighbors::Neighbors;
+use random::Rng;
+use render::{RenderPool, Waiter};
+use snapshots::{PinnedImage, Snapshots};
+use workers::WorkerPool;
 
 const TTL: Timespec = Timespec { sec: 1, nsec: 0 };
+/// For files belonging to a comic, which never change once it's published
+const COMIC_TTL: Timespec = Timespec {
+    sec: 6 * 60 * 60,
+    nsec: 0,
+};
 const EPOCH: Timespec = Timespec { sec: 0, nsec: 0 };
 const GEN: u64 = 0;
 const BLOCK_SIZE: u64 = 512;
 const DIR_SIZE: u64 = 4096;
 const DEFAULT_SIZE: u64 = 4096;
 const DEFAULT_PERM: u16 = 0o444;
+/// Most comics listed in `history`
+const HISTORY_LEN: u32 = 100;
 
-const CREDITS_DATA: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/credits.txt"));
+/// What pinning an open image counts for in the memory budget
+const PINNED_BYTES: u64 = mem::size_of::<PinnedImage>() as u64;
+/// Extended attribute with an image's type, which file managers read
+const MIME_TYPE_XATTR: &str = "user.mime_type";
+
+/// A directory entry's inode, type and name
+type DirEntry = (u64, FileType, String);
+
+pub const CREDITS_DATA: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/credits.txt"));
 
 pub struct XkcdFs {
-    client: crate::XkcdClient,
+    /// xkcd first, then any other sources, in inode order
+    clients: Vec<XkcdClient>,
+    /// Directories the other sources are in, with xkcd's left empty
+    names: Vec<String>,
+    /// Whether each source is a translation of xkcd, listed in `translations`
+    /// instead of at the top
+    translations: Vec<bool>,
     next_fh: u64,
+    /// What open files that change between reads had in them when opened
+    snapshots: Snapshots<Vec<u8>>,
+    /// What open directories had in them when opened
+    listings: Snapshots<Vec<DirEntry>>,
+    /// What open comic images are read as
+    images: Snapshots<PinnedImage>,
+    /// Widths of the thumbnails in each comic's info folder
+    thumbnail_sizes: Vec<u32>,
+    thumbnail_filter: ScaleFilter,
+    ascii_width: u32,
+    /// Serve transcripts as the API has them, markup and all
+    raw_transcripts: bool,
+    /// Comics most recently written to the montage file
+    montage: Vec<u32>,
+    /// Size and background of `wallpaper.png`
+    wallpaper: WallpaperSpec,
+    render_threads: usize,
+    fuse_threads: usize,
+    /// What renders and the snapshots of open files use memory out of
+    budget: MemoryBudget,
+    /// As shown in `.status`
+    mount_options: String,
+    /// Files at the top of the mount that were asked to be left out
+    hidden: Vec<File>,
+    /// Note when comics are read, and show it as their files' atimes
+    track_atime: bool,
+    /// Comics next to ones that were read, fetched in case they're next
+    neighbors: Neighbors,
+    /// Where `random` points next
+    rng: Rng,
+    /// Started in `init`, so no threads exist until the filesystem is mounted
+    render_pool: Option<Arc<RenderPool>>,
+    /// Also started in `init`. Without it, everything runs on the FUSE thread.
+    workers: Option<WorkerPool<Vec<XkcdClient>>>,
+    reloader: Option<Reloader>,
+    stats: Arc<Stats>,
+    watchdog: Watchdog,
 }
 
 impl XkcdFs {
-    pub fn new(client: crate::XkcdClient) -> Self {
-        Self { client, next_fh: 1 }
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        client: XkcdClient,
+        thumbnail_sizes: Vec<u32>,
+        thumbnail_filter: ScaleFilter,
+        ascii_width: u32,
+        render_threads: usize,
+        fuse_threads: usize,
+        reloader: Option<Reloader>,
+        watchdog: Watchdog,
+    ) -> Self {
+        let stats = client.stats();
+
+        Self {
+            budget: MemoryBudget::new(None, Arc::clone(&stats)),
+            stats,
+            clients: vec![client],
+            names: vec![String::new()],
+            translations: vec![false],
+            next_fh: 1,
+            snapshots: Snapshots::default(),
+            listings: Snapshots::default(),
+            images: Snapshots::default(),
+            thumbnail_sizes,
+            thumbnail_filter,
+            ascii_width,
+            raw_transcripts: false,
+            montage: Vec::new(),
+            wallpaper: WallpaperSpec::default(),
+            render_threads,
+            fuse_threads,
+            mount_options: String::new(),
+            hidden: Vec::new(),
+            track_atime: false,
+            neighbors: Neighbors::default(),
+            rng: Rng::from_clock(),
+            render_pool: None,
+            workers: None,
+            reloader,
+            watchdog,
+        }
+    }
+
+    /// Make `wallpaper.png` this size, on this background
+    pub fn set_wallpaper(&mut self, wallpaper: WallpaperSpec) {
+        self.wallpaper = wallpaper;
+    }
+
+    /// Hold renders back, and refuse to open files, while they'd take more
+    /// than `max_memory` bytes
+    pub fn set_max_memory(&mut self, max_memory: Option<u64>) {
+        self.budget = MemoryBudget::new(max_memory, Arc::clone(&self.stats));
+    }
+
+    /// Note the options the filesystem was mounted with, for `.status`
+    pub fn set_mount_options(&mut self, mount_options: String) {
+        self.mount_options = mount_options;
+    }
+
+    /// Leave `hidden` out of the top of the mount, and every source's
+    pub fn set_hidden(&mut self, hidden: Vec<File>) {
+        self.hidden = hidden;
+    }
+
+    /// Serve transcripts as the API has them, instead of cleaned up
+    pub fn set_raw_transcripts(&mut self, raw_transcripts: bool) {
+        self.raw_transcripts = raw_transcripts;
+    }
+
+    /// Note when comics are read, and show it as their files' atimes
+    pub fn set_track_atime(&mut self, track_atime: bool) {
+        self.track_atime = track_atime;
+    }
+
+    /// Show another webcomic's comics in directory `name`
+    pub fn add_source(&mut self, name: String, client: XkcdClient) {
+        self.clients.push(client);
+        self.names.push(name);
+        self.translations.push(false);
+    }
+
+    /// Show a translation of xkcd in directory `translations/<lang>`
+    pub fn add_translation(&mut self, lang: String, client: XkcdClient) {
+        self.clients.push(client);
+        self.names.push(lang);
+        self.translations.push(true);
+    }
+
+    /// Pick up a new config file if one was asked for
+    fn check_reload(&mut self) {
+        let changed = match &mut self.reloader {
+            Some(reloader) => reloader.reload_if_requested(&mut self.clients),
+            None => false,
+        };
+
+        // Workers have their own clients, which still have the old settings
+        if changed && self.workers.is_some() {
+            if let Err(e) = self.start_workers() {
+                error!("Could not restart filesystem workers: {}", e);
+                self.workers = None;
+            }
+        }
+    }
+
+    /// Start (or replace) the workers, each with clients like these
+    ///
+    /// Replaced workers finish whatever they already have before exiting.
+    fn start_workers(&mut self) -> Result<(), String> {
+        let clients = &self.clients;
+
+        self.workers = Some(WorkerPool::new(self.fuse_threads, || {
+            clients.iter().map(XkcdClient::try_clone).collect()
+        })?);
+
+        Ok(())
+    }
+
+    /// Run `job` with a client for `source` on a worker, or right here if
+    /// there are no workers
+    fn on_worker<F>(&self, source: usize, job: F)
+    where
+        F: FnOnce(&XkcdClient) + Send + 'static,
+    {
+        match &self.workers {
+            Some(w) => w.submit(move |clients: &Vec<XkcdClient>| job(&clients[source])),
+            None => job(&self.clients[source]),
+        }
+    }
+
+    /// Everything in directory `file` from `source`, as `readdir` lists it
+    fn listing(&self, source: usize, file: &File) -> Vec<DirEntry> {
+        let client = &self.clients[source];
+        let comic_count: u64 = client.get_cached_count() as u64;
+        let titles = match *file {
+            File::ByTitle => client.cached_titles(),
+            _ => Vec::new(),
+        };
+        // History, years, days, titles and thumbnails are listed here, not by
+        // `child_by_index`
+        let listed: Vec<File> = match *file {
+            File::History => client
+                .recently_read(HISTORY_LEN)
+                .into_iter()
+                .map(File::Image)
+                .collect(),
+            File::ByYear => client
+                .cached_years()
+                .into_iter()
+                .filter_map(File::year)
+                .collect(),
+            File::Year(year) => client
+                .cached_comics_in_year(year as i32)
+                .into_iter()
+                .map(File::Image)
+                .collect(),
+            File::Day(days) => {
+                let (year, month, day) = File::day_date(days);
+
+                client
+                    .cached_comics_on(year, month, day)
+                    .into_iter()
+                    .map(File::Image)
+                    .collect()
+            }
+            File::ByTitle => titles.iter().map(|&(num, _)| File::Image(num)).collect(),
+            File::MetaFolder(num) => self
+                .thumbnail_sizes
+                .iter()
+                .map(|&width| File::Thumbnail(num, width))
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let mut entries = Vec::new();
+        // How many came from `child_by_index`, before the listed ones
+        let mut indexed = 0;
+
+        for current in 0.. {
+            let indexed_child = file.child_by_index(current, comic_count);
+
+            if indexed_child.is_some() {
+                indexed += 1;
+            }
+
+            let child = match indexed_child {
+                // Other sources' directories come after everything else at
+                // the top
+                None if source == 0 && *file == File::Root => {
+                    let index = current - File::root_len(comic_count);
+
+                    match self.source_dir(false, index as usize) {
+                        Some(dir) => dir,
+                        None => break,
+                    }
+                }
+                None if *file == File::Translations => {
+                    match self.source_dir(true, current as usize - 2) {
+                        Some(dir) => dir,
+                        None => break,
+                    }
+                }
+                // Comics are the same files as at the top, most recently read
+                // first in history and in order in years and days
+                None => match current
+                    .checked_sub(indexed)
+                    .and_then(|i| listed.get(i as usize))
+                {
+                    Some(child) => (
+                        file::source_inode(source, child.inode()),
+                        child.filetype(),
+                        child.filename(),
+                    ),
+                    None => break,
+                },
+                // A source's parent is the top of the mount, or translations
+                Some((_, filetype, filename))
+                    if source != 0 && *file == File::Root && current == 1 =>
+                {
+                    let parent = if self.translations[source] {
+                        File::Translations
+                    } else {
+                        File::Root
+                    };
+
+                    (parent.inode(), filetype, filename)
+                }
+                Some((ino, filetype, filename)) => {
+                    (file::source_inode(source, ino), filetype, filename)
+                }
+            };
+
+            let (ino, filetype, filename) = child;
+
+            if self.node(ino).1.is_some() {
+                let name = entry_name(client, file::split_inode(ino).1, filename);
+                // Named for their titles in by_title, with the extension they
+                // have at the top
+                let name = match (current as usize)
+                    .checked_sub(2)
+                    .and_then(|i| titles.get(i))
+                {
+                    Some((_, title)) => {
+                        let extension = name.rfind('.').map_or("", |i| &name[i..]);
+
+                        format!("{}{}", title, extension)
+                    }
+                    None => name,
+                };
+
+                entries.push((ino, filetype, name));
+            }
+        }
+
+        entries
+    }
+
+    /// Check for a new latest comic on a worker, then call `done`
+    ///
+    /// Whatever asked for the refresh is replied to from `done`, so it still
+    /// only returns once the refresh has finished.
+    fn refresh_latest<F>(&self, source: usize, done: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.on_worker(source, move |client| {
+            let _op = logging::operation_scope("refresh", None);
+
+            client.request_latest_comic(None, BustCache);
+            done()
+        });
+    }
+
+    /// The source and file an inode is for, if the file can be seen
+    ///
+    /// Only xkcd has the credits, montage, status and wallpaper files, and the
+    /// translations directory if there are any. Files that need rendering are
+    /// hidden if rendering is disabled, and so is anything asked to be. Comics
+    /// only have error files while fetching or rendering them is failing, and
+    /// `history` is only there while reads are noted.
+    fn node(&self, ino: u64) -> (usize, Option<File>) {
+        let (source, ino) = file::split_inode(ino);
+
+        let file = File::from_inode(ino)
+            .filter(|_| source < self.clients.len())
+            .filter(|f| self.clients[source].rendering_enabled() || !f.is_rendered())
+            .filter(|f| !self.hidden.contains(f))
+            .filter(|f| match f {
+                File::Credits | File::Montage | File::Status | File::Wallpaper => source == 0,
+                File::Translations => source == 0 && self.translations.contains(&true),
+                File::History => self.track_atime,
+                // Only there while something's failing
+                File::Error(num) => !self.clients[source].stats().comic_errors(*num).is_empty(),
+                File::Thumbnail(_, width) => self.thumbnail_sizes.contains(width),
+                _ => true,
+            });
+
+        (source, file)
+    }
+
+    /// The sources listed at the top of the mount, or in `translations`
+    fn sources(&self, translations: bool) -> impl Iterator<Item = usize> + '_ {
+        (1..self.clients.len()).filter(move |&s| self.translations[s] == translations)
+    }
+
+    /// The directory for the `index`th source at the top of the mount, or in
+    /// `translations`
+    fn source_dir(&self, translations: bool, index: usize) -> Option<(u64, FileType, String)> {
+        let source = self.sources(translations).nth(index)?;
+
+        Some((
+            file::source_inode(source, File::Root.inode()),
+            FileType::Directory,
+            self.names[source].clone(),
+        ))
+    }
+
+    /// Whether a file always has the same contents
+    ///
+    /// Published comics never change, but their rendered images do when a
+    /// reloaded config file changes how they're drawn. The latest comic is
+    /// sometimes fixed soon after it's published, too, and a refresh fetches it
+    /// again, so its files aren't kept for long either.
+    fn immutable(&self, source: usize, file: &File) -> bool {
+        match file.comic_num() {
+            None => false,
+            Some(num) if *file == File::Error(num) => false,
+            Some(num) if self.might_be_latest(source, num) => false,
+            Some(num) if file.is_rendered() || *file == File::Image(num) => self.reloader.is_none(),
+            Some(_) => true,
+        }
+    }
+
+    /// Whether comic `num` is (or is after) the latest one cached from `source`
+    fn might_be_latest(&self, source: usize, num: u32) -> bool {
+        self.clients[source]
+            .cached_latest_num()
+            .is_none_or(|latest| num >= latest)
+    }
+
+    /// Flags for opening a file, keeping it in the page cache between opens if
+    /// it's the same every time
+    fn open_flags(&self, source: usize, file: &File) -> u32 {
+        if self.immutable(source, file) {
+            FOPEN_KEEP_CACHE
+        } else {
+            0
+        }
+    }
+
+    /// What comic `num`'s `error` file says right now
+    fn comic_errors(&self, source: usize, num: u32) -> String {
+        status::comic_errors(&self.clients[source].stats().comic_errors(num))
+    }
+
+    /// Where `latest` points right now, the latest comic's image as it's
+    /// listed, and when that comic was published
+    fn latest_link(&self, source: usize) -> Option<(String, Timespec)> {
+        let client = &self.clients[source];
+        let latest = client.request_latest_comic(None, VeryFast)?;
+        let image = File::Image(latest.num);
+
+        Some((
+            entry_name(client, image.inode(), image.filename()),
+            latest.time(),
+        ))
+    }
+
+    /// Where `random` points this time, a cached comic's image as it's listed
+    fn random_link(&mut self, source: usize) -> Option<String> {
+        let client = &self.clients[source];
+        let nums = client.cached_nums();
+
+        if nums.is_empty() {
+            return None;
+        }
+
+        let num = nums[self.rng.up_to(nums.len() as u32) as usize - 1];
+        let image = File::Image(num);
+
+        Some(entry_name(client, image.inode(), image.filename()))
+    }
+
+    /// What `.status` says right now
+    fn status(&self) -> String {
+        let mut fetches = Vec::new();
+
+        for client in &self.clients {
+            match client.fetches(Duration::from_secs(24 * 60 * 60)) {
+                Ok(f) => fetches.extend(f),
+                Err(e) => warn!("Could not read the fetch log: {}", e),
+            }
+        }
+
+        status::status(&self.stats.snapshot(), &self.mount_options, &fetches)
+    }
+
+    /// How long the kernel can keep a file's attributes
+    ///
+    /// Until a comic is known, its files' times are the epoch and their sizes
+    /// are a guess, so they're checked again as soon as anything else. So are
+    /// atimes, when they're tracked.
+    fn ttl(&self, source: usize, file: &File, attr: &FileAttr) -> Timespec {
+        let known =
+            attr.mtime != EPOCH && (attr.kind == FileType::Directory || attr.size != DEFAULT_SIZE);
+
+        if known && self.immutable(source, file) && !self.track_atime {
+            COMIC_TTL
+        } else {
+            TTL
+        }
     }
 
     const fn blocks(size: u64) -> u64 {
-        (size + BLOCK_SIZE - 1) / BLOCK_SIZE
+        size.div_ceil(BLOCK_SIZE)
+    }
+
+    /// Open a file that's read from `contents` until it's released
+    fn open_snapshot(&mut self, contents: Vec<u8>, flags: u32, reply: ReplyOpen) {
+        let fh = self.gen_fh();
+        let bytes = contents.len() as u64;
+
+        match self.snapshots.insert(fh, contents, bytes, &self.budget) {
+            Ok(()) => reply.opened(fh, flags),
+            Err(e) => {
+                // The kernel never sees the handle, so it won't be released
+                self.stats.released();
+                reply.error(e)
+            }
+        }
     }
 
     fn gen_fh(&mut self) -> u64 {
         let fh = self.next_fh;
 
         self.next_fh = self.next_fh.wrapping_add(1);
+        self.stats.opened();
 
         fh
     }
 
-    fn file_attr(&self, request: &Request, file: File) -> Option<FileAttr> {
+    fn file_attr(&self, request: &Request, source: usize, file: &File) -> Option<FileAttr> {
         info!("Getting attributes for {:?}", file);
 
+        let client = &self.clients[source];
+        let ino = file::source_inode(source, file.inode());
+
         let rdev = 0;
         let flags = 0;
         let nlink = 0;
@@ -55,7 +578,7 @@ impl XkcdFs {
             let size = size.map(|s| s as u64).unwrap_or(DEFAULT_SIZE);
 
             Some(FileAttr {
-                ino: file.inode(),
+                ino,
                 size,
                 blocks: Self::blocks(size),
                 atime: time,
@@ -72,9 +595,16 @@ impl XkcdFs {
             })
         };
 
-        match file {
-            File::Root => Some(FileAttr {
-                ino: file.inode(),
+        let attr = match *file {
+            File::Root
+            | File::Translations
+            | File::History
+            | File::ByYear
+            | File::Year(_)
+            | File::ByDate
+            | File::Day(_)
+            | File::ByTitle => Some(FileAttr {
+                ino,
                 size: DIR_SIZE,
                 blocks: Self::blocks(DIR_SIZE),
                 atime: Timespec::new(0, 0),
@@ -90,7 +620,7 @@ impl XkcdFs {
                 flags,
             }),
             File::Refresh => Some(FileAttr {
-                ino: file.inode(),
+                ino,
                 size: 0,
                 blocks: 1,
                 atime: Timespec::new(0, 0),
@@ -106,11 +636,60 @@ impl XkcdFs {
                 flags,
             }),
             File::Credits => attrs(Some(CREDITS_DATA.len()), None),
+            File::Status => attrs(Some(self.status().len()), None),
+            File::Error(num) => {
+                let errors = client.stats().comic_errors(num);
+                let at = errors.last().map(|&(_, at, _)| Timespec::new(at, 0));
+
+                attrs(Some(status::comic_errors(&errors).len()), at)
+            }
+            File::MetadataCsv => attrs(csv::metadata_csv(client).ok().map(|c| c.len()), None),
+            File::Wallpaper => {
+                let latest = client.request_latest_comic(None, VeryFast);
+                let image = latest
+                    .as_ref()
+                    .and_then(|c| client.request_wallpaper(c, self.wallpaper, None, VeryFast));
+
+                attrs(image.map(|i| i.len()), latest.map(|c| c.time()))
+            }
+            // Changes as new comics are found, so it's never cached for long
+            File::LatestLink => {
+                let target = self.latest_link(source);
+
+                Some(FileAttr {
+                    perm: 0o777,
+                    ..attrs(
+                        target.as_ref().map(|(t, _)| t.len()),
+                        target.map(|(_, time)| time),
+                    )?
+                })
+            }
+            // Comics' names are all about as long, whichever it points to
+            File::RandomLink => {
+                let count = client.get_cached_count() as u32;
+
+                Some(FileAttr {
+                    perm: 0o777,
+                    ..attrs(Some(File::Image(count.max(1)).filename().len()), None)?
+                })
+            }
+            File::Montage => {
+                let image = if self.montage.is_empty() {
+                    None
+                } else {
+                    client.request_montage(&self.montage, None, VeryFast)
+                };
+
+                Some(FileAttr {
+                    perm: 0o666,
+                    ..attrs(image.map(|i| i.len()), None)?
+                })
+            }
             File::Image(num) => {
-                let comic: Option<Comic> = self.client.request_comic(num, None, VeryFast);
+                let comic: Option<Comic> = client.request_comic(num, None, VeryFast);
                 let image = comic
                     .as_ref()
-                    .and_then(|c| self.client.request_rendered_image(&c, None, VeryFast));
+                    .and_then(|c| client.request_rendered_image(c, None, VeryFast));
 
                 debug!(
                     "Rendered image has size {:?}",
@@ -120,12 +699,12 @@ impl XkcdFs {
                 attrs(image.map(|i| i.len()), comic.map(|c| c.time()))
             }
             File::MetaFolder(num) => {
-                let comic: Option<Comic> = self.client.request_comic(num, None, VeryFast);
+                let comic: Option<Comic> = client.request_comic(num, None, VeryFast);
 
                 let time = comic.map(|c| c.time()).unwrap_or(EPOCH);
 
                 Some(FileAttr {
-                    ino: file.inode(),
+                    ino,
                     size: DIR_SIZE,
                     blocks: Self::blocks(DIR_SIZE),
                     atime: time,
@@ -142,12 +721,12 @@ impl XkcdFs {
                 })
             }
             File::AltText(num) => {
-                let comic = self.client.request_comic(num, None, VeryFast);
+                let comic = client.request_comic(num, None, VeryFast);
 
                 attrs(comic.as_ref().map(|c| c.alt.len()), comic.map(|c| c.time()))
             }
             File::Title(num) => {
-                let comic = self.client.request_comic(num, None, VeryFast);
+                let comic = client.request_comic(num, None, VeryFast);
 
                 attrs(
                     comic.as_ref().map(|c| c.title.len()),
@@ -155,17 +734,18 @@ impl XkcdFs {
                 )
             }
             File::Transcript(num) => {
-                let comic = self.client.request_comic(num, None, VeryFast);
+                let comic = client.request_comic(num, None, VeryFast);
 
                 attrs(
                     comic
                         .as_ref()
-                        .and_then(|c| c.transcript.as_ref().map(|t| t.len())),
+                        .and_then(|c| c.transcript_text(self.raw_transcripts))
+                        .map(|t| t.len()),
                     comic.map(|c| c.time()),
                 )
             }
             File::Date(num) => {
-                let comic = self.client.request_comic(num, None, VeryFast);
+                let comic = client.request_comic(num, None, VeryFast);
 
                 attrs(
                     comic.as_ref().map(|c| c.isodate().len()),
@@ -173,31 +753,458 @@ impl XkcdFs {
                 )
             }
             File::RawImage(num) => {
-                let comic: Option<Comic> = self.client.request_comic(num, None, VeryFast);
+                let comic: Option<Comic> = client.request_comic(num, None, VeryFast);
                 let raw_image = comic
                     .as_ref()
-                    .and_then(|c| self.client.request_raw_image(&c, None, VeryFast));
+                    .and_then(|c| client.request_raw_image(c, None, VeryFast));
 
                 attrs(raw_image.map(|i| i.len()), comic.map(|c| c.time()))
             }
+            File::Thumbnail(num, width) => {
+                let spec = ThumbnailSpec {
+                    width,
+                    filter: self.thumbnail_filter,
+                };
+                let comic: Option<Comic> = client.request_comic(num, None, VeryFast);
+                let thumbnail = comic
+                    .as_ref()
+                    .and_then(|c| client.request_thumbnail(c, spec, None, VeryFast));
+
+                attrs(thumbnail.map(|i| i.len()), comic.map(|c| c.time()))
+            }
+            File::Pdf(num) => {
+                let comic: Option<Comic> = client.request_comic(num, None, VeryFast);
+                let pdf = comic.as_ref().and_then(|c| {
+                    client.request_vector_image(c, VectorFormat::Pdf, None, VeryFast)
+                });
+
+                attrs(pdf.map(|i| i.len()), comic.map(|c| c.time()))
+            }
+            File::Svg(num) => {
+                let comic: Option<Comic> = client.request_comic(num, None, VeryFast);
+                let svg = comic.as_ref().and_then(|c| {
+                    client.request_vector_image(c, VectorFormat::Svg, None, VeryFast)
+                });
+
+                attrs(svg.map(|i| i.len()), comic.map(|c| c.time()))
+            }
+            File::Ascii(num) => {
+                let comic: Option<Comic> = client.request_comic(num, None, VeryFast);
+                let art = comic
+                    .as_ref()
+                    .and_then(|c| client.request_ascii_art(c, self.ascii_width, None, VeryFast));
+
+                attrs(art.map(|a| a.len()), comic.map(|c| c.time()))
+            }
+            File::Described(num) => {
+                let comic = client.request_comic(num, None, VeryFast);
+
+                attrs(
+                    comic
+                        .as_ref()
+                        .map(|c| c.described(self.raw_transcripts).len()),
+                    comic.map(|c| c.time()),
+                )
+            }
+            File::ExtraParts(num) => {
+                let comic = client.request_comic(num, None, VeryFast);
+
+                attrs(
+                    comic
+                        .as_ref()
+                        .map(|c| c.extra_parts.as_ref().map_or(0, String::len)),
+                    comic.map(|c| c.time()),
+                )
+            }
+        };
+
+        match (self.track_atime, file.comic_num()) {
+            (true, Some(num)) => attr.map(|attr| FileAttr {
+                atime: client.accessed(num).unwrap_or(attr.atime),
+                ..attr
+            }),
+            _ => attr,
         }
     }
+
+    /// Fetch the comics either side of `num` in the background, unless the
+    /// network is down
+    fn fetch_neighbors(&mut self, source: usize, num: u32) {
+        let client = &self.clients[source];
+
+        if client.stats().offline() {
+            return;
+        }
+
+        let latest = match client.request_latest_comic(None, VeryFast) {
+            Some(c) => c.num,
+            None => return,
+        };
+
+        let now = Instant::now();
+
+        for neighbor in self.neighbors.around(source, num, latest, now) {
+            self.on_worker(source, move |client| {
+                let _op = logging::operation_scope("prefetch", Some(neighbor));
+
+                if prefetch::fetch(client, neighbor).is_none() {
+                    debug!("Could not fetch comic {} next to {}", neighbor, num);
+                }
+            });
+        }
+    }
+
+    /// Cache any renders that have finished in the background
+    fn collect_renders(&self) {
+        let pool = match &self.render_pool {
+            Some(p) => p,
+            None => return,
+        };
+
+        for finished in pool.finished() {
+            self.clients[finished.source].store_rendered_image(
+                finished.num,
+                &finished.render_key,
+                &finished.image,
+            );
+
+            // Cached, so the budget can have it back
+            drop(finished.memory);
+        }
+    }
+
+    /// Cache finished renders, and get everything cached into the databases
+    /// themselves, so they can be copied while mounted
+    ///
+    /// Renders still running aren't waited for.
+    fn sync_caches(&self) -> Result<(), c_int> {
+        self.collect_renders();
+
+        let mut result = Ok(());
+
+        for client in &self.clients {
+            if let Err(e) = client.sync() {
+                error!("Could not sync the cache: {}", e);
+                result = Err(EIO);
+            }
+        }
+
+        result
+    }
+
+    /// Get comic `num`'s rendered image to `waiter`, fetching on a worker
+    /// and rendering in the background if it isn't cached yet
+    ///
+    /// It's rendered with `options`, or the client's own without them.
+    fn rendered_image_for(
+        &self,
+        operation: &'static str,
+        source: usize,
+        num: u32,
+        options: Option<RenderOptions>,
+        waiter: Waiter,
+    ) {
+        self.collect_renders();
+
+        let render_pool = self.render_pool.clone();
+
+        self.on_worker(source, move |client| {
+            let _op = logging::operation_scope(operation, Some(num));
+            let pool = render_pool.as_ref().map(|p| (p.as_ref(), source));
+
+            rendered_image(client, pool, num, options, waiter)
+        });
+    }
+}
+
+/// Get the name a directory entry should be listed under
+///
+/// This is normally just the file's name, but comics that are passed through
+/// unrendered keep the extension of the original image.
+pub fn entry_name(client: &XkcdClient, ino: u64, filename: String) -> String {
+    if client.rendering_enabled() && !client.keep_animations() {
+        return filename;
+    }
+
+    match File::from_inode(ino) {
+        Some(File::Image(num)) => match client.request_comic(num, None, VeryFast) {
+            Some(ref c) if !client.rendering_enabled() => {
+                File::image_filename(num, c.image_extension())
+            }
+            Some(ref c) if client.is_animated(c, VeryFast) => File::image_filename(num, "gif"),
+            _ => filename,
+        },
+        _ => filename,
+    }
 }
 
-impl<'q> Filesystem for XkcdFs {
+/// Whether `name` has the extension `file` is listed with
+///
+/// Images are looked up by number whatever their extension, but each is only
+/// listed with one: `.gif` for animations passed through, the original's with
+/// rendering off, and `.png` otherwise. Other names are left to the lookup.
+pub fn listed_with(client: &XkcdClient, file: &File, name: &str) -> bool {
+    match file {
+        File::Image(_) => {
+            let listed = entry_name(client, file.inode(), file.filename());
+            let extension = listed.rfind('.').map_or("", |i| &listed[i..]);
+
+            name.ends_with(extension)
+        }
+        _ => true,
+    }
+}
+
+/// The part of `rendered_image_for` that runs on a worker
+///
+/// Renders go to `pool`, if there is one, tagged with the comic's source.
+fn rendered_image(
+    client: &XkcdClient,
+    pool: Option<(&RenderPool, usize)>,
+    num: u32,
+    options: Option<RenderOptions>,
+    waiter: Waiter,
+) {
+    let comic = match client.request_comic(num, None, Normal) {
+        Some(c) => c,
+        None => return waiter.reply(None),
+    };
+    let options = options.unwrap_or_else(|| client.render_options().clone());
+
+    // Reads of a cached image only need their part of it
+    if let Some((offset, len)) = waiter.range() {
+        if let Some(part) = client.cached_image_range_with(&comic, &options, offset, len) {
+            return waiter.reply_part(&part);
+        }
+    }
+
+    // Cached renders and animations that skip rendering don't need a render thread
+    if let Some(image) = client.request_rendered_image_with(&comic, &options, None, NoRender) {
+        return waiter.reply(Some(&image));
+    }
+
+    let (pool, source) = match pool {
+        Some(p) => p,
+        None => {
+            let image = client.request_rendered_image_with(&comic, &options, None, Normal);
+            return waiter.reply(image.as_deref());
+        }
+    };
+
+    let raw_image = match client.request_raw_image(&comic, None, Normal) {
+        Some(i) => i,
+        None => return waiter.reply(None),
+    };
+
+    let memory = budget::render_estimate(&raw_image);
+    let stats = client.stats();
+
+    pool.submit(
+        source,
+        num,
+        options.cache_key(),
+        memory,
+        waiter,
+        move || {
+            let image =
+                crate::image::render(&comic, &mut std::io::Cursor::new(&raw_image), &options);
+
+            match image {
+                Ok(_) => stats.comic_succeeded(comic.num, "render"),
+                Err(ref e) => stats.comic_failed(comic.num, "render", e),
+            }
+
+            image
+        },
+    );
+}
+
+/// The contents of one of a comic's files, other than its rendered image, or
+/// of `metadata.csv`
+pub fn file_contents(
+    client: &XkcdClient,
+    file: &File,
+    thumbnail_filter: ScaleFilter,
+    ascii_width: u32,
+    raw_transcripts: bool,
+) -> Option<Vec<u8>> {
+    if *file == File::MetadataCsv {
+        return csv::metadata_csv(client)
+            .map_err(|e| warn!("Could not list cached comics: {}", e))
+            .ok();
+    }
+
+    let comic = client.request_comic(file.comic_num()?, None, Normal)?;
+
+    match file {
+        File::AltText(_) => Some(comic.alt.into_bytes()),
+        File::Title(_) => Some(comic.title.into_bytes()),
+        File::Transcript(_) => comic
+            .transcript_text(raw_transcripts)
+            .map(String::into_bytes),
+        File::Date(_) => Some(comic.isodate().into_bytes()),
+        File::Described(_) => Some(comic.described(raw_transcripts).into_bytes()),
+        // Empty for the comics without any
+        File::ExtraParts(_) => Some(comic.extra_parts.unwrap_or_default().into_bytes()),
+        File::RawImage(_) => client.request_raw_image(&comic, None, Normal),
+        File::Thumbnail(_, width) => {
+            let spec = ThumbnailSpec {
+                width: *width,
+                filter: thumbnail_filter,
+            };
+
+            client.request_thumbnail(&comic, spec, None, Normal)
+        }
+        File::Pdf(_) => client.request_vector_image(&comic, VectorFormat::Pdf, None, Normal),
+        File::Svg(_) => client.request_vector_image(&comic, VectorFormat::Svg, None, Normal),
+        File::Ascii(_) => client
+            .request_ascii_art(&comic, ascii_width, None, Normal)
+            .map(String::into_bytes),
+        _ => None,
+    }
+}
+
+/// The part of comic `num`'s original image that a read wants, if it's cached
+fn cached_raw_part(client: &XkcdClient, num: u32, offset: i64, size: u32) -> Option<Vec<u8>> {
+    let offset = offset.try_into().ok()?;
+    let comic = client.request_comic(num, None, VeryFast)?;
+
+    client.cached_raw_image_range(&comic, offset, size as usize)
+}
+
+/// The latest comic as a wallpaper
+fn wallpaper(client: &XkcdClient, spec: WallpaperSpec) -> Option<Vec<u8>> {
+    let latest = client.request_latest_comic(None, Normal)?;
+
+    client.request_wallpaper(&latest, spec, None, Normal)
+}
+
+/// Why `file` can't be opened with `flags`, if it can't
+///
+/// Directories are turned away on their own, since they're opened with
+/// `opendir`.
+fn open_error(file: &File, flags: u32) -> Option<c_int> {
+    let flags = flags as c_int;
+
+    if flags & O_DIRECTORY != 0 {
+        Some(ENOTDIR)
+    } else if flags & O_ACCMODE != O_RDONLY && !file.is_writable() {
+        Some(EACCES)
+    } else {
+        None
+    }
+}
+
+/// The part of `len` bytes that a read of `size` bytes at `offset` gets
+///
+/// Reads that start at or past the end get nothing, which is how the end of a
+/// file is found. Only negative offsets are an error.
+fn read_range(len: usize, offset: i64, size: u32) -> Result<Range<usize>, c_int> {
+    let start: usize = match offset.try_into() {
+        Ok(o) => o,
+        Err(_) if offset > 0 => len,
+        Err(_) => return Err(EINVAL),
+    };
+
+    let start = start.min(len);
+    let end = start.saturating_add(size as usize).min(len);
+
+    Ok(start..end)
+}
+
+/// Reply with the part of `bytes` that a read asked for
+fn reply_data(reply: ReplyData, bytes: Result<&[u8], i32>, offset: i64, size: u32) {
+    match bytes.and_then(|b| Ok(&b[read_range(b.len(), offset, size)?])) {
+        Ok(b) => reply.data(b),
+        Err(code) => {
+            logging::set_outcome("error");
+            reply.error(code);
+        }
+    }
+}
+
+/// Reply with an extended attribute's value, or only its size if that's what
+/// was asked for
+fn reply_xattr(reply: ReplyXattr, value: &[u8], size: u32) {
+    if size == 0 {
+        reply.size(value.len() as u32)
+    } else if (size as usize) < value.len() {
+        reply.error(ERANGE)
+    } else {
+        reply.data(value)
+    }
+}
+
+/// The type of a comic's image or original image, if the cache knows it
+fn mime_type(client: &XkcdClient, file: &File) -> Option<&'static str> {
+    let comic = match file {
+        File::Image(num) | File::RawImage(num) => client.request_comic(*num, None, VeryFast)?,
+        _ => return None,
+    };
+
+    // Rendered images are always PNGs
+    let rendered = *file == File::Image(comic.num)
+        && client.rendering_enabled()
+        && !(client.keep_animations() && client.is_animated(&comic, VeryFast));
+
+    if rendered {
+        Some(ImageFormat::Png.content_type())
+    } else {
+        client
+            .image_format(&comic, VeryFast)
+            .map(ImageFormat::content_type)
+    }
+}
+
+// The fuse crate (as of 0.3) has no ioctl callback, so the kernel answers
+// every ioctl on the mount with ENOSYS. Scripts drive the daemon through
+// `--control-socket` instead (see `control`).
+impl Filesystem for XkcdFs {
+    fn init(&mut self, _req: &Request) -> Result<(), c_int> {
+        info!("Starting {} render threads", self.render_threads);
+
+        if let Some(max) = self.budget.max() {
+            info!("Keeping renders and open files under {} bytes", max);
+        }
+
+        self.render_pool = Some(Arc::new(RenderPool::new(
+            self.render_threads,
+            Arc::clone(&self.stats),
+            self.budget.clone(),
+        )));
+
+        info!("Starting {} filesystem threads", self.fuse_threads);
+
+        self.start_workers().map_err(|e| {
+            error!("Could not start filesystem threads: {}", e);
+            EIO
+        })
+    }
+
     fn getattr(&mut self, req: &Request, ino: u64, reply: ReplyAttr) {
-        let file = File::from_inode(ino);
+        let _busy = self.watchdog.busy();
+        self.stats.served();
+        self.check_reload();
+        self.collect_renders();
+
+        let (source, file) = self.node(ino);
+        let _op = logging::operation_scope("getattr", file.as_ref().and_then(File::comic_num));
 
         match &file {
             Some(f) => info!("getattr for {:?}", f),
             None => warn!("getattr for invalid inode {:x}", ino),
         }
 
-        let attr = file.and_then(|f| self.file_attr(req, f));
+        let attr = file
+            .as_ref()
+            .and_then(|f| Some((self.file_attr(req, source, f)?, f)));
 
         match attr {
-            None => reply.error(ENOENT),
-            Some(attr) => reply.attr(&TTL, &attr),
+            None => {
+                logging::set_outcome("missing");
+                reply.error(ENOENT)
+            }
+            Some((attr, f)) => reply.attr(&self.ttl(source, f, &attr), &attr),
         }
     }
 
@@ -205,11 +1212,16 @@ impl<'q> Filesystem for XkcdFs {
         &mut self,
         _req: &Request,
         ino: u64,
-        _fh: u64,
+        fh: u64,
         offset: i64,
         mut reply: fuse::ReplyDirectory,
     ) {
-        let file = File::from_inode(ino);
+        let _busy = self.watchdog.busy();
+        self.stats.served();
+        self.check_reload();
+
+        let (source, file) = self.node(ino);
+        let _op = logging::operation_scope("readdir", file.as_ref().and_then(File::comic_num));
 
         match &file {
             Some(f) => info!("readdir for {:?} at offset {}", f, offset),
@@ -219,14 +1231,34 @@ impl<'q> Filesystem for XkcdFs {
         let file = match file {
             Some(f @ File::Root) => f,
             Some(f @ File::MetaFolder(_)) => f,
+            Some(f @ File::Translations) => f,
+            Some(f @ File::History) => f,
+            Some(f @ File::ByYear) => f,
+            Some(f @ File::Year(_)) => f,
+            Some(f @ File::ByDate) => f,
+            Some(f @ File::Day(_)) => f,
+            Some(f @ File::ByTitle) => f,
             Some(File::Refresh)
             | Some(File::Credits)
+            | Some(File::Montage)
+            | Some(File::MetadataCsv)
+            | Some(File::Status)
+            | Some(File::Wallpaper)
+            | Some(File::LatestLink)
+            | Some(File::RandomLink)
             | Some(File::Image(_))
             | Some(File::AltText(_))
             | Some(File::Title(_))
             | Some(File::Transcript(_))
             | Some(File::Date(_))
-            | Some(File::RawImage(_)) => {
+            | Some(File::RawImage(_))
+            | Some(File::Thumbnail(_, _))
+            | Some(File::Pdf(_))
+            | Some(File::Svg(_))
+            | Some(File::Ascii(_))
+            | Some(File::Described(_))
+            | Some(File::Error(_))
+            | Some(File::ExtraParts(_)) => {
                 reply.error(ENOTDIR);
                 return;
             }
@@ -236,31 +1268,78 @@ impl<'q> Filesystem for XkcdFs {
             }
         };
 
-        let mut current: u64 = offset as u64;
-        let comic_count: u64 = self.client.get_cached_count() as u64;
-
-        loop {
-            let child = file.child_by_index(current, comic_count);
+        let start: usize = match offset.try_into() {
+            Ok(o) => o,
+            Err(_) => return reply.error(EINVAL),
+        };
 
-            let done = match child {
-                None => break,
-                Some((ino, filetype, filename)) => {
-                    reply.add(ino, (current + 1) as i64, filetype, filename)
-                }
-            };
+        // Handles from opendir keep the listing they started with, so a
+        // refresh partway through doesn't move entries between pages
+        let entries = match self.listings.get(fh) {
+            Some(entries) => entries,
+            None => Arc::new(self.listing(source, &file)),
+        };
 
-            if done {
+        for (index, (ino, filetype, name)) in entries.iter().enumerate().skip(start) {
+            if reply.add(*ino, (index + 1) as i64, *filetype, name) {
                 break;
             }
+        }
+
+        reply.ok();
+    }
 
-            current += 1;
+    fn opendir(&mut self, _req: &Request, ino: u64, _flags: u32, reply: ReplyOpen) {
+        let _busy = self.watchdog.busy();
+        self.stats.served();
+        self.check_reload();
+
+        let (source, file) = self.node(ino);
+        let _op = logging::operation_scope("opendir", file.as_ref().and_then(File::comic_num));
+
+        match &file {
+            Some(f) => info!("opendir for {:?}", f),
+            None => warn!("opendir for invalid inode {:x}", ino),
         }
 
+        let file = match file {
+            Some(f) if f.filetype() == FileType::Directory => f,
+            Some(_) => return reply.error(ENOTDIR),
+            None => return reply.error(ENOENT),
+        };
+
+        let fh = self.gen_fh();
+        let listing = self.listing(source, &file);
+        let bytes = listing
+            .iter()
+            .map(|(_, _, name)| (mem::size_of::<DirEntry>() + name.len()) as u64)
+            .sum();
+
+        match self.listings.insert(fh, listing, bytes, &self.budget) {
+            Ok(()) => reply.opened(fh, 0),
+            Err(e) => {
+                // The kernel never sees the handle, so it won't be released
+                self.stats.released();
+                reply.error(e)
+            }
+        }
+    }
+
+    fn releasedir(&mut self, _req: &Request, ino: u64, fh: u64, _flags: u32, reply: ReplyEmpty) {
+        trace!("releasedir for inode {:x} (handle {})", ino, fh);
+
+        self.listings.remove(fh);
+        self.stats.released();
         reply.ok();
     }
 
     fn lookup(&mut self, req: &Request, parent_ino: u64, name: &OsStr, reply: ReplyEntry) {
-        let parent = File::from_inode(parent_ino);
+        let _busy = self.watchdog.busy();
+        self.stats.served();
+        self.check_reload();
+
+        let (source, parent) = self.node(parent_ino);
+        let _op = logging::operation_scope("lookup", parent.as_ref().and_then(File::comic_num));
 
         match &parent {
             Some(p) => info!("lookup for {:?} with parent {:?}", name, p),
@@ -270,12 +1349,85 @@ impl<'q> Filesystem for XkcdFs {
             ),
         }
 
-        let attr = parent
-            .and_then(|p| File::from_filename(&p, name))
-            .and_then(|f| self.file_attr(req, f));
+        // Other sources' directories are at the top or in translations, and
+        // everything else is in the same source as its parent
+        let in_translations = match parent {
+            Some(File::Root) if source == 0 => Some(false),
+            Some(File::Translations) => Some(true),
+            _ => None,
+        };
+        let source_dir = in_translations
+            .and_then(|t| {
+                self.sources(t)
+                    .find(|&s| OsStr::new(&self.names[s]) == name)
+            })
+            .map(|s| file::source_inode(s, File::Root.inode()));
+        let ino = source_dir.or_else(|| {
+            let file = parent.and_then(|p| match p {
+                // Comics that have been read, under the names they have at
+                // the top
+                File::History => File::from_filename(&File::Root, name).filter(|f| match f {
+                    File::Image(num) => self.clients[source]
+                        .recently_read(HISTORY_LEN)
+                        .contains(num),
+                    _ => false,
+                }),
+                // Only years, days and comics that are cached
+                File::ByYear => File::from_filename(&p, name).filter(|f| match f {
+                    File::Year(year) => self.clients[source]
+                        .cached_years()
+                        .contains(&(*year as i32)),
+                    _ => false,
+                }),
+                File::Year(year) => File::from_filename(&File::Root, name).filter(|f| match f {
+                    File::Image(num) => self.clients[source]
+                        .cached_comics_in_year(year as i32)
+                        .contains(num),
+                    _ => false,
+                }),
+                File::ByDate => File::from_filename(&p, name).filter(|f| match f {
+                    File::Day(days) => {
+                        let (year, month, day) = File::day_date(*days);
+
+                        !self.clients[source]
+                            .cached_comics_on(year, month, day)
+                            .is_empty()
+                    }
+                    _ => false,
+                }),
+                File::Day(days) => File::from_filename(&File::Root, name).filter(|f| match f {
+                    File::Image(num) => {
+                        let (year, month, day) = File::day_date(days);
+
+                        self.clients[source]
+                            .cached_comics_on(year, month, day)
+                            .contains(num)
+                    }
+                    _ => false,
+                }),
+                // Comics under their titles, which the cache looks back up
+                File::ByTitle => name
+                    .to_str()
+                    .and_then(|name| IMAGE_EXTENSIONS.iter().find_map(|e| name.strip_suffix(e)))
+                    .and_then(|title| self.clients[source].cached_comic_titled(title))
+                    .map(File::Image),
+                p => File::from_filename(&p, name),
+            });
+            let file = file.filter(|f| {
+                name.to_str()
+                    .is_some_and(|n| listed_with(&self.clients[source], f, n))
+            })?;
+
+            Some(file::source_inode(source, file.inode()))
+        });
+
+        let attr = ino.and_then(|ino| match self.node(ino) {
+            (source, Some(f)) => Some((self.file_attr(req, source, &f)?, source, f)),
+            (_, None) => None,
+        });
 
         match attr {
-            Some(a) => reply.entry(&TTL, &a, GEN),
+            Some((a, source, f)) => reply.entry(&self.ttl(source, &f, &a), &a, GEN),
             None => reply.error(ENOENT),
         }
     }
@@ -284,12 +1436,27 @@ impl<'q> Filesystem for XkcdFs {
         &mut self,
         _req: &Request,
         ino: u64,
-        _fh: u64,
+        fh: u64,
         offset: i64,
         size: u32,
         reply: ReplyData,
     ) {
-        let file = File::from_inode(ino);
+        let _busy = self.watchdog.busy();
+        self.stats.served();
+        self.check_reload();
+
+        let (source, file) = self.node(ino);
+        // Comic images are read as they were opened, whatever's changed since
+        let pinned = self.images.get(fh);
+        let file = match pinned.as_deref() {
+            Some(PinnedImage {
+                num,
+                options: Some(_),
+            }) => Some(File::Image(*num)),
+            Some(PinnedImage { num, options: None }) => Some(File::RawImage(*num)),
+            None => file,
+        };
+        let _op = logging::operation_scope("read", file.as_ref().and_then(File::comic_num));
 
         match &file {
             Some(f) => info!("read for {:?} at {} size {}", f, offset, size),
@@ -299,84 +1466,144 @@ impl<'q> Filesystem for XkcdFs {
             ),
         }
 
-        // Utility function that handles some of the edge cases related to
-        // converting a slice into a response
-        let reply_from_slice = |bytes: Result<&[u8], i32>| {
-            let bytes = match bytes {
-                Ok(b) => b,
-                Err(code) => {
-                    reply.error(code);
-                    return;
-                }
-            };
-
-            let offset_usize: usize = offset.try_into().unwrap();
-
-            let range_end = std::cmp::min(offset_usize + size as usize, bytes.len());
+        // Reads start at the beginning, so that's when a comic counts as read
+        if let (true, 0, Some(num)) = (
+            self.track_atime,
+            offset,
+            file.as_ref().and_then(File::comic_num),
+        ) {
+            self.on_worker(source, move |client| client.note_access(num));
+        }
 
-            if offset >= bytes.len() as i64 {
-                // Start of request is beyond the end of the range
-                reply.error(EINVAL);
-            } else if range_end <= offset_usize {
-                // Range ends before it begins
-                reply.error(EINVAL);
-            } else {
-                reply.data(&bytes[offset_usize..range_end]);
+        if offset == 0 {
+            match file {
+                Some(File::Image(num)) | Some(File::RawImage(num)) => {
+                    self.fetch_neighbors(source, num)
+                }
+                _ => {}
             }
-        };
+        }
+
+        // Handles opened with a snapshot only ever read from it
+        if let Some(contents) = self.snapshots.get(fh) {
+            return reply_data(reply, Ok(contents.as_slice()), offset, size);
+        }
 
+        // Comics might have to be fetched, and images rendered, so they're
+        // replied to from elsewhere and don't hold up other requests
         match file {
             Some(File::Image(num)) => {
                 debug!("Requesting image file for comic {}", num);
 
-                let comic = self.client.request_comic(num, None, Normal);
-                let image =
-                    comic.and_then(|c| self.client.request_rendered_image(&c, None, Normal));
+                let waiter = Waiter::Read {
+                    reply,
+                    offset,
+                    size,
+                };
 
-                reply_from_slice(image.as_ref().map(Vec::as_slice).ok_or(EREMOTEIO))
+                let options = pinned.and_then(|p| p.options.clone());
+
+                return self.rendered_image_for("read", source, num, options, waiter);
             }
-            Some(File::AltText(num)) => {
-                debug!("Requesting comic for alt text {}", num);
+            // Any of the selection might still have to be fetched
+            Some(File::Montage) if !self.montage.is_empty() => {
+                let montage = self.montage.clone();
 
-                let comic = self.client.request_comic(num, None, Normal);
-                let string = comic.map(|c| c.alt);
-                let bytes = string.as_ref().map(String::as_bytes);
+                return self.on_worker(0, move |client| {
+                    let _op = logging::operation_scope("read", None);
+                    let image = client.request_montage(&montage, None, Normal);
 
-                reply_from_slice(bytes.ok_or(EREMOTEIO))
-            }
-            Some(File::Credits) => reply_from_slice(Ok(CREDITS_DATA.as_bytes())),
-            Some(File::Refresh) => {
-                debug!("Refreshing latest comic");
-                reply_from_slice(Ok(&[]))
+                    reply_data(reply, image.as_deref().ok_or(EREMOTEIO), offset, size)
+                });
             }
-            Some(File::Title(num)) => {
-                let comic = self.client.request_comic(num, None, Normal);
-                let string = comic.map(|c| c.title);
-                let bytes = string.as_ref().map(String::as_bytes);
+            // Always the latest comic, so it changes once a new one is found
+            Some(File::Wallpaper) => {
+                let spec = self.wallpaper;
 
-                reply_from_slice(bytes.ok_or(EREMOTEIO))
-            }
-            Some(File::Transcript(num)) => {
-                let comic = self.client.request_comic(num, None, Normal);
-                let string = comic.and_then(|c| c.transcript);
-                let bytes = string.as_ref().map(String::as_bytes);
+                return self.on_worker(source, move |client| {
+                    let _op = logging::operation_scope("read", None);
+                    let image = wallpaper(client, spec);
 
-                reply_from_slice(bytes.ok_or(EREMOTEIO))
+                    reply_data(reply, image.as_deref().ok_or(EREMOTEIO), offset, size)
+                });
             }
-            Some(File::Date(num)) => {
-                let comic = self.client.request_comic(num, None, Normal);
-                let string = comic.map(|c| c.isodate());
-                let bytes = string.as_ref().map(String::as_bytes);
-
-                reply_from_slice(bytes.ok_or(EREMOTEIO))
+            Some(f @ File::AltText(_))
+            | Some(f @ File::Title(_))
+            | Some(f @ File::Transcript(_))
+            | Some(f @ File::Date(_))
+            | Some(f @ File::RawImage(_))
+            | Some(f @ File::Thumbnail(_, _))
+            | Some(f @ File::Pdf(_))
+            | Some(f @ File::Svg(_))
+            | Some(f @ File::Ascii(_))
+            | Some(f @ File::Described(_))
+            | Some(f @ File::ExtraParts(_))
+            | Some(f @ File::MetadataCsv) => {
+                let (thumbnail_filter, ascii_width) = (self.thumbnail_filter, self.ascii_width);
+                let raw_transcripts = self.raw_transcripts;
+
+                return self.on_worker(source, move |client| {
+                    let _op = logging::operation_scope("read", f.comic_num());
+
+                    if let File::RawImage(num) = f {
+                        if let Some(part) = cached_raw_part(client, num, offset, size) {
+                            return reply.data(&part);
+                        }
+                    }
+
+                    let contents =
+                        file_contents(client, &f, thumbnail_filter, ascii_width, raw_transcripts);
+
+                    reply_data(reply, contents.as_deref().ok_or(EREMOTEIO), offset, size)
+                });
             }
-            Some(File::RawImage(num)) => {
-                let comic = self.client.request_comic(num, None, Normal);
-                let raw_image = comic.and_then(|c| self.client.request_raw_image(&c, None, Normal));
+            _ => {}
+        }
+
+        let reply_from_slice = |bytes: Result<&[u8], i32>| reply_data(reply, bytes, offset, size);
 
-                reply_from_slice(raw_image.as_ref().map(Vec::as_slice).ok_or(EREMOTEIO));
+        match file {
+            Some(File::Image(_))
+            | Some(File::AltText(_))
+            | Some(File::Title(_))
+            | Some(File::Transcript(_))
+            | Some(File::Date(_))
+            | Some(File::RawImage(_))
+            | Some(File::Thumbnail(_, _))
+            | Some(File::Pdf(_))
+            | Some(File::Svg(_))
+            | Some(File::Ascii(_))
+            | Some(File::Described(_))
+            | Some(File::ExtraParts(_))
+            | Some(File::MetadataCsv)
+            | Some(File::Wallpaper) => {
+                unreachable!("Comic, metadata.csv and wallpaper reads are handled above")
+            }
+            Some(File::Credits) => reply_from_slice(Ok(CREDITS_DATA.as_bytes())),
+            Some(File::Status) => reply_from_slice(Ok(self.status().as_bytes())),
+            Some(File::Error(num)) => {
+                reply_from_slice(Ok(self.comic_errors(source, num).as_bytes()))
+            }
+            // Montages with comics in them are read above
+            Some(File::Montage) => {
+                debug!("No comics written to the montage file yet");
+                reply_from_slice(Err(ENODATA))
             }
-            Some(f @ File::Root) | Some(f @ File::MetaFolder(_)) => {
+            Some(File::Refresh) => {
+                debug!("Refreshing latest comic");
+                reply_from_slice(Ok(&[]))
+            }
+            // Read with readlink instead
+            Some(File::LatestLink) | Some(File::RandomLink) => reply_from_slice(Err(EINVAL)),
+            Some(f @ File::Root)
+            | Some(f @ File::MetaFolder(_))
+            | Some(f @ File::Translations)
+            | Some(f @ File::History)
+            | Some(f @ File::ByYear)
+            | Some(f @ File::Year(_))
+            | Some(f @ File::ByDate)
+            | Some(f @ File::Day(_))
+            | Some(f @ File::ByTitle) => {
                 warn!("{:?} is a directory, returning EISDIR", f);
 
                 reply_from_slice(Err(EISDIR))
@@ -388,42 +1615,294 @@ impl<'q> Filesystem for XkcdFs {
         };
     }
 
-    fn open(&mut self, _req: &Request, ino: u64, _flags: u32, reply: ReplyOpen) {
+    fn readlink(&mut self, _req: &Request, ino: u64, reply: ReplyData) {
+        let _busy = self.watchdog.busy();
+        self.stats.served();
+        self.check_reload();
+
+        let (source, file) = self.node(ino);
+        let _op = logging::operation_scope("readlink", None);
+
+        match &file {
+            Some(f) => info!("readlink for {:?}", f),
+            None => warn!("readlink for invalid inode {:x}", ino),
+        }
+
+        match file {
+            Some(File::LatestLink) => match self.latest_link(source) {
+                Some((target, _)) => reply.data(target.as_bytes()),
+                None => {
+                    warn!("The latest comic isn't known yet");
+                    logging::set_outcome("error");
+                    reply.error(ENOENT)
+                }
+            },
+            Some(File::RandomLink) => match self.random_link(source) {
+                Some(target) => reply.data(target.as_bytes()),
+                None => {
+                    warn!("No comics are cached to pick from");
+                    logging::set_outcome("error");
+                    reply.error(ENOENT)
+                }
+            },
+            Some(_) => reply.error(EINVAL),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn open(&mut self, _req: &Request, ino: u64, flags: u32, reply: ReplyOpen) {
         use File::*;
         const DEFAULT_FLAGS: u32 = 0;
 
-        let file = File::from_inode(ino);
+        let _busy = self.watchdog.busy();
+        self.stats.served();
+        self.check_reload();
+
+        let (source, file) = self.node(ino);
+        let _op = logging::operation_scope("open", file.as_ref().and_then(File::comic_num));
 
         match &file {
             Some(f) => info!("open for {:?}", f),
             None => warn!("open for invalid inode {:x}", ino),
         }
 
+        let error = file
+            .as_ref()
+            .filter(|f| f.filetype() != FileType::Directory)
+            .and_then(|f| open_error(f, flags));
+
+        if let Some(error) = error {
+            logging::set_outcome("error");
+            return reply.error(error);
+        }
+
         match file {
-            Some(Root) | Some(MetaFolder(_)) => reply.error(EISDIR),
+            Some(Root) | Some(MetaFolder(_)) | Some(Translations) | Some(History)
+            | Some(ByYear) | Some(Year(_)) | Some(ByDate) | Some(Day(_)) | Some(ByTitle) => {
+                reply.error(EISDIR)
+            }
+            // The kernel follows it instead, unless asked not to
+            Some(LatestLink) | Some(RandomLink) => reply.error(ELOOP),
+            // Truncating it, like `> refresh` does, counts as a write
+            Some(Refresh) if flags as c_int & O_TRUNC != 0 => {
+                info!("Refreshing latest comic (via open)");
+
+                let fh = self.gen_fh();
+
+                self.refresh_latest(source, move || reply.opened(fh, DEFAULT_FLAGS))
+            }
             Some(Refresh) | Some(Credits) => reply.opened(self.gen_fh(), DEFAULT_FLAGS),
-            Some(AltText(num)) | Some(Title(num)) | Some(Transcript(num)) | Some(Date(num)) => {
-                match self.client.request_comic(num, None, Normal) {
-                    Some(_) => reply.opened(self.gen_fh(), DEFAULT_FLAGS),
-                    None => reply.error(EREMOTEIO),
+            // Nothing to keep until comics are written to it
+            Some(Montage) if self.montage.is_empty() => reply.opened(self.gen_fh(), DEFAULT_FLAGS),
+            // Their sizes change, so reads shouldn't stop at the size they
+            // last had in getattr
+            Some(Status) => {
+                let contents = self.status().into_bytes();
+
+                self.open_snapshot(contents, FOPEN_DIRECT_IO, reply)
+            }
+            Some(Error(num)) => {
+                let contents = self.comic_errors(source, num).into_bytes();
+
+                self.open_snapshot(contents, FOPEN_DIRECT_IO, reply)
+            }
+            Some(f @ Montage) | Some(f @ MetadataCsv) | Some(f @ Wallpaper) => {
+                let fh = self.gen_fh();
+                let stats = Arc::clone(&self.stats);
+                let (snapshots, budget) = (self.snapshots.clone(), self.budget.clone());
+                let (spec, montage) = (self.wallpaper, self.montage.clone());
+                let (thumbnail_filter, ascii_width) = (self.thumbnail_filter, self.ascii_width);
+                let raw_transcripts = self.raw_transcripts;
+
+                self.on_worker(source, move |client| {
+                    let _op = logging::operation_scope("open", None);
+
+                    let contents = match f {
+                        Montage => client.request_montage(&montage, None, Normal),
+                        Wallpaper => wallpaper(client, spec),
+                        _ => file_contents(
+                            client,
+                            &f,
+                            thumbnail_filter,
+                            ascii_width,
+                            raw_transcripts,
+                        ),
+                    };
+
+                    // Without one, reads try again for themselves
+                    let kept = match contents {
+                        Some(contents) => {
+                            let bytes = contents.len() as u64;
+                            snapshots.insert(fh, contents, bytes, &budget)
+                        }
+                        None => Ok(()),
+                    };
+
+                    match kept {
+                        Ok(()) => reply.opened(fh, DEFAULT_FLAGS),
+                        Err(e) => {
+                            stats.released();
+                            reply.error(e)
+                        }
+                    }
+                })
+            }
+            Some(Image(num)) => {
+                let flags = self.open_flags(source, &Image(num));
+                let fh = self.gen_fh();
+                let options = self.clients[source].render_options().clone();
+                let pinned = PinnedImage {
+                    num,
+                    options: Some(options.clone()),
+                };
+
+                if let Err(e) = self.images.insert(fh, pinned, PINNED_BYTES, &self.budget) {
+                    // The kernel never sees the handle, so it won't be released
+                    self.stats.released();
+                    return reply.error(e);
                 }
+
+                // Opening starts the render, so it's often done by the first read
+                let waiter = Waiter::Open {
+                    reply,
+                    fh,
+                    flags,
+                    stats: Arc::clone(&self.stats),
+                    images: self.images.clone(),
+                };
+
+                self.rendered_image_for("open", source, num, Some(options), waiter)
+            }
+            Some(f @ AltText(_))
+            | Some(f @ Title(_))
+            | Some(f @ Transcript(_))
+            | Some(f @ Date(_))
+            | Some(f @ RawImage(_))
+            | Some(f @ Thumbnail(_, _))
+            | Some(f @ Pdf(_))
+            | Some(f @ Svg(_))
+            | Some(f @ Ascii(_))
+            | Some(f @ Described(_))
+            | Some(f @ ExtraParts(_)) => {
+                let flags = self.open_flags(source, &f);
+                let fh = self.gen_fh();
+                let stats = Arc::clone(&self.stats);
+                let (snapshots, budget) = (self.snapshots.clone(), self.budget.clone());
+                let images = self.images.clone();
+                let (thumbnail_filter, ascii_width) = (self.thumbnail_filter, self.ascii_width);
+                let raw_transcripts = self.raw_transcripts;
+
+                self.on_worker(source, move |client| {
+                    let _op = logging::operation_scope("open", f.comic_num());
+
+                    let contents =
+                        file_contents(client, &f, thumbnail_filter, ascii_width, raw_transcripts);
+
+                    // Text files can be opened once the comic is known, even
+                    // without a transcript or extra parts
+                    let found = contents.is_some()
+                        || match f {
+                            AltText(num) | Title(num) | Transcript(num) | Date(num)
+                            | Described(num) | ExtraParts(num) => {
+                                client.request_comic(num, None, Normal).is_some()
+                            }
+                            _ => false,
+                        };
+
+                    let kept = match (&f, contents) {
+                        _ if !found => Err(EREMOTEIO),
+                        // Raw images are read from the cache a part at a
+                        // time instead
+                        (&RawImage(num), _) => {
+                            let pinned = PinnedImage { num, options: None };
+                            images.insert(fh, pinned, PINNED_BYTES, &budget)
+                        }
+                        (_, None) => Ok(()),
+                        (_, Some(contents)) => {
+                            let bytes = contents.len() as u64;
+                            snapshots.insert(fh, contents, bytes, &budget)
+                        }
+                    };
+
+                    match kept {
+                        Ok(()) => reply.opened(fh, flags),
+                        Err(e) => {
+                            // The kernel never sees the handle, so it won't
+                            // be released
+                            stats.released();
+                            reply.error(e)
+                        }
+                    }
+                })
             }
-            Some(Image(num)) => match self
-                .client
-                .request_comic(num, None, Normal)
-                .and_then(|c| self.client.request_rendered_image(&c, None, Normal))
-            {
-                Some(_) => reply.opened(self.gen_fh(), DEFAULT_FLAGS),
-                None => reply.error(EREMOTEIO),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        _flags: u32,
+        _lock_owner: u64,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        trace!("release for inode {:x} (handle {})", ino, fh);
+
+        self.snapshots.remove(fh);
+        self.images.remove(fh);
+        self.stats.released();
+        reply.ok();
+    }
+
+    /// Renders that have finished go into the cache on every close
+    fn flush(&mut self, _req: &Request, ino: u64, fh: u64, _lock_owner: u64, reply: ReplyEmpty) {
+        trace!("flush for inode {:x} (handle {})", ino, fh);
+
+        self.collect_renders();
+        reply.ok();
+    }
+
+    /// Syncing `refresh` syncs the caches, for a consistent point to back
+    /// them up from
+    fn fsync(&mut self, _req: &Request, ino: u64, fh: u64, _datasync: bool, reply: ReplyEmpty) {
+        let _busy = self.watchdog.busy();
+        self.stats.served();
+
+        let (_, file) = self.node(ino);
+        let _op = logging::operation_scope("fsync", None);
+
+        info!("fsync for {:?} (handle {})", file, fh);
+
+        match file {
+            Some(File::Refresh) => match self.sync_caches() {
+                Ok(()) => reply.ok(),
+                Err(e) => reply.error(e),
             },
-            Some(RawImage(num)) => match self
-                .client
-                .request_comic(num, None, Normal)
-                .and_then(|c| self.client.request_raw_image(&c, None, Normal))
-            {
-                Some(_) => reply.opened(self.gen_fh(), DEFAULT_FLAGS),
-                None => reply.error(EREMOTEIO),
+            // Nothing else can be written to disk
+            Some(_) => reply.ok(),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    /// Syncing the top of the mount syncs the caches, like syncing `refresh`
+    fn fsyncdir(&mut self, _req: &Request, ino: u64, fh: u64, _datasync: bool, reply: ReplyEmpty) {
+        let _busy = self.watchdog.busy();
+        self.stats.served();
+
+        let (_, file) = self.node(ino);
+        let _op = logging::operation_scope("fsyncdir", None);
+
+        info!("fsyncdir for {:?} (handle {})", file, fh);
+
+        match file {
+            Some(File::Root) => match self.sync_caches() {
+                Ok(()) => reply.ok(),
+                Err(e) => reply.error(e),
             },
+            Some(_) => reply.ok(),
             None => reply.error(ENOENT),
         }
     }
@@ -432,13 +1911,18 @@ impl<'q> Filesystem for XkcdFs {
         &mut self,
         _req: &Request,
         ino: u64,
-        _fh: u64,
+        fh: u64,
         _offset: i64,
         data: &[u8],
         _flags: u32,
         reply: ReplyWrite,
     ) {
-        let file = File::from_inode(ino);
+        let _busy = self.watchdog.busy();
+        self.stats.served();
+        self.check_reload();
+
+        let (source, file) = self.node(ino);
+        let _op = logging::operation_scope("write", file.as_ref().and_then(File::comic_num));
 
         match &file {
             Some(f) => info!("write for {:?} with {} bytes of data", f, data.len()),
@@ -449,19 +1933,118 @@ impl<'q> Filesystem for XkcdFs {
             ),
         }
 
+        let written = data.len() as u32;
+
         match file {
             Some(File::Refresh) => {
-                info!("Refreshing latest comic (via write)");
-
-                self.client.request_latest_comic(None, BustCache);
+                match refresh::Target::parse(data) {
+                    refresh::Target::Latest => {
+                        info!("Refreshing latest comic (via write)");
+
+                        return self.refresh_latest(source, move || reply.written(written));
+                    }
+                    refresh::Target::Comic(num) => {
+                        info!("Refreshing comic {} (via write)", num);
+
+                        self.on_worker(source, move |client| refresh::refresh_comic(client, num));
+                    }
+                    refresh::Target::All => {
+                        info!("Refreshing every cached comic (via write)");
+
+                        let revalidating = self.clients[source]
+                            .try_clone()
+                            .and_then(|c| refresh::spawn_revalidate("revalidate".to_string(), c));
+
+                        if let Err(e) = revalidating {
+                            error!("Could not start refreshing every comic: {}", e);
+                            return reply.error(EIO);
+                        }
+                    }
+                    refresh::Target::Rendered => {
+                        info!("Clearing rendered images (via write)");
+
+                        self.on_worker(source, |client| {
+                            if let Err(e) = client.clear_rendered() {
+                                error!("Could not clear rendered images: {}", e);
+                            }
+                        });
+                    }
+                }
 
-                reply.written(data.len() as u32);
+                reply.written(written);
+            }
+            Some(File::Montage) => {
+                // Each write replaces the whole selection
+                let selection = String::from_utf8_lossy(data);
+
+                match montage::parse_selection(&selection) {
+                    Ok(nums) => {
+                        info!("Montage selection set to {} comics", nums.len());
+
+                        self.montage = nums;
+                        // Reads through the same handle get the new selection
+                        self.snapshots.remove(fh);
+                        reply.written(written);
+                    }
+                    Err(e) => {
+                        warn!("Invalid montage selection {:?}: {}", selection, e);
+                        reply.error(EINVAL);
+                    }
+                }
             }
             Some(_) => reply.error(EPERM),
             None => reply.error(ENOENT),
         }
     }
 
+    fn getxattr(&mut self, _req: &Request, ino: u64, name: &OsStr, size: u32, reply: ReplyXattr) {
+        let _busy = self.watchdog.busy();
+        self.stats.served();
+
+        let (source, file) = self.node(ino);
+        let _op = logging::operation_scope("getxattr", file.as_ref().and_then(File::comic_num));
+
+        let file = match file {
+            Some(f) => f,
+            None => return reply.error(ENOENT),
+        };
+
+        let value = Some(file)
+            .filter(|_| name == OsStr::new(MIME_TYPE_XATTR))
+            .and_then(|f| mime_type(&self.clients[source], &f));
+
+        match value {
+            Some(v) => reply_xattr(reply, v.as_bytes(), size),
+            None => {
+                logging::set_outcome("missing");
+                reply.error(ENODATA)
+            }
+        }
+    }
+
+    fn listxattr(&mut self, _req: &Request, ino: u64, size: u32, reply: ReplyXattr) {
+        let _busy = self.watchdog.busy();
+        self.stats.served();
+
+        let (source, file) = self.node(ino);
+        let _op = logging::operation_scope("listxattr", file.as_ref().and_then(File::comic_num));
+
+        let file = match file {
+            Some(f) => f,
+            None => return reply.error(ENOENT),
+        };
+
+        // Each name ends with a NUL
+        let mut names = Vec::new();
+
+        if mime_type(&self.clients[source], &file).is_some() {
+            names.extend_from_slice(MIME_TYPE_XATTR.as_bytes());
+            names.push(0);
+        }
+
+        reply_xattr(reply, &names, size)
+    }
+
     fn setattr(
         &mut self,
         req: &Request,
@@ -479,7 +2062,12 @@ impl<'q> Filesystem for XkcdFs {
         _flags: Option<u32>,
         reply: ReplyAttr,
     ) {
-        let file = File::from_inode(ino);
+        let _busy = self.watchdog.busy();
+        self.stats.served();
+        self.check_reload();
+
+        let (source, file) = self.node(ino);
+        let _op = logging::operation_scope("setattr", file.as_ref().and_then(File::comic_num));
 
         match &file {
             Some(f) => info!("setattr for {:?}", f),
@@ -490,11 +2078,115 @@ impl<'q> Filesystem for XkcdFs {
             Some(File::Refresh) => {
                 info!("Refreshing latest comic (via setattr)");
 
-                self.client.request_latest_comic(None, BustCache);
+                // The refresh file's attributes never change, so they can be
+                // worked out before it's done
+                let attr = match self.file_attr(req, source, &File::Refresh) {
+                    Some(a) => a,
+                    None => return reply.error(ENOENT),
+                };
+                let ttl = self.ttl(source, &File::Refresh, &attr);
 
-                self.getattr(req, ino, reply)
+                self.refresh_latest(source, move || reply.attr(&ttl, &attr))
             }
             _ => self.getattr(req, ino, reply),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::image::RenderOptions;
+    use crate::source::Source;
+    use reqwest::header::HeaderMap;
+    use xkcdfs_core::{CacheStore, XkcdApi};
+
+    #[test]
+    fn reads_within_bounds() {
+        assert_eq!(read_range(100, 0, 4096), Ok(0..100));
+        assert_eq!(read_range(100, 40, 20), Ok(40..60));
+        assert_eq!(read_range(100, 0, 0), Ok(0..0));
+
+        // Bigger than any kernel default
+        assert_eq!(read_range(10_000_000, 0, u32::MAX), Ok(0..10_000_000));
+    }
+
+    #[test]
+    fn reads_past_the_end_get_nothing() {
+        assert_eq!(read_range(100, 100, 4096), Ok(100..100));
+        assert_eq!(read_range(100, 5000, 4096), Ok(100..100));
+        assert_eq!(read_range(100, i64::MAX, u32::MAX), Ok(100..100));
+        assert_eq!(read_range(0, 0, 4096), Ok(0..0));
+    }
+
+    #[test]
+    fn negative_offsets_are_invalid() {
+        assert_eq!(read_range(100, -1, 4096), Err(EINVAL));
+        assert_eq!(read_range(100, i64::MIN, 4096), Err(EINVAL));
+    }
+
+    /// A client for a cache with comic 614, a PNG, and comics 1116 and 1117,
+    /// GIFs, of which only 1117 is animated
+    fn client(keep_animations: bool) -> XkcdClient {
+        let api = XkcdApi::new(Duration::from_secs(1), "test".to_string(), HeaderMap::new());
+        let client = XkcdClient::with_source(
+            Source::Api(api.unwrap()),
+            OsStr::new(":memory:"),
+            keep_animations,
+            false,
+            RenderOptions::default(),
+            None,
+        )
+        .unwrap();
+
+        for (num, extension, frames) in &[(614, "png", 1), (1116, "gif", 1), (1117, "gif", 2)] {
+            let comic = Comic {
+                num: *num,
+                day: 24,
+                month: 7,
+                year: 2009,
+                link: None,
+                news: None,
+                alt: String::new(),
+                title: format!("Comic {}", num),
+                safe_title: format!("Comic {}", num),
+                transcript: None,
+                img_url: format!("https://imgs.xkcd.com/comics/{}.{}", num, extension),
+                img_len: None,
+                extra_parts: None,
+            };
+
+            // One pixel, once per frame
+            let mut image = b"GIF89a\x01\x00\x01\x00\x00\x00\x00".to_vec();
+            for _ in 0..*frames {
+                image.extend_from_slice(b"\x2c\x00\x00\x00\x00\x01\x00\x01\x00\x00");
+                image.extend_from_slice(b"\x02\x02\x44\x01\x00");
+            }
+            image.push(b'\x3b');
+
+            client.store().put_comic(&comic).unwrap();
+            client.store().put_raw_image(*num, &image).unwrap();
+        }
+
+        client
+    }
+
+    #[test]
+    fn only_animations_are_found_as_gifs() {
+        let client = client(true);
+
+        assert!(listed_with(&client, &File::Image(614), "comic_0614.png"));
+        assert!(!listed_with(&client, &File::Image(614), "comic_0614.gif"));
+        assert!(!listed_with(&client, &File::Image(614), "comic_0614.jpg"));
+        assert!(listed_with(&client, &File::Image(1116), "comic_1116.png"));
+        assert!(!listed_with(&client, &File::Image(1116), "comic_1116.gif"));
+        assert!(listed_with(&client, &File::Image(1117), "comic_1117.gif"));
+        assert!(!listed_with(&client, &File::Image(1117), "comic_1117.png"));
+
+        // Animations are rendered like anything else without --keep-animations
+        let client = self::client(false);
+
+        assert!(listed_with(&client, &File::Image(1117), "comic_1117.png"));
+        assert!(!listed_with(&client, &File::Image(1117), "comic_1117.gif"));
+    }
+}
@@ -0,0 +1,102 @@
+//! Cleaning up after a previous mount that didn't exit cleanly, and making
+//! sure a new one can work
+
+use std::fs::OpenOptions;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Programs that can unmount a FUSE filesystem without root, newest first
+const UNMOUNTERS: &[&str] = &["fusermount3", "fusermount"];
+
+/// The device FUSE filesystems talk to the kernel through
+const FUSE_DEVICE: &str = "/dev/fuse";
+
+/// Whether `path` is a FUSE mount whose process has gone away
+///
+/// Anything touching such a mount fails with "transport endpoint is not
+/// connected".
+pub fn is_stale(path: &Path) -> bool {
+    match std::fs::metadata(path) {
+        Err(e) => e.raw_os_error() == Some(libc::ENOTCONN),
+        Ok(_) => false,
+    }
+}
+
+/// Whether something is already mounted at `path`
+///
+/// A mountpoint is on a different device than the directory it's in.
+pub fn is_mounted(path: &Path) -> io::Result<bool> {
+    let dev = std::fs::metadata(path)?.dev();
+    let parent_dev = std::fs::metadata(path.join(".."))?.dev();
+
+    Ok(dev != parent_dev)
+}
+
+/// Make sure FUSE filesystems can be mounted and unmounted
+///
+/// Returns the program that will do the unmounting.
+pub fn check_fuse() -> Result<&'static str, String> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(FUSE_DEVICE)
+        .map_err(|e| format!("Could not open {}: {}", FUSE_DEVICE, e))?;
+
+    for program in UNMOUNTERS {
+        let status = Command::new(program)
+            .arg("-V")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+
+        match status {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(format!("Could not run {}: {}", program, e)),
+            Ok(_) => return Ok(program),
+        }
+    }
+
+    Err(format!("None of {} are installed", UNMOUNTERS.join(", ")))
+}
+
+/// Unmount a stale mount at `path`, if there is one
+///
+/// Returns whether anything was unmounted.
+pub fn recover_stale_mount(path: &Path) -> io::Result<bool> {
+    if !is_stale(path) {
+        return Ok(false);
+    }
+
+    warn!(
+        "{} is left over from a mount that didn't exit cleanly, unmounting it",
+        path.display()
+    );
+
+    unmount(path).map(|_| true)
+}
+
+/// Unmount the FUSE filesystem at `path`
+///
+/// This is lazy, so it works even if something still has files open in it.
+pub fn unmount(path: &Path) -> io::Result<()> {
+    for program in UNMOUNTERS {
+        match Command::new(program).arg("-u").arg("-z").arg(path).status() {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+            Ok(status) if status.success() => return Ok(()),
+            Ok(status) => {
+                return Err(io::Error::other(format!(
+                    "{} exited with {}",
+                    program, status
+                )))
+            }
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("None of {} are installed", UNMOUNTERS.join(", ")),
+    ))
+}
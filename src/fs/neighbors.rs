@@ -0,0 +1,99 @@
+//! Fetching the comics either side of one that's read
+//!
+//! People browsing in a file manager almost always open the next or previous
+//! comic after the one they're looking at, so those are fetched in the
+//! background as soon as a comic's image is read. Only so many are queued in
+//! a while, so flicking through the whole archive doesn't queue up the whole
+//! archive, and comics queued recently aren't queued again.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Most comics queued in any `WINDOW`
+const MAX_QUEUED: usize = 8;
+
+/// How long a queued comic counts against the limit, and isn't queued again
+const WINDOW: Duration = Duration::from_secs(10);
+
+/// Comics recently queued for fetching, from any source
+#[derive(Default)]
+pub struct Neighbors {
+    /// When each was queued, oldest first, as (source, comic)
+    queued: VecDeque<(Instant, (usize, u32))>,
+}
+
+impl Neighbors {
+    /// The comics next to `num` in `source` to fetch, as of `now`
+    ///
+    /// Whatever's returned counts as queued. There's no comic 0, and comics
+    /// after `latest` don't exist yet.
+    pub fn around(&mut self, source: usize, num: u32, latest: u32, now: Instant) -> Vec<u32> {
+        while let Some((at, _)) = self.queued.front() {
+            if now.duration_since(*at) < WINDOW {
+                break;
+            }
+
+            self.queued.pop_front();
+        }
+
+        let mut fetch = Vec::new();
+
+        for neighbor in &[num.saturating_sub(1), num.saturating_add(1)] {
+            let neighbor = *neighbor;
+
+            if neighbor == 0 || neighbor == num || neighbor > latest {
+                continue;
+            }
+
+            if self.queued.len() >= MAX_QUEUED {
+                debug!("Not fetching past comic {}, too many fetched lately", num);
+                break;
+            }
+
+            if self.queued.iter().any(|(_, q)| *q == (source, neighbor)) {
+                continue;
+            }
+
+            self.queued.push_back((now, (source, neighbor)));
+            fetch.push(neighbor);
+        }
+
+        fetch
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn queues_each_side_once() {
+        let mut neighbors = Neighbors::default();
+        let now = Instant::now();
+
+        assert_eq!(neighbors.around(0, 614, 2000, now), vec![613, 615]);
+        assert_eq!(neighbors.around(0, 615, 2000, now), vec![614, 616]);
+        assert_eq!(neighbors.around(0, 614, 2000, now), Vec::<u32>::new());
+        assert_eq!(neighbors.around(1, 615, 2000, now), vec![614, 616]);
+
+        // Ends of the archive
+        assert_eq!(neighbors.around(0, 1, 2000, now), vec![2]);
+        assert_eq!(neighbors.around(0, 2000, 2000, now), vec![1999]);
+
+        // Queued long enough ago to go again
+        assert_eq!(neighbors.around(0, 614, 2000, now + WINDOW), vec![613, 615]);
+    }
+
+    #[test]
+    fn limits_how_many_are_queued() {
+        let mut neighbors = Neighbors::default();
+        let now = Instant::now();
+
+        let queued: usize = (0..10)
+            .map(|i| neighbors.around(0, 100 + i * 10, 2000, now).len())
+            .sum();
+
+        assert_eq!(queued, MAX_QUEUED);
+        assert_eq!(neighbors.around(0, 500, 2000, now + WINDOW), vec![499, 501]);
+    }
+}
@@ -0,0 +1,249 @@
+//! Options passed through to the FUSE mount
+
+use std::ffi::OsString;
+
+/// The default fsname and subtype, which `mount` shows as the source and
+/// `fuse.xkcdfs` as the type
+const DEFAULT_NAME: &str = "xkcdfs";
+
+/// Options that are just present or not
+const FLAGS: &[&str] = &[
+    "allow_other",
+    "allow_root",
+    "auto_unmount",
+    "nonempty",
+    "default_permissions",
+    "large_read",
+    "async_read",
+    "sync_read",
+    "auto_cache",
+    "kernel_cache",
+    "direct_io",
+    "ro",
+    "rw",
+    "exec",
+    "noexec",
+    "suid",
+    "nosuid",
+    "dev",
+    "nodev",
+    "atime",
+    "noatime",
+    "sync",
+    "async",
+    "dirsync",
+];
+
+/// Options that need a value, like `uid=1000`
+const VALUED: &[&str] = &[
+    "fsname",
+    "subtype",
+    "max_read",
+    "max_readahead",
+    "uid",
+    "gid",
+    "umask",
+    "entry_timeout",
+    "attr_timeout",
+    "negative_timeout",
+];
+
+/// Whether `option` is the one called `name`, with or without a value
+fn is_named(option: &str, name: &str) -> bool {
+    option == name || option.starts_with(&format!("{}=", name))
+}
+
+/// Validated mount options, in the order they were given
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MountOptions {
+    options: Vec<String>,
+    /// Leave out the default `auto_unmount`
+    no_auto_unmount: bool,
+}
+
+impl MountOptions {
+    /// Add options from one `-o` argument, which may be comma-separated
+    pub fn add(&mut self, arg: &str) -> Result<(), String> {
+        for option in arg.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+            let (name, value) = match option.find('=') {
+                Some(i) => (&option[..i], Some(&option[i + 1..])),
+                None => (option, None),
+            };
+
+            match value {
+                None if FLAGS.contains(&name) => {}
+                Some(v) if VALUED.contains(&name) && !v.is_empty() => {}
+                None if VALUED.contains(&name) => {
+                    return Err(format!("Mount option {} needs a value", name));
+                }
+                Some(_) if FLAGS.contains(&name) => {
+                    return Err(format!("Mount option {} does not take a value", name));
+                }
+                Some(_) if VALUED.contains(&name) => {
+                    return Err(format!("Mount option {} has an empty value", name));
+                }
+                _ => return Err(format!("Unknown mount option: {}", option)),
+            }
+
+            if (name == "allow_other" && self.has("allow_root"))
+                || (name == "allow_root" && self.has("allow_other"))
+            {
+                return Err("allow_other and allow_root can't be used together".to_string());
+            }
+
+            if (name == "ro" && self.has("rw")) || (name == "rw" && self.has("ro")) {
+                return Err("ro and rw can't be used together".to_string());
+            }
+
+            self.options.push(option.to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Set a valued option, replacing any earlier value
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        if value.contains(',') {
+            return Err(format!("Mount option {} can't contain a comma", name));
+        }
+
+        let mut replacement = Self::default();
+        replacement.add(&format!("{}={}", name, value))?;
+
+        self.options.retain(|o| !is_named(o, name));
+        self.options.extend(replacement.options);
+
+        Ok(())
+    }
+
+    /// Don't have the kernel unmount the filesystem when xkcdfs exits
+    pub fn disable_auto_unmount(&mut self) {
+        self.no_auto_unmount = true;
+    }
+
+    /// Whether an option with this name was given
+    pub fn has(&self, name: &str) -> bool {
+        self.options.iter().any(|o| is_named(o, name))
+    }
+
+    /// Every option, including defaults for anything not given, as `-o` takes
+    /// them
+    pub fn joined(&self) -> String {
+        let mut options = Vec::with_capacity(self.options.len() + 3);
+
+        for name in &["fsname", "subtype"] {
+            if !self.has(name) {
+                options.push(format!("{}={}", name, DEFAULT_NAME));
+            }
+        }
+
+        // So a crash doesn't leave a dead mount behind. fusermount has to do
+        // the mounting for this to work, which libfuse arranges on Linux.
+        if cfg!(target_os = "linux") && !self.no_auto_unmount && !self.has("auto_unmount") {
+            options.push("auto_unmount".to_string());
+        }
+
+        options.extend(self.options.iter().cloned());
+
+        options.join(",")
+    }
+
+    /// Arguments for `fuse::mount`, including defaults for anything not given
+    pub fn args(&self) -> Vec<OsString> {
+        vec!["-o".into(), self.joined().into()]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<MountOptions, String> {
+        let mut options = MountOptions::default();
+
+        for arg in args {
+            options.add(arg)?;
+        }
+
+        Ok(options)
+    }
+
+    #[test]
+    fn defaults_to_fsname_and_subtype() {
+        let mut options = MountOptions::default();
+        options.disable_auto_unmount();
+
+        assert_eq!(
+            options.args(),
+            vec![
+                OsString::from("-o"),
+                OsString::from("fsname=xkcdfs,subtype=xkcdfs")
+            ]
+        );
+    }
+
+    #[test]
+    fn names_can_be_set() {
+        let mut options = parse(&["fsname=comics", "ro"]).unwrap();
+        options.disable_auto_unmount();
+
+        options.set("fsname", "xkcd-archive").unwrap();
+        options.set("subtype", "xkcd").unwrap();
+
+        assert_eq!(
+            options.args()[1],
+            OsString::from("ro,fsname=xkcd-archive,subtype=xkcd")
+        );
+
+        assert!(options.set("fsname", "a,b").is_err());
+        assert!(options.set("fsname", "").is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn auto_unmounts_by_default() {
+        assert_eq!(
+            MountOptions::default().args()[1],
+            OsString::from("fsname=xkcdfs,subtype=xkcdfs,auto_unmount")
+        );
+
+        let options = parse(&["auto_unmount"]).unwrap();
+
+        assert_eq!(
+            options.args()[1],
+            OsString::from("fsname=xkcdfs,subtype=xkcdfs,auto_unmount")
+        );
+    }
+
+    #[test]
+    fn passes_options_through() {
+        let mut options = parse(&["allow_other,ro", "uid=1000"]).unwrap();
+        options.disable_auto_unmount();
+
+        assert_eq!(
+            options.args(),
+            vec![
+                OsString::from("-o"),
+                OsString::from("fsname=xkcdfs,subtype=xkcdfs,allow_other,ro,uid=1000")
+            ]
+        );
+
+        let mut options = parse(&["fsname=comics"]).unwrap();
+        options.disable_auto_unmount();
+
+        assert_eq!(
+            options.args()[1],
+            OsString::from("subtype=xkcdfs,fsname=comics")
+        );
+    }
+
+    #[test]
+    fn rejects_bad_options() {
+        assert!(parse(&["allow_everyone"]).is_err());
+        assert!(parse(&["uid"]).is_err());
+        assert!(parse(&["uid="]).is_err());
+        assert!(parse(&["ro=yes"]).is_err());
+        assert!(parse(&["allow_other", "allow_root"]).is_err());
+        assert!(parse(&["ro,rw"]).is_err());
+    }
+}
@@ -0,0 +1,61 @@
+//! Picking comics for `random`
+//!
+//! This only has to look random to someone clicking through comics, so it's a
+//! xorshift generator seeded from the clock rather than anything that could
+//! stand up to someone trying to guess it.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub struct Rng {
+    /// Never zero, or it'd stay that way
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed | 1 }
+    }
+
+    /// Seeded with the time, so each mount picks different comics
+    pub fn from_clock() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() ^ (u64::from(d.subsec_nanos()) << 32))
+            .unwrap_or(0);
+
+        Self::new(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// A number from 1 to `max`, which has to be at least 1
+    pub fn up_to(&mut self, max: u32) -> u32 {
+        (self.next_u64() % u64::from(max)) as u32 + 1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn picks_every_comic_in_range() {
+        let mut rng = Rng::new(614);
+        let mut seen = [false; 10];
+
+        for _ in 0..1000 {
+            let num = rng.up_to(10);
+
+            assert!((1..=10).contains(&num));
+            seen[num as usize - 1] = true;
+        }
+
+        assert!(seen.iter().all(|&s| s));
+        assert_eq!(Rng::new(0).up_to(1), 1);
+    }
+}
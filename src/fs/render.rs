@@ -0,0 +1,233 @@
+//! Rendering off the FUSE thread
+//!
+//! A slow render would hold up whichever thread asked for it. Renders run on
+//! a small thread pool instead, and whoever was waiting on them gets replied
+//! to from the render thread. Finished images are handed back to the FUSE
+//! thread to be cached, since render threads have no database connection.
+//!
+//! Each render takes what it needs out of a memory budget first, and the
+//! finished image keeps its share until it's cached.
+
+use fuse::{ReplyData, ReplyOpen};
+use std::collections::HashMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use threadpool::ThreadPool;
+
+use super::budget::{MemoryBudget, Reservation};
+use super::snapshots::{PinnedImage, Snapshots};
+use crate::logging;
+use crate::stats::Stats;
+
+use libc::EREMOTEIO;
+
+/// A FUSE request waiting on a render
+pub enum Waiter {
+    Read {
+        reply: ReplyData,
+        offset: i64,
+        size: u32,
+    },
+    Open {
+        reply: ReplyOpen,
+        fh: u64,
+        flags: u32,
+        /// The handle was counted as open when it was made
+        stats: Arc<Stats>,
+        /// And what it reads was pinned
+        images: Snapshots<PinnedImage>,
+    },
+}
+
+impl Waiter {
+    /// Where a read starts and how much it wants, if this is one
+    pub fn range(&self) -> Option<(u64, usize)> {
+        match *self {
+            Waiter::Read { offset, size, .. } if offset >= 0 => {
+                Some((offset as u64, size as usize))
+            }
+            _ => None,
+        }
+    }
+
+    /// Reply with only the part of the image in `range`
+    pub fn reply_part(self, part: &[u8]) {
+        match self {
+            Waiter::Read { reply, .. } => reply.data(part),
+            open @ Waiter::Open { .. } => open.reply(Some(part)),
+        }
+    }
+
+    pub fn reply(self, image: Option<&[u8]>) {
+        match self {
+            Waiter::Read {
+                reply,
+                offset,
+                size,
+            } => super::reply_data(reply, image.ok_or(EREMOTEIO), offset, size),
+            Waiter::Open {
+                reply,
+                fh,
+                flags,
+                stats,
+                images,
+            } => match image {
+                Some(_) => reply.opened(fh, flags),
+                None => {
+                    // The kernel never sees the handle, so it won't be released
+                    images.remove(fh);
+                    stats.released();
+                    reply.error(EREMOTEIO)
+                }
+            },
+        }
+    }
+}
+
+/// A render that has finished, and needs to be cached
+pub struct Finished {
+    /// Which comic source it's from
+    pub source: usize,
+    pub num: u32,
+    pub render_key: String,
+    pub image: Vec<u8>,
+    /// The image's share of the memory budget, until it's dropped
+    pub memory: Reservation,
+}
+
+/// Who's waiting on each render, by source, comic and render key
+type Pending = HashMap<(usize, u32, String), Vec<Waiter>>;
+
+/// Shared between the FUSE thread and its workers, hence all the locks
+pub struct RenderPool {
+    pool: Mutex<ThreadPool>,
+    pending: Arc<Mutex<Pending>>,
+    finished_tx: Mutex<Sender<Finished>>,
+    finished_rx: Mutex<Receiver<Finished>>,
+    stats: Arc<Stats>,
+    budget: MemoryBudget,
+}
+
+impl RenderPool {
+    pub fn new(threads: usize, stats: Arc<Stats>, budget: MemoryBudget) -> Self {
+        let (finished_tx, finished_rx) = channel();
+
+        Self {
+            pool: Mutex::new(ThreadPool::with_name("render".to_string(), threads)),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            finished_tx: Mutex::new(finished_tx),
+            finished_rx: Mutex::new(finished_rx),
+            stats,
+            budget,
+        }
+    }
+
+    /// Render comic `num` from `source` in the background, and reply to
+    /// `waiter` when done
+    ///
+    /// The render waits for `memory` bytes of the budget before it starts. If
+    /// the same render is already running, the waiter just joins it.
+    pub fn submit<F>(
+        &self,
+        source: usize,
+        num: u32,
+        render_key: String,
+        memory: u64,
+        waiter: Waiter,
+        render: F,
+    ) where
+        F: FnOnce() -> Result<Vec<u8>, String> + Send + 'static,
+    {
+        let key = (source, num, render_key);
+
+        {
+            let mut pending = self.pending.lock().unwrap();
+
+            if let Some(waiters) = pending.get_mut(&key) {
+                debug!("Render of comic {} already running, waiting on it", num);
+                waiters.push(waiter);
+                return;
+            }
+
+            pending.insert(key.clone(), vec![waiter]);
+            self.stats.set_renders_pending(pending.len());
+        }
+
+        let pool = self.pool.lock().unwrap();
+
+        debug!(
+            "Queueing render of comic {} ({} already queued)",
+            num,
+            pool.queued_count()
+        );
+
+        let pending = Arc::clone(&self.pending);
+        let finished_tx = self.finished_tx.lock().unwrap().clone();
+        let stats = Arc::clone(&self.stats);
+        let budget = self.budget.clone();
+
+        pool.execute(move || {
+            let _op = logging::operation_scope("render", Some(key.1));
+            let mut reservation = budget.reserve(memory);
+
+            // Catch panics so the waiters still get an answer
+            let image = match catch_unwind(AssertUnwindSafe(render)) {
+                Ok(Ok(image)) => {
+                    stats.rendered();
+                    Some(image)
+                }
+                Ok(Err(e)) => {
+                    warn!("Error rendering comic {}: {}", key.1, e);
+                    None
+                }
+                Err(_) => {
+                    error!("Rendering comic {} panicked", key.1);
+                    None
+                }
+            };
+
+            // Hand the image over for caching before letting go of the
+            // waiters, so nothing can see the render as neither pending nor
+            // finished and start it again
+            let waiters = {
+                let mut pending = pending.lock().unwrap();
+
+                if let Some(ref image) = image {
+                    reservation.shrink_to(image.len() as u64);
+
+                    // The FUSE thread might be gone if we're shutting down, in
+                    // which case there's nothing to cache the image for anyway
+                    finished_tx
+                        .send(Finished {
+                            source: key.0,
+                            num: key.1,
+                            render_key: key.2.clone(),
+                            image: image.clone(),
+                            memory: reservation,
+                        })
+                        .ok();
+                }
+
+                let waiters = pending.remove(&key).unwrap_or_default();
+                stats.set_renders_pending(pending.len());
+                waiters
+            };
+
+            trace!(
+                "Render of comic {} finished, replying to {} waiters",
+                key.1,
+                waiters.len()
+            );
+
+            for waiter in waiters {
+                waiter.reply(image.as_deref());
+            }
+        });
+    }
+
+    /// Renders that have finished since the last call
+    pub fn finished(&self) -> Vec<Finished> {
+        self.finished_rx.lock().unwrap().try_iter().collect()
+    }
+}
@@ -0,0 +1,178 @@
+//! What open files and directories had in them when they were opened
+//!
+//! Files like `.status` and `metadata.csv` are made again for every read, so
+//! one read a piece at a time could change size partway through. Instead,
+//! their contents are taken when they're opened, every read of that handle
+//! gets the same contents, and they're let go of when it's released.
+//! Directories are listed once in `opendir` the same way, so a refresh while
+//! `readdir` pages through one can't skip or repeat entries.
+//!
+//! What's kept counts against the memory budget renders use, until it's let
+//! go of. Opening can't wait for room like a render can, so contents up to
+//! `SMALL_SNAPSHOT` are kept whether there's room or not, and only bigger ones
+//! fail with ENOMEM when there's none.
+//!
+//! Comic images aren't kept. They can be read from the cache a part at a
+//! time, so only what they were opened for is: which comic, and what it's
+//! rendered with.
+
+use super::budget::{MemoryBudget, Reservation};
+use crate::image::RenderOptions;
+use libc::{c_int, ENOMEM};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Contents up to this many bytes are kept even when the budget is used up
+///
+/// That's most files and directories, so a render taking the whole budget
+/// only holds up opening the biggest, like montages.
+pub const SMALL_SNAPSHOT: u64 = 4 << 20;
+
+/// What a comic image handle reads, fixed when it's opened
+///
+/// Reloading the config can change what comics are rendered with, and a
+/// handle shouldn't get part of one render and part of another.
+pub struct PinnedImage {
+    pub num: u32,
+    /// What the image is rendered with, or nothing for `raw_image`
+    pub options: Option<RenderOptions>,
+}
+
+/// What a handle reads, and its share of the budget
+type Snapshot<T> = (Arc<T>, Reservation);
+
+/// Shared with the workers that open files, hence the lock
+pub struct Snapshots<T> {
+    by_fh: Arc<Mutex<HashMap<u64, Snapshot<T>>>>,
+}
+
+// Derived, these would need T: Clone and T: Default
+impl<T> Clone for Snapshots<T> {
+    fn clone(&self) -> Self {
+        Self {
+            by_fh: Arc::clone(&self.by_fh),
+        }
+    }
+}
+
+impl<T> Default for Snapshots<T> {
+    fn default() -> Self {
+        Self {
+            by_fh: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl<T> Snapshots<T> {
+    /// Keep `contents`, `bytes` long, for handle `fh` until it's released
+    ///
+    /// Fails with ENOMEM if they're bigger than `SMALL_SNAPSHOT` and `budget`
+    /// has no room for them.
+    pub fn insert(
+        &self,
+        fh: u64,
+        contents: T,
+        bytes: u64,
+        budget: &MemoryBudget,
+    ) -> Result<(), c_int> {
+        let reservation = if bytes <= SMALL_SNAPSHOT {
+            budget.take(bytes)
+        } else {
+            budget.try_reserve(bytes).ok_or(ENOMEM)?
+        };
+
+        self.by_fh
+            .lock()
+            .unwrap()
+            .insert(fh, (Arc::new(contents), reservation));
+
+        Ok(())
+    }
+
+    /// What handle `fh` was opened with, if it was kept
+    pub fn get(&self, fh: u64) -> Option<Arc<T>> {
+        self.by_fh
+            .lock()
+            .unwrap()
+            .get(&fh)
+            .map(|(contents, _)| Arc::clone(contents))
+    }
+
+    /// Let go of what handle `fh` was opened with, and give its memory back
+    pub fn remove(&self, fh: u64) {
+        self.by_fh.lock().unwrap().remove(&fh);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::stats::Stats;
+
+    #[test]
+    fn keeps_contents_until_released() {
+        let budget = MemoryBudget::new(None, Arc::new(Stats::default()));
+        let snapshots = Snapshots::default();
+        let worker = snapshots.clone();
+
+        worker
+            .insert(1, b"uptime: 1s".to_vec(), 10, &budget)
+            .unwrap();
+        worker
+            .insert(2, b"uptime: 2s".to_vec(), 10, &budget)
+            .unwrap();
+
+        let first = snapshots.get(1).unwrap();
+        snapshots.remove(1);
+
+        // Reads already under way keep what they have
+        assert_eq!(first.as_slice(), b"uptime: 1s");
+        assert_eq!(snapshots.get(1), None);
+        assert_eq!(snapshots.get(2).unwrap().as_slice(), b"uptime: 2s");
+    }
+
+    #[test]
+    fn counts_against_the_budget() {
+        const BIG: u64 = SMALL_SNAPSHOT + 1;
+
+        let stats = Arc::new(Stats::default());
+        let budget = MemoryBudget::new(Some(BIG + 100), Arc::clone(&stats));
+        let snapshots = Snapshots::default();
+
+        snapshots.insert(1, vec![0; 60], BIG, &budget).unwrap();
+        assert_eq!(snapshots.insert(2, vec![0; 60], BIG, &budget), Err(ENOMEM));
+        assert_eq!(snapshots.get(2), None);
+        assert_eq!(stats.snapshot().memory_reserved, BIG);
+
+        snapshots.remove(1);
+        assert_eq!(stats.snapshot().memory_reserved, 0);
+        snapshots.insert(2, vec![0; 60], BIG, &budget).unwrap();
+    }
+
+    #[test]
+    fn small_ones_open_beside_a_render() {
+        let stats = Arc::new(Stats::default());
+        let budget = MemoryBudget::new(Some(100), Arc::clone(&stats));
+        let files = Snapshots::default();
+        let listings = Snapshots::default();
+
+        // A render with the whole budget
+        let render = budget.reserve(100);
+
+        files
+            .insert(1, b"uptime: 1s".to_vec(), 10, &budget)
+            .unwrap();
+        listings
+            .insert(2, vec![".".to_string(), "..".to_string()], 60, &budget)
+            .unwrap();
+        assert_eq!(stats.snapshot().memory_reserved, 170);
+
+        assert_eq!(files.get(1).unwrap().as_slice(), b"uptime: 1s");
+        assert_eq!(listings.get(2).unwrap().len(), 2);
+
+        drop(render);
+        files.remove(1);
+        listings.remove(2);
+        assert_eq!(stats.snapshot().memory_reserved, 0);
+    }
+}
@@ -0,0 +1,124 @@
+//! `.status`, for checking on a mount with a single `cat`
+//!
+//! One `name: value` line for each thing, so scripts can grep for what they
+//! care about. Times are in whole seconds, and times in the past are how long
+//! ago they were.
+//!
+//! Each comic's `error` file is here too, with one line for each thing that's
+//! failing for it.
+
+use crate::history;
+use xkcdfs_core::stats::Snapshot;
+use xkcdfs_core::store::Fetch;
+
+/// The whole file, for a filesystem mounted with `options` that made
+/// `fetches` in the last day
+pub fn status(stats: &Snapshot, options: &str, fetches: &[Fetch]) -> String {
+    let network = match stats.failing {
+        0 => "ok".to_string(),
+        n => format!("failing, {} requests in a row", n),
+    };
+
+    let last_error = match &stats.last_error {
+        Some((ago, e)) => format!("{}s ago, {}", ago.as_secs(), e.replace('\n', " ")),
+        None => "none".to_string(),
+    };
+
+    let last_refresh = match stats.last_refresh {
+        Some((ago, Some(latest))) => format!("{}s ago, latest comic {}", ago.as_secs(), latest),
+        Some((ago, None)) => format!("{}s ago, failed", ago.as_secs()),
+        None => "never".to_string(),
+    };
+
+    let lines = [
+        ("uptime", format!("{}s", stats.uptime.as_secs())),
+        ("mount_options", options.to_string()),
+        ("network", network),
+        ("last_error", last_error),
+        ("last_refresh", last_refresh),
+        ("fetched_last_day", history::summary(fetches)),
+        ("prefetch_remaining", stats.prefetch_remaining.to_string()),
+        ("renders_pending", stats.renders_pending.to_string()),
+        ("requests_in_flight", stats.in_flight.to_string()),
+        ("open_files", stats.open_handles.to_string()),
+        ("memory_reserved", stats.memory_reserved.to_string()),
+    ];
+
+    lines
+        .iter()
+        .map(|(name, value)| format!("{}: {}\n", name, value))
+        .collect()
+}
+
+/// A comic's `error` file, from what's failed for it, when, and why
+///
+/// Times are in UTC, like `2009-07-24T13:00:00Z`.
+pub fn comic_errors(errors: &[(&str, i64, String)]) -> String {
+    errors
+        .iter()
+        .map(|(what, at, error)| {
+            let at = time::at_utc(time::Timespec::new(*at, 0));
+
+            format!("{} {}: {}\n", at.rfc3339(), what, error.replace('\n', " "))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn reports_health() {
+        let healthy = status(
+            &Snapshot {
+                uptime: Duration::from_secs(3600),
+                ..Snapshot::default()
+            },
+            "ro",
+            &[],
+        );
+
+        assert!(healthy.starts_with("uptime: 3600s\nmount_options: ro\nnetwork: ok\n"));
+        assert!(healthy.contains("last_error: none\nlast_refresh: never\n"));
+
+        let failing = status(
+            &Snapshot {
+                failing: 3,
+                last_error: Some((Duration::from_secs(5), "Comic 404:\nNot Found".to_string())),
+                last_refresh: Some((Duration::from_secs(60), None)),
+                ..Snapshot::default()
+            },
+            "ro",
+            &[],
+        );
+
+        assert!(failing.contains("network: failing, 3 requests in a row\n"));
+        assert!(failing.contains("last_error: 5s ago, Comic 404: Not Found\n"));
+        assert!(failing.contains("last_refresh: 60s ago, failed\n"));
+        assert!(failing.contains("fetched_last_day: 0 requests, 0 failed, 0 bytes\n"));
+        assert_eq!(failing.lines().count(), 11);
+    }
+
+    #[test]
+    fn describes_comic_errors() {
+        let errors = [
+            ("image", 1_248_440_400, "404 Not Found".to_string()),
+            (
+                "render",
+                1_248_440_460,
+                "Could not decode\nthe image".to_string(),
+            ),
+        ];
+
+        assert_eq!(
+            comic_errors(&errors),
+            concat!(
+                "2009-07-24T13:00:00Z image: 404 Not Found\n",
+                "2009-07-24T13:01:00Z render: Could not decode the image\n",
+            )
+        );
+        assert_eq!(comic_errors(&[]), "");
+    }
+}
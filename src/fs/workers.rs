@@ -0,0 +1,164 @@
+//! Handling FUSE requests off the FUSE thread
+//!
+//! The session reads one request at a time, so anything that might wait on
+//! the network is handed to a worker and replied to from there. Each worker
+//! has its own state (in practice, its own client and database connection),
+//! so independent reads go ahead in parallel and a slow fetch only holds up
+//! the worker it's on.
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+type Job<S> = Box<dyn FnOnce(&S) + Send>;
+
+pub struct WorkerPool<S> {
+    jobs: Sender<Job<S>>,
+}
+
+impl<S: Send + 'static> WorkerPool<S> {
+    /// Start `threads` workers, each with state from `make_state`
+    ///
+    /// Workers exit once the pool is dropped and they've finished what was
+    /// already submitted.
+    pub fn new<F>(threads: usize, mut make_state: F) -> Result<Self, String>
+    where
+        F: FnMut() -> Result<S, String>,
+    {
+        let (jobs, queue) = channel::<Job<S>>();
+        let queue = Arc::new(Mutex::new(queue));
+
+        for i in 0..threads {
+            let state = make_state()?;
+            let queue = Arc::clone(&queue);
+
+            thread::Builder::new()
+                .name(format!("fuse-worker-{}", i))
+                .spawn(move || loop {
+                    // The lock is only held while waiting for a job
+                    let job = match queue.lock().unwrap().recv() {
+                        Ok(j) => j,
+                        Err(_) => return,
+                    };
+
+                    // Anything waiting on a reply from a panicked job gets an
+                    // error when the reply is dropped, and the worker carries on
+                    if catch_unwind(AssertUnwindSafe(|| job(&state))).is_err() {
+                        error!("A filesystem request panicked");
+                    }
+                })
+                .map_err(|e| e.to_string())?;
+        }
+
+        Ok(Self { jobs })
+    }
+
+    /// Run `job` on the next free worker
+    pub fn submit<F>(&self, job: F)
+    where
+        F: FnOnce(&S) + Send + 'static,
+    {
+        if self.jobs.send(Box::new(job)).is_err() {
+            error!("Every filesystem worker has stopped");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ffi::OsStr;
+    use std::sync::Barrier;
+    use std::time::Duration;
+    use xkcdfs_core::{CacheStore, SqliteStore};
+
+    #[test]
+    fn slow_job_does_not_stall_others() {
+        let pool = WorkerPool::new(4, || Ok(())).unwrap();
+        let (unblock_tx, unblock_rx) = channel::<()>();
+        let (done_tx, done_rx) = channel();
+
+        // Stands in for a fetch that never finishes
+        let blocked_done = done_tx.clone();
+        pool.submit(move |_| {
+            unblock_rx.recv().ok();
+            blocked_done.send("slow").unwrap();
+        });
+
+        for _ in 0..100 {
+            let done_tx = done_tx.clone();
+            pool.submit(move |_| done_tx.send("fast").unwrap());
+        }
+
+        for _ in 0..100 {
+            let done = done_rx.recv_timeout(Duration::from_secs(5));
+            assert_eq!(done, Ok("fast"));
+        }
+
+        unblock_tx.send(()).unwrap();
+        assert_eq!(done_rx.recv_timeout(Duration::from_secs(5)), Ok("slow"));
+    }
+
+    #[test]
+    fn workers_have_their_own_state() {
+        let mut next = 0;
+        let pool = WorkerPool::new(3, || {
+            next += 1;
+            Ok(next)
+        })
+        .unwrap();
+
+        let (tx, rx) = channel();
+        let barrier = Arc::new(Barrier::new(3));
+
+        // Each job waits for the others, so all three workers must take one
+        for _ in 0..3 {
+            let tx = tx.clone();
+            let barrier = Arc::clone(&barrier);
+            pool.submit(move |state: &u32| {
+                barrier.wait();
+                tx.send(*state).unwrap();
+            });
+        }
+
+        let mut states = (0..3)
+            .map(|_| rx.recv_timeout(Duration::from_secs(5)).unwrap())
+            .collect::<Vec<_>>();
+        states.sort_unstable();
+
+        assert_eq!(states, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn workers_share_an_in_memory_store() {
+        let store = SqliteStore::open(OsStr::new(":memory:")).unwrap();
+        store.put_raw_image(614, b"woodpecker").unwrap();
+
+        let pool = WorkerPool::new(2, || store.try_clone()).unwrap();
+        let (tx, rx) = channel();
+
+        pool.submit(move |store: &SqliteStore| {
+            let image = store.raw_image(614);
+            store.put_raw_image(615, b"friendship").unwrap();
+            tx.send(image).unwrap();
+        });
+
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)),
+            Ok(Ok(Some(b"woodpecker".to_vec())))
+        );
+        assert_eq!(store.raw_image(615), Ok(Some(b"friendship".to_vec())));
+    }
+
+    #[test]
+    fn panics_do_not_kill_workers() {
+        let pool = WorkerPool::new(1, || Ok(())).unwrap();
+        let (tx, rx) = channel();
+
+        pool.submit(|_| panic!("oops"));
+        pool.submit(move |_| tx.send(()).unwrap());
+
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(()));
+    }
+}
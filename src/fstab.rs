@@ -0,0 +1,291 @@
+//! Running as `mount.xkcdfs`, so xkcdfs can be mounted from /etc/fstab
+//!
+//! mount(8) runs `/sbin/mount.<type>` as `mount.<type> device mountpoint
+//! [-sfnv] [-o options]`. With a symlink to xkcdfs under that name, a line like
+//!
+//! ```text
+//! xkcdfs /mnt/xkcd fuse.xkcdfs database=/var/cache/xkcd.db,allow_other 0 0
+//! ```
+//!
+//! works with `mount -a` and systemd mount units. This turns those arguments
+//! into the usual command line: options that xkcdfs knows become flags, and
+//! the rest are passed on to FUSE.
+
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+
+/// Command-line options that can also be given in fstab
+const OPTIONS: &[&str] = &[
+    "config",
+    "database",
+    "max-cache-size",
+    "timeout",
+    "refresh-interval",
+    "update-source",
+    "backup-dir",
+    "backup-interval",
+    "backup-keep",
+    "prefetch-on-mount",
+    "pidfile",
+    "control-socket",
+    "run-as",
+    "no-auto-unmount",
+    "hide",
+    "track-atime",
+    "on-new-comic",
+    "notify",
+    "foreground",
+    "log-filter",
+    "log-target",
+    "log-file",
+    "log-rotate",
+    "log-format",
+    "user-agent",
+    "header",
+    "pack",
+    "archive",
+    "thumbnail-sizes",
+    "thumbnail-filter",
+    "wallpaper-size",
+    "wallpaper-background",
+    "ascii-width",
+    "raw-transcripts",
+    "render-threads",
+    "fuse-threads",
+    "max-memory",
+    "keep-animations",
+    "no-render",
+    "max-render-width",
+    "max-render-height",
+    "margin",
+    "alt-width",
+    "alt-padding",
+    "alt-background",
+    "contrast",
+];
+
+/// Options that are for mount(8) or systemd, not for the filesystem
+const IGNORED: &[&str] = &[
+    "defaults", "auto", "noauto", "user", "users", "nouser", "owner", "group", "nofail", "_netdev",
+];
+
+/// Whether the program was run as a mount helper
+pub fn is_mount_helper(program: &OsStr) -> bool {
+    Path::new(program)
+        .file_name()
+        .and_then(OsStr::to_str)
+        .is_some_and(|name| name.starts_with("mount."))
+}
+
+/// Turn one fstab option into xkcdfs arguments
+fn translate_option(option: &str, args: &mut Vec<OsString>) {
+    let (name, value) = match option.find('=') {
+        Some(i) => (&option[..i], Some(&option[i + 1..])),
+        None => (option, None),
+    };
+
+    if name.is_empty() || IGNORED.contains(&name) || name.starts_with("x-") || name == "comment" {
+        return;
+    }
+
+    // Underscores are more usual in mount options
+    let flag = name.replace('_', "-");
+
+    if OPTIONS.contains(&flag.as_str()) {
+        args.push(match value {
+            Some(v) => format!("--{}={}", flag, v).into(),
+            None => format!("--{}", flag).into(),
+        });
+    } else {
+        args.push("-o".into());
+        args.push(option.into());
+    }
+}
+
+/// Turn the arguments mount(8) gives a helper into the usual ones
+///
+/// The device becomes the name shown in the mount table.
+pub fn translate(mut helper_args: impl Iterator<Item = OsString>) -> Result<Vec<OsString>, String> {
+    let program = helper_args.next().unwrap_or_else(|| "xkcdfs".into());
+    let mut positional = Vec::new();
+    let mut flags: Vec<OsString> = Vec::new();
+    let mut options = Vec::new();
+
+    while let Some(arg) = helper_args.next() {
+        match arg.to_str() {
+            Some("-o") => match helper_args.next() {
+                Some(o) => options.push(
+                    o.into_string()
+                        .map_err(|o| format!("Invalid mount options {:?}", o))?,
+                ),
+                None => return Err("-o needs a list of options".to_string()),
+            },
+            // Mount namespaces and filesystem types aren't anything to do
+            // with the filesystem itself
+            Some("-N") | Some("-t") => {
+                helper_args.next();
+            }
+            // Not writing to /etc/mtab, and sloppy about unknown options,
+            // which go to FUSE anyway
+            Some("-n") | Some("-s") => {}
+            Some("-v") => flags.push("--verbose".into()),
+            // Faking the mount is as close as xkcdfs gets to checking it
+            Some("-f") => flags.push("--check".into()),
+            Some(a) if a.starts_with('-') => {
+                return Err(format!("Unknown mount helper option {}", a));
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    if positional.len() != 2 {
+        return Err(format!(
+            "Usage: {} device mountpoint [-o options]",
+            Path::new(&program).display()
+        ));
+    }
+
+    let mountpoint = positional.pop().unwrap();
+    let device = positional.pop().unwrap();
+
+    let mut args = vec![program];
+    args.append(&mut flags);
+
+    let options = options.iter().flat_map(|o| o.split(','));
+    let mut named = false;
+
+    for option in options {
+        named |= option.starts_with("fsname=");
+        translate_option(option, &mut args);
+    }
+
+    if !named {
+        args.push("--fsname".into());
+        args.push(device);
+    }
+
+    args.push(mountpoint);
+
+    Ok(args)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run(args: &[&str]) -> Result<Vec<String>, String> {
+        let args = args.iter().map(OsString::from).collect::<Vec<_>>();
+
+        translate(args.into_iter())
+            .map(|args| args.into_iter().map(|a| a.into_string().unwrap()).collect())
+    }
+
+    #[test]
+    fn detects_helper_name() {
+        assert!(is_mount_helper(OsStr::new("/sbin/mount.xkcdfs")));
+        assert!(is_mount_helper(OsStr::new("mount.fuse.xkcdfs")));
+        assert!(!is_mount_helper(OsStr::new("/usr/bin/xkcdfs")));
+    }
+
+    #[test]
+    fn translates_fstab_options() {
+        assert_eq!(
+            run(&[
+                "mount.xkcdfs",
+                "xkcdfs",
+                "/mnt/xkcd",
+                "-o",
+                "rw,database=/var/cache/xkcd.db,allow_other,render_threads=2",
+                "-o",
+                "noauto,x-systemd.automount",
+            ])
+            .unwrap(),
+            vec![
+                "mount.xkcdfs",
+                "-o",
+                "rw",
+                "--database=/var/cache/xkcd.db",
+                "-o",
+                "allow_other",
+                "--render-threads=2",
+                "--fsname",
+                "xkcdfs",
+                "/mnt/xkcd",
+            ]
+        );
+    }
+
+    #[test]
+    fn translates_memory_limit() {
+        assert_eq!(
+            run(&[
+                "mount.xkcdfs",
+                "xkcdfs",
+                "/mnt/xkcd",
+                "-o",
+                "max_memory=256M,render-threads=1",
+            ])
+            .unwrap(),
+            vec![
+                "mount.xkcdfs",
+                "--max-memory=256M",
+                "--render-threads=1",
+                "--fsname",
+                "xkcdfs",
+                "/mnt/xkcd",
+            ]
+        );
+    }
+
+    #[test]
+    fn repeats_list_options() {
+        assert_eq!(
+            run(&[
+                "mount.xkcdfs",
+                "xkcdfs",
+                "/mnt/xkcd",
+                "-o",
+                "thumbnail_sizes=64,thumbnail_sizes=256",
+            ])
+            .unwrap(),
+            vec![
+                "mount.xkcdfs",
+                "--thumbnail-sizes=64",
+                "--thumbnail-sizes=256",
+                "--fsname",
+                "xkcdfs",
+                "/mnt/xkcd",
+            ]
+        );
+    }
+
+    #[test]
+    fn handles_helper_flags() {
+        assert_eq!(
+            run(&[
+                "mount.xkcdfs",
+                "comics",
+                "/mnt/xkcd",
+                "-n",
+                "-v",
+                "-o",
+                "fsname=xkcd"
+            ])
+            .unwrap(),
+            vec![
+                "mount.xkcdfs",
+                "--verbose",
+                "-o",
+                "fsname=xkcd",
+                "/mnt/xkcd"
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_usage() {
+        assert!(run(&["mount.xkcdfs", "/mnt/xkcd"]).is_err());
+        assert!(run(&["mount.xkcdfs", "xkcdfs", "/mnt/xkcd", "-o"]).is_err());
+        assert!(run(&["mount.xkcdfs", "xkcdfs", "/mnt/xkcd", "-x"]).is_err());
+    }
+}
@@ -0,0 +1,272 @@
+//! `serve-http`: a gallery for web browsers
+//!
+//! For anyone who'd rather open a URL than a mount. Pages are plain HTML,
+//! newest comics first:
+//!
+//! - `/` and `/?page=N` list comics with their thumbnails
+//! - `/N/` shows comic N with its alt text
+//! - `/N/image` and `/N/thumbnail.png` are the images those pages use
+//!
+//! Everything comes through the client, so the gallery and a mount share
+//! their cache, renders and thumbnails.
+
+use crate::fs::{self, file::File};
+use crate::http::{self, content_type, escape, header, Handler};
+use crate::image::ThumbnailSpec;
+use crate::requests::RequestMode::*;
+use crate::{Comic, XkcdClient};
+use tiny_http::{Method, Request, Response};
+
+/// Comics on each index page
+const PAGE_SIZE: u32 = 60;
+
+const STYLE: &str = "body { font-family: sans-serif; max-width: 60em; margin: auto; }
+ul.comics { list-style: none; padding: 0; }
+ul.comics li { display: inline-block; width: 12em; margin: 0.5em; vertical-align: top; }
+img { max-width: 100%; }
+.alt { font-style: italic; }";
+
+/// A page or image in the gallery
+#[derive(Debug, PartialEq)]
+enum Page {
+    Index(u32),
+    Comic(u32),
+    Image(u32),
+    Thumbnail(u32),
+}
+
+impl Page {
+    /// The page at a URL, like `/12/` or `/?page=2`
+    fn from_url(url: &str) -> Option<Self> {
+        let mut parts = url.splitn(2, '?');
+        let path = parts.next().unwrap_or("");
+        let query = parts.next().unwrap_or("");
+
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+        let page = match segments.as_slice() {
+            [] => {
+                let page = query
+                    .split('&')
+                    .filter_map(|pair| {
+                        let mut pair = pair.splitn(2, '=');
+                        match (pair.next(), pair.next()) {
+                            (Some("page"), Some(value)) => value.parse().ok(),
+                            _ => None,
+                        }
+                    })
+                    .next()
+                    .unwrap_or(1);
+
+                Some(Page::Index(page)).filter(|_| page > 0)
+            }
+            [num] => num.parse().ok().map(Page::Comic),
+            [num, "image"] => num.parse().ok().map(Page::Image),
+            [num, "thumbnail.png"] => num.parse().ok().map(Page::Thumbnail),
+            _ => None,
+        };
+
+        page.filter(|p| !matches!(p, Page::Comic(0) | Page::Image(0) | Page::Thumbnail(0)))
+    }
+}
+
+/// What a thread needs to answer requests
+struct GalleryHandler {
+    client: XkcdClient,
+    thumbnail: ThumbnailSpec,
+}
+
+/// Serve the gallery on `listen` until the process is killed
+pub fn serve(client: XkcdClient, listen: &str, thumbnail: ThumbnailSpec) -> Result<(), String> {
+    http::serve(listen, "the gallery", || {
+        Ok(GalleryHandler {
+            client: client.try_clone()?,
+            thumbnail,
+        })
+    })
+}
+
+fn html(body: String) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_string(body).with_header(header("Content-Type", "text/html; charset=utf-8"))
+}
+
+/// A whole HTML document
+fn document(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width\">\n\
+         <title>{}</title>\n<style>\n{}\n</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        escape(title),
+        STYLE,
+        body
+    )
+}
+
+impl Handler for GalleryHandler {
+    fn handle(&self, request: Request) {
+        let method = request.method().clone();
+        let page = Page::from_url(request.url());
+
+        debug!("Gallery {} for {} ({:?})", method, request.url(), page);
+
+        let result = match (method, page) {
+            (Method::Get, Some(page)) | (Method::Head, Some(page)) => match self.page(page) {
+                Some(response) => request.respond(response),
+                None => request.respond(Response::empty(404)),
+            },
+            (Method::Get, None) | (Method::Head, None) => request.respond(Response::empty(404)),
+            _ => request.respond(Response::empty(405).with_header(header("Allow", "GET, HEAD"))),
+        };
+
+        if let Err(e) = result {
+            debug!("Could not send a gallery response: {}", e);
+        }
+    }
+}
+
+impl GalleryHandler {
+    /// The newest comic listed
+    fn latest(&self) -> u32 {
+        self.client.get_cached_count() as u32
+    }
+
+    fn page(&self, page: Page) -> Option<Response<std::io::Cursor<Vec<u8>>>> {
+        match page {
+            Page::Index(index) => self.index(index).map(html),
+            Page::Comic(num) => {
+                let comic = self.client.request_comic(num, None, Normal)?;
+
+                Some(html(self.comic(&comic)))
+            }
+            Page::Image(num) => {
+                let comic = self.client.request_comic(num, None, Normal)?;
+                let image = self.client.request_rendered_image(&comic, None, Normal)?;
+
+                let file = File::Image(num);
+                let name = fs::entry_name(&self.client, file.inode(), file.filename());
+
+                Some(
+                    Response::from_data(image)
+                        .with_header(header("Content-Type", content_type(&name))),
+                )
+            }
+            Page::Thumbnail(num) => {
+                let comic = self.client.request_comic(num, None, Normal)?;
+                let thumbnail =
+                    self.client
+                        .request_thumbnail(&comic, self.thumbnail, None, Normal)?;
+
+                Some(
+                    Response::from_data(thumbnail).with_header(header("Content-Type", "image/png")),
+                )
+            }
+        }
+    }
+
+    /// Index page `page`, counting from 1
+    fn index(&self, page: u32) -> Option<String> {
+        let latest = self.latest();
+        let pages = latest.div_ceil(PAGE_SIZE);
+
+        if page > pages.max(1) {
+            return None;
+        }
+
+        let newest = latest - (page - 1) * PAGE_SIZE;
+        let oldest = newest.saturating_sub(PAGE_SIZE - 1).max(1);
+
+        let mut body = String::from("<h1>xkcd</h1>\n<ul class=\"comics\">\n");
+
+        for num in (oldest..=newest).rev() {
+            // Only what's cached, so listing doesn't wait on the network
+            let title = match self.client.request_comic(num, None, VeryFast) {
+                Some(c) => format!("{}: {}", num, c.safe_title),
+                None => num.to_string(),
+            };
+
+            let thumbnail = if self.client.rendering_enabled() {
+                format!(
+                    "<img src=\"/{}/thumbnail.png\" alt=\"\" loading=\"lazy\"><br>",
+                    num
+                )
+            } else {
+                String::new()
+            };
+
+            body.push_str(&format!(
+                "<li><a href=\"/{}/\">{}{}</a></li>\n",
+                num,
+                thumbnail,
+                escape(&title)
+            ));
+        }
+
+        body.push_str("</ul>\n<p>\n");
+
+        if page > 1 {
+            body.push_str(&format!("<a href=\"/?page={}\">Newer</a>\n", page - 1));
+        }
+        if page < pages {
+            body.push_str(&format!("<a href=\"/?page={}\">Older</a>\n", page + 1));
+        }
+
+        body.push_str("</p>\n");
+
+        Some(document("xkcd", &body))
+    }
+
+    /// The page for one comic
+    fn comic(&self, comic: &Comic) -> String {
+        let num = comic.num;
+        let title = format!("{}: {}", num, comic.safe_title);
+
+        let mut body = format!(
+            "<h1>{}</h1>\n<p><img src=\"/{}/image\" alt=\"{}\" title=\"{}\"></p>\n\
+             <p class=\"alt\">{}</p>\n<p>{}</p>\n<p>\n",
+            escape(&title),
+            num,
+            escape(&comic.safe_title),
+            escape(&comic.alt),
+            escape(&comic.alt),
+            comic.isodate()
+        );
+
+        if num > 1 {
+            body.push_str(&format!("<a href=\"/{}/\">Previous</a>\n", num - 1));
+        }
+
+        let page = (self.latest().saturating_sub(num)) / PAGE_SIZE + 1;
+        body.push_str(&format!("<a href=\"/?page={}\">All comics</a>\n", page));
+
+        if num < self.latest() {
+            body.push_str(&format!("<a href=\"/{}/\">Next</a>\n", num + 1));
+        }
+
+        body.push_str("</p>\n");
+
+        document(&title, &body)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pages_from_urls() {
+        assert_eq!(Page::from_url("/"), Some(Page::Index(1)));
+        assert_eq!(Page::from_url("/?page=3"), Some(Page::Index(3)));
+        assert_eq!(Page::from_url("/?x=1&page=2"), Some(Page::Index(2)));
+        assert_eq!(Page::from_url("/?page=0"), None);
+        assert_eq!(Page::from_url("/12/"), Some(Page::Comic(12)));
+        assert_eq!(Page::from_url("/12"), Some(Page::Comic(12)));
+        assert_eq!(Page::from_url("/12/image"), Some(Page::Image(12)));
+        assert_eq!(
+            Page::from_url("/12/thumbnail.png"),
+            Some(Page::Thumbnail(12))
+        );
+        assert_eq!(Page::from_url("/0/"), None);
+        assert_eq!(Page::from_url("/abc/"), None);
+        assert_eq!(Page::from_url("/12/alt"), None);
+    }
+}
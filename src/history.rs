@@ -0,0 +1,129 @@
+//! `xkcdfs cache history`: what the fetch log says about network use
+//!
+//! Every request xkcdfs makes is noted in the database, so a long-running
+//! mount can be asked afterwards where its bandwidth went. The report adds
+//! them up by hour, in UTC so it reads the same wherever it's run.
+
+use xkcdfs_core::store::Fetch;
+
+/// Seconds in each row of the report
+const BUCKET: i64 = 60 * 60;
+
+/// Requests, failures and bytes, for a row of the report or `.status`
+#[derive(Debug, Default, PartialEq)]
+struct Totals {
+    requests: u64,
+    failed: u64,
+    bytes: u64,
+}
+
+impl Totals {
+    fn add(&mut self, fetch: &Fetch) {
+        self.requests += 1;
+        self.bytes += fetch.bytes.unwrap_or(0);
+
+        if fetch.status != "ok" {
+            self.failed += 1;
+        }
+    }
+
+    fn row(&self, label: &str) -> String {
+        format!(
+            "{:<16}  {:>8}  {:>6}  {:>12}\n",
+            label, self.requests, self.failed, self.bytes
+        )
+    }
+}
+
+/// One line for `.status`, like `12 requests, 1 failed, 31337 bytes`
+pub fn summary(fetches: &[Fetch]) -> String {
+    let mut totals = Totals::default();
+
+    for fetch in fetches {
+        totals.add(fetch);
+    }
+
+    format!(
+        "{} requests, {} failed, {} bytes",
+        totals.requests, totals.failed, totals.bytes
+    )
+}
+
+/// The whole report, one row per hour with any requests in it
+pub fn report(fetches: &[Fetch]) -> String {
+    let mut report = format!(
+        "{:<16}  {:>8}  {:>6}  {:>12}\n",
+        "hour (UTC)", "requests", "failed", "bytes"
+    );
+    let mut hour = None;
+    let mut totals = Totals::default();
+    let mut overall = Totals::default();
+
+    // The log is in order, so each hour's requests are together
+    for fetch in fetches {
+        let bucket = fetch.at - fetch.at % BUCKET;
+
+        if let Some(h) = hour.filter(|&h| h != bucket) {
+            report.push_str(&totals.row(&label(h)));
+            totals = Totals::default();
+        }
+
+        hour = Some(bucket);
+        totals.add(fetch);
+        overall.add(fetch);
+    }
+
+    if let Some(h) = hour {
+        report.push_str(&totals.row(&label(h)));
+    }
+
+    report.push_str(&overall.row("total"));
+
+    report
+}
+
+/// Like `2009-07-24 13:00`
+fn label(hour: i64) -> String {
+    time::at_utc(time::Timespec::new(hour, 0))
+        .strftime("%Y-%m-%d %H:%M")
+        .map(|t| t.to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fetch(at: i64, status: &str, bytes: Option<u64>) -> Fetch {
+        Fetch {
+            at,
+            class: "image".to_string(),
+            status: status.to_string(),
+            millis: 100,
+            bytes,
+        }
+    }
+
+    #[test]
+    fn adds_up_by_hour() {
+        // 2009-07-24 13:xx and 14:xx
+        let fetches = [
+            fetch(1_248_440_400, "ok", Some(1000)),
+            fetch(1_248_443_999, "timed out", None),
+            fetch(1_248_444_000, "ok", Some(500)),
+        ];
+
+        assert_eq!(
+            report(&fetches),
+            concat!(
+                "hour (UTC)        requests  failed         bytes\n",
+                "2009-07-24 13:00         2       1          1000\n",
+                "2009-07-24 14:00         1       0           500\n",
+                "total                    3       1          1500\n",
+            )
+        );
+
+        assert_eq!(summary(&fetches), "3 requests, 1 failed, 1500 bytes");
+        assert!(report(&[]).ends_with("total                    0       0             0\n"));
+    }
+}
@@ -0,0 +1,100 @@
+//! `--on-new-comic`: running a command whenever a new comic shows up
+//!
+//! The command runs under `sh -c` once for each new comic the background
+//! refresh finds, with the comic's number, title and image path in its
+//! environment. It's waited for, so a slow command holds up the next check
+//! instead of piling up.
+
+use crate::fs::{self, file::File};
+use crate::{Comic, XkcdClient};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+pub struct Hook {
+    command: String,
+    /// Where the filesystem is mounted, for the image's path
+    mountpoint: PathBuf,
+}
+
+impl Hook {
+    pub fn new(command: String, mountpoint: PathBuf) -> Self {
+        Self {
+            command,
+            mountpoint,
+        }
+    }
+
+    /// What the command is told about `comic`, whose image is listed as
+    /// `filename`
+    fn env(&self, comic: &Comic, filename: &str) -> Vec<(&'static str, String)> {
+        vec![
+            ("XKCDFS_NUM", comic.num.to_string()),
+            ("XKCDFS_TITLE", comic.title.clone()),
+            (
+                "XKCDFS_PATH",
+                self.mountpoint
+                    .join(filename)
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+        ]
+    }
+
+    /// Run the command for `comic`, and wait for it to finish
+    pub fn run(&self, client: &XkcdClient, comic: &Comic) {
+        let image = File::Image(comic.num);
+        let filename = fs::entry_name(client, image.inode(), image.filename());
+
+        info!("Running the new comic command for comic {}", comic.num);
+
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .envs(self.env(comic, &filename))
+            .stdin(Stdio::null())
+            .status();
+
+        match status {
+            Ok(s) if s.success() => {}
+            Ok(s) => warn!(
+                "The new comic command for comic {} failed ({})",
+                comic.num, s
+            ),
+            Err(e) => warn!("Could not run the new comic command: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn describes_the_comic() {
+        let hook = Hook::new("true".to_string(), PathBuf::from("/mnt/xkcd"));
+        let comic = Comic {
+            num: 614,
+            day: 24,
+            month: 7,
+            year: 2009,
+            link: None,
+            news: None,
+            alt: String::new(),
+            title: "Woodpecker".to_string(),
+            safe_title: "Woodpecker".to_string(),
+            transcript: None,
+            img_url: String::new(),
+            img_len: None,
+            extra_parts: None,
+        };
+
+        assert_eq!(
+            hook.env(&comic, "comic_0614.png"),
+            vec![
+                ("XKCDFS_NUM", "614".to_string()),
+                ("XKCDFS_TITLE", "Woodpecker".to_string()),
+                ("XKCDFS_PATH", "/mnt/xkcd/comic_0614.png".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,106 @@
+//! What `serve-dav` and `serve-http` have in common
+//!
+//! Both answer requests on a few threads, each with its own client, since
+//! clients can't be shared between threads.
+
+use std::sync::Arc;
+use std::thread;
+use tiny_http::{Header, Request, Server};
+
+/// Requests handled at once
+const THREADS: usize = 4;
+
+/// Something that answers requests on one thread
+pub trait Handler: Send + 'static {
+    fn handle(&self, request: Request);
+}
+
+/// Serve on `listen` until the process is killed
+///
+/// `what` names the server in the log. `make_handler` is called once per
+/// thread.
+pub fn serve<H, F>(listen: &str, what: &str, mut make_handler: F) -> Result<(), String>
+where
+    H: Handler,
+    F: FnMut() -> Result<H, String>,
+{
+    let server =
+        Server::http(listen).map_err(|e| format!("Could not listen on {}: {}", listen, e))?;
+    let server = Arc::new(server);
+
+    info!("Serving {} at http://{}/", what, server.server_addr());
+
+    let mut threads = Vec::new();
+
+    for i in 0..THREADS {
+        let server = Arc::clone(&server);
+        let handler = make_handler()?;
+        let what = what.to_string();
+
+        let thread = thread::Builder::new()
+            .name(format!("http-{}", i))
+            .spawn(move || loop {
+                match server.recv() {
+                    Ok(request) => handler.handle(request),
+                    Err(e) => warn!("Could not receive a {} request: {}", what, e),
+                }
+            })
+            .map_err(|e| e.to_string())?;
+
+        threads.push(thread);
+    }
+
+    for thread in threads {
+        if thread.join().is_err() {
+            return Err(format!("A {} thread panicked", what));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field.as_bytes(), value.as_bytes()).expect("Invalid header")
+}
+
+/// Make text safe to put in XML or HTML, including in attributes
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The content type of a file in the tree, by name
+pub fn content_type(name: &str) -> &'static str {
+    if name == "raw_image" {
+        return "application/octet-stream";
+    }
+
+    match name.rsplit('.').next() {
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("jpg") => "image/jpeg",
+        Some("pdf") => "application/pdf",
+        Some("svg") => "image/svg+xml",
+        _ => "text/plain; charset=utf-8",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn content_types() {
+        assert_eq!(content_type("comic_0001.png"), "image/png");
+        assert_eq!(content_type("comic_0001.gif"), "image/gif");
+        assert_eq!(content_type("raw_image"), "application/octet-stream");
+        assert_eq!(content_type("alt"), "text/plain; charset=utf-8");
+    }
+
+    #[test]
+    fn escapes_markup() {
+        assert_eq!(escape("a < b & \"c\""), "a &lt; b &amp; &quot;c&quot;");
+    }
+}
@@ -0,0 +1,107 @@
+//! Making sure only one xkcdfs uses a database at a time
+//!
+//! Two instances inserting into and evicting from the same cache can leave it
+//! in a mess, so each one holds an exclusive lock on a file next to the
+//! database. The holder writes its PID and what it's doing into the file, so
+//! that anything else trying to take the lock can say who has it.
+
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+/// Held for as long as this process uses the database
+pub struct DatabaseLock {
+    _file: File,
+}
+
+/// Describe whoever holds a lock, from the contents of its lock file
+fn describe_holder(contents: &str) -> String {
+    let mut lines = contents.lines();
+
+    match (lines.next(), lines.next()) {
+        (Some(pid), Some(activity)) => format!("xkcdfs (PID {}, {})", pid, activity),
+        (Some(pid), None) => format!("xkcdfs (PID {})", pid),
+        _ => "xkcdfs".to_string(),
+    }
+}
+
+impl DatabaseLock {
+    /// Lock `database`, noting that this process is doing `activity`
+    ///
+    /// Fails straight away if another process holds the lock.
+    pub fn acquire(database: &OsStr, activity: &str) -> Result<Self, String> {
+        let mut path = database.to_owned();
+        path.push(".lock");
+        let path = PathBuf::from(path);
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            // Whoever holds the lock wrote what they're doing in there
+            .truncate(false)
+            .open(&path)
+            .map_err(|e| format!("Could not open {}: {}", path.display(), e))?;
+
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let error = io::Error::last_os_error();
+
+            if error.raw_os_error() != Some(libc::EWOULDBLOCK) {
+                return Err(format!("Could not lock {}: {}", path.display(), error));
+            }
+
+            let mut contents = String::new();
+            file.read_to_string(&mut contents).ok();
+
+            return Err(format!(
+                "Another {} is already using {}",
+                describe_holder(&contents),
+                database.to_string_lossy()
+            ));
+        }
+
+        file.set_len(0)
+            .and_then(|_| write!(file, "{}\n{}\n", std::process::id(), activity))
+            .map_err(|e| format!("Could not write to {}: {}", path.display(), e))?;
+
+        Ok(Self { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn describes_the_holder() {
+        assert_eq!(
+            describe_holder("1234\nmounted at /mnt/xkcd\n"),
+            "xkcdfs (PID 1234, mounted at /mnt/xkcd)"
+        );
+        assert_eq!(describe_holder("1234\n"), "xkcdfs (PID 1234)");
+        assert_eq!(describe_holder(""), "xkcdfs");
+    }
+
+    #[test]
+    fn second_lock_fails() {
+        let dir = std::env::temp_dir().join(format!("xkcdfs-lock-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let database = dir.join("cache.db");
+
+        let first = DatabaseLock::acquire(database.as_os_str(), "mounted at /mnt/xkcd").unwrap();
+
+        // flock locks belong to the open file, so this conflicts even from
+        // the same process
+        let error = DatabaseLock::acquire(database.as_os_str(), "not mounted")
+            .err()
+            .unwrap();
+        assert!(error.contains("mounted at /mnt/xkcd"), "{}", error);
+
+        drop(first);
+        assert!(DatabaseLock::acquire(database.as_os_str(), "not mounted").is_ok());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,901 @@
+//! Logging to stderr, a file, syslog, or journald
+//!
+//! Log files can be rotated by size or by date, and are reopened on SIGHUP so
+//! external tools like logrotate can move them out of the way. Messages logged
+//! while working on a particular comic are tagged with its number, which
+//! journald gets as a separate `XKCD_COMIC` field.
+//!
+//! Lines on stderr or in a file can also be written as JSON, one object per
+//! line, for log shippers. Those include the operation being worked on and how
+//! long it has been running.
+//!
+//! The level can be set per module with a filter like
+//! `xkcdfs_core::requests=trace,xkcdfs::fs=warn`.
+//!
+//! When something keeps failing the same way, like every read while xkcd.com
+//! is down, only the first warning or error is logged in full. Repeats are
+//! held back for a minute, then summed up in one line.
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::units::parse_size;
+use xkcdfs_core::scope;
+
+pub use xkcdfs_core::scope::{comic_scope, operation_scope, set_outcome};
+
+/// Only log messages from this crate and xkcdfs_core, not from dependencies
+const TARGET: &str = "xkcdfs";
+
+/// How many old log files are kept when rotating
+const ROTATED_FILES: u32 = 5;
+
+/// Where journald listens for its native protocol
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// How long repeats of a warning or error are held back after it's logged
+const REPEAT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Most different warnings and errors watched for repeats at once, past which
+/// new ones are logged every time
+const MAX_WATCHED: usize = 1000;
+
+/// When to start a new log file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rotation {
+    Never,
+    /// Once the file would grow past this many bytes
+    Size(u64),
+    /// At the first message after midnight, local time
+    Daily,
+}
+
+impl FromStr for Rotation {
+    type Err = String;
+
+    /// Parse `never`, `daily`, or a size like `10M`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => return Ok(Self::Never),
+            "daily" => return Ok(Self::Daily),
+            _ => {}
+        }
+
+        match parse_size(s) {
+            Ok(0) | Err(_) => Err(format!(
+                "Invalid log rotation {:?}, expected never, daily, or a size like 10M",
+                s
+            )),
+            Ok(size) => Ok(Self::Size(size)),
+        }
+    }
+}
+
+/// How lines written to stderr or a file look
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("Invalid log format {:?}, expected text or json", s)),
+        }
+    }
+}
+
+/// One `module=level` part of a filter
+#[derive(Clone, Debug, PartialEq)]
+struct Directive {
+    module: String,
+    level: LevelFilter,
+}
+
+/// Log levels for particular modules, overriding the overall level
+///
+/// Written as comma-separated `module=level` directives. A bare level
+/// replaces the overall level, and the most specific module wins.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Filter {
+    default: Option<LevelFilter>,
+    directives: Vec<Directive>,
+}
+
+impl Filter {
+    /// The level for messages from `target`, if the filter sets one
+    fn level_for(&self, target: &str) -> Option<LevelFilter> {
+        self.directives
+            .iter()
+            .filter(|d| {
+                target == d.module
+                    || (target.starts_with(&d.module) && target[d.module.len()..].starts_with("::"))
+            })
+            .max_by_key(|d| d.module.len())
+            .map(|d| d.level)
+            .or(self.default)
+    }
+
+    /// The most verbose level the filter allows anywhere
+    fn max_level(&self) -> Option<LevelFilter> {
+        self.directives
+            .iter()
+            .map(|d| d.level)
+            .chain(self.default)
+            .max()
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = Self::default();
+
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (module, level) = match part.find('=') {
+                Some(i) => (Some(&part[..i]), &part[i + 1..]),
+                None => (None, part),
+            };
+
+            let level = level
+                .parse::<LevelFilter>()
+                .map_err(|_| format!("Invalid log level {:?} in filter", level))?;
+
+            match module {
+                Some("") => return Err(format!("Missing module name in {:?}", part)),
+                Some(module) => filter.directives.push(Directive {
+                    module: module.to_string(),
+                    level,
+                }),
+                None => filter.default = Some(level),
+            }
+        }
+
+        Ok(filter)
+    }
+}
+
+/// Where log messages go
+pub enum Destination {
+    Stderr,
+    File { path: PathBuf, rotation: Rotation },
+    Syslog,
+    Journald,
+}
+
+/// Today's date, for noticing when a daily log needs rotating
+fn today() -> (i32, i32) {
+    let now = time::now();
+
+    (now.tm_year, now.tm_yday)
+}
+
+struct LogFile {
+    path: PathBuf,
+    rotation: Rotation,
+    file: File,
+    size: u64,
+    opened_on: (i32, i32),
+}
+
+impl LogFile {
+    fn open(path: &Path, rotation: Rotation) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path: path.to_owned(),
+            rotation,
+            file,
+            size,
+            opened_on: today(),
+        })
+    }
+
+    fn reopen(&mut self) -> io::Result<()> {
+        *self = Self::open(&self.path, self.rotation)?;
+
+        Ok(())
+    }
+
+    /// The name of the `n`th most recent old log file
+    fn rotated_path(&self, n: u32) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+
+        name.into()
+    }
+
+    /// Move the current file to `.1`, `.1` to `.2`, and so on, and start a
+    /// new one
+    fn rotate(&mut self) -> io::Result<()> {
+        for n in (1..ROTATED_FILES).rev() {
+            let from = self.rotated_path(n);
+
+            if from.exists() {
+                std::fs::rename(&from, self.rotated_path(n + 1))?;
+            }
+        }
+
+        std::fs::rename(&self.path, self.rotated_path(1))?;
+
+        self.reopen()
+    }
+
+    fn needs_rotation(&self, incoming: u64) -> bool {
+        match self.rotation {
+            Rotation::Never => false,
+            Rotation::Size(max) => self.size > 0 && self.size + incoming > max,
+            Rotation::Daily => self.opened_on != today(),
+        }
+    }
+
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        if self.needs_rotation(line.len() as u64) {
+            self.rotate()?;
+        }
+
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+
+        Ok(())
+    }
+}
+
+/// Where messages actually get written
+enum Sink {
+    Stderr,
+    File {
+        file: Mutex<LogFile>,
+        /// Set by the SIGHUP handler
+        reopen: Arc<AtomicBool>,
+    },
+    Syslog,
+    Journald(UnixDatagram),
+}
+
+/// The overall level, and any per-module levels
+struct Levels {
+    level: LevelFilter,
+    filter: Filter,
+}
+
+impl Levels {
+    /// The most verbose level anything can be logged at
+    fn max(&self) -> LevelFilter {
+        // Messages for a more verbose module still need to get through
+        self.filter
+            .max_level()
+            .map_or(self.level, |m| m.max(self.level))
+    }
+}
+
+/// Changes the log levels after the logger is set up
+#[derive(Clone)]
+pub struct LevelHandle(Arc<RwLock<Levels>>);
+
+impl LevelHandle {
+    pub fn set(&self, level: LevelFilter, filter: Filter) {
+        let levels = Levels { level, filter };
+
+        log::set_max_level(levels.max());
+        *self.0.write().unwrap() = levels;
+    }
+}
+
+/// A warning or error, as far as telling repeats apart goes
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct Message {
+    level: Level,
+    target: String,
+    comic: Option<u32>,
+    text: String,
+}
+
+/// Warnings and errors logged lately, and how often each has been repeated
+#[derive(Default)]
+struct Repeats {
+    /// When each was logged in full, and repeats since
+    watched: HashMap<Message, (Instant, u64)>,
+}
+
+impl Repeats {
+    /// Whether `message` should be logged as of `now`, rather than held back
+    fn first(&mut self, message: &Message, now: Instant) -> bool {
+        match self.watched.get_mut(message) {
+            Some((_, repeats)) => {
+                *repeats += 1;
+                false
+            }
+            None => {
+                if self.watched.len() < MAX_WATCHED {
+                    self.watched.insert(message.clone(), (now, 0));
+                }
+
+                true
+            }
+        }
+    }
+
+    /// Stop watching messages logged over `REPEAT_WINDOW` before `now`,
+    /// returning those that were repeated and how many times
+    fn expire(&mut self, now: Instant) -> Vec<(Message, u64)> {
+        let mut repeated = Vec::new();
+
+        self.watched.retain(|message, (logged, repeats)| {
+            if now.duration_since(*logged) < REPEAT_WINDOW {
+                return true;
+            }
+
+            if *repeats > 0 {
+                repeated.push((message.clone(), *repeats));
+            }
+
+            false
+        });
+
+        repeated
+    }
+}
+
+struct Logger {
+    levels: Arc<RwLock<Levels>>,
+    format: Format,
+    sink: Sink,
+    repeats: Mutex<Repeats>,
+}
+
+/// What this thread was doing when a message was logged
+struct Context {
+    comic: Option<u32>,
+    operation: Option<&'static str>,
+    /// How long the operation has been running, in milliseconds
+    duration_ms: Option<f64>,
+}
+
+impl Context {
+    fn current() -> Self {
+        let operation = scope::current_operation();
+
+        Self {
+            comic: scope::current_comic(),
+            operation: operation.map(|(name, _)| name),
+            duration_ms: operation.map(|(_, start)| {
+                let elapsed = start.elapsed();
+                elapsed.as_secs() as f64 * 1e3 + elapsed.subsec_nanos() as f64 / 1e6
+            }),
+        }
+    }
+}
+
+/// A log line in JSON form
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    timestamp: String,
+    level: &'a str,
+    target: &'a str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comic: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    operation: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_ms: Option<f64>,
+}
+
+fn text_line(record: &Record, context: &Context) -> String {
+    let time = time::now();
+    let comic = context
+        .comic
+        .map(|n| format!("[comic {}] ", n))
+        .unwrap_or_default();
+
+    format!(
+        "{}.{:03} [{:5}] {}{}\n",
+        time.strftime("%Y-%m-%d %H:%M:%S").unwrap(),
+        time.tm_nsec / 1_000_000,
+        record.level(),
+        comic,
+        record.args()
+    )
+}
+
+fn json_line(record: &Record, context: &Context) -> String {
+    let time = time::now_utc();
+    let line = JsonLine {
+        timestamp: format!(
+            "{}.{:03}Z",
+            time.strftime("%Y-%m-%dT%H:%M:%S").unwrap(),
+            time.tm_nsec / 1_000_000
+        ),
+        level: record.level().as_str(),
+        target: record.target(),
+        message: record.args().to_string(),
+        comic: context.comic,
+        operation: context.operation,
+        duration_ms: context.duration_ms,
+    };
+
+    // Nothing in there can fail to serialize
+    serde_json::to_string(&line).unwrap() + "\n"
+}
+
+/// The syslog severity for a log level
+fn severity(level: Level) -> libc::c_int {
+    match level {
+        Level::Error => libc::LOG_ERR,
+        Level::Warn => libc::LOG_WARNING,
+        Level::Info => libc::LOG_INFO,
+        Level::Debug | Level::Trace => libc::LOG_DEBUG,
+    }
+}
+
+/// Add a field to a journald message
+///
+/// Values with newlines need the binary form, with an explicit length.
+fn journal_field(message: &mut Vec<u8>, name: &str, value: &str) {
+    message.extend_from_slice(name.as_bytes());
+
+    if value.contains('\n') {
+        message.push(b'\n');
+        message.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        message.push(b'=');
+    }
+
+    message.extend_from_slice(value.as_bytes());
+    message.push(b'\n');
+}
+
+impl Logger {
+    fn write_line(&self, record: &Record, context: &Context) -> Result<(), String> {
+        let line = match self.format {
+            Format::Text => text_line(record, context),
+            Format::Json => json_line(record, context),
+        };
+
+        let (file, reopen) = match self.sink {
+            Sink::File {
+                ref file,
+                ref reopen,
+            } => (file, reopen),
+            _ => {
+                eprint!("{}", line);
+                return Ok(());
+            }
+        };
+
+        let mut file = file.lock().unwrap();
+
+        if reopen.swap(false, Ordering::Relaxed) {
+            file.reopen()
+                .map_err(|e| format!("Could not reopen {}: {}", file.path.display(), e))?;
+        }
+
+        file.write(&line)
+            .map_err(|e| format!("Could not write to {}: {}", file.path.display(), e))
+    }
+
+    fn write_syslog(&self, record: &Record, comic: Option<u32>) -> Result<(), String> {
+        let message = match comic {
+            Some(n) => format!("[comic {}] {}", n, record.args()),
+            None => record.args().to_string(),
+        };
+
+        // Neither can contain a NUL after this
+        let message = CString::new(message.replace('\0', "")).unwrap();
+        let format = CString::new("%s").unwrap();
+
+        unsafe { libc::syslog(severity(record.level()), format.as_ptr(), message.as_ptr()) };
+
+        Ok(())
+    }
+
+    fn write_journal(
+        &self,
+        socket: &UnixDatagram,
+        record: &Record,
+        context: &Context,
+    ) -> Result<(), String> {
+        let mut message = Vec::new();
+
+        journal_field(&mut message, "MESSAGE", &record.args().to_string());
+        journal_field(
+            &mut message,
+            "PRIORITY",
+            &severity(record.level()).to_string(),
+        );
+        journal_field(&mut message, "SYSLOG_IDENTIFIER", TARGET);
+        journal_field(&mut message, "TARGET", record.target());
+
+        if let Some(file) = record.file() {
+            journal_field(&mut message, "CODE_FILE", file);
+        }
+        if let Some(line) = record.line() {
+            journal_field(&mut message, "CODE_LINE", &line.to_string());
+        }
+        if let Some(n) = context.comic {
+            journal_field(&mut message, "XKCD_COMIC", &n.to_string());
+        }
+        if let Some(operation) = context.operation {
+            journal_field(&mut message, "XKCD_OPERATION", operation);
+        }
+        if let Some(duration) = context.duration_ms {
+            journal_field(
+                &mut message,
+                "XKCD_DURATION_MS",
+                &format!("{:.3}", duration),
+            );
+        }
+
+        socket
+            .send_to(&message, JOURNALD_SOCKET)
+            .map(|_| ())
+            .map_err(|e| format!("Could not write to the journal: {}", e))
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let levels = self.levels.read().unwrap();
+        let level = levels
+            .filter
+            .level_for(metadata.target())
+            .unwrap_or(levels.level);
+
+        metadata.level() <= level && metadata.target().starts_with(TARGET)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let context = Context::current();
+        let now = Instant::now();
+
+        // Taken out first, so the lock isn't held while writing
+        let repeated = self.repeats.lock().unwrap().expire(now);
+
+        for (message, repeats) in repeated {
+            self.write_repeated(&message, repeats);
+        }
+
+        if record.level() <= Level::Warn {
+            let message = Message {
+                level: record.level(),
+                target: record.target().to_string(),
+                comic: context.comic,
+                text: record.args().to_string(),
+            };
+
+            if !self.repeats.lock().unwrap().first(&message, now) {
+                return;
+            }
+        }
+
+        self.write(record, &context);
+    }
+
+    fn flush(&self) {
+        if let Sink::File { ref file, .. } = self.sink {
+            file.lock().unwrap().file.flush().ok();
+        }
+    }
+}
+
+impl Logger {
+    fn write(&self, record: &Record, context: &Context) {
+        let result = match self.sink {
+            Sink::Stderr | Sink::File { .. } => self.write_line(record, context),
+            Sink::Syslog => self.write_syslog(record, context.comic),
+            Sink::Journald(ref socket) => self.write_journal(socket, record, context),
+        };
+
+        // There's nowhere better to complain, and stderr may well be
+        // /dev/null by now
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            eprintln!("[{}] {}", record.level(), record.args());
+        }
+    }
+
+    /// Sum up the repeats of a message that were held back
+    fn write_repeated(&self, message: &Message, repeats: u64) {
+        let context = Context {
+            comic: message.comic,
+            operation: None,
+            duration_ms: None,
+        };
+
+        self.write(
+            &Record::builder()
+                .level(message.level)
+                .target(&message.target)
+                .args(format_args!(
+                    "Last message repeated {} times: {}",
+                    repeats, message.text
+                ))
+                .build(),
+            &context,
+        );
+    }
+}
+
+/// Set up the global logger
+pub fn init(
+    level: LevelFilter,
+    filter: Filter,
+    format: Format,
+    destination: &Destination,
+) -> Result<LevelHandle, String> {
+    let sink = match *destination {
+        Destination::Stderr => Sink::Stderr,
+        Destination::File { ref path, rotation } => {
+            // Keep working if the daemon changes directory
+            let path = std::env::current_dir()
+                .map_err(|e| e.to_string())?
+                .join(path);
+
+            let file = LogFile::open(&path, rotation)
+                .map_err(|e| format!("Could not open {}: {}", path.display(), e))?;
+
+            let reopen = Arc::new(AtomicBool::new(false));
+
+            signal_hook::flag::register(signal_hook::SIGHUP, Arc::clone(&reopen))
+                .map_err(|e| format!("Could not handle SIGHUP: {}", e))?;
+
+            Sink::File {
+                file: Mutex::new(file),
+                reopen,
+            }
+        }
+        Destination::Syslog => {
+            // openlog keeps the pointer, so the name has to live forever
+            static IDENT: &[u8] = b"xkcdfs\0";
+
+            unsafe {
+                libc::openlog(
+                    IDENT.as_ptr() as *const libc::c_char,
+                    libc::LOG_PID,
+                    libc::LOG_DAEMON,
+                )
+            };
+
+            Sink::Syslog
+        }
+        Destination::Journald => {
+            if !Path::new(JOURNALD_SOCKET).exists() {
+                return Err(format!(
+                    "journald is not running ({} does not exist)",
+                    JOURNALD_SOCKET
+                ));
+            }
+
+            Sink::Journald(UnixDatagram::unbound().map_err(|e| e.to_string())?)
+        }
+    };
+
+    let levels = Levels { level, filter };
+    let max_level = levels.max();
+    let levels = Arc::new(RwLock::new(levels));
+
+    log::set_boxed_logger(Box::new(Logger {
+        levels: Arc::clone(&levels),
+        format,
+        sink,
+        repeats: Mutex::new(Repeats::default()),
+    }))
+    .map_err(|e| e.to_string())?;
+
+    log::set_max_level(max_level);
+
+    Ok(LevelHandle(levels))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn holds_back_repeats() {
+        let message = |text: &str| Message {
+            level: Level::Warn,
+            target: "xkcdfs::fs".to_string(),
+            comic: Some(614),
+            text: text.to_string(),
+        };
+        let down = message("Could not get comic 614 on the network: timed out");
+        let mut repeats = Repeats::default();
+        let start = Instant::now();
+
+        assert!(repeats.first(&down, start));
+        assert!(!repeats.first(&down, start));
+        assert!(!repeats.first(&down, start + Duration::from_secs(30)));
+        assert!(repeats.first(&message("Something else"), start));
+        assert!(repeats.first(
+            &Message {
+                comic: None,
+                ..down.clone()
+            },
+            start
+        ));
+
+        assert_eq!(repeats.expire(start + Duration::from_secs(59)), vec![]);
+        assert_eq!(
+            repeats.expire(start + REPEAT_WINDOW),
+            vec![(down.clone(), 2)]
+        );
+
+        // Logged in full again once it's been summed up
+        assert!(repeats.first(&down, start + REPEAT_WINDOW));
+    }
+
+    #[test]
+    fn parses_rotation() {
+        assert_eq!("never".parse(), Ok(Rotation::Never));
+        assert_eq!("daily".parse(), Ok(Rotation::Daily));
+        assert_eq!("4096".parse(), Ok(Rotation::Size(4096)));
+        assert_eq!("10K".parse(), Ok(Rotation::Size(10 << 10)));
+        assert_eq!("10M".parse(), Ok(Rotation::Size(10 << 20)));
+        assert_eq!("1g".parse(), Ok(Rotation::Size(1 << 30)));
+
+        for bad in &["", "0", "M", "weekly", "-5M", "99999999999G"] {
+            assert!(bad.parse::<Rotation>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn parses_filters() {
+        let filter: Filter = "xkcdfs::requests=trace, xkcdfs::fs=warn".parse().unwrap();
+
+        assert_eq!(filter.default, None);
+        assert_eq!(
+            filter.level_for("xkcdfs::requests"),
+            Some(LevelFilter::Trace)
+        );
+        assert_eq!(
+            filter.level_for("xkcdfs::fs::render"),
+            Some(LevelFilter::Warn)
+        );
+        assert_eq!(filter.level_for("xkcdfs::image"), None);
+        assert_eq!(filter.max_level(), Some(LevelFilter::Trace));
+
+        let filter: Filter = "error,xkcdfs::fs=debug".parse().unwrap();
+
+        assert_eq!(filter.level_for("xkcdfs::image"), Some(LevelFilter::Error));
+        assert_eq!(filter.level_for("xkcdfs::fs"), Some(LevelFilter::Debug));
+
+        for bad in &["xkcdfs=loud", "=info", "verbose"] {
+            assert!(bad.parse::<Filter>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn most_specific_filter_wins() {
+        let filter: Filter = "xkcdfs::fs::render=off,xkcdfs::fs=trace,xkcdfs=warn"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            filter.level_for("xkcdfs::fs::render"),
+            Some(LevelFilter::Off)
+        );
+        assert_eq!(
+            filter.level_for("xkcdfs::fs::file"),
+            Some(LevelFilter::Trace)
+        );
+        assert_eq!(filter.level_for("xkcdfs::fsx"), Some(LevelFilter::Warn));
+        assert_eq!(filter.level_for("xkcdfs"), Some(LevelFilter::Warn));
+    }
+
+    #[test]
+    fn parses_format() {
+        assert_eq!("text".parse(), Ok(Format::Text));
+        assert_eq!("json".parse(), Ok(Format::Json));
+        assert!("yaml".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn json_lines_include_context() {
+        let context = Context {
+            comic: Some(353),
+            operation: Some("read"),
+            duration_ms: Some(1.5),
+        };
+
+        let line = json_line(
+            &Record::builder()
+                .args(format_args!("Say \"hi\""))
+                .level(Level::Info)
+                .target("xkcdfs::fs")
+                .build(),
+            &context,
+        );
+
+        assert!(line.ends_with('\n'));
+
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+
+        assert_eq!(value["level"], "INFO");
+        assert_eq!(value["target"], "xkcdfs::fs");
+        assert_eq!(value["message"], "Say \"hi\"");
+        assert_eq!(value["comic"], 353);
+        assert_eq!(value["operation"], "read");
+        assert_eq!(value["duration_ms"], 1.5);
+        assert!(value["timestamp"].as_str().unwrap().ends_with('Z'));
+    }
+
+    #[test]
+    fn json_lines_leave_out_missing_context() {
+        let context = Context {
+            comic: None,
+            operation: None,
+            duration_ms: None,
+        };
+
+        let line = json_line(
+            &Record::builder()
+                .args(format_args!("Starting"))
+                .level(Level::Warn)
+                .target("xkcdfs")
+                .build(),
+            &context,
+        );
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+
+        assert!(value.get("comic").is_none());
+        assert!(value.get("operation").is_none());
+        assert!(value.get("duration_ms").is_none());
+    }
+
+    #[test]
+    fn encodes_journal_fields() {
+        let mut message = Vec::new();
+
+        journal_field(&mut message, "MESSAGE", "hello");
+        assert_eq!(message, b"MESSAGE=hello\n");
+
+        message.clear();
+        journal_field(&mut message, "MESSAGE", "two\nlines");
+        assert_eq!(message, b"MESSAGE\n\x09\0\0\0\0\0\0\0two\nlines\n");
+    }
+
+    #[test]
+    fn rotates_by_size() {
+        let dir = std::env::temp_dir().join(format!("xkcdfs-log-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("xkcdfs.log");
+
+        let mut file = LogFile::open(&path, Rotation::Size(10)).unwrap();
+
+        for line in &["first\n", "second\n", "third\n"] {
+            file.write(line).unwrap();
+        }
+
+        let read = |p: PathBuf| std::fs::read_to_string(p).unwrap();
+
+        assert_eq!(read(path.clone()), "third\n");
+        assert_eq!(read(file.rotated_path(1)), "second\n");
+        assert_eq!(read(file.rotated_path(2)), "first\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,53 +1,787 @@
 #[macro_use]
 extern crate log;
 
+mod archive;
+mod backup;
+mod check;
 mod cli;
+mod config;
+mod control;
+mod crash;
+mod daemon;
+mod dav;
+mod desktop;
+mod exit;
+mod export;
+mod feed;
 mod fs;
-mod image;
-mod requests;
-mod xkcd;
+mod fstab;
+mod gallery;
+mod history;
+mod hook;
+mod http;
+mod lock;
+mod logging;
+mod mirror;
+mod notify;
+mod pack;
+mod prefetch;
+mod privileges;
+mod refresh;
+mod reload;
+mod source;
+mod stats;
+mod timings;
+mod units;
 
 pub use fs::file::File;
-pub use requests::XkcdClient;
-pub use xkcd::Comic;
+pub use xkcdfs_core::Comic;
 
+/// The client everything in the binary uses, for comics from either source
+pub type XkcdClient = xkcdfs_core::XkcdClient<source::Source>;
+
+// The rest of the binary refers to these as if they were its own modules
+use xkcdfs_core::{image, requests};
+
+use archive::Archive;
+use cli::Command;
+use daemon::{Daemon, Pidfile};
+use export::ExportFormat;
+use feed::{Feed, UpdateSource};
+use pack::Pack;
 use requests::RequestMode::*;
-use simplelog::{ConfigBuilder, SimpleLogger};
+use source::Source;
 use std::ffi::OsStr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use xkcdfs_core::{SqliteStore, XkcdApi};
 
 fn main() {
-    let conf = cli::get_args().unwrap();
+    if let Err(code) = run() {
+        std::process::exit(code);
+    }
+}
+
+/// Everything `main` does, returning the exit code on failure
+fn run() -> Result<(), i32> {
+    let conf = match cli::get_args() {
+        Ok(Some(c)) => c,
+        Ok(None) => return Ok(()),
+        Err(e) => {
+            eprintln!("{}", e);
+            return Err(exit::CONFIG);
+        }
+    };
+
+    // Checks report on stdout, not in the log
+    if let Command::Check {
+        ref mountpoint,
+        network,
+    } = conf.command
+    {
+        if !check::run(&conf, Path::new(mountpoint), network) {
+            return Err(exit::FAILURE);
+        }
+
+        return Ok(());
+    }
+
+    // So does history, which only reads the database and works while mounted
+    if let Command::CacheHistory { since } = conf.command {
+        match requests::fetch_history(&conf.database, since) {
+            Ok(fetches) => print!("{}", history::report(&fetches)),
+            Err(e) => {
+                eprintln!("Could not read the fetch log: {}", e);
+                return Err(exit::DATABASE);
+            }
+        }
+
+        return Ok(());
+    }
+
+    // Clearing only deletes from the database, so it works while mounted too
+    if let Command::CacheClear { rendered } = conf.command {
+        if rendered {
+            match requests::clear_rendered(&conf.database) {
+                Ok(freed) => println!("Cleared {} bytes of rendered images", freed),
+                Err(e) => {
+                    eprintln!("Could not clear rendered images: {}", e);
+                    return Err(exit::DATABASE);
+                }
+            }
+        }
 
-    SimpleLogger::init(
+        return Ok(());
+    }
+
+    let log_levels = match logging::init(
         conf.log_level,
-        ConfigBuilder::new().add_filter_allow_str("xkcdfs").build(),
-    )
-    .unwrap();
+        conf.log_filter.clone(),
+        conf.log_format,
+        &conf.log_destination,
+    ) {
+        Ok(l) => l,
+        Err(e) => {
+            eprintln!("Could not set up logging: {}", e);
+            return Err(exit::CONFIG);
+        }
+    };
+
+    if let Err(e) = timings::init() {
+        warn!("Could not time operations: {}", e);
+    }
+
+    // This has to come before anything starts a thread. systemd needs to hear
+    // from the process it started, so there's no daemonizing under it.
+    let mut daemon = match conf.command {
+        Command::Mount { .. } if !conf.foreground && !notify::expected() => {
+            match daemon::daemonize() {
+                Ok(d) => Some(d),
+                Err(e) => {
+                    error!("Could not start daemon: {}", e);
+                    return Err(exit::FAILURE);
+                }
+            }
+        }
+        _ => None,
+    };
+
+    let result = serve(conf, log_levels, &mut daemon);
+
+    // If the daemon never got as far as detaching, the original process is
+    // still waiting to hear how it went
+    if let (Err(code), Some(daemon)) = (result, daemon) {
+        daemon.fail(code);
+    }
+
+    result
+}
+
+/// Clients for the `[[source]]`s in the config file, each with its own cache
+fn source_clients(conf: &cli::Config) -> Result<Vec<(config::SourceSettings, XkcdClient)>, String> {
+    let mut clients = Vec::new();
+
+    for source in &conf.sources {
+        let api = XkcdApi::new(conf.timeout, conf.user_agent.clone(), conf.headers.clone())?
+            .with_urls(source.latest.clone(), source.comic.clone());
+
+        let client = XkcdClient::with_source(
+            Source::Api(api),
+            &source.database(&conf.database),
+            conf.keep_animations,
+            conf.no_render,
+            conf.render_options.clone(),
+            conf.max_cache_size,
+        )?;
+
+        // Comics are listed up to the latest one that's cached
+        match client.request_latest_comic(None, BustCache) {
+            Some(c) => info!("Most recent comic from {} is {}", source.name, c),
+            None => warn!(
+                "Could not fetch the latest comic from {}, only cached comics will be listed",
+                source.name
+            ),
+        }
+
+        clients.push((source.clone(), client));
+    }
+
+    Ok(clients)
+}
+
+/// Start a `[[source]]`'s own prefetching and checks for new comics, if it
+/// wants them
+fn start_source_threads(source: &config::SourceSettings, client: XkcdClient) -> Result<(), String> {
+    if let Some((first, last)) = source.prefetch()? {
+        let last = match last {
+            Some(l) => l,
+            None => match client.request_latest_comic(None, NoNetwork) {
+                Some(c) => c.num,
+                None => return Err("its latest comic isn't known yet".to_string()),
+            },
+        };
+
+        prefetch::spawn(
+            format!("prefetch-{}", source.name),
+            client.try_clone()?,
+            first,
+            last,
+        )?;
+    }
+
+    if let Some(interval) = source.refresh_interval()? {
+        refresh::spawn(
+            format!("refresh-{}", source.name),
+            client,
+            Arc::new(Mutex::new(Some(interval))),
+            false,
+            refresh::OnNewComic::default(),
+            None,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Mount or export, once logging and any daemon are set up
+fn serve(
+    conf: cli::Config,
+    log_levels: logging::LevelHandle,
+    daemon: &mut Option<Daemon>,
+) -> Result<(), i32> {
+    // Taken before anything is moved out of the config
+    let settings = reload::Settings::new(&conf);
+    let databases = conf.databases();
+
+    info!("Using database {}", conf.database.to_string_lossy());
+
+    // Looked up early, so a typo fails before anything is mounted
+    let run_as = match (&conf.command, &conf.run_as) {
+        (Command::Mount { .. }, Some(name)) if !privileges::is_root() => {
+            error!("--run-as {} needs xkcdfs to be started as root", name);
+            return Err(exit::CONFIG);
+        }
+        (Command::Mount { .. }, Some(name)) => match privileges::User::lookup(name) {
+            Ok(u) => Some(u),
+            Err(e) => {
+                error!("{}", e);
+                return Err(exit::CONFIG);
+            }
+        },
+        _ => None,
+    };
+
+    let activity = match conf.command {
+        Command::Mount { ref mountpoint } => format!(
+            "mounted at {}",
+            std::env::current_dir()
+                .unwrap_or_default()
+                .join(mountpoint)
+                .display()
+        ),
+        Command::ServeDav { ref listen } => format!("serving WebDAV at {}", listen),
+        Command::ServeHttp { ref listen } => format!("serving a gallery at {}", listen),
+        _ => "not mounted".to_string(),
+    };
+
+    // Taken after daemonizing so the PID is the daemon's, and held until exit
+    let _lock = match lock::DatabaseLock::acquire(&conf.database, &activity) {
+        Ok(l) => l,
+        Err(e) => {
+            error!("{}", e);
+            return Err(exit::DATABASE);
+        }
+    };
+
+    // Packs are for machines without the network, so nothing else is needed
+    if let Command::ImportPack { ref input } = conf.command {
+        return import_pack(&conf.database, input);
+    }
+
+    // Archives and packs are for machines that mustn't use the network
+    let local = conf.pack.is_some() || conf.archive.is_some();
+
+    // Other webcomics are only for mounting, and only come from the network
+    let sources = match conf.command {
+        Command::Mount { .. } if local => {
+            if !conf.sources.is_empty() {
+                warn!("Other webcomics are left out without the network");
+            }
+
+            Vec::new()
+        }
+        Command::Mount { .. } => match source_clients(&conf) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("{}", e);
+                return Err(exit::DATABASE);
+            }
+        },
+        _ => Vec::new(),
+    };
+
+    let feed = match conf.update_source {
+        UpdateSource::Feed if !local => {
+            match Feed::new(conf.timeout, conf.user_agent.clone(), conf.headers.clone()) {
+                Ok(f) => Some(f),
+                Err(e) => {
+                    error!("{}", e);
+                    return Err(exit::FAILURE);
+                }
+            }
+        }
+        _ => None,
+    };
 
-    let client = XkcdClient::new(conf.timeout, &conf.database, conf.user_agent);
+    let source = match (conf.pack, conf.archive) {
+        (Some(path), _) => match Pack::open(&path) {
+            Ok(p) => {
+                info!("Mounting {}", path.to_string_lossy());
+                Source::Pack(p)
+            }
+            Err(e) => {
+                error!("{}", e);
+                return Err(exit::FAILURE);
+            }
+        },
+        (None, Some(dir)) => match Archive::open(Path::new(&dir)) {
+            Ok(a) => Source::Archive(a),
+            Err(e) => {
+                error!("{}", e);
+                return Err(exit::FAILURE);
+            }
+        },
+        (None, None) => {
+            let mut api = match XkcdApi::new(conf.timeout, conf.user_agent, conf.headers) {
+                Ok(a) => a,
+                Err(e) => {
+                    error!("{}", e);
+                    return Err(exit::FAILURE);
+                }
+            };
+
+            // Only for testing against a stand-in
+            if let Some(url) = conf.api_url {
+                api = api.with_urls(
+                    format!("{}/info.0.json", url),
+                    format!("{}/{{num}}/info.0.json", url),
+                );
+            }
+
+            Source::Api(api)
+        }
+    };
+
+    let client = match XkcdClient::with_source(
+        source,
+        &conf.database,
+        conf.keep_animations,
+        conf.no_render,
+        conf.render_options,
+        conf.max_cache_size,
+    ) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("{}", e);
+            return Err(exit::DATABASE);
+        }
+    };
 
     info!("Requesting latest comic (to get file count)");
 
+    let mut offline = false;
+
     let latest_comic = match client.request_latest_comic(None, BustCache) {
         Some(c) => c,
-        None => {
-            error!("Could not fetch latest comic from https://xkcd.com");
-            error!("Are you connected to the Internet?");
-            return;
-        }
+        // The cache might have everything that's needed anyway
+        None => match client.request_latest_comic(None, NoNetwork) {
+            // Without the network, there's nothing to try again later
+            Some(c) => {
+                if !local {
+                    warn!("Could not fetch latest comic from https://xkcd.com, using the cache");
+                    offline = true;
+                }
+
+                c
+            }
+            None if local => {
+                error!("Could not find any comics to mount, in the cache or otherwise");
+                return Err(exit::FAILURE);
+            }
+            None => {
+                error!("Could not fetch latest comic from https://xkcd.com");
+                error!("Are you connected to the Internet?");
+                return Err(exit::NETWORK);
+            }
+        },
     };
 
     info!("Most recent comic is {}", latest_comic);
 
-    let fs = fs::XkcdFs::new(client);
+    match conf.command {
+        Command::Mount { mountpoint } => {
+            // The filesystem takes the client, so background threads need their
+            // own
+            let prefetch = conf.prefetch.map(|range| (range, client.try_clone()));
+            let stats_client = client.try_clone();
+            let session_client = client.try_clone();
+            let control_client = client.try_clone();
+
+            // Reloading can start checking for new comics, so the thread runs
+            // whenever there's a config file, even if it starts out idle. It
+            // also keeps trying the network if that was down at startup.
+            let interval: refresh::Interval = Arc::new(Mutex::new(conf.refresh_interval));
+            let refresh =
+                if offline || conf.refresh_interval.is_some() || conf.reload.has_config_file() {
+                    Some(client.try_clone())
+                } else {
+                    None
+                };
+
+            let reloader = if conf.reload.has_config_file() {
+                match reload::Reloader::new(
+                    conf.reload,
+                    settings,
+                    log_levels,
+                    Arc::clone(&interval),
+                ) {
+                    Ok(r) => Some(r),
+                    Err(e) => {
+                        warn!("Config file will not be reloaded: {}", e);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+
+            let watchdog = notify::Watchdog::default();
+
+            let mut fs = fs::XkcdFs::new(
+                client,
+                conf.thumbnail_sizes.clone(),
+                conf.thumbnail_filter,
+                conf.ascii_width,
+                conf.render_threads,
+                conf.fuse_threads,
+                reloader,
+                watchdog.clone(),
+            );
+
+            // Each source prefetches and checks for new comics on its own
+            let source_threads: Vec<_> = sources
+                .iter()
+                .map(|(source, client)| (source.clone(), client.try_clone()))
+                .collect();
+
+            for (source, client) in sources {
+                if source.translation {
+                    fs.add_translation(source.name, client);
+                } else {
+                    fs.add_source(source.name, client);
+                }
+            }
+
+            fs.set_max_memory(conf.max_memory);
+            fs.set_mount_options(conf.mount_options.joined());
+            fs.set_hidden(conf.hidden);
+            fs.set_track_atime(conf.track_atime);
+            fs.set_raw_transcripts(conf.raw_transcripts);
+            fs.set_wallpaper(conf.wallpaper);
+
+            let options = conf.mount_options.args();
+            let options = options.iter().map(|o| o.as_ref()).collect::<Vec<&OsStr>>();
+
+            match fs::mount::recover_stale_mount(Path::new(&mountpoint)) {
+                Ok(true) => info!(
+                    "Cleaned up a stale mount at {}",
+                    mountpoint.to_string_lossy()
+                ),
+                Ok(false) => {}
+                Err(e) => {
+                    error!("Could not clean up a stale mount: {}", e);
+                    return Err(exit::MOUNTPOINT);
+                }
+            }
+
+            // Unmounting happens after the daemon changes directory
+            let mountpoint = match Path::new(&mountpoint).canonicalize() {
+                Ok(m) => m,
+                Err(e) => {
+                    error!("Could not find {}: {}", mountpoint.to_string_lossy(), e);
+                    return Err(exit::MOUNTPOINT);
+                }
+            };
+
+            let mut session = match fuse::Session::new(fs, &mountpoint, &options) {
+                Ok(s) => s,
+                Err(e) => {
+                    error!("Mounting error: {}", e);
+                    return Err(exit::FUSE);
+                }
+            };
+
+            let _pidfile = match conf.pidfile.as_ref().map(|p| Pidfile::create(Path::new(p))) {
+                None => None,
+                Some(Ok(p)) => Some(p),
+                Some(Err(e)) => {
+                    error!("Could not write pidfile: {}", e);
+                    return Err(exit::FAILURE);
+                }
+            };
+
+            // Before detaching, so a failure here still gets reported
+            if let Some(user) = run_as {
+                if let Err(e) = databases
+                    .iter()
+                    .try_for_each(|d| user.take_database(d))
+                    .and_then(|_| user.switch())
+                {
+                    error!("Could not drop privileges: {}", e);
+                    return Err(exit::FAILURE);
+                }
+            }
+
+            // After dropping privileges, so the socket is the user's, and
+            // before detaching, so a failure here still gets reported
+            let control = match conf.control_socket.as_ref() {
+                None => None,
+                Some(path) => match control::Socket::bind(Path::new(path)) {
+                    Ok(s) => Some(s),
+                    Err(e) => {
+                        error!("Could not make the control socket: {}", e);
+                        return Err(exit::FAILURE);
+                    }
+                },
+            };
+
+            if let Some(daemon) = daemon.take() {
+                info!(
+                    "Mounted at {}, running in the background",
+                    mountpoint.display()
+                );
+
+                if let Err(e) = daemon.detach() {
+                    error!("Could not detach from the terminal: {}", e);
+                    return Err(exit::FAILURE);
+                }
+            }
+
+            if let Some(((first, last), prefetch_client)) = prefetch {
+                let last = last.unwrap_or(latest_comic.num);
+
+                let prefetching = prefetch_client
+                    .and_then(|c| prefetch::spawn("prefetch".to_string(), c, first, last));
+
+                if let Err(e) = prefetching {
+                    warn!("Could not start prefetching: {}", e);
+                }
+            }
+
+            if let Err(e) = stats_client.and_then(stats::spawn) {
+                warn!("Statistics will not be logged on SIGUSR1: {}", e);
+            }
+
+            if let Some(socket) = &control {
+                if let Err(e) = control_client.and_then(|c| socket.spawn(c)) {
+                    warn!("Commands will not be taken on the control socket: {}", e);
+                }
+            }
+
+            if let Some(backups) = conf.backups {
+                if let Err(e) = backup::spawn(databases.clone(), backups) {
+                    warn!("The cache will not be backed up: {}", e);
+                }
+            }
+
+            let on_new = refresh::OnNewComic {
+                hook: conf
+                    .on_new_comic
+                    .map(|command| hook::Hook::new(command, mountpoint.clone())),
+                notify: conf.notify,
+            };
+
+            if let Some(refresh_client) = refresh {
+                let refreshing = refresh_client.and_then(|c| {
+                    refresh::spawn("refresh".to_string(), c, interval, offline, on_new, feed)
+                });
 
-    let options = ["-o", "fsname=xkcdfs"]
-        .iter()
-        .map(|o| o.as_ref())
-        .collect::<Vec<&OsStr>>();
+                if let Err(e) = refreshing {
+                    warn!("Could not start checking for new comics: {}", e);
+                }
+            } else if on_new.any() {
+                warn!("--on-new-comic and --notify do nothing without --refresh-interval");
+            }
 
-    match fuse::mount(fs, &conf.mountpoint, &options) {
-        Err(e) => error!("Mounting error: {}", e),
-        Ok(()) => info!("Exiting gracefully"),
+            for (source, client) in source_threads {
+                if let Err(e) = client.and_then(|c| start_source_threads(&source, c)) {
+                    warn!(
+                        "Could not start prefetching or refreshing {}: {}",
+                        source.name, e
+                    );
+                }
+            }
+
+            let notifier = match notify::Notifier::from_env() {
+                Ok(n) => n,
+                Err(e) => {
+                    warn!("Could not notify systemd: {}", e);
+                    None
+                }
+            };
+
+            if let Some(notifier) = &notifier {
+                notifier.send(&format!(
+                    "READY=1\nSTATUS=Mounted at {}",
+                    mountpoint.display()
+                ));
+
+                if let Err(e) = watchdog.spawn(notifier) {
+                    warn!("Could not start the systemd watchdog: {}", e);
+                }
+            }
+
+            // This thread runs the session from here on, and a panic on it
+            // would otherwise leave the mount wedged
+            crash::install(mountpoint.clone(), databases);
+
+            let mounted_at = time::get_time().sec;
+            let result = session.run();
+
+            if let Some(notifier) = &notifier {
+                notifier.send("STOPPING=1");
+            }
+
+            match result {
+                Err(e) => {
+                    error!("Filesystem error: {}", e);
+                    return Err(exit::FUSE);
+                }
+                Ok(()) => {
+                    match session_client {
+                        Ok(c) => stats::record_session(&c, mounted_at),
+                        Err(e) => warn!("Could not note a summary of this session: {}", e),
+                    }
+
+                    info!("Exiting gracefully");
+                }
+            }
+        }
+        Command::Export {
+            format,
+            output,
+            first,
+            last,
+        } => {
+            let last = last.unwrap_or(latest_comic.num);
+
+            let file = match std::fs::File::create(&output) {
+                Ok(f) => f,
+                Err(e) => {
+                    error!("Could not create {}: {}", output.to_string_lossy(), e);
+                    return Err(exit::FAILURE);
+                }
+            };
+
+            let output_file = std::io::BufWriter::new(file);
+            let title = format!("xkcd {}-{}", first, last);
+
+            let result = match format {
+                ExportFormat::Epub => export::epub::EpubExporter::new(output_file, title)
+                    .and_then(|mut epub| export::run(&client, first, last, None, &mut epub)),
+                ExportFormat::Cbz => {
+                    let mut cbz = export::cbz::CbzExporter::new(output_file, title);
+                    export::run(&client, first, last, None, &mut cbz)
+                }
+                ExportFormat::Pack => {
+                    let mut pack = export::pack::PackExporter::new(output_file);
+                    export::run(&client, first, last, None, &mut pack)
+                }
+            };
+
+            match result {
+                Err(e) => {
+                    error!("{} export error: {}", format.name(), e);
+                    return Err(exit::FAILURE);
+                }
+                Ok(count) => info!("Exported {} comics to {}", count, output.to_string_lossy()),
+            }
+        }
+        Command::Mirror { rendered, delay } => {
+            match mirror::run(&client, latest_comic.num, rendered, delay) {
+                Err(e) => {
+                    error!("Mirror error: {}", e);
+                    return Err(exit::DATABASE);
+                }
+                Ok(summary) => {
+                    info!(
+                        "Mirrored {} comics ({} already done), {} failed",
+                        summary.fetched + summary.skipped,
+                        summary.skipped,
+                        summary.failed
+                    );
+
+                    if summary.corrupt > 0 {
+                        error!(
+                            "{} images failed their checksums, run again to fetch them",
+                            summary.corrupt
+                        );
+                        return Err(exit::FAILURE);
+                    }
+                }
+            }
+        }
+        Command::ServeDav { listen } => {
+            let dav = dav::serve(
+                client,
+                &listen,
+                conf.thumbnail_sizes.clone(),
+                conf.thumbnail_filter,
+                conf.ascii_width,
+                conf.raw_transcripts,
+            );
+
+            if let Err(e) = dav {
+                error!("WebDAV error: {}", e);
+                return Err(exit::FAILURE);
+            }
+        }
+        Command::ServeHttp { listen } => {
+            // The gallery only shows one size
+            let thumbnail = image::ThumbnailSpec {
+                width: conf.thumbnail_sizes[0],
+                filter: conf.thumbnail_filter,
+            };
+
+            if let Err(e) = gallery::serve(client, &listen, thumbnail) {
+                error!("Gallery error: {}", e);
+                return Err(exit::FAILURE);
+            }
+        }
+        Command::Check { .. }
+        | Command::CacheHistory { .. }
+        | Command::CacheClear { .. }
+        | Command::ImportPack { .. } => {
+            unreachable!("Checks, cache commands and imports finish before the client is created")
+        }
+    }
+
+    Ok(())
+}
+
+/// Copy the comics in the pack at `input` into the cache
+fn import_pack(database: &OsStr, input: &OsStr) -> Result<(), i32> {
+    let pack = match Pack::open(input) {
+        Ok(p) => p,
+        Err(e) => {
+            error!("{}", e);
+            return Err(exit::FAILURE);
+        }
+    };
+
+    let imported = SqliteStore::open(database).and_then(|store| pack::import(&pack, &store));
+
+    match imported {
+        Err(e) => {
+            error!("Import error: {}", e);
+            Err(exit::DATABASE)
+        }
+        Ok(summary) => {
+            info!(
+                "Imported {} comics from {}",
+                summary.imported,
+                input.to_string_lossy()
+            );
+
+            if summary.damaged > 0 {
+                error!(
+                    "{} images failed their checksums and were left out",
+                    summary.damaged
+                );
+                return Err(exit::FAILURE);
+            }
+
+            Ok(())
+        }
     }
 }
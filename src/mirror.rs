@@ -0,0 +1,251 @@
+//! Downloading every comic, for keeps
+//!
+//! `xkcdfs mirror` fetches each comic's metadata and original image (and its
+//! rendered image, if asked), and notes the raw image's checksum in the
+//! database once a comic is done. Comics already noted are skipped, so an
+//! interrupted mirror picks up where it left off. At the end, every noted
+//! image is checked against its checksum, and any that don't match are
+//! forgotten so the next run fetches them again.
+//!
+//! Comics with a double-size image have it fetched too, and a comic isn't
+//! noted until it's cached. The check at the end makes sure it's still there
+//! and, if the server said how big it is, that it's that big.
+
+use crate::logging;
+use crate::requests::RequestMode::*;
+use crate::XkcdClient;
+use std::io::Write;
+use std::thread;
+use std::time::{Duration, Instant};
+use xkcdfs_core::store::HiRes;
+use xkcdfs_core::CacheStore;
+
+/// Width of the progress bar, between the brackets
+const BAR_WIDTH: usize = 30;
+
+/// How many comics to get through between progress messages, when there's no
+/// terminal to draw a progress bar on
+const PROGRESS_INTERVAL: u32 = 100;
+
+/// How a mirror went
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    /// Fetched this time
+    pub fetched: u32,
+    /// Already done by an earlier run
+    pub skipped: u32,
+    /// Couldn't be fetched (there is no comic 404, for one)
+    pub failed: u32,
+    /// Didn't match their checksums at the end
+    pub corrupt: u32,
+}
+
+/// Like `3m 12s`, to the second
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {}s", m, s),
+        (h, m, _) => format!("{}h {}m", h, m),
+    }
+}
+
+/// One line showing `done` comics out of `total`
+///
+/// `eta` is left off until there's something to estimate it from.
+fn progress_line(done: u32, total: u32, eta: Option<Duration>) -> String {
+    let filled = BAR_WIDTH * done as usize / total.max(1) as usize;
+
+    let mut line = format!(
+        "[{}{}] {}/{} {}%",
+        "#".repeat(filled),
+        "-".repeat(BAR_WIDTH - filled),
+        done,
+        total,
+        u64::from(done) * 100 / u64::from(total.max(1))
+    );
+
+    if let Some(eta) = eta {
+        line.push_str(&format!(", about {} left", format_duration(eta)));
+    }
+
+    line
+}
+
+/// How long the rest will take, going by how long the ones fetched so far did
+///
+/// Skipped comics take no time, so they don't count.
+fn estimate(elapsed: Duration, fetched: u32, remaining: u32) -> Option<Duration> {
+    if fetched == 0 {
+        return None;
+    }
+
+    Some(elapsed / fetched * remaining)
+}
+
+struct Progress {
+    total: u32,
+    start: Instant,
+    /// Only drawn as a bar on a terminal, and logged every so often otherwise
+    terminal: bool,
+}
+
+impl Progress {
+    fn new(total: u32) -> Self {
+        Self {
+            total,
+            start: Instant::now(),
+            terminal: unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1,
+        }
+    }
+
+    fn update(&self, done: u32, summary: &Summary) {
+        let fetching = done - summary.skipped;
+        let eta = estimate(self.start.elapsed(), fetching, self.total - done);
+        let line = progress_line(done, self.total, eta);
+
+        if self.terminal {
+            print!("\r{}", line);
+            std::io::stdout().flush().ok();
+        } else if done.is_multiple_of(PROGRESS_INTERVAL) && done < self.total {
+            info!("Mirrored {}", line);
+        }
+    }
+
+    fn finish(&self) {
+        if self.terminal {
+            println!();
+        }
+    }
+}
+
+/// Mirror comics 1 to `latest`, waiting `delay` after each one fetched
+///
+/// `rendered` gets each comic's rendered image cached too.
+pub fn run(
+    client: &XkcdClient,
+    latest: u32,
+    rendered: bool,
+    delay: Option<Duration>,
+) -> Result<Summary, String> {
+    let store = client.store();
+    let mut summary = Summary::default();
+    let progress = Progress::new(latest);
+
+    info!("Mirroring comics 1 to {}", latest);
+
+    for num in 1..=latest {
+        let _op = logging::operation_scope("mirror", Some(num));
+
+        if store.mirrored(num)?.is_some() {
+            summary.skipped += 1;
+            progress.update(num, &summary);
+            continue;
+        }
+
+        let comic = client.request_comic(num, None, Normal);
+        let image = comic
+            .as_ref()
+            .and_then(|c| client.request_raw_image(c, None, Normal));
+
+        let done = match (comic, image) {
+            (Some(ref comic), Some(ref image)) => {
+                let hires_ok = match client.request_hires(comic, Normal) {
+                    Some(HiRes::Missing) => true,
+                    Some(HiRes::Available(_)) => {
+                        client.request_hires_image(comic, Normal).is_some()
+                    }
+                    None => false,
+                };
+                let rendered_ok =
+                    !rendered || client.request_rendered_image(comic, None, Normal).is_some();
+
+                if hires_ok && rendered_ok {
+                    store.put_mirrored(num, crc32fast::hash(image))?;
+                }
+
+                hires_ok && rendered_ok
+            }
+            _ => false,
+        };
+
+        if done {
+            summary.fetched += 1;
+        } else {
+            debug!("Could not mirror comic {}", num);
+            summary.failed += 1;
+        }
+
+        progress.update(num, &summary);
+
+        if let Some(delay) = delay {
+            thread::sleep(delay);
+        }
+    }
+
+    progress.finish();
+
+    info!("Checking {} images", summary.fetched + summary.skipped);
+
+    for num in 1..=latest {
+        let checksum = match store.mirrored(num)? {
+            Some(c) => c,
+            None => continue,
+        };
+
+        let intact = match store.raw_image(num)? {
+            Some(image) => crc32fast::hash(&image) == checksum,
+            // Evicted since
+            None => false,
+        };
+        let hires_intact = match store.hires(num)? {
+            Some(HiRes::Available(size)) => match store.hires_image(num)? {
+                Some(image) => size == 0 || image.len() as u64 == size,
+                None => false,
+            },
+            _ => true,
+        };
+
+        if !intact || !hires_intact {
+            warn!("Comic {}'s image is missing or damaged, forgetting it", num);
+            store.remove_mirrored(num)?;
+            summary.corrupt += 1;
+        }
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn draws_progress() {
+        assert_eq!(
+            progress_line(0, 2000, None),
+            "[------------------------------] 0/2000 0%"
+        );
+        assert_eq!(
+            progress_line(1000, 2000, Some(Duration::from_secs(192))),
+            "[###############---------------] 1000/2000 50%, about 3m 12s left"
+        );
+        assert_eq!(
+            progress_line(2000, 2000, Some(Duration::from_secs(0))),
+            "[##############################] 2000/2000 100%, about 0s left"
+        );
+    }
+
+    #[test]
+    fn estimates_from_fetched_comics() {
+        let elapsed = Duration::from_secs(100);
+
+        assert_eq!(estimate(elapsed, 0, 50), None);
+        assert_eq!(estimate(elapsed, 50, 50), Some(elapsed));
+        assert_eq!(
+            format_duration(estimate(elapsed, 10, 500).unwrap()),
+            "1h 23m"
+        );
+    }
+}
@@ -0,0 +1,144 @@
+//! Telling systemd how the mount is doing, for `Type=notify` units
+//!
+//! systemd passes a socket in `NOTIFY_SOCKET`, and xkcdfs sends `READY=1` once
+//! mounted and `STOPPING=1` once unmounted. If the unit sets `WatchdogSec=`,
+//! a thread also sends `WATCHDOG=1` pings, but only while the filesystem
+//! thread isn't stuck on one request for longer than the watchdog allows. A
+//! hung mount stops pinging, and systemd restarts it.
+
+use std::env;
+use std::os::unix::net::UnixDatagram;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+pub struct Notifier {
+    socket: UnixDatagram,
+    path: String,
+}
+
+impl Notifier {
+    /// Connect to systemd, if it's listening
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let path = match env::var("NOTIFY_SOCKET") {
+            Ok(p) => p,
+            Err(_) => return Ok(None),
+        };
+
+        if path.starts_with('@') {
+            return Err(format!("Abstract notify socket {} is not supported", path));
+        }
+
+        let socket = UnixDatagram::unbound()
+            .map_err(|e| format!("Could not create a notify socket: {}", e))?;
+
+        Ok(Some(Self { socket, path }))
+    }
+
+    /// Send a state change, like `READY=1`
+    pub fn send(&self, state: &str) {
+        if let Err(e) = self.socket.send_to(state.as_bytes(), &self.path) {
+            warn!("Could not notify systemd of {}: {}", state, e);
+        }
+    }
+
+    fn try_clone(&self) -> Result<Self, String> {
+        Ok(Self {
+            socket: self.socket.try_clone().map_err(|e| e.to_string())?,
+            path: self.path.clone(),
+        })
+    }
+}
+
+/// Whether systemd is waiting to hear when xkcdfs is ready
+///
+/// It has to hear from the process it started, so there's no daemonizing.
+pub fn expected() -> bool {
+    env::var_os("NOTIFY_SOCKET").is_some()
+}
+
+/// How often systemd wants watchdog pings, if at all
+fn watchdog_interval() -> Option<Duration> {
+    // The watchdog might be meant for some other process
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+
+    env::var("WATCHDOG_USEC")
+        .ok()
+        .and_then(|usec| usec.parse().ok())
+        .filter(|&usec| usec > 0)
+        .map(Duration::from_micros)
+}
+
+/// Tracks whether the filesystem thread is stuck
+#[derive(Clone, Default)]
+pub struct Watchdog {
+    /// When the request being handled started, if there is one
+    busy_since: Arc<Mutex<Option<Instant>>>,
+}
+
+/// A request being handled, until it's dropped
+pub struct Busy(Watchdog);
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        *self.0.busy_since.lock().unwrap() = None;
+    }
+}
+
+impl Watchdog {
+    pub fn busy(&self) -> Busy {
+        *self.busy_since.lock().unwrap() = Some(Instant::now());
+
+        Busy(self.clone())
+    }
+
+    /// Start pinging systemd, if it asked for pings
+    pub fn spawn(&self, notifier: &Notifier) -> Result<Option<JoinHandle<()>>, String> {
+        let interval = match watchdog_interval() {
+            Some(i) => i,
+            None => return Ok(None),
+        };
+
+        let notifier = notifier.try_clone()?;
+        let watchdog = self.clone();
+
+        info!("Pinging the systemd watchdog every {:?}", interval / 2);
+
+        thread::Builder::new()
+            .name("watchdog".to_string())
+            .spawn(move || watchdog.run(&notifier, interval))
+            .map(Some)
+            .map_err(|e| e.to_string())
+    }
+
+    fn run(&self, notifier: &Notifier, interval: Duration) {
+        let mut stuck = false;
+
+        loop {
+            // Twice as often as needed, so a late ping isn't a missed one
+            thread::sleep(interval / 2);
+
+            let busy_for = self.busy_since.lock().unwrap().map(|t| t.elapsed());
+
+            match busy_for {
+                Some(b) if b >= interval => {
+                    if !stuck {
+                        error!(
+                            "A request has been running for {:?}, stopping watchdog pings",
+                            b
+                        );
+                        stuck = true;
+                    }
+                }
+                _ => {
+                    stuck = false;
+                    notifier.send("WATCHDOG=1");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,355 @@
+//! Packs: comics and their images in one file, for copying between machines
+//!
+//! A pack is a ZIP with each comic's original image under `images/`, and an
+//! `index.json` listing every comic's metadata along with its image's name,
+//! size and CRC-32. `xkcdfs export-pack` writes them, `xkcdfs import-pack`
+//! copies one into the cache, and `--pack` mounts one without the network.
+//!
+//! Images are checked against the index whenever they're read, so a pack
+//! damaged on its way across is noticed instead of cached.
+
+use crate::Comic;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Read, Seek};
+use std::sync::{Arc, Mutex};
+use xkcdfs_core::{CacheStore, ComicSource};
+use zip::ZipArchive;
+
+/// The index's name in the ZIP
+pub const INDEX: &str = "index.json";
+
+/// The version of the format this reads and writes
+pub const VERSION: u32 = 1;
+
+/// Most memory set aside for an image before reading it, however big it says
+/// it is. Bigger images still read fine, just growing as they go.
+const MAX_IMAGE_CAPACITY: u64 = 16 * 1024 * 1024;
+
+/// Everything in a pack besides the images
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Index {
+    pub version: u32,
+    pub comics: Vec<Entry>,
+}
+
+/// One comic in the index
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Entry {
+    pub comic: PackComic,
+    /// The image's name in the ZIP
+    pub image: String,
+    pub content_type: Option<String>,
+    pub size: u64,
+    pub crc32: u32,
+}
+
+/// A comic's metadata, as written in the index
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PackComic {
+    pub num: u32,
+    pub day: i32,
+    pub month: i32,
+    pub year: i32,
+    pub link: Option<String>,
+    pub news: Option<String>,
+    pub alt: String,
+    pub title: String,
+    pub safe_title: String,
+    pub transcript: Option<String>,
+    pub img_url: String,
+    pub extra_parts: Option<String>,
+}
+
+impl From<&Comic> for PackComic {
+    fn from(comic: &Comic) -> Self {
+        Self {
+            num: comic.num,
+            day: comic.day,
+            month: comic.month,
+            year: comic.year,
+            link: comic.link.clone(),
+            news: comic.news.clone(),
+            alt: comic.alt.clone(),
+            title: comic.title.clone(),
+            safe_title: comic.safe_title.clone(),
+            transcript: comic.transcript.clone(),
+            img_url: comic.img_url.clone(),
+            extra_parts: comic.extra_parts.clone(),
+        }
+    }
+}
+
+impl From<&PackComic> for Comic {
+    fn from(comic: &PackComic) -> Self {
+        Self {
+            num: comic.num,
+            day: comic.day,
+            month: comic.month,
+            year: comic.year,
+            link: comic.link.clone(),
+            news: comic.news.clone(),
+            alt: comic.alt.clone(),
+            title: comic.title.clone(),
+            safe_title: comic.safe_title.clone(),
+            transcript: comic.transcript.clone(),
+            img_url: comic.img_url.clone(),
+            img_len: None,
+            extra_parts: comic.extra_parts.clone(),
+        }
+    }
+}
+
+/// An open pack, read as comics are asked for
+///
+/// Clones share the file, so only one reads from it at a time.
+pub struct Pack<R = std::fs::File> {
+    zip: Arc<Mutex<ZipArchive<R>>>,
+    /// By comic number
+    entries: Arc<BTreeMap<u32, Entry>>,
+}
+
+// Derived, this would need R: Clone, which files aren't
+impl<R> Clone for Pack<R> {
+    fn clone(&self) -> Self {
+        Self {
+            zip: self.zip.clone(),
+            entries: self.entries.clone(),
+        }
+    }
+}
+
+impl Pack {
+    pub fn open(path: &std::ffi::OsStr) -> Result<Self, String> {
+        let file = std::fs::File::open(path)
+            .map_err(|e| format!("Could not open {}: {}", path.to_string_lossy(), e))?;
+
+        Self::new(file)
+    }
+}
+
+impl<R: Read + Seek> Pack<R> {
+    /// Read a pack's index
+    pub fn new(reader: R) -> Result<Self, String> {
+        let mut zip = ZipArchive::new(reader).map_err(|e| format!("Not a pack: {}", e))?;
+
+        let index: Index = {
+            let file = zip
+                .by_name(INDEX)
+                .map_err(|e| format!("Not a pack, no {}: {}", INDEX, e))?;
+
+            serde_json::from_reader(file).map_err(|e| format!("Invalid {}: {}", INDEX, e))?
+        };
+
+        if index.version != VERSION {
+            return Err(format!(
+                "Pack version {} is not supported, expected {}",
+                index.version, VERSION
+            ));
+        }
+
+        let entries = index.comics.into_iter().map(|e| (e.comic.num, e)).collect();
+
+        Ok(Self {
+            zip: Arc::new(Mutex::new(zip)),
+            entries: Arc::new(entries),
+        })
+    }
+
+    /// Every comic in the pack, in order
+    pub fn comics(&self) -> impl Iterator<Item = Comic> + '_ {
+        self.entries.values().map(|e| Comic::from(&e.comic))
+    }
+
+    /// Comic `num`'s image and content type, if it's intact
+    pub fn read_image(&self, num: u32) -> Result<(Vec<u8>, Option<String>), String> {
+        let entry = self.entry(num)?;
+        let mut zip = self.zip.lock().unwrap();
+        let mut file = zip
+            .by_name(&entry.image)
+            .map_err(|e| format!("Could not read {}: {}", entry.image, e))?;
+
+        // A damaged index can claim any size, so it's only trusted as far as
+        // reading one byte more than it says, to notice the image is bigger
+        let mut image = Vec::with_capacity(file.size().min(MAX_IMAGE_CAPACITY) as usize);
+        file.by_ref()
+            .take(entry.size.saturating_add(1))
+            .read_to_end(&mut image)
+            .map_err(|e| format!("Could not read {}: {}", entry.image, e))?;
+
+        if image.len() as u64 != entry.size || crc32fast::hash(&image) != entry.crc32 {
+            return Err(format!("Comic {}'s image is damaged", num));
+        }
+
+        Ok((image, entry.content_type.clone()))
+    }
+
+    fn entry(&self, num: u32) -> Result<&Entry, String> {
+        self.entries
+            .get(&num)
+            .ok_or_else(|| format!("Comic {} is not in the pack", num))
+    }
+}
+
+impl<R: Read + Seek + Send + 'static> ComicSource for Pack<R> {
+    fn latest(&self) -> Result<Comic, String> {
+        self.entries
+            .values()
+            .next_back()
+            .map(|e| Comic::from(&e.comic))
+            .ok_or_else(|| "The pack is empty".to_string())
+    }
+
+    fn comic(&self, num: u32) -> Result<Comic, String> {
+        self.entry(num).map(|e| Comic::from(&e.comic))
+    }
+
+    fn image(&self, comic: &Comic) -> Result<Vec<u8>, String> {
+        self.read_image(comic.num).map(|(image, _)| image)
+    }
+
+    fn image_and_type(&self, comic: &Comic) -> Result<(Vec<u8>, Option<String>), String> {
+        self.read_image(comic.num)
+    }
+}
+
+/// How an import went
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    pub imported: u32,
+    /// Left out for failing their checksums
+    pub damaged: u32,
+}
+
+/// Copy every intact comic in `pack` into `store`, replacing what's there
+pub fn import<R: Read + Seek, S: CacheStore>(pack: &Pack<R>, store: &S) -> Result<Summary, String> {
+    let mut summary = Summary::default();
+
+    for comic in pack.comics() {
+        let (image, content_type) = match pack.read_image(comic.num) {
+            Ok(i) => i,
+            Err(e) => {
+                warn!("{}, skipping it", e);
+                summary.damaged += 1;
+                continue;
+            }
+        };
+
+        store.put_comic(&comic)?;
+        store.put_raw_image(comic.num, &image)?;
+
+        if let Some(content_type) = content_type {
+            store.put_image_type(comic.num, &content_type)?;
+        }
+
+        summary.imported += 1;
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::export::pack::PackExporter;
+    use crate::export::Exporter;
+    use std::io::{Cursor, Write};
+    use zip::write::{FileOptions, ZipWriter};
+
+    fn comic(num: u32) -> Comic {
+        Comic {
+            num,
+            day: 24,
+            month: 7,
+            year: 2009,
+            link: None,
+            news: None,
+            alt: "If you don't have an extension cord I can get that too.".to_string(),
+            title: "Woodpecker".to_string(),
+            safe_title: "Woodpecker".to_string(),
+            transcript: None,
+            img_url: "https://imgs.xkcd.com/comics/woodpecker.png".to_string(),
+            img_len: None,
+            extra_parts: None,
+        }
+    }
+
+    fn pack(images: &[(u32, &[u8])]) -> Vec<u8> {
+        let mut data = Cursor::new(Vec::new());
+        let mut exporter = PackExporter::new(&mut data);
+
+        for (num, image) in images {
+            exporter.add(&comic(*num), image).unwrap();
+        }
+
+        exporter.finish().unwrap();
+        drop(exporter);
+
+        data.into_inner()
+    }
+
+    #[test]
+    fn reads_back_what_was_packed() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        let pack = Pack::new(Cursor::new(pack(&[(613, b"GIF89a"), (614, png)]))).unwrap();
+
+        assert_eq!(pack.latest().unwrap().num, 614);
+        assert_eq!(pack.comic(614).unwrap().title, "Woodpecker");
+        assert_eq!(
+            pack.read_image(614).unwrap(),
+            (png.to_vec(), Some("image/png".to_string()))
+        );
+        assert_eq!(
+            pack.read_image(613).unwrap().1,
+            Some("image/gif".to_string())
+        );
+        assert!(pack.comic(404).is_err());
+        assert_eq!(pack.comics().count(), 2);
+    }
+
+    #[test]
+    fn notices_damaged_images() {
+        let mut data = pack(&[(614, b"GIF89a original")]);
+
+        // Images are stored, so the bytes are in the ZIP as they are
+        let at = data.windows(8).position(|w| w == b"original").unwrap();
+        data[at..at + 8].copy_from_slice(b"damaged!");
+
+        let pack = Pack::new(Cursor::new(data)).unwrap();
+
+        assert!(pack.read_image(614).is_err());
+        assert!(Pack::new(Cursor::new(b"not a zip".to_vec())).is_err());
+    }
+
+    #[test]
+    fn notices_wrong_sizes() {
+        let image = b"GIF89a";
+
+        for &size in &[0, 5, 7, u64::MAX] {
+            let mut data = Cursor::new(Vec::new());
+            let mut zip = ZipWriter::new(&mut data);
+            let index = Index {
+                version: VERSION,
+                comics: vec![Entry {
+                    comic: PackComic::from(&comic(614)),
+                    image: "images/614.gif".to_string(),
+                    content_type: Some("image/gif".to_string()),
+                    size,
+                    crc32: crc32fast::hash(image),
+                }],
+            };
+
+            zip.start_file("images/614.gif", FileOptions::default())
+                .unwrap();
+            zip.write_all(image).unwrap();
+            zip.start_file(INDEX, FileOptions::default()).unwrap();
+            zip.write_all(&serde_json::to_vec(&index).unwrap()).unwrap();
+            zip.finish().unwrap();
+            drop(zip);
+
+            // Without setting aside room for what the index says
+            let pack = Pack::new(Cursor::new(data.into_inner())).unwrap();
+            assert!(pack.read_image(614).is_err());
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! Downloading comics in the background after mounting
+//!
+//! This fills the cache with every comic's metadata and original image, so
+//! they're there later even without a connection. It runs on its own thread
+//! with its own database connection, and doesn't hold up the filesystem.
+
+use crate::logging;
+use crate::requests::RequestMode::Normal;
+use crate::XkcdClient;
+use std::thread::{self, JoinHandle};
+
+/// How many comics to get through between progress messages
+const PROGRESS_INTERVAL: u32 = 100;
+
+/// Start prefetching comics `first` to `last`, on a thread called `name`
+pub fn spawn(
+    name: String,
+    client: XkcdClient,
+    first: u32,
+    last: u32,
+) -> Result<JoinHandle<()>, String> {
+    if first == 0 || first > last {
+        return Err(format!("{}-{} is not a valid range of comics", first, last));
+    }
+
+    thread::Builder::new()
+        .name(name)
+        .spawn(move || run(&client, first, last))
+        .map_err(|e| e.to_string())
+}
+
+/// Get comic `num`'s metadata and original image into the cache
+pub fn fetch(client: &XkcdClient, num: u32) -> Option<Vec<u8>> {
+    client
+        .request_comic(num, None, Normal)
+        .and_then(|c| client.request_raw_image(&c, None, Normal))
+}
+
+fn run(client: &XkcdClient, first: u32, last: u32) {
+    let total = last - first + 1;
+    let mut fetched = 0;
+    let mut failed = 0;
+    let stats = client.stats();
+
+    info!("Prefetching comics {} to {}", first, last);
+
+    for num in first..=last {
+        stats.set_prefetch_remaining(last - num + 1);
+        let _op = logging::operation_scope("prefetch", Some(num));
+
+        match fetch(client, num) {
+            Some(_) => fetched += 1,
+            None => {
+                // There is no comic 404, so some of these are expected
+                debug!("Could not prefetch comic {}", num);
+                failed += 1;
+            }
+        }
+
+        let done = num - first + 1;
+
+        if done.is_multiple_of(PROGRESS_INTERVAL) && done < total {
+            info!(
+                "Prefetched {} of {} comics ({}%)",
+                done,
+                total,
+                done * 100 / total
+            );
+        }
+    }
+
+    stats.set_prefetch_remaining(0);
+
+    info!(
+        "Finished prefetching comics {} to {}: {} fetched, {} failed",
+        first, last, fetched, failed
+    );
+}
@@ -0,0 +1,133 @@
+//! `--run-as`: dropping root privileges once mounted
+//!
+//! Mounting with allow_other from a system mount unit means starting as root,
+//! but there's no need to stay root while talking to the network. Once the
+//! filesystem is mounted, xkcdfs hands the database over to another user and
+//! switches to them for good.
+
+use std::ffi::{CString, OsStr};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::{mem, ptr};
+
+/// Big enough for any sane passwd entry
+const PASSWD_BUFFER_SIZE: usize = 16 * 1024;
+
+/// Files next to the database that SQLite and the lock use
+const DATABASE_SUFFIXES: &[&str] = &["", "-journal", "-wal", "-shm", ".lock"];
+
+pub struct User {
+    name: String,
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+}
+
+/// Whether this process can switch users at all
+pub fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+fn c_string(bytes: &[u8]) -> Result<CString, String> {
+    CString::new(bytes)
+        .map_err(|_| format!("{} contains a NUL byte", String::from_utf8_lossy(bytes)))
+}
+
+impl User {
+    pub fn lookup(name: &str) -> Result<Self, String> {
+        let c_name = c_string(name.as_bytes())?;
+        let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+        let mut buffer = vec![0; PASSWD_BUFFER_SIZE];
+        let mut result = ptr::null_mut();
+
+        let code = unsafe {
+            libc::getpwnam_r(
+                c_name.as_ptr(),
+                &mut passwd,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+
+        if code != 0 {
+            return Err(format!(
+                "Could not look up user {}: {}",
+                name,
+                io::Error::from_raw_os_error(code)
+            ));
+        }
+
+        if result.is_null() {
+            return Err(format!("There is no user {}", name));
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            uid: passwd.pw_uid,
+            gid: passwd.pw_gid,
+        })
+    }
+
+    /// Give the database and the files around it to this user
+    ///
+    /// Anything that doesn't exist yet is skipped, and will be created by the
+    /// user later.
+    pub fn take_database(&self, database: &OsStr) -> Result<(), String> {
+        for suffix in DATABASE_SUFFIXES {
+            let mut path = database.to_owned();
+            path.push(suffix);
+            let path = PathBuf::from(path);
+
+            let c_path = c_string(path.as_os_str().as_bytes())?;
+
+            if unsafe { libc::chown(c_path.as_ptr(), self.uid, self.gid) } != 0 {
+                let error = io::Error::last_os_error();
+
+                if error.kind() != io::ErrorKind::NotFound {
+                    return Err(format!(
+                        "Could not give {} to {}: {}",
+                        path.display(),
+                        self.name,
+                        error
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Switch to this user, with no way back
+    pub fn switch(&self) -> Result<(), String> {
+        let c_name = c_string(self.name.as_bytes())?;
+        let failed = |what: &str| format!("Could not {}: {}", what, io::Error::last_os_error());
+
+        // Groups first, since that needs root
+        if unsafe { libc::initgroups(c_name.as_ptr(), self.gid) } != 0 {
+            return Err(failed("set supplementary groups"));
+        }
+
+        if unsafe { libc::setgid(self.gid) } != 0 {
+            return Err(failed("set group"));
+        }
+
+        if unsafe { libc::setuid(self.uid) } != 0 {
+            return Err(failed("set user"));
+        }
+
+        if self.uid != 0 && unsafe { libc::setuid(0) } == 0 {
+            return Err(format!(
+                "Switched to {}, but could still get root back",
+                self.name
+            ));
+        }
+
+        info!(
+            "Running as {} (uid {}, gid {})",
+            self.name, self.uid, self.gid
+        );
+
+        Ok(())
+    }
+}
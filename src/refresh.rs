@@ -0,0 +1,276 @@
+//! Checking for new comics every so often while mounted
+//!
+//! Like prefetching, this runs on its own thread with its own database
+//! connection. New comics show up in the filesystem once they're cached.
+//!
+//! Writing to the `refresh` file checks straight away, or fetches a single
+//! comic or every cached comic's metadata again. It can also clear rendered
+//! images, so they're rendered again from the cached originals.
+//!
+//! Comics newer than the latest one known when the thread started are handed
+//! to the `--on-new-comic` hook and `--notify` as the thread finds them,
+//! including any a write to `refresh` found first.
+//!
+//! With a feed, each check asks the Atom feed first, and only fetches the
+//! latest comic's JSON when the feed has a newer comic, or couldn't be read.
+
+use crate::desktop;
+use crate::feed::Feed;
+use crate::hook::Hook;
+use crate::logging;
+use crate::requests::RequestMode::{BustCache, NoNetwork, Normal};
+use crate::{Comic, XkcdClient};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// The longest the thread sleeps before noticing a changed interval
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often to try reaching xkcd.com after starting without it
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often to check, shared so it can be changed while running
+pub type Interval = Arc<Mutex<Option<Duration>>>;
+
+/// What to do with each new comic the thread finds
+#[derive(Default)]
+pub struct OnNewComic {
+    pub hook: Option<Hook>,
+    /// Show a desktop notification
+    pub notify: bool,
+}
+
+impl OnNewComic {
+    /// Whether there's anything to do at all
+    pub fn any(&self) -> bool {
+        self.hook.is_some() || self.notify
+    }
+
+    fn run(&self, client: &XkcdClient, comic: &Comic) {
+        if self.notify {
+            desktop::notify(comic);
+        }
+
+        if let Some(hook) = &self.hook {
+            hook.run(client, comic);
+        }
+    }
+}
+
+/// Start checking for a new latest comic every `interval`, if it's set
+///
+/// If xkcd.com couldn't be reached at startup, `offline` has it retried more
+/// often until it can be. Each source has its own thread, called `name`, and
+/// `on_new` says what to do with each new comic it finds. A `feed` is asked
+/// before the JSON API.
+pub fn spawn(
+    name: String,
+    client: XkcdClient,
+    interval: Interval,
+    offline: bool,
+    on_new: OnNewComic,
+    feed: Option<Feed>,
+) -> Result<JoinHandle<()>, String> {
+    thread::Builder::new()
+        .name(name)
+        .spawn(move || run(&client, &interval, offline, &on_new, feed))
+        .map_err(|e| e.to_string())
+}
+
+/// What a write to the `refresh` file asked for
+#[derive(Debug, PartialEq)]
+pub enum Target {
+    Latest,
+    /// One comic, along with its image
+    Comic(u32),
+    /// Every cached comic's metadata
+    All,
+    /// Every rendered image, without fetching anything
+    Rendered,
+}
+
+impl Target {
+    /// A comic number, `all` or `rendered`, and anything else (even nothing)
+    /// for the latest comic
+    pub fn parse(data: &[u8]) -> Self {
+        let text = String::from_utf8_lossy(data);
+        let text = text.trim();
+
+        match text {
+            "all" => return Self::All,
+            "rendered" => return Self::Rendered,
+            _ => {}
+        }
+
+        match text.parse() {
+            Ok(num) if num > 0 => Self::Comic(num),
+            _ => Self::Latest,
+        }
+    }
+}
+
+/// Fetch comic `num` and its image again, and render it again if it's
+/// rendered, replacing whatever was cached
+pub fn refresh_comic(client: &XkcdClient, num: u32) {
+    let _op = logging::operation_scope("refresh", Some(num));
+
+    let refreshed = client
+        .request_comic(num, None, BustCache)
+        .and_then(|c| client.request_rendered_image(&c, None, BustCache));
+
+    match refreshed {
+        Some(_) => info!("Refreshed comic {}", num),
+        None => warn!("Could not refresh comic {}", num),
+    }
+}
+
+/// Start fetching every cached comic's metadata again, on a thread called
+/// `name`
+pub fn spawn_revalidate(name: String, client: XkcdClient) -> Result<JoinHandle<()>, String> {
+    thread::Builder::new()
+        .name(name)
+        .spawn(move || revalidate(&client))
+        .map_err(|e| e.to_string())
+}
+
+fn revalidate(client: &XkcdClient) {
+    let latest = match client.request_latest_comic(None, NoNetwork) {
+        Some(c) => c.num,
+        None => return,
+    };
+
+    info!("Fetching metadata again for every cached comic");
+
+    let mut refreshed = 0;
+
+    for num in 1..=latest {
+        if client.request_comic(num, None, NoNetwork).is_none() {
+            continue;
+        }
+
+        let _op = logging::operation_scope("refresh", Some(num));
+
+        if client.request_comic(num, None, BustCache).is_some() {
+            refreshed += 1;
+        }
+    }
+
+    info!("Fetched metadata again for {} comics", refreshed);
+}
+
+/// Handle each comic after `known`, up to `latest`
+fn new_comics(client: &XkcdClient, on_new: &OnNewComic, known: u32, latest: u32) {
+    for num in known + 1..=latest {
+        let _op = logging::operation_scope("new comic", Some(num));
+
+        match client.request_comic(num, None, Normal) {
+            Some(comic) => on_new.run(client, &comic),
+            // Like 404, or just not reachable right now
+            None => warn!("Could not fetch new comic {}", num),
+        }
+    }
+}
+
+/// Whether the feed says there's a comic newer than `known`
+///
+/// Anything but a definite no is a yes, so the JSON API is asked instead.
+fn feed_has_new(feed: &mut Feed, known: Option<u32>) -> bool {
+    match feed.check() {
+        Ok(Some(entry)) if known.is_none_or(|k| entry.num > k) => {
+            info!("Comic {} was published {}", entry.num, entry.updated);
+            true
+        }
+        Ok(_) => false,
+        Err(e) => {
+            warn!("Could not check the feed for new comics: {}", e);
+            true
+        }
+    }
+}
+
+fn run(
+    client: &XkcdClient,
+    interval: &Interval,
+    mut offline: bool,
+    on_new: &OnNewComic,
+    mut feed: Option<Feed>,
+) {
+    let mut last = Instant::now();
+
+    // Without anything cached, there's nothing for a comic to be newer than
+    let mut known = client.request_latest_comic(None, NoNetwork).map(|c| c.num);
+
+    if let Some(i) = *interval.lock().unwrap() {
+        info!("Checking for new comics every {:?}", i);
+    }
+
+    loop {
+        // Copied out so the lock isn't held while sleeping
+        let current = if offline {
+            Some(RETRY_INTERVAL)
+        } else {
+            *interval.lock().unwrap()
+        };
+
+        match current {
+            Some(i) if last.elapsed() >= i => {
+                let _op = logging::operation_scope("refresh", None);
+
+                // Nothing new, and nothing more to ask
+                if let Some(feed) = feed.as_mut().filter(|_| !offline) {
+                    if !feed_has_new(feed, known) {
+                        client.stats().refreshed(known);
+                        last = Instant::now();
+                        continue;
+                    }
+                }
+
+                // Failures are already logged, and there's always next time
+                let latest = client.request_latest_comic(None, BustCache);
+                let num = latest.as_ref().map(|c| c.num);
+                client.stats().refreshed(num);
+
+                match latest {
+                    Some(latest) if offline => {
+                        info!("Reached xkcd.com again, the latest comic is {}", latest);
+                        offline = false;
+                    }
+                    Some(latest) => debug!("Latest comic is {}", latest),
+                    None => {}
+                }
+
+                if let Some(num) = num {
+                    match known {
+                        Some(known) if num > known && on_new.any() => {
+                            new_comics(client, on_new, known, num)
+                        }
+                        _ => {}
+                    }
+
+                    known = Some(known.map_or(num, |k| k.max(num)));
+                }
+
+                last = Instant::now();
+            }
+            Some(i) => thread::sleep((i - last.elapsed()).min(POLL_INTERVAL)),
+            None => thread::sleep(POLL_INTERVAL),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_targets() {
+        assert_eq!(Target::parse(b""), Target::Latest);
+        assert_eq!(Target::parse(b"\n"), Target::Latest);
+        assert_eq!(Target::parse(b"614\n"), Target::Comic(614));
+        assert_eq!(Target::parse(b"all"), Target::All);
+        assert_eq!(Target::parse(b"rendered\n"), Target::Rendered);
+        assert_eq!(Target::parse(b"0"), Target::Latest);
+        assert_eq!(Target::parse(b"please"), Target::Latest);
+    }
+}
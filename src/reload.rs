@@ -0,0 +1,153 @@
+//! Re-reading the config file on SIGHUP
+//!
+//! Only some settings can change while mounted: the log levels, the render
+//! theme, the cache size limit, and the refresh interval. Anything else in
+//! the config file, like the timeout or the comic sources, needs a remount,
+//! and changes to it are pointed out but otherwise ignored.
+//!
+//! The signal just sets a flag. The filesystem checks it before handling each
+//! request, since that's where the client lives, and restarts its workers if
+//! the client changed. So the reload happens, and is logged, when the mount is
+//! next used rather than when the signal arrives, as `--help` says.
+
+use crate::cli::{Config, Reload};
+use crate::config::SourceSettings;
+use crate::image::RenderOptions;
+use crate::logging::{Filter, LevelHandle};
+use crate::refresh;
+use crate::XkcdClient;
+use log::LevelFilter;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The settings that can be reloaded, plus the ones worth warning about
+#[derive(Clone, PartialEq)]
+pub struct Settings {
+    log_level: LevelFilter,
+    log_filter: Filter,
+    render_options: RenderOptions,
+    max_cache_size: Option<u64>,
+    refresh_interval: Option<Duration>,
+    timeout: Duration,
+    sources: Vec<SourceSettings>,
+}
+
+impl Settings {
+    pub fn new(conf: &Config) -> Self {
+        Self {
+            log_level: conf.log_level,
+            log_filter: conf.log_filter.clone(),
+            render_options: conf.render_options.clone(),
+            max_cache_size: conf.max_cache_size,
+            refresh_interval: conf.refresh_interval,
+            timeout: conf.timeout,
+            sources: conf.sources.clone(),
+        }
+    }
+}
+
+pub struct Reloader {
+    requested: Arc<AtomicBool>,
+    source: Reload,
+    log_levels: LevelHandle,
+    refresh_interval: refresh::Interval,
+    /// What was last applied, so changes can be logged
+    current: Settings,
+}
+
+impl Reloader {
+    pub fn new(
+        source: Reload,
+        current: Settings,
+        log_levels: LevelHandle,
+        refresh_interval: refresh::Interval,
+    ) -> Result<Self, String> {
+        let requested = Arc::new(AtomicBool::new(false));
+
+        signal_hook::flag::register(signal_hook::SIGHUP, Arc::clone(&requested))
+            .map_err(|e| format!("Could not handle SIGHUP: {}", e))?;
+
+        Ok(Self {
+            requested,
+            source,
+            log_levels,
+            refresh_interval,
+            current,
+        })
+    }
+
+    /// Reload the config file if SIGHUP has arrived since the last call
+    ///
+    /// Returns whether the settings of any of the `clients` changed.
+    pub fn reload_if_requested(&mut self, clients: &mut [XkcdClient]) -> bool {
+        if !self.requested.swap(false, Ordering::Relaxed) {
+            return false;
+        }
+
+        if !self.source.has_config_file() {
+            debug!("Got SIGHUP, but there's no config file to reload");
+            return false;
+        }
+
+        info!("Reloading the config file");
+
+        let new = match self.source.config() {
+            Ok(conf) => Settings::new(&conf),
+            Err(e) => {
+                error!(
+                    "Could not reload the config file, keeping the old settings: {}",
+                    e
+                );
+                return false;
+            }
+        };
+
+        if new == self.current {
+            info!("Nothing in the config file changed");
+            return false;
+        }
+
+        let old = std::mem::replace(&mut self.current, new.clone());
+
+        if (new.log_level, &new.log_filter) != (old.log_level, &old.log_filter) {
+            info!("Log levels changed, overall level is now {}", new.log_level);
+            self.log_levels.set(new.log_level, new.log_filter);
+        }
+
+        if new.render_options != old.render_options {
+            info!("Render options changed, comics will be rendered again");
+            for client in clients.iter_mut() {
+                client.set_render_options(new.render_options.clone());
+            }
+        }
+
+        if new.max_cache_size != old.max_cache_size {
+            match new.max_cache_size {
+                Some(size) => info!("Cache size limit changed to {} bytes", size),
+                None => info!("Cache size limit removed"),
+            }
+            for client in clients.iter_mut() {
+                client.set_max_cache_size(new.max_cache_size);
+            }
+        }
+
+        if new.refresh_interval != old.refresh_interval {
+            match new.refresh_interval {
+                Some(i) => info!("Checking for new comics every {:?} from now on", i),
+                None => info!("No longer checking for new comics"),
+            }
+            *self.refresh_interval.lock().unwrap() = new.refresh_interval;
+        }
+
+        if new.timeout != old.timeout {
+            warn!("The new timeout will only take effect after remounting");
+        }
+
+        if new.sources != old.sources {
+            warn!("Comic sources will only change after remounting");
+        }
+
+        new.render_options != old.render_options || new.max_cache_size != old.max_cache_size
+    }
+}
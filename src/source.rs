@@ -0,0 +1,64 @@
+//! Where the binary's comics come from: xkcd.com (or something like it), or a
+//! pack or archive mounted without the network
+
+use crate::archive::Archive;
+use crate::pack::Pack;
+use crate::Comic;
+use xkcdfs_core::{ComicSource, XkcdApi};
+
+#[derive(Clone)]
+pub enum Source {
+    Api(XkcdApi),
+    Pack(Pack),
+    Archive(Archive),
+}
+
+impl ComicSource for Source {
+    fn latest(&self) -> Result<Comic, String> {
+        match self {
+            Source::Api(api) => api.latest(),
+            Source::Pack(pack) => pack.latest(),
+            Source::Archive(archive) => archive.latest(),
+        }
+    }
+
+    fn comic(&self, num: u32) -> Result<Comic, String> {
+        match self {
+            Source::Api(api) => api.comic(num),
+            Source::Pack(pack) => pack.comic(num),
+            Source::Archive(archive) => archive.comic(num),
+        }
+    }
+
+    fn image(&self, comic: &Comic) -> Result<Vec<u8>, String> {
+        match self {
+            Source::Api(api) => api.image(comic),
+            Source::Pack(pack) => pack.image(comic),
+            Source::Archive(archive) => archive.image(comic),
+        }
+    }
+
+    fn image_and_type(&self, comic: &Comic) -> Result<(Vec<u8>, Option<String>), String> {
+        match self {
+            Source::Api(api) => api.image_and_type(comic),
+            Source::Pack(pack) => pack.image_and_type(comic),
+            Source::Archive(archive) => archive.image_and_type(comic),
+        }
+    }
+
+    fn hires_size(&self, comic: &Comic) -> Result<Option<u64>, String> {
+        match self {
+            Source::Api(api) => api.hires_size(comic),
+            Source::Pack(pack) => pack.hires_size(comic),
+            Source::Archive(archive) => archive.hires_size(comic),
+        }
+    }
+
+    fn hires_image(&self, comic: &Comic) -> Result<Vec<u8>, String> {
+        match self {
+            Source::Api(api) => api.hires_image(comic),
+            Source::Pack(pack) => pack.hires_image(comic),
+            Source::Archive(archive) => archive.hires_image(comic),
+        }
+    }
+}
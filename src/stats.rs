@@ -0,0 +1,95 @@
+//! Runtime statistics, logged on SIGUSR1
+//!
+//! The counters themselves live in the client. The snapshot is logged from its
+//! own thread, so `kill -USR1` still says something useful when the filesystem
+//! thread is stuck on a slow request.
+//!
+//! A summary of the whole mount is logged when it's cleanly unmounted, and
+//! kept in the database's `sessions` table.
+
+use crate::XkcdClient;
+use std::thread::{self, JoinHandle};
+use xkcdfs_core::store::Session;
+use xkcdfs_core::CacheStore;
+
+pub use xkcdfs_core::stats::Stats;
+
+/// Log everything, along with what's in the cache
+fn log(client: &XkcdClient) {
+    let stats = client.stats().snapshot();
+
+    info!("Statistics after {}s running:", stats.uptime.as_secs());
+    info!(
+        "  Cache: {} hits, {} misses",
+        stats.cache_hits, stats.cache_misses
+    );
+    info!(
+        "  Network: {} requests, {} failed, {} in flight",
+        stats.network_requests, stats.network_failures, stats.in_flight
+    );
+    info!(
+        "  Renders: {} finished, {} pending",
+        stats.renders, stats.renders_pending
+    );
+    info!("  Prefetch: {} comics left", stats.prefetch_remaining);
+    info!("  Open files: {}", stats.open_handles);
+    info!("  Memory: {} bytes reserved", stats.memory_reserved);
+
+    // Last, since the database might be what everything is stuck on
+    match client.cache_size() {
+        Ok(size) => info!(
+            "  Database: {} comics, {} bytes of images",
+            client.get_cached_count(),
+            size
+        ),
+        Err(e) => warn!("  Database: could not get the cache size: {}", e),
+    }
+}
+
+/// Log a snapshot every time SIGUSR1 arrives
+pub fn spawn(client: XkcdClient) -> Result<JoinHandle<()>, String> {
+    let signals = signal_hook::iterator::Signals::new([signal_hook::SIGUSR1])
+        .map_err(|e| format!("Could not handle SIGUSR1: {}", e))?;
+
+    thread::Builder::new()
+        .name("stats".to_string())
+        .spawn(move || {
+            for _ in signals.forever() {
+                log(&client);
+            }
+        })
+        .map_err(|e| e.to_string())
+}
+
+/// Log and note what the mount did, from `started` (in seconds since the epoch)
+/// to now
+pub fn record_session(client: &XkcdClient, started: i64) {
+    let stats = client.stats().snapshot();
+    let session = Session {
+        started,
+        ended: time::get_time().sec,
+        operations: stats.operations,
+        cache_hits: stats.cache_hits,
+        cache_misses: stats.cache_misses,
+        downloaded: stats.downloaded,
+        renders: stats.renders,
+        errors: stats.errors,
+    };
+
+    let lookups = session.cache_hits + session.cache_misses;
+    let hit_rate = (session.cache_hits * 100).checked_div(lookups).unwrap_or(0);
+
+    info!(
+        "Mounted {}s: {} operations, {}% cache hits, {} bytes fetched, {} renders, {} errors",
+        session.ended - session.started,
+        session.operations,
+        hit_rate,
+        session.downloaded,
+        session.renders,
+        session.errors
+    );
+
+    if let Err(e) = client.store().put_session(&session) {
+        warn!("Could not note a summary of this session: {}", e);
+    }
+}
@@ -0,0 +1,132 @@
+//! Parsing human-friendly quantities from the command line and config file
+
+use std::time::Duration;
+
+/// Parse a size in bytes, like `4096`, `10M`, or `2GiB`
+///
+/// Suffixes are binary: `K` is 1024 bytes, `M` is 1024 `K`, and so on.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let invalid = || format!("Invalid size {:?}, expected something like 500M or 2G", s);
+
+    let trimmed = s.trim();
+    let number = trimmed.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let suffix = &trimmed[number.len()..];
+
+    let multiplier: u64 = match suffix.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return Err(invalid()),
+    };
+
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(invalid)
+}
+
+/// Split `s` after the leading characters that match `pattern`
+fn split_leading(s: &str, pattern: impl Fn(char) -> bool) -> (&str, &str) {
+    s.split_at(s.find(|c| !pattern(c)).unwrap_or(s.len()))
+}
+
+/// Parse a duration, like `500ms`, `30m`, or `1h30m`
+///
+/// A bare number is in seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || {
+        format!(
+            "Invalid duration {:?}, expected something like 30s or 6h",
+            s
+        )
+    };
+
+    let trimmed = s.trim();
+
+    if trimmed.is_empty() {
+        return Err(invalid());
+    }
+
+    if trimmed.chars().all(|c| c.is_ascii_digit()) {
+        return trimmed
+            .parse()
+            .map(Duration::from_secs)
+            .map_err(|_| invalid());
+    }
+
+    let mut rest = trimmed;
+    let mut total = Duration::from_secs(0);
+
+    while !rest.is_empty() {
+        let (number, after) = split_leading(rest, |c| c.is_ascii_digit());
+        let (unit, after) = split_leading(after, |c| c.is_ascii_alphabetic());
+        rest = after;
+
+        let number: u32 = number.parse().map_err(|_| invalid())?;
+        let unit = match unit {
+            "ms" => Duration::from_millis(1),
+            "s" => Duration::from_secs(1),
+            "m" => Duration::from_secs(60),
+            "h" => Duration::from_secs(60 * 60),
+            "d" => Duration::from_secs(24 * 60 * 60),
+            _ => return Err(invalid()),
+        };
+
+        total = unit
+            .checked_mul(number)
+            .and_then(|d| total.checked_add(d))
+            .ok_or_else(invalid)?;
+    }
+
+    Ok(total)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("0"), Ok(0));
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("100B"), Ok(100));
+        assert_eq!(parse_size("10K"), Ok(10 << 10));
+        assert_eq!(parse_size("10k"), Ok(10 << 10));
+        assert_eq!(parse_size("500MB"), Ok(500 << 20));
+        assert_eq!(parse_size("2G"), Ok(2 << 30));
+        assert_eq!(parse_size("2GiB"), Ok(2 << 30));
+        assert_eq!(parse_size("1 T"), Ok(1 << 40));
+    }
+
+    #[test]
+    fn parses_durations() {
+        let secs = Duration::from_secs;
+
+        assert_eq!(parse_duration("5"), Ok(secs(5)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("30s"), Ok(secs(30)));
+        assert_eq!(parse_duration("30m"), Ok(secs(30 * 60)));
+        assert_eq!(parse_duration("6h"), Ok(secs(6 * 60 * 60)));
+        assert_eq!(parse_duration("1d"), Ok(secs(24 * 60 * 60)));
+        assert_eq!(parse_duration("1h30m"), Ok(secs(90 * 60)));
+        assert_eq!(parse_duration(" 2m "), Ok(secs(120)));
+    }
+
+    #[test]
+    fn rejects_bad_durations() {
+        for bad in &["", "h", "5x", "1.5h", "-5s", "5 m", "h5", "99999999999d"] {
+            assert!(parse_duration(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_sizes() {
+        for bad in &["", "G", "-5M", "1.5G", "10X", "99999999999G"] {
+            assert!(parse_size(bad).is_err(), "{}", bad);
+        }
+    }
+}
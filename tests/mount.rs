@@ -0,0 +1,318 @@
+//! Mounting for real, against a local stand-in for xkcd.com
+//!
+//! These need FUSE and `fusermount`, so they only run when asked for:
+//!
+//! ```text
+//! cargo test --test mount -- --ignored
+//! ```
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use tiny_http::{Response, Server};
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/xkcdfs-core/tests/fixtures");
+
+/// How long mounting can take before a test gives up
+const MOUNT_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn fixture(name: &str) -> Vec<u8> {
+    fs::read(Path::new(FIXTURES).join(name)).unwrap()
+}
+
+/// Serve comics 1 and 2 from the fixtures, returning the base URL
+fn start_server() -> String {
+    let server = Server::http("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", server.server_addr());
+    let json = |name| {
+        String::from_utf8(fixture(name))
+            .unwrap()
+            .replace("{base}", &base)
+    };
+
+    let latest = json("info.0.json");
+    let first = json("1.info.0.json");
+    let image = fixture("progressive.jpg");
+
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let body = match request.url() {
+                "/info.0.json" | "/2/info.0.json" => latest.clone().into_bytes(),
+                "/1/info.0.json" => first.clone().into_bytes(),
+                url if url.starts_with("/comics/") => image.clone(),
+                _ => {
+                    request.respond(Response::empty(404)).ok();
+                    continue;
+                }
+            };
+
+            request.respond(Response::from_data(body)).ok();
+        }
+    });
+
+    base
+}
+
+/// xkcdfs, mounted until dropped
+struct Mount {
+    dir: PathBuf,
+    mountpoint: PathBuf,
+    process: Child,
+}
+
+impl Mount {
+    fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("xkcdfs-mount-test-{}-{}", std::process::id(), name));
+        let mountpoint = dir.join("mnt");
+        fs::create_dir_all(&mountpoint).unwrap();
+
+        // Built alongside the tests, one directory up
+        let mut binary = std::env::current_exe().unwrap();
+        binary.pop();
+        if binary.ends_with("deps") {
+            binary.pop();
+        }
+
+        let process = Command::new(binary.join(env!("CARGO_PKG_NAME")))
+            .arg("--foreground")
+            .arg("--no-render")
+            .arg("--database")
+            .arg(dir.join("cache.db"))
+            .arg("--api-url")
+            .arg(start_server())
+            .arg(&mountpoint)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        let mut mount = Self {
+            dir,
+            mountpoint,
+            process,
+        };
+
+        let start = Instant::now();
+
+        while !mount.path("credits").exists() {
+            if let Some(status) = mount.process.try_wait().unwrap() {
+                panic!("xkcdfs exited before mounting: {}", status);
+            }
+            if start.elapsed() > MOUNT_TIMEOUT {
+                panic!("xkcdfs did not mount within {:?}", MOUNT_TIMEOUT);
+            }
+
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        mount
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.mountpoint.join(name)
+    }
+
+    /// Run a command in the mountpoint, returning what it printed
+    fn run(&self, program: &str, args: &[&str]) -> Vec<u8> {
+        let output = Command::new(program)
+            .args(args)
+            .current_dir(&self.mountpoint)
+            .env("LC_ALL", "C")
+            .output()
+            .unwrap();
+
+        assert!(
+            output.status.success(),
+            "{} {:?} failed: {}",
+            program,
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        output.stdout
+    }
+
+    fn run_text(&self, program: &str, args: &[&str]) -> String {
+        String::from_utf8(self.run(program, args)).unwrap()
+    }
+}
+
+impl Drop for Mount {
+    fn drop(&mut self) {
+        Command::new("fusermount")
+            .arg("-u")
+            .arg("-z")
+            .arg(&self.mountpoint)
+            .status()
+            .ok();
+
+        if self.process.try_wait().ok().and_then(|s| s).is_none() {
+            thread::sleep(Duration::from_millis(500));
+            self.process.kill().ok();
+        }
+        self.process.wait().ok();
+
+        fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+#[test]
+#[ignore]
+fn lists_comics() {
+    let mount = Mount::new("ls");
+
+    // Only the latest comic is cached after mounting, and until comic 1 is
+    // too, its image is listed as a PNG
+    mount.run("cat", &["info_0001/title"]);
+
+    let root = mount.run_text("ls", &["-1"]);
+    let root: Vec<&str> = root.lines().collect();
+    assert_eq!(
+        root,
+        &[
+            "by_date",
+            "by_title",
+            "by_year",
+            "comic_0001.jpg",
+            "comic_0002.jpg",
+            "credits",
+            "info_0001",
+            "info_0002",
+            "latest",
+            "metadata.csv",
+            "random",
+            "refresh",
+        ]
+    );
+
+    let info = mount.run_text("ls", &["-1", "info_0001"]);
+    let info: Vec<&str> = info.lines().collect();
+    assert_eq!(
+        info,
+        &[
+            "alt",
+            "date",
+            "described.txt",
+            "raw_image",
+            "title",
+            "transcript"
+        ]
+    );
+}
+
+#[test]
+#[ignore]
+fn reads_files() {
+    let mount = Mount::new("cat");
+
+    assert_eq!(mount.run_text("cat", &["info_0001/alt"]), "Don't we all.");
+    assert_eq!(
+        mount.run_text("cat", &["info_0002/title"]),
+        "Petit Trees (sketch)"
+    );
+    assert_eq!(mount.run_text("cat", &["info_0001/date"]), "2006-01-01");
+    assert_eq!(
+        mount.run("cat", &["comic_0001.jpg"]),
+        fixture("progressive.jpg")
+    );
+    assert_eq!(
+        mount.run("cat", &["info_0002/raw_image"]),
+        fixture("progressive.jpg")
+    );
+
+    // Both comics and their images are cached by now
+    let csv = mount.run_text("cat", &["metadata.csv"]);
+    let csv: Vec<&str> = csv.lines().collect();
+    assert_eq!(csv.len(), 3);
+    assert_eq!(
+        csv[0],
+        "num,date,title,alt_length,has_transcript,image_size"
+    );
+    assert!(csv[2].starts_with("2,"));
+    assert!(csv[2].ends_with(&format!(",{}", fixture("progressive.jpg").len())));
+
+    assert_eq!(mount.run_text("readlink", &["latest"]), "comic_0002.jpg\n");
+    assert_eq!(mount.run("cat", &["latest"]), fixture("progressive.jpg"));
+
+    let random = mount.run_text("readlink", &["random"]);
+    assert!(random == "comic_0001.jpg\n" || random == "comic_0002.jpg\n");
+
+    assert_eq!(mount.run_text("ls", &["by_year"]), "2006\n");
+    assert_eq!(
+        mount.run_text("ls", &["by_year/2006"]),
+        "comic_0001.jpg\ncomic_0002.jpg\n"
+    );
+    assert_eq!(mount.run_text("ls", &["by_date"]), "");
+    assert_eq!(
+        mount.run_text("ls", &["by_date/2006-01-01"]),
+        "comic_0001.jpg\ncomic_0002.jpg\n"
+    );
+    assert_eq!(
+        mount.run_text("ls", &["by_title"]),
+        "Barrel - Part 1.jpg\nPetit Trees (sketch).jpg\n"
+    );
+    assert_eq!(
+        mount.run("cat", &["by_title/Petit Trees (sketch).jpg"]),
+        fixture("progressive.jpg")
+    );
+
+    let status = mount.run_text("cat", &[".status"]);
+    assert!(status.starts_with("uptime: "));
+    assert!(status.contains("\nlast_refresh: "));
+}
+
+#[test]
+#[ignore]
+fn stats_files() {
+    let mount = Mount::new("stat");
+
+    assert_eq!(
+        mount.run_text("stat", &["-c", "%F %a", "info_0002"]),
+        "directory 444\n"
+    );
+    assert_eq!(
+        mount.run_text("stat", &["-c", "%F %a %s", "info_0002/title"]),
+        "regular file 444 20\n"
+    );
+
+    // Images aren't fetched just to be listed, so the size is only right once
+    // the image is cached and the kernel has forgotten the old one
+    mount.run("cat", &["comic_0002.jpg"]);
+    thread::sleep(Duration::from_millis(1500));
+
+    assert_eq!(
+        mount.run_text("stat", &["-c", "%s", "comic_0002.jpg"]),
+        format!("{}\n", fixture("progressive.jpg").len())
+    );
+}
+
+#[test]
+#[ignore]
+fn errors() {
+    let mount = Mount::new("errors");
+
+    let missing = fs::metadata(mount.path("nope")).unwrap_err();
+    assert_eq!(missing.kind(), ErrorKind::NotFound);
+
+    let missing = fs::metadata(mount.path("info_0001/nope")).unwrap_err();
+    assert_eq!(missing.kind(), ErrorKind::NotFound);
+
+    let directory = fs::read(mount.path("info_0001")).unwrap_err();
+    assert_eq!(directory.raw_os_error(), Some(libc::EISDIR));
+
+    let not_directory = fs::read_dir(mount.path("info_0001/alt/")).unwrap_err();
+    assert_eq!(not_directory.raw_os_error(), Some(libc::ENOTDIR));
+
+    let read_only = fs::OpenOptions::new()
+        .write(true)
+        .open(mount.path("info_0001/alt"))
+        .unwrap_err();
+    assert_eq!(read_only.raw_os_error(), Some(libc::EACCES));
+
+    // Like `> refresh`, which truncates
+    fs::write(mount.path("refresh"), b"").unwrap();
+}
@@ -0,0 +1,90 @@
+//! Reads through `XkcdClient`, from the cache and from scratch
+//!
+//! Nothing touches the network: every comic is the one in `tests/fixtures`,
+//! with the fixture JPEG as its image.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use std::ffi::OsStr;
+use xkcdfs_core::image::RenderOptions;
+use xkcdfs_core::requests::parse_comic;
+use xkcdfs_core::{Comic, ComicSource, RequestMode, XkcdClient};
+
+const COMIC: &[u8] = include_bytes!("../tests/fixtures/info.0.json");
+const IMAGE: &[u8] = include_bytes!("../tests/fixtures/progressive.jpg");
+
+#[derive(Clone)]
+struct FixtureSource;
+
+impl ComicSource for FixtureSource {
+    fn latest(&self) -> Result<Comic, String> {
+        self.comic(1)
+    }
+
+    fn comic(&self, num: u32) -> Result<Comic, String> {
+        Ok(Comic {
+            num,
+            ..parse_comic(COMIC)?
+        })
+    }
+
+    fn image(&self, _comic: &Comic) -> Result<Vec<u8>, String> {
+        Ok(IMAGE.to_vec())
+    }
+}
+
+/// A client with an empty cache
+fn client() -> XkcdClient<FixtureSource> {
+    XkcdClient::with_source(
+        FixtureSource,
+        OsStr::new(":memory:"),
+        false,
+        false,
+        RenderOptions::default(),
+        None,
+    )
+    .unwrap()
+}
+
+fn cached(c: &mut Criterion) {
+    let client = client();
+    let comic = client.request_comic(1, None, RequestMode::Normal).unwrap();
+    client
+        .request_rendered_image(&comic, None, RequestMode::Normal)
+        .unwrap();
+
+    // VeryFast only looks in the cache, so a miss would show up as a panic
+    c.bench_function("cached comic", |b| {
+        b.iter(|| {
+            client
+                .request_comic(1, None, RequestMode::VeryFast)
+                .unwrap()
+        })
+    });
+
+    c.bench_function("cached rendered image", |b| {
+        b.iter(|| {
+            client
+                .request_rendered_image(&comic, None, RequestMode::VeryFast)
+                .unwrap()
+        })
+    });
+}
+
+fn cold(c: &mut Criterion) {
+    c.bench_function("cold read", |b| {
+        b.iter_batched(
+            client,
+            |client| {
+                let comic = client.request_comic(1, None, RequestMode::Normal).unwrap();
+
+                client
+                    .request_rendered_image(&comic, None, RequestMode::Normal)
+                    .unwrap()
+            },
+            BatchSize::PerIteration,
+        )
+    });
+}
+
+criterion_group!(reads, cached, cold);
+criterion_main!(reads);
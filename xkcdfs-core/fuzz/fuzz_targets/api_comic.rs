@@ -0,0 +1,9 @@
+//! Whatever the API sends back, reading it is an error at worst
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use xkcdfs_core::requests::parse_comic;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_comic(data);
+});
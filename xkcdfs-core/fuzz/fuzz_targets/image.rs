@@ -0,0 +1,11 @@
+//! Comic images come from the network, so decoding one that's truncated or
+//! corrupt has to fail cleanly instead of panicking
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use std::io::Cursor;
+use xkcdfs_core::image::create_image_surface;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = create_image_surface(&mut Cursor::new(data));
+});
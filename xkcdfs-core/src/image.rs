@@ -0,0 +1,1721 @@
+use crate::Comic;
+use cairo::{
+    Antialias, Context, Filter, FontOptions, Format, HintMetrics, HintStyle, ImageSurface,
+    PdfMetadata, PdfSurface, SurfacePattern, SvgSurface, TextExtents,
+};
+use jpeg_decoder::PixelFormat;
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+use std::str::FromStr;
+
+use self::format::ImageFormat;
+
+pub mod ascii;
+mod chunks;
+pub mod dimensions;
+mod fallback;
+pub mod format;
+pub mod gif;
+pub mod montage;
+pub mod wallpaper;
+
+const FONT_FAMILY: &str = "NimbusSans";
+
+/// Enough of an image to tell what kind it is
+const SNIFF_LEN: u64 = 12;
+
+// The classic theme, which the other presets start from
+const OUTER_MARGIN: f64 = 40.0;
+
+const HEADER_FONT_SIZE: f64 = 20.0;
+const HEADER_TO_COMIC_SPACING: f64 = 30.0;
+
+const COMIC_TO_ALT_SPACING: f64 = 30.0;
+const ALT_WIDTH_TARGET: f64 = 500.0;
+const ALT_FONT_SIZE: f64 = 16.0;
+const ALT_LEADING: f64 = 5.0;
+const ALT_BOX_PADDING: f64 = 10.0;
+const ALT_BORDER_WIDTH: f64 = 1.0;
+
+const BLACK: Color = Color {
+    red: 0x00,
+    green: 0x00,
+    blue: 0x00,
+};
+const WHITE: Color = Color {
+    red: 0xFF,
+    green: 0xFF,
+    blue: 0xFF,
+};
+const ALT_BG: Color = Color {
+    red: 0xFF,
+    green: 0xF9,
+    blue: 0xBD,
+};
+
+// High-contrast renders use bigger text, and split the comic into pure black
+// and white at this luminance
+const HIGH_CONTRAST_TEXT_SCALE: f64 = 1.5;
+const HIGH_CONTRAST_THRESHOLD: f64 = 0.5;
+
+// Limits for user-supplied layout options
+const MAX_SPACING: f64 = 1000.0;
+const MIN_FONT_SIZE: f64 = 4.0;
+const MAX_FONT_SIZE: f64 = 200.0;
+const MAX_BORDER_WIDTH: f64 = 50.0;
+const MIN_ALT_WIDTH: f64 = 100.0;
+const MAX_ALT_WIDTH: f64 = 5000.0;
+
+const AUTHOR: &str = "Randall Munroe";
+
+// Cairo's name for hyperlinks in tagged output
+const LINK_TAG: &str = "Link";
+
+/// Filtering used when scaling an image down
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScaleFilter {
+    /// Averages the covered source pixels, which keeps line art crisp
+    Box,
+    /// Windowed sinc, which is smoother on gradients but a bit slower
+    Lanczos,
+}
+
+impl ScaleFilter {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Box => "box",
+            Self::Lanczos => "lanczos",
+        }
+    }
+
+    fn cairo_filter(&self) -> Filter {
+        // Pixman implements GOOD as a box filter and BEST as Lanczos when
+        // downscaling
+        match self {
+            Self::Box => Filter::Good,
+            Self::Lanczos => Filter::Best,
+        }
+    }
+}
+
+impl FromStr for ScaleFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "box" => Ok(Self::Box),
+            "lanczos" => Ok(Self::Lanczos),
+            other => Err(format!("Unknown scale filter: {}", other)),
+        }
+    }
+}
+
+/// How much contrast rendered comics have
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Contrast {
+    /// Black on transparent, like the original comics
+    Normal,
+    /// Larger white text and a white-on-black comic, for low-vision readers
+    High,
+}
+
+impl Contrast {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Normal => "normal",
+            Self::High => "high",
+        }
+    }
+}
+
+impl FromStr for Contrast {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "normal" => Ok(Self::Normal),
+            "high" => Ok(Self::High),
+            other => Err(format!("Unknown contrast: {}", other)),
+        }
+    }
+}
+
+/// An opaque RGB color
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Color {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Color {
+    /// Components scaled to the 0-1 range Cairo expects
+    pub fn rgb(&self) -> (f64, f64, f64) {
+        (
+            self.red as f64 / 255.0,
+            self.green as f64 / 255.0,
+            self.blue as f64 / 255.0,
+        )
+    }
+}
+
+impl std::fmt::Display for Color {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "#{:02x}{:02x}{:02x}", self.red, self.green, self.blue)
+    }
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    /// Parse a hex color like `#fff9bd` (the `#` is optional)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.trim_start_matches('#');
+
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!(
+                "Invalid color {:?}, expected something like #fff9bd",
+                s
+            ));
+        }
+
+        let component = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+
+        Ok(Color {
+            red: component(0),
+            green: component(2),
+            blue: component(4),
+        })
+    }
+}
+
+/// Named starting points for render settings
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Preset {
+    /// Black text and a yellow alt box on transparent, like the original
+    /// xkcdfs renders
+    Classic,
+    /// Light text on a dark background
+    Dark,
+    /// Black on white with a plain alt box, to go easy on ink
+    Print,
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "classic" => Ok(Self::Classic),
+            "dark" => Ok(Self::Dark),
+            "print" => Ok(Self::Print),
+            other => Err(format!("Unknown preset: {}", other)),
+        }
+    }
+}
+
+/// Settings that change what `render` produces
+///
+/// Anything in here must also be reflected in `cache_key`, so that renders
+/// made with different options don't get mixed up in the cache.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderOptions {
+    /// Maximum width of the whole render, in pixels
+    pub max_width: Option<u32>,
+    /// Maximum height of the whole render, in pixels
+    pub max_height: Option<u32>,
+    /// Space around the edge of the render
+    pub outer_margin: f64,
+    /// Width the alt text is wrapped to
+    pub alt_width: f64,
+    /// Space between the alt text and the edge of its box
+    pub alt_padding: f64,
+    pub header_font_size: f64,
+    /// Space between the title and the comic
+    pub header_spacing: f64,
+    pub alt_font_size: f64,
+    /// Extra space between lines of alt text
+    pub alt_leading: f64,
+    /// Space between the comic and the alt text box
+    pub alt_spacing: f64,
+    pub alt_border_width: f64,
+    /// Color of the title and alt text
+    pub foreground: Color,
+    /// Color behind everything, or None to leave it transparent
+    pub background: Option<Color>,
+    pub alt_background: Color,
+    pub alt_border: Color,
+    pub contrast: Contrast,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self::preset(Preset::Classic)
+    }
+}
+
+impl RenderOptions {
+    pub fn preset(preset: Preset) -> Self {
+        let classic = Self {
+            max_width: None,
+            max_height: None,
+            outer_margin: OUTER_MARGIN,
+            alt_width: ALT_WIDTH_TARGET,
+            alt_padding: ALT_BOX_PADDING,
+            header_font_size: HEADER_FONT_SIZE,
+            header_spacing: HEADER_TO_COMIC_SPACING,
+            alt_font_size: ALT_FONT_SIZE,
+            alt_leading: ALT_LEADING,
+            alt_spacing: COMIC_TO_ALT_SPACING,
+            alt_border_width: ALT_BORDER_WIDTH,
+            foreground: BLACK,
+            background: None,
+            alt_background: ALT_BG,
+            alt_border: BLACK,
+            contrast: Contrast::Normal,
+        };
+
+        match preset {
+            Preset::Classic => classic,
+            Preset::Dark => {
+                let light = Color {
+                    red: 0xE6,
+                    green: 0xE6,
+                    blue: 0xE6,
+                };
+
+                Self {
+                    foreground: light,
+                    background: Some(Color {
+                        red: 0x1E,
+                        green: 0x1E,
+                        blue: 0x1E,
+                    }),
+                    alt_background: Color {
+                        red: 0x3A,
+                        green: 0x37,
+                        blue: 0x26,
+                    },
+                    alt_border: light,
+                    ..classic
+                }
+            }
+            Preset::Print => Self {
+                background: Some(WHITE),
+                alt_background: WHITE,
+                ..classic
+            },
+        }
+    }
+
+    /// Check that the options will produce a sensible image
+    pub fn validate(&self) -> Result<(), String> {
+        fn spacing(name: &str, value: f64) -> Result<(), String> {
+            if (0.0..=MAX_SPACING).contains(&value) {
+                Ok(())
+            } else {
+                Err(format!(
+                    "{} must be between 0 and {} pixels, not {}",
+                    name, MAX_SPACING, value
+                ))
+            }
+        }
+
+        fn font_size(name: &str, value: f64) -> Result<(), String> {
+            if (MIN_FONT_SIZE..=MAX_FONT_SIZE).contains(&value) {
+                Ok(())
+            } else {
+                Err(format!(
+                    "{} must be between {} and {} pixels, not {}",
+                    name, MIN_FONT_SIZE, MAX_FONT_SIZE, value
+                ))
+            }
+        }
+
+        spacing("Margin", self.outer_margin)?;
+        spacing("Alt text padding", self.alt_padding)?;
+        spacing("Title spacing", self.header_spacing)?;
+        spacing("Alt text spacing", self.alt_spacing)?;
+        spacing("Alt text leading", self.alt_leading)?;
+        font_size("Title font size", self.header_font_size)?;
+        font_size("Alt text font size", self.alt_font_size)?;
+
+        if !(0.0..=MAX_BORDER_WIDTH).contains(&self.alt_border_width) {
+            return Err(format!(
+                "Alt text border width must be between 0 and {} pixels, not {}",
+                MAX_BORDER_WIDTH, self.alt_border_width
+            ));
+        }
+
+        for &(name, max) in &[("width", self.max_width), ("height", self.max_height)] {
+            if max == Some(0) {
+                return Err(format!("Maximum render {} must be at least 1 pixel", name));
+            }
+        }
+
+        if !(MIN_ALT_WIDTH..=MAX_ALT_WIDTH).contains(&self.alt_width) {
+            return Err(format!(
+                "Alt text width must be between {} and {} pixels, not {}",
+                MIN_ALT_WIDTH, MAX_ALT_WIDTH, self.alt_width
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Stable string identifying these options in the render cache
+    pub fn cache_key(&self) -> String {
+        fn opt(value: Option<u32>) -> String {
+            value
+                .map(|v| v.to_string())
+                .unwrap_or_else(|| "-".to_string())
+        }
+
+        format!(
+            concat!(
+                "max_width={};max_height={};margin={};alt_width={};alt_padding={};",
+                "header_font_size={};header_spacing={};alt_font_size={};alt_leading={};",
+                "alt_spacing={};alt_border_width={};foreground={};background={};",
+                "alt_background={};alt_border={};contrast={}",
+            ),
+            opt(self.max_width),
+            opt(self.max_height),
+            self.outer_margin,
+            self.alt_width,
+            self.alt_padding,
+            self.header_font_size,
+            self.header_spacing,
+            self.alt_font_size,
+            self.alt_leading,
+            self.alt_spacing,
+            self.alt_border_width,
+            self.foreground,
+            self.background
+                .map(|c| c.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            self.alt_background,
+            self.alt_border,
+            self.contrast.name(),
+        )
+    }
+
+    /// How much bigger than normal the text is drawn
+    fn text_scale(&self) -> f64 {
+        match self.contrast {
+            Contrast::Normal => 1.0,
+            Contrast::High => HIGH_CONTRAST_TEXT_SCALE,
+        }
+    }
+
+    // High contrast overrides the theme's colors with plain white on black
+
+    /// Color of the text
+    fn foreground_rgb(&self) -> (f64, f64, f64) {
+        match self.contrast {
+            Contrast::Normal => self.foreground.rgb(),
+            Contrast::High => WHITE.rgb(),
+        }
+    }
+
+    /// Color behind everything, if the render isn't transparent
+    fn background_rgb(&self) -> Option<(f64, f64, f64)> {
+        match self.contrast {
+            Contrast::Normal => self.background.map(|c| c.rgb()),
+            Contrast::High => Some(BLACK.rgb()),
+        }
+    }
+
+    fn alt_box_fill(&self) -> (f64, f64, f64) {
+        match self.contrast {
+            Contrast::Normal => self.alt_background.rgb(),
+            Contrast::High => BLACK.rgb(),
+        }
+    }
+
+    fn alt_box_border(&self) -> (f64, f64, f64) {
+        match self.contrast {
+            Contrast::Normal => self.alt_border.rgb(),
+            Contrast::High => WHITE.rgb(),
+        }
+    }
+}
+
+/// Formats `render_vector` can produce
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VectorFormat {
+    Pdf,
+    Svg,
+}
+
+impl VectorFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Pdf => "pdf",
+            Self::Svg => "svg",
+        }
+    }
+}
+
+/// Everything needed to produce (and cache) one size of thumbnail
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ThumbnailSpec {
+    pub width: u32,
+    pub filter: ScaleFilter,
+}
+
+fn jpeg_to_cairo(
+    old_data: Vec<u8>,
+    width: usize,
+    height: usize,
+    old_format: PixelFormat,
+    new_format: Format,
+) -> Result<(usize, Vec<u8>), String> {
+    if width == 0 || height == 0 {
+        return Err(format!("JPEG has no pixels ({}x{})", width, height));
+    }
+
+    let old_pixel_size = match old_format {
+        PixelFormat::RGB24 => 3,
+        PixelFormat::L8 => 1,
+        other => Err(format!("Unsupported pixel format: {:?}", other))?,
+    };
+
+    let new_pixel_size = match new_format {
+        Format::Rgb24 => 4,
+        other => Err(format!("Unsupported Cairo pixel format: {:?}", other))?,
+    };
+
+    // Old stride => byte width of each row
+    // New stride => calculated from the format and width
+    let old_stride = old_pixel_size * width;
+    let new_stride = new_format.stride_for_width(width as u32).map_err(|()| {
+        format!(
+            "Failed to calculate stride for {} with width {}",
+            new_format, width
+        )
+    })? as usize;
+
+    if old_stride * height != old_data.len() {
+        return Err(format!(
+            "JPEG decoded to {} bytes, expected {} for {}x{}",
+            old_data.len(),
+            old_stride * height,
+            width,
+            height
+        ));
+    }
+
+    debug_assert!(new_pixel_size * width <= new_stride);
+
+    // This is a specific conversion based on what formats are moving
+    // It's hard to generalize this bit, because the fields within each pixel
+    // have to change.
+
+    match (old_format, new_format) {
+        (PixelFormat::RGB24, Format::Rgb24) => {
+            let mut new_data: Vec<u8> = Vec::with_capacity(new_stride * height);
+
+            for row in 0..height {
+                new_data.resize_with(row * new_stride, Default::default);
+
+                for col in 0..width {
+                    let old_index = row * old_stride + col * old_pixel_size;
+
+                    let rgb_data = [
+                        0,
+                        old_data[old_index],
+                        old_data[old_index + 1],
+                        old_data[old_index + 2],
+                    ];
+                    let rgb_data = i32::from_be_bytes(rgb_data);
+
+                    new_data.extend_from_slice(&rgb_data.to_ne_bytes());
+                }
+            }
+
+            Ok((new_stride, new_data))
+        }
+        (PixelFormat::L8, Format::Rgb24) => {
+            let mut new_data: Vec<u8> = Vec::with_capacity(new_stride * height);
+
+            for row in 0..height {
+                new_data.resize_with(row * new_stride, Default::default);
+
+                for col in 0..width {
+                    let old_index = row * old_stride + col * old_pixel_size;
+
+                    let rgb_data = [
+                        0,
+                        old_data[old_index],
+                        old_data[old_index],
+                        old_data[old_index],
+                    ];
+                    let rgb_data = i32::from_be_bytes(rgb_data);
+
+                    new_data.extend_from_slice(&rgb_data.to_ne_bytes());
+                }
+            }
+
+            Ok((new_stride, new_data))
+        }
+        (o, n) => Err(format!(
+            "Cannot convert between JPEG pixel format {:?} and Cairo pixel format {:?}",
+            o, n
+        )),
+    }
+}
+
+/// Decode a PNG or JPEG into something Cairo can draw
+///
+/// The format is told by the image's first few bytes, and anything else is an
+/// error.
+pub fn create_image_surface<R: Read + Seek>(image: &mut R) -> Result<ImageSurface, String> {
+    let mut start = Vec::new();
+    let read_error = |e| format!("Could not read the image: {}", e);
+
+    image
+        .by_ref()
+        .take(SNIFF_LEN)
+        .read_to_end(&mut start)
+        .map_err(read_error)?;
+    image.seek(SeekFrom::Start(0)).map_err(read_error)?;
+
+    match ImageFormat::sniff(&start) {
+        Some(ImageFormat::Png) => decode_png(image),
+        Some(ImageFormat::Jpeg) => decode_jpeg(image),
+        Some(other) => Err(format!("Cannot decode {} images", other.content_type())),
+        None => Err("Could not tell what kind of image this is".to_string()),
+    }
+}
+
+fn decode_png<R: Read>(image: &mut R) -> Result<ImageSurface, String> {
+    // Cairo only ever reports "out of memory" on a bad PNG
+    ImageSurface::create_from_png(image).map_err(|e| format!("Could not decode the PNG: {}", e))
+}
+
+/// Decode a JPEG, baseline or progressive
+fn decode_jpeg<R: Read>(image: &mut R) -> Result<ImageSurface, String> {
+    let mut decoder = jpeg_decoder::Decoder::new(image);
+    let pixels = decoder
+        .decode()
+        .map_err(|e| format!("Could not decode the JPEG: {}", e))?;
+
+    let info = decoder
+        .info()
+        .ok_or_else(|| "JPEG decode succeeded but could not get metadata".to_string())?;
+
+    // Decide which Cairo pixel format is appropriate for the decoded JPEG pixel format
+    let cairo_format = Format::Rgb24;
+
+    // Convert from JPEG's pixel format to Cairo's
+    // There's a bunch of nuance tucked away in this function, and not all
+    // format pairs are supported
+    let (stride, adjusted_pixels) = jpeg_to_cairo(
+        pixels,
+        info.width as usize,
+        info.height as usize,
+        info.pixel_format,
+        cairo_format,
+    )?;
+
+    // Be sure to use the stride value returned before
+    ImageSurface::create_for_data(
+        adjusted_pixels,
+        cairo_format,
+        info.width as i32,
+        info.height as i32,
+        stride as i32,
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// Scale an image so that it fits within `max_width` by `max_height`
+///
+/// The aspect ratio is preserved, and images that already fit are copied at
+/// their original size rather than being scaled up.
+pub fn downscale(
+    source: &ImageSurface,
+    max_width: u32,
+    max_height: u32,
+    filter: ScaleFilter,
+) -> Result<ImageSurface, String> {
+    let source_width = source.get_width();
+    let source_height = source.get_height();
+
+    if source_width <= 0 || source_height <= 0 {
+        return Err(format!(
+            "Cannot scale an image with dimensions {} by {}",
+            source_width, source_height
+        ));
+    }
+
+    let scale = (max_width as f64 / source_width as f64)
+        .min(max_height as f64 / source_height as f64)
+        .min(1.0);
+
+    let target_width = ((source_width as f64 * scale).round() as i32).max(1);
+    let target_height = ((source_height as f64 * scale).round() as i32).max(1);
+
+    trace!(
+        "Scaling {} by {} image to {} by {} ({:?} filter)",
+        source_width,
+        source_height,
+        target_width,
+        target_height,
+        filter
+    );
+
+    let surface = ImageSurface::create(Format::ARgb32, target_width, target_height)
+        .map_err(|e| e.to_string())?;
+    let ctx = Context::new(&surface);
+
+    ctx.scale(
+        target_width as f64 / source_width as f64,
+        target_height as f64 / source_height as f64,
+    );
+
+    let pattern = SurfacePattern::create(source);
+    pattern.set_filter(filter.cairo_filter());
+
+    ctx.set_source(&pattern);
+    ctx.paint();
+
+    Ok(surface)
+}
+
+/// Relative luminance (0 to 1) of a pixel from an RGB24 surface
+fn luminance(pixel: u32) -> f64 {
+    let red = ((pixel >> 16) & 0xFF) as f64 / 255.0;
+    let green = ((pixel >> 8) & 0xFF) as f64 / 255.0;
+    let blue = (pixel & 0xFF) as f64 / 255.0;
+
+    0.2126 * red + 0.7152 * green + 0.0722 * blue
+}
+
+/// Reduce an image to white lines on a black background
+///
+/// Anything darker than the threshold becomes white and everything else
+/// black. Transparent areas count as white paper.
+fn high_contrast(source: &ImageSurface) -> Result<ImageSurface, String> {
+    let width = source.get_width();
+    let height = source.get_height();
+
+    let mut surface =
+        ImageSurface::create(Format::Rgb24, width, height).map_err(|e| e.to_string())?;
+
+    {
+        let ctx = Context::new(&surface);
+
+        ctx.set_source_rgb(1.0, 1.0, 1.0);
+        ctx.paint();
+        ctx.set_source_surface(source, 0.0, 0.0);
+        ctx.paint();
+    }
+
+    surface.flush();
+
+    let stride = surface.get_stride() as usize;
+
+    {
+        let mut data = surface.get_data().map_err(|e| format!("{:?}", e))?;
+
+        for row in 0..height as usize {
+            for col in 0..width as usize {
+                let offset = row * stride + col * 4;
+                let pixel = &mut data[offset..offset + 4];
+                let rgb = u32::from_ne_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+
+                let value: u32 = if luminance(rgb) < HIGH_CONTRAST_THRESHOLD {
+                    0x00FF_FFFF
+                } else {
+                    0
+                };
+
+                pixel.copy_from_slice(&value.to_ne_bytes());
+            }
+        }
+    }
+
+    surface.mark_dirty();
+
+    Ok(surface)
+}
+
+/// Create a PNG thumbnail of a raw comic image
+pub fn thumbnail<R: Read + Seek>(image: &mut R, spec: ThumbnailSpec) -> Result<Vec<u8>, String> {
+    let source = create_image_surface(image)?;
+    let scaled = downscale(&source, spec.width, u32::MAX, spec.filter)?;
+
+    let mut buffer = Vec::new();
+
+    scaled
+        .write_to_png(&mut buffer)
+        .map_err(|e| format!("Could not write thumbnail PNG: {:?}", e))?;
+
+    chunks::remove_chunks(&buffer, chunks::TIMESTAMPS)
+}
+
+pub fn break_text(ctx: Context, text: &str, target_width: f64) -> Vec<(TextExtents, &str)> {
+    use unicode_linebreak::BreakOpportunity::Mandatory;
+
+    let mut segment_start = 0;
+    let mut last_location = 0;
+
+    let mut lines = Vec::new();
+
+    let mut last_extents = TextExtents {
+        x_bearing: 0.0,
+        y_bearing: 0.0,
+        width: 0.0,
+        height: 0.0,
+        x_advance: 0.0,
+        y_advance: 0.0,
+    };
+
+    for (location, opp_kind) in unicode_linebreak::linebreaks(text) {
+        let proposed_line = &text[segment_start..location];
+        let proposed_extents = fallback::text_extents(&ctx, proposed_line);
+
+        trace!("Proposed break: ({}, {:?})", location, opp_kind);
+
+        // If this is a mandatory break, use the proposed line directly
+        if opp_kind == Mandatory {
+            segment_start = location;
+            last_location = location;
+            last_extents = proposed_extents;
+
+            lines.push((proposed_extents, proposed_line));
+
+            trace!(
+                "Mandatory break line: {:?}, {} wide",
+                proposed_line,
+                proposed_extents.width
+            );
+
+            continue;
+        }
+
+        // If we're too wide and there *is* a previous segment, return that
+        // segment
+        if proposed_extents.width > target_width && last_location != segment_start {
+            let last_line = &text[segment_start..last_location];
+
+            segment_start = last_location;
+            last_location = location;
+
+            trace!(
+                "Overflow, fallback to last line: {:?}, {} wide",
+                last_line,
+                last_extents.width
+            );
+
+            last_extents = proposed_extents;
+            lines.push((last_extents, last_line));
+
+            continue;
+        }
+
+        // If we're too wide, but there's nothing shorter to fall back on,
+        // just roll with it
+        if proposed_extents.width > target_width && last_location == segment_start {
+            segment_start = location;
+            last_location = location;
+
+            trace!(
+                "Too wide, but can't break: {:?}, {} wide",
+                proposed_line,
+                proposed_extents.width
+            );
+
+            lines.push((proposed_extents, proposed_line));
+
+            last_extents = proposed_extents;
+            continue;
+        }
+
+        // If we got here, we're not too wide, but we might not be wide enough
+        // Update this location in case the next line wants it, but don't
+        // start a new segment
+        last_location = location;
+        last_extents = proposed_extents;
+    }
+
+    lines
+}
+
+pub fn aligned_start_points(sizes: &mut [f64]) -> &mut [f64] {
+    let widest = sizes.iter().fold(f64::NEG_INFINITY, |a, &b| a.max(b));
+
+    for size in sizes.iter_mut() {
+        *size = (widest - *size) / 2.0;
+    }
+
+    sizes
+}
+
+pub fn text_block_extents<'e, I: IntoIterator<Item = &'e TextExtents>>(
+    iter: I,
+    line_spacing: f64,
+) -> TextExtents {
+    let mut iter = iter.into_iter();
+    let first_extents = *iter.next().expect("Could not get first line");
+
+    iter.fold(first_extents, |mut acc, new| {
+        acc.width = acc.width.max(new.width);
+        acc.height += line_spacing + new.height;
+
+        acc.x_advance = acc.x_advance.max(new.x_advance);
+        acc.y_advance += line_spacing + new.y_advance;
+
+        acc
+    })
+}
+
+/// Where everything goes in a rendered comic
+struct Layout<'c> {
+    comic_surface: ImageSurface,
+    alt_lines: Vec<(TextExtents, &'c str)>,
+
+    /// Size of the whole render, already scaled
+    width: f64,
+    height: f64,
+    /// How much everything is scaled by to fit in the maximum size
+    scale: f64,
+
+    header_extents: TextExtents,
+    header_start: (f64, f64),
+    comic_start: (f64, f64),
+    alt_box: (f64, f64, f64, f64),
+    alt_start: (f64, f64),
+}
+
+impl<'c> Layout<'c> {
+    /// Area covered by the title in the render, as (x, y, width, height)
+    fn header_rect(&self) -> (f64, f64, f64, f64) {
+        (
+            (self.header_start.0 + self.header_extents.x_bearing) * self.scale,
+            (self.header_start.1 + self.header_extents.y_bearing) * self.scale,
+            self.header_extents.width * self.scale,
+            self.header_extents.height * self.scale,
+        )
+    }
+}
+
+/// Make sure the font comics are rendered in is installed
+///
+/// Cairo quietly substitutes another font when it isn't, so this asks
+/// fontconfig directly.
+pub fn check_font() -> Result<&'static str, String> {
+    let output = std::process::Command::new("fc-list")
+        .arg(FONT_FAMILY)
+        .arg("family")
+        .output()
+        .map_err(|e| format!("Could not run fc-list: {}", e))?;
+
+    if output.stdout.iter().all(u8::is_ascii_whitespace) {
+        return Err(format!(
+            "{} is not installed, so another font will be used",
+            FONT_FAMILY
+        ));
+    }
+
+    Ok(FONT_FAMILY)
+}
+
+/// Text rendering settings, pinned so that output doesn't depend on the
+/// system's font configuration
+fn font_options() -> FontOptions {
+    let mut options = FontOptions::new();
+
+    options.set_antialias(Antialias::Gray);
+    options.set_hint_style(HintStyle::None);
+    options.set_hint_metrics(HintMetrics::Off);
+
+    options
+}
+
+fn layout<'c, R: Read + Seek>(
+    comic: &'c Comic,
+    image: &mut R,
+    options: &RenderOptions,
+) -> Result<Layout<'c>, String> {
+    // Load this first because we need its coordinates
+    let original_surface = create_image_surface(image)?;
+    let comic_ctx = Context::new(&original_surface);
+    comic_ctx.set_font_options(&font_options());
+
+    // Set title font settings
+    comic_ctx.select_font_face(
+        FONT_FAMILY,
+        cairo::FontSlant::Normal,
+        cairo::FontWeight::Bold,
+    );
+    comic_ctx.set_font_size(options.header_font_size * options.text_scale());
+
+    // Get the title size
+    let header_size = fallback::text_extents(&comic_ctx, &comic.safe_title);
+
+    // Set alt text font settings
+    comic_ctx.select_font_face(
+        FONT_FAMILY,
+        cairo::FontSlant::Normal,
+        cairo::FontWeight::Normal,
+    );
+    comic_ctx.set_font_size(options.alt_font_size * options.text_scale());
+
+    // Wrap the alt text narrower if it wouldn't fit otherwise
+    let alt_width = options.max_width.map_or(options.alt_width, |w| {
+        options.alt_width.min(
+            (w as f64
+                - options.outer_margin
+                - options.alt_padding
+                - options.alt_padding
+                - options.outer_margin)
+                .max(1.0),
+        )
+    });
+
+    // Set alt text size
+    let alt_lines = break_text(comic_ctx, &comic.alt, alt_width);
+    let alt_extents = text_block_extents(
+        alt_lines.iter().map(|(ref e, _)| e),
+        options.alt_leading * options.text_scale(),
+    );
+
+    trace!(
+        "Alt text is {} by {}, {:?}",
+        alt_extents.width,
+        alt_extents.height,
+        alt_lines
+    );
+
+    // Set alt box size -- Need to floor and ceil explicitly to avoid bluriness
+    let alt_box_width = (options.alt_padding + alt_extents.width + options.alt_padding).floor();
+    let alt_box_height = (options.alt_padding + alt_extents.height + options.alt_padding).ceil();
+
+    trace!("Alt box is {} by {}", alt_box_width, alt_box_height);
+
+    // Shrink the comic itself if the overall image would be out of bounds.
+    // Everything else stays the same size so the text is still readable.
+    let max_comic_width = options
+        .max_width
+        .map(|w| (w as f64 - options.outer_margin - options.outer_margin).max(1.0) as u32)
+        .unwrap_or(u32::MAX);
+    let max_comic_height = options
+        .max_height
+        .map(|h| {
+            (h as f64
+                - options.outer_margin
+                - header_size.height
+                - options.header_spacing
+                - options.alt_spacing
+                - alt_box_height
+                - options.outer_margin)
+                .max(1.0) as u32
+        })
+        .unwrap_or(u32::MAX);
+
+    let comic_surface = if original_surface.get_width() as u32 > max_comic_width
+        || original_surface.get_height() as u32 > max_comic_height
+    {
+        debug!(
+            "Scaling {} to fit within {} by {}",
+            comic, max_comic_width, max_comic_height
+        );
+
+        downscale(
+            &original_surface,
+            max_comic_width,
+            max_comic_height,
+            ScaleFilter::Box,
+        )?
+    } else {
+        original_surface
+    };
+
+    let comic_surface = match options.contrast {
+        Contrast::Normal => comic_surface,
+        Contrast::High => high_contrast(&comic_surface)?,
+    };
+
+    let comic_width = comic_surface.get_width() as f64;
+    let comic_height = comic_surface.get_height() as f64;
+
+    // Overall width is the largest of the elements, plus the margins
+    let overall_width = options.outer_margin
+        + header_size
+            .width
+            .max(comic_surface.get_width() as f64)
+            .max(alt_box_width)
+        + options.outer_margin;
+
+    // Overall height is the sum of the element heights, plus the margins, plus the spacing
+    let overall_height = options.outer_margin
+        + header_size.height
+        + options.header_spacing
+        + comic_height as f64
+        + options.alt_spacing
+        + alt_box_height
+        + options.outer_margin;
+
+    trace!("Overall image: ({}, {})", overall_width, overall_height);
+
+    // A long title or word in the alt text can still be too wide, and the
+    // text too tall, so as a last resort everything is scaled down to fit
+    let scale = options
+        .max_width
+        .map_or(1.0, |w| w as f64 / overall_width)
+        .min(
+            options
+                .max_height
+                .map_or(1.0, |h| h as f64 / overall_height),
+        )
+        .min(1.0);
+
+    if scale < 1.0 {
+        debug!("Scaling all of {} by {} to fit", comic, scale);
+    }
+
+    // X start points
+    let mut start_points = [header_size.width, comic_width, alt_box_width];
+    let start_points = aligned_start_points(&mut start_points);
+    let header_start_x = options.outer_margin + start_points[0].floor();
+    let comic_start_x = options.outer_margin + start_points[1].floor();
+    let alt_box_start_x = options.outer_margin + start_points[2].floor() + 0.5;
+
+    // Y start points
+    let header_start_y = options.outer_margin + header_size.height;
+    let comic_start_y = header_start_y + options.header_spacing;
+    let alt_box_start_y = (comic_start_y + comic_height + options.alt_spacing).floor() + 0.5;
+
+    // Alt start points
+    let alt_start_x = alt_box_start_x + options.alt_padding - alt_extents.x_bearing;
+    let alt_start_y = alt_box_start_y + options.alt_padding - alt_extents.y_bearing;
+
+    trace!("Comic start point: ({}, {})", comic_start_x, comic_start_y);
+
+    Ok(Layout {
+        comic_surface,
+        alt_lines,
+
+        width: (overall_width * scale).floor().max(1.0),
+        height: (overall_height * scale).floor().max(1.0),
+        scale,
+
+        header_extents: header_size,
+        header_start: (header_start_x, header_start_y),
+        comic_start: (comic_start_x, comic_start_y),
+        alt_box: (
+            alt_box_start_x,
+            alt_box_start_y,
+            alt_box_width,
+            alt_box_height,
+        ),
+        alt_start: (alt_start_x, alt_start_y),
+    })
+}
+
+/// Draw a laid-out comic
+///
+/// If `link` is given, the title is tagged as a link to it. Only some
+/// surfaces (PDF) do anything with the tag.
+fn draw(cr: &Context, comic: &Comic, layout: &Layout, options: &RenderOptions, link: Option<&str>) {
+    let (header_start_x, header_start_y) = layout.header_start;
+    let (comic_start_x, comic_start_y) = layout.comic_start;
+    let (alt_box_start_x, alt_box_start_y, alt_box_width, alt_box_height) = layout.alt_box;
+    let (alt_start_x, alt_start_y) = layout.alt_start;
+
+    cr.scale(layout.scale, layout.scale);
+    cr.set_font_options(&font_options());
+    cr.select_font_face(
+        FONT_FAMILY,
+        cairo::FontSlant::Normal,
+        cairo::FontWeight::Bold,
+    );
+    cr.set_font_size(options.header_font_size * options.text_scale());
+
+    if let Some((red, green, blue)) = options.background_rgb() {
+        cr.set_source_rgb(red, green, blue);
+        cr.paint();
+    }
+
+    if let Some(url) = link {
+        cr.tag_begin(LINK_TAG, &format!("uri='{}'", url));
+    }
+
+    let (fg_red, fg_green, fg_blue) = options.foreground_rgb();
+
+    cr.set_source_rgb(fg_red, fg_green, fg_blue);
+    cr.move_to(header_start_x, header_start_y);
+    fallback::show_text(cr, &comic.safe_title);
+
+    if link.is_some() {
+        cr.tag_end(LINK_TAG);
+    }
+
+    cr.set_source_surface(&layout.comic_surface, comic_start_x, comic_start_y);
+    cr.paint();
+
+    // Create the alt-text rectangle
+    trace!(
+        "Drawing alt-text rectangle from ({}, {}), dims {} by {}",
+        alt_box_start_x,
+        alt_box_start_y,
+        alt_box_width,
+        alt_box_height,
+    );
+
+    let (red, green, blue) = options.alt_box_fill();
+    cr.set_source_rgb(red, green, blue);
+    cr.rectangle(
+        alt_box_start_x,
+        alt_box_start_y,
+        alt_box_width,
+        alt_box_height,
+    );
+    cr.fill();
+
+    if options.alt_border_width > 0.0 {
+        let (red, green, blue) = options.alt_box_border();
+        cr.set_source_rgb(red, green, blue);
+        cr.set_line_width(options.alt_border_width);
+        cr.rectangle(
+            alt_box_start_x,
+            alt_box_start_y,
+            alt_box_width,
+            alt_box_height,
+        );
+        cr.stroke();
+    }
+
+    cr.set_source_rgb(fg_red, fg_green, fg_blue);
+
+    // Set alt text font settings
+    cr.select_font_face(
+        FONT_FAMILY,
+        cairo::FontSlant::Normal,
+        cairo::FontWeight::Normal,
+    );
+    cr.set_font_size(options.alt_font_size * options.text_scale());
+
+    cr.move_to(alt_start_x, alt_start_y);
+
+    for (extents, line) in layout.alt_lines.iter() {
+        fallback::show_text(cr, line);
+
+        let (_, curr_y) = cr.get_current_point();
+
+        cr.move_to(
+            alt_start_x,
+            curr_y + options.alt_leading * options.text_scale() + extents.height,
+        )
+    }
+}
+
+fn source_url(comic: &Comic) -> String {
+    format!("https://xkcd.com/{}/", comic.num)
+}
+
+pub fn render<R: Read + Seek>(
+    comic: &Comic,
+    image: &mut R,
+    options: &RenderOptions,
+) -> Result<Vec<u8>, String> {
+    let layout = layout(comic, image, options)?;
+
+    // Create a surface with the calculated dimensions
+    let surface = ImageSurface::create(Format::ARgb32, layout.width as i32, layout.height as i32)
+        .expect("Can't create surface");
+    let cr = Context::new(&surface);
+
+    draw(&cr, comic, &layout, options, None);
+
+    // Create the final PNG
+    let mut buffer = Vec::new();
+
+    surface
+        .write_to_png(&mut buffer)
+        .expect("Can't write surface to PNG");
+
+    // Same comic and settings, same bytes
+    let buffer = chunks::remove_chunks(&buffer, chunks::TIMESTAMPS)?;
+
+    // Tag the PNG so it stays self-describing once it leaves the filesystem
+    let creation_time = time::at_utc(comic.time()).rfc822().to_string();
+    let source_url = source_url(comic);
+
+    chunks::insert_text(
+        &buffer,
+        &[
+            ("Title", comic.safe_title.as_str()),
+            ("Author", AUTHOR),
+            ("Description", comic.alt.as_str()),
+            ("Creation Time", creation_time.as_str()),
+            ("Source", source_url.as_str()),
+        ],
+    )
+}
+
+/// Render a comic to a vector format, with the title linking back to xkcd
+///
+/// The comic itself is still a bitmap, but the text stays selectable.
+pub fn render_vector<R: Read + Seek>(
+    comic: &Comic,
+    image: &mut R,
+    options: &RenderOptions,
+    format: VectorFormat,
+) -> Result<Vec<u8>, String> {
+    let layout = layout(comic, image, options)?;
+    let url = source_url(comic);
+
+    let output = match format {
+        VectorFormat::Pdf => {
+            let surface = PdfSurface::for_stream(layout.width, layout.height, Vec::<u8>::new())
+                .map_err(|e| e.to_string())?;
+
+            surface
+                .set_metadata(PdfMetadata::Title, &comic.safe_title)
+                .map_err(|e| e.to_string())?;
+            surface
+                .set_metadata(PdfMetadata::Author, AUTHOR)
+                .map_err(|e| e.to_string())?;
+            surface
+                .set_metadata(PdfMetadata::Subject, &comic.alt)
+                .map_err(|e| e.to_string())?;
+
+            // Cairo would otherwise stamp the PDF with the current time
+            let date = time::at_utc(comic.time())
+                .strftime("%Y-%m-%dT%H:%M:%SZ")
+                .map_err(|e| e.to_string())?
+                .to_string();
+
+            surface
+                .set_metadata(PdfMetadata::CreateDate, &date)
+                .map_err(|e| e.to_string())?;
+            surface
+                .set_metadata(PdfMetadata::ModDate, &date)
+                .map_err(|e| e.to_string())?;
+
+            draw(&Context::new(&surface), comic, &layout, options, Some(&url));
+
+            surface.finish_output_stream()
+        }
+        VectorFormat::Svg => {
+            let surface = SvgSurface::for_stream(layout.width, layout.height, Vec::<u8>::new())
+                .map_err(|e| e.to_string())?;
+
+            draw(&Context::new(&surface), comic, &layout, options, None);
+
+            surface.finish_output_stream()
+        }
+    };
+
+    let output = output
+        .map_err(|e| format!("Could not write {}: {}", format.extension(), e))?
+        .downcast::<Vec<u8>>()
+        .map_err(|_| "Vector surface had an unexpected output stream".to_string())?;
+
+    match format {
+        VectorFormat::Pdf => Ok(*output),
+        VectorFormat::Svg => {
+            let svg = String::from_utf8(*output).map_err(|e| e.to_string())?;
+            svg_link(&svg_ids(&svg), &url, layout.header_rect()).map(String::into_bytes)
+        }
+    }
+}
+
+/// Cairo numbers clips, images and surfaces in SVG with counters shared by
+/// the whole process, so the same comic would come out different each time.
+/// Number them again from 1 in the order they first appear.
+///
+/// Only `id`s, `href`s to `#id`s and `url(#id)`s in tags are touched, never
+/// the text between tags or anything else in an attribute.
+fn svg_ids(svg: &str) -> String {
+    let mut ids = SvgIds::default();
+    let mut renumbered = String::with_capacity(svg.len());
+    let mut rest = svg;
+
+    while let Some(start) = rest.find('<') {
+        renumbered.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        // Comments and CDATA can have anything in them, including '>'
+        let close = if rest.starts_with("<!--") {
+            "-->"
+        } else if rest.starts_with("<![CDATA[") {
+            "]]>"
+        } else {
+            ">"
+        };
+        let end = rest.find(close).map_or(rest.len(), |e| e + close.len());
+
+        if close == ">" {
+            svg_tag_ids(&rest[..end], &mut ids, &mut renumbered);
+        } else {
+            renumbered.push_str(&rest[..end]);
+        }
+
+        rest = &rest[end..];
+    }
+
+    renumbered.push_str(rest);
+    renumbered
+}
+
+/// New numbers for the ids Cairo gave out
+#[derive(Default)]
+struct SvgIds<'s> {
+    ids: HashMap<&'s str, String>,
+    counts: HashMap<&'static str, usize>,
+}
+
+impl<'s> SvgIds<'s> {
+    /// The new id for one of Cairo's, or `None` for any other id
+    fn renumber(&mut self, id: &'s str) -> Option<String> {
+        const PREFIXES: &[&str] = &["clip", "image", "mask", "pattern", "source", "surface"];
+
+        let prefix = *PREFIXES.iter().find(|p| id.starts_with(*p))?;
+        let number = &id[prefix.len()..];
+
+        if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        let counts = &mut self.counts;
+        let new = self.ids.entry(id).or_insert_with(|| {
+            let count = counts.entry(prefix).or_insert(0);
+            *count += 1;
+            format!("{}{}", prefix, count)
+        });
+
+        Some(new.clone())
+    }
+}
+
+/// Renumber the ids in one tag's attributes, onto `out`
+fn svg_tag_ids<'s>(tag: &'s str, ids: &mut SvgIds<'s>, out: &mut String) {
+    let mut rest = tag;
+
+    while let Some(equals) = rest.find('=') {
+        let quote = match rest[equals + 1..].chars().next() {
+            Some(q) if q == '"' || q == '\'' => q,
+            _ => {
+                out.push_str(&rest[..=equals]);
+                rest = &rest[equals + 1..];
+                continue;
+            }
+        };
+
+        let start = equals + 2;
+        let end = match rest[start..].find(quote) {
+            Some(e) => start + e,
+            None => break,
+        };
+
+        let name = rest[..equals]
+            .trim_end()
+            .rsplit(char::is_whitespace)
+            .next()
+            .unwrap_or("");
+        let value = &rest[start..end];
+
+        out.push_str(&rest[..start]);
+
+        match name {
+            "id" => out.push_str(&ids.renumber(value).unwrap_or_else(|| value.to_string())),
+            _ if name.ends_with("href") && value.starts_with('#') => {
+                out.push('#');
+                out.push_str(
+                    &ids.renumber(&value[1..])
+                        .unwrap_or_else(|| value[1..].to_string()),
+                );
+            }
+            _ => svg_url_ids(value, ids, out),
+        }
+
+        rest = &rest[end..];
+    }
+
+    out.push_str(rest);
+}
+
+/// Renumber the ids in an attribute's `url(#id)`s, onto `out`
+fn svg_url_ids<'s>(value: &'s str, ids: &mut SvgIds<'s>, out: &mut String) {
+    let mut rest = value;
+
+    while let Some(at) = rest.find("url(#") {
+        let start = at + "url(#".len();
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(end) = rest.find(')') {
+            match ids.renumber(&rest[..end]) {
+                Some(id) => out.push_str(&id),
+                None => out.push_str(&rest[..end]),
+            }
+            rest = &rest[end..];
+        }
+    }
+
+    out.push_str(rest);
+}
+
+/// Cairo ignores link tags when writing SVG, so add the link to the title by
+/// hand: an invisible, clickable rectangle on top of everything else
+fn svg_link(svg: &str, url: &str, rect: (f64, f64, f64, f64)) -> Result<String, String> {
+    let end = svg
+        .rfind("</svg>")
+        .ok_or_else(|| "SVG has no closing tag".to_string())?;
+
+    let (x, y, width, height) = rect;
+    let link = format!(
+        concat!(
+            "<a xlink:href=\"{}\">",
+            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill-opacity=\"0\"/>",
+            "</a>\n",
+        ),
+        url, x, y, width, height
+    );
+
+    let mut linked = String::with_capacity(svg.len() + link.len());
+    linked.push_str(&svg[..end]);
+    linked.push_str(&link);
+    linked.push_str(&svg[end..]);
+
+    Ok(linked)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    const PROGRESSIVE_JPEG: &[u8] = include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/progressive.jpg"
+    ));
+
+    #[test]
+    fn decodes_progressive_jpeg() {
+        let surface = create_image_surface(&mut Cursor::new(PROGRESSIVE_JPEG)).unwrap();
+
+        assert_eq!(surface.get_width(), 512);
+        assert_eq!(surface.get_height(), 512);
+    }
+
+    #[test]
+    fn other_images_are_errors() {
+        for image in &[&b"GIF89a\x01\0\x01\0"[..], b"<html>", b""] {
+            assert!(create_image_surface(&mut Cursor::new(image)).is_err());
+        }
+    }
+
+    #[test]
+    fn downscaling_keeps_the_aspect_ratio_and_never_upscales() {
+        let source = ImageSurface::create(Format::ARgb32, 400, 100).unwrap();
+        let size = |max_width, max_height, filter| {
+            let scaled = downscale(&source, max_width, max_height, filter).unwrap();
+
+            (scaled.get_width(), scaled.get_height())
+        };
+
+        for &filter in &[ScaleFilter::Box, ScaleFilter::Lanczos] {
+            assert_eq!(size(200, u32::MAX, filter), (200, 50));
+            assert_eq!(size(100, 10, filter), (40, 10));
+            assert_eq!(size(1000, u32::MAX, filter), (400, 100));
+            assert_eq!(size(400, 100, filter), (400, 100));
+        }
+    }
+
+    #[test]
+    fn renders_emoji_and_non_latin_text() {
+        let comic = Comic {
+            num: 1,
+            day: 1,
+            month: 1,
+            year: 2006,
+            link: None,
+            news: None,
+            alt: "Emoji 🙂👍🏽❤️ and other scripts: 日本語, Ελληνικά, עברית, ℵ ♞".to_string(),
+            title: "Unicode ☃ ♞".to_string(),
+            safe_title: "Unicode ☃ ♞".to_string(),
+            transcript: None,
+            img_url: String::new(),
+            img_len: None,
+            extra_parts: None,
+        };
+
+        let plain = Comic {
+            alt: "Emoji and other scripts:".to_string(),
+            ..comic.clone()
+        };
+
+        let options = RenderOptions::default();
+        let render = |comic| {
+            let png = render(comic, &mut Cursor::new(PROGRESSIVE_JPEG), &options).unwrap();
+            ImageSurface::create_from_png(&mut Cursor::new(png)).unwrap()
+        };
+
+        // The fallback text should take up room rather than vanish
+        assert!(render(&comic).get_height() >= render(&plain).get_height());
+
+        // Emoji and CJK only come out where a font has them, but DejaVu Sans
+        // has the rest, so none of it should be boxes
+        let surface = ImageSurface::create(Format::ARgb32, 10, 10).unwrap();
+        let ctx = Context::new(&surface);
+
+        for &(weight, text) in &[
+            (cairo::FontWeight::Bold, "Unicode ☃ ♞"),
+            (cairo::FontWeight::Normal, "Ελληνικά, עברית, ℵ ♞"),
+        ] {
+            ctx.select_font_face(FONT_FAMILY, cairo::FontSlant::Normal, weight);
+            ctx.set_font_size(options.alt_font_size);
+
+            assert_eq!(fallback::missing_glyphs(&ctx, text), Vec::<char>::new());
+        }
+    }
+
+    #[test]
+    fn renders_fit_within_the_maximum_size() {
+        let comic = Comic {
+            num: 1,
+            day: 1,
+            month: 1,
+            year: 2006,
+            link: None,
+            news: None,
+            alt: "Supercalifragilisticexpialidocious ".repeat(20),
+            title: "A title much wider than the render is allowed to be".to_string(),
+            safe_title: "A title much wider than the render is allowed to be".to_string(),
+            transcript: None,
+            img_url: String::new(),
+            img_len: None,
+            extra_parts: None,
+        };
+        let options = RenderOptions {
+            max_width: Some(200),
+            max_height: Some(150),
+            ..RenderOptions::default()
+        };
+
+        let png = render(&comic, &mut Cursor::new(PROGRESSIVE_JPEG), &options).unwrap();
+        let surface = ImageSurface::create_from_png(&mut Cursor::new(png)).unwrap();
+
+        assert!(surface.get_width() <= 200, "{} wide", surface.get_width());
+        assert!(surface.get_height() <= 150, "{} tall", surface.get_height());
+    }
+
+    #[test]
+    fn maximum_size_must_be_positive() {
+        for options in &[
+            RenderOptions {
+                max_width: Some(0),
+                ..RenderOptions::default()
+            },
+            RenderOptions {
+                max_height: Some(0),
+                ..RenderOptions::default()
+            },
+        ] {
+            assert!(options.validate().is_err());
+        }
+
+        let options = RenderOptions {
+            max_width: Some(1),
+            max_height: Some(1),
+            ..RenderOptions::default()
+        };
+        assert_eq!(options.validate(), Ok(()));
+    }
+
+    #[test]
+    fn svg_ids_are_numbered_in_order() {
+        let svg = concat!(
+            r##"<image id="image54"/><g id="surface51" clip-path="url(#clip7)">"##,
+            r##"<use xlink:href="#image54"/><use xlink:href="#glyph0-1"/>"##,
+            r##"<image id="image60"/></g>"##,
+            r##"<text x="1">"image54" url(#clip7)</text><!-- id="clip7" -->"##,
+            r##"<desc title="surface51">#image54"</desc>"##,
+        );
+
+        assert_eq!(
+            svg_ids(svg),
+            concat!(
+                r##"<image id="image1"/><g id="surface1" clip-path="url(#clip1)">"##,
+                r##"<use xlink:href="#image1"/><use xlink:href="#glyph0-1"/>"##,
+                r##"<image id="image2"/></g>"##,
+                r##"<text x="1">"image54" url(#clip7)</text><!-- id="clip7" -->"##,
+                r##"<desc title="surface51">#image54"</desc>"##,
+            )
+        );
+    }
+
+    /// Set in the process `renders_are_reproducible` starts, which only
+    /// prints its renders' checksums
+    const CHECKSUMS_ONLY: &str = "XKCDFS_RENDER_CHECKSUMS_ONLY";
+
+    /// CRC-32s of a render in every format
+    fn render_checksums() -> String {
+        let comic = Comic {
+            num: 1,
+            day: 1,
+            month: 1,
+            year: 2006,
+            link: None,
+            news: None,
+            alt: "The same every time".to_string(),
+            title: "Reproducible".to_string(),
+            safe_title: "Reproducible".to_string(),
+            transcript: None,
+            img_url: String::new(),
+            img_len: None,
+            extra_parts: None,
+        };
+        let options = RenderOptions::default();
+
+        let mut checksums = vec![crc32fast::hash(
+            &render(&comic, &mut Cursor::new(PROGRESSIVE_JPEG), &options).unwrap(),
+        )];
+
+        for &format in &[VectorFormat::Pdf, VectorFormat::Svg] {
+            let vector =
+                render_vector(&comic, &mut Cursor::new(PROGRESSIVE_JPEG), &options, format)
+                    .unwrap();
+            checksums.push(crc32fast::hash(&vector));
+        }
+
+        format!("{:08x?}", checksums)
+    }
+
+    #[test]
+    fn renders_are_reproducible() {
+        if std::env::var_os(CHECKSUMS_ONLY).is_some() {
+            println!("checksums {}", render_checksums());
+            return;
+        }
+
+        // Cairo's counters and caches have moved on by the second render
+        let ours = render_checksums();
+        assert_eq!(render_checksums(), ours);
+
+        // And a fresh process starts them over
+        let theirs = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "image::test::renders_are_reproducible",
+                "--nocapture",
+            ])
+            .env(CHECKSUMS_ONLY, "1")
+            .output()
+            .unwrap();
+        let theirs = String::from_utf8_lossy(&theirs.stdout);
+        // The harness prints the test's name on the same line
+        let theirs = theirs
+            .split("checksums ")
+            .nth(1)
+            .and_then(|rest| rest.lines().next())
+            .expect("The other process printed no checksums");
+
+        assert_eq!(theirs, ours);
+    }
+}
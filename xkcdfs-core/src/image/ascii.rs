@@ -0,0 +1,171 @@
+//! Plain-text renderings of comics, for reading in a terminal
+
+use super::{create_image_surface, luminance};
+use crate::Comic;
+use cairo::{Context, Filter, Format, ImageSurface, SurfacePattern};
+use std::io::{Read, Seek};
+
+/// Glyphs from lightest to darkest
+const RAMP: &[u8] = b" .:-=+*#%@";
+
+/// Terminal cells are about twice as tall as they are wide
+const CELL_ASPECT: f64 = 2.0;
+
+pub const MIN_WIDTH: u32 = 10;
+pub const MAX_WIDTH: u32 = 1000;
+
+/// Pick the glyph for a luminance between 0 (black) and 1 (white)
+fn glyph(luminance: f64) -> char {
+    let darkness = 1.0 - luminance.clamp(0.0, 1.0);
+    let index = (darkness * (RAMP.len() - 1) as f64).round() as usize;
+
+    RAMP[index] as char
+}
+
+/// Wrap text to lines of at most `width` characters, breaking at spaces
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+
+    for word in text.split_whitespace() {
+        let line_len = line.chars().count();
+
+        if line_len > 0 && line_len + 1 + word.chars().count() > width {
+            lines.push(std::mem::take(&mut line));
+        }
+
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Convert an image to lines of ASCII art `width` characters wide
+fn art<R: Read + Seek>(image: &mut R, width: u32) -> Result<Vec<String>, String> {
+    let source = create_image_surface(image)?;
+
+    let source_width = source.get_width() as f64;
+    let source_height = source.get_height() as f64;
+
+    if source_width <= 0.0 || source_height <= 0.0 {
+        return Err(format!(
+            "Cannot convert an image with dimensions {} by {}",
+            source_width, source_height
+        ));
+    }
+
+    let columns = width as i32;
+    let rows = ((source_height * width as f64 / source_width / CELL_ASPECT).round() as i32).max(1);
+
+    // Average each cell's worth of pixels, over white so that transparent
+    // areas come out blank
+    let mut cells =
+        ImageSurface::create(Format::Rgb24, columns, rows).map_err(|e| e.to_string())?;
+
+    {
+        let ctx = Context::new(&cells);
+
+        ctx.set_source_rgb(1.0, 1.0, 1.0);
+        ctx.paint();
+
+        ctx.scale(columns as f64 / source_width, rows as f64 / source_height);
+
+        let pattern = SurfacePattern::create(&source);
+        pattern.set_filter(Filter::Good);
+
+        ctx.set_source(&pattern);
+        ctx.paint();
+    }
+
+    cells.flush();
+
+    let stride = cells.get_stride() as usize;
+    let data = cells.get_data().map_err(|e| format!("{:?}", e))?;
+
+    let mut lines = Vec::with_capacity(rows as usize);
+
+    for row in 0..rows as usize {
+        let line: String = (0..columns as usize)
+            .map(|col| {
+                let offset = row * stride + col * 4;
+                let pixel = u32::from_ne_bytes([
+                    data[offset],
+                    data[offset + 1],
+                    data[offset + 2],
+                    data[offset + 3],
+                ]);
+
+                glyph(luminance(pixel))
+            })
+            .collect();
+
+        lines.push(line.trim_end().to_string());
+    }
+
+    Ok(lines)
+}
+
+/// Render a comic as text: the title, the image as ASCII art, and the alt text
+pub fn render<R: Read + Seek>(comic: &Comic, image: &mut R, width: u32) -> Result<String, String> {
+    if !(MIN_WIDTH..=MAX_WIDTH).contains(&width) {
+        return Err(format!(
+            "ASCII art must be between {} and {} characters wide",
+            MIN_WIDTH, MAX_WIDTH
+        ));
+    }
+
+    let mut text = String::new();
+
+    for line in wrap(&comic.safe_title, width as usize) {
+        text.push_str(&line);
+        text.push('\n');
+    }
+    text.push('\n');
+
+    for line in art(image, width)? {
+        text.push_str(&line);
+        text.push('\n');
+    }
+    text.push('\n');
+
+    for line in wrap(&comic.alt, width as usize) {
+        text.push_str(&line);
+        text.push('\n');
+    }
+
+    Ok(text)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn glyphs_get_denser_as_they_get_darker() {
+        assert_eq!(glyph(1.0), ' ');
+        assert_eq!(glyph(0.0), '@');
+        assert_eq!(glyph(-1.0), '@');
+        assert_eq!(glyph(2.0), ' ');
+
+        let index = |g| RAMP.iter().position(|&r| r as char == g).unwrap();
+        assert!(index(glyph(0.3)) > index(glyph(0.7)));
+    }
+
+    #[test]
+    fn wraps_at_spaces() {
+        assert_eq!(wrap("", 10), Vec::<String>::new());
+        assert_eq!(wrap("one two three", 7), vec!["one two", "three"]);
+        assert_eq!(wrap("one two three", 80), vec!["one two three"]);
+        assert_eq!(
+            wrap("extraordinarily long", 5),
+            vec!["extraordinarily", "long"]
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! Just enough PNG chunk handling to attach text metadata to Cairo's output,
+//! and to keep that output reproducible
+
+const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Chunks that record when a PNG was written, rather than what's in it
+pub const TIMESTAMPS: &[&[u8; 4]] = &[b"tIME"];
+
+/// Insert text metadata into an encoded PNG
+///
+/// Each `(keyword, text)` pair becomes its own chunk, placed right before
+/// `IEND`. Text that fits in Latin-1 is written as `tEXt`; anything else is
+/// written as an uncompressed `iTXt` so it survives as UTF-8.
+pub fn insert_text(png: &[u8], entries: &[(&str, &str)]) -> Result<Vec<u8>, String> {
+    if !png.starts_with(SIGNATURE) {
+        return Err("Data does not start with a PNG signature".to_string());
+    }
+
+    let iend = find_chunk(png, b"IEND").ok_or_else(|| "PNG has no IEND chunk".to_string())?;
+
+    let mut out = Vec::with_capacity(png.len() + entries.len() * 64);
+
+    out.extend_from_slice(&png[..iend]);
+
+    for (keyword, text) in entries {
+        let (kind, data) = text_chunk(keyword, text)?;
+
+        write_chunk(&mut out, &kind, &data);
+    }
+
+    out.extend_from_slice(&png[iend..]);
+
+    Ok(out)
+}
+
+/// Remove every chunk of the given types from an encoded PNG
+pub fn remove_chunks(png: &[u8], kinds: &[&[u8; 4]]) -> Result<Vec<u8>, String> {
+    if !png.starts_with(SIGNATURE) {
+        return Err("Data does not start with a PNG signature".to_string());
+    }
+
+    let mut out = Vec::with_capacity(png.len());
+    let mut pos = SIGNATURE.len();
+
+    out.extend_from_slice(SIGNATURE);
+
+    while pos < png.len() {
+        let end = chunk_end(png, pos).ok_or_else(|| "PNG has a truncated chunk".to_string())?;
+
+        if !kinds.iter().any(|kind| &png[pos + 4..pos + 8] == *kind) {
+            out.extend_from_slice(&png[pos..end]);
+        }
+
+        pos = end;
+    }
+
+    Ok(out)
+}
+
+/// The byte offset just past the chunk starting at `pos`
+fn chunk_end(png: &[u8], pos: usize) -> Option<usize> {
+    if pos.checked_add(12)? > png.len() {
+        return None;
+    }
+
+    let len = u32::from_be_bytes([png[pos], png[pos + 1], png[pos + 2], png[pos + 3]]);
+
+    // Length, type, and CRC wrap the data
+    let end = pos.checked_add(12)?.checked_add(len as usize)?;
+
+    if end <= png.len() {
+        Some(end)
+    } else {
+        None
+    }
+}
+
+/// Find the byte offset of the first chunk with the given type
+fn find_chunk(png: &[u8], kind: &[u8; 4]) -> Option<usize> {
+    let mut pos = SIGNATURE.len();
+
+    while pos.checked_add(12)? <= png.len() {
+        let len = u32::from_be_bytes([png[pos], png[pos + 1], png[pos + 2], png[pos + 3]]);
+
+        if &png[pos + 4..pos + 8] == kind {
+            return Some(pos);
+        }
+
+        // Length, type, and CRC wrap the data
+        pos = pos.checked_add(12)?.checked_add(len as usize)?;
+    }
+
+    None
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+
+    // The CRC covers the chunk type and data, but not the length
+    let crc_start = out.len();
+
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+
+    let crc = crc32fast::hash(&out[crc_start..]);
+
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn text_chunk(keyword: &str, text: &str) -> Result<([u8; 4], Vec<u8>), String> {
+    if keyword.is_empty() || keyword.len() > 79 || !keyword.is_ascii() {
+        return Err(format!("Invalid PNG text keyword: {:?}", keyword));
+    }
+
+    // Neither chunk type allows a NUL inside the text
+    let text = text.replace('\0', "");
+
+    let mut data = Vec::with_capacity(keyword.len() + text.len() + 5);
+    data.extend_from_slice(keyword.as_bytes());
+    data.push(0);
+
+    if text.chars().all(|c| (c as u32) < 0x100) {
+        data.extend(text.chars().map(|c| c as u8));
+
+        Ok((*b"tEXt", data))
+    } else {
+        // Uncompressed, with empty language tag and translated keyword
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        data.extend_from_slice(text.as_bytes());
+
+        Ok((*b"iTXt", data))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn minimal_png() -> Vec<u8> {
+        let mut png = SIGNATURE.to_vec();
+
+        write_chunk(&mut png, b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]);
+        write_chunk(&mut png, b"IEND", &[]);
+
+        png
+    }
+
+    #[test]
+    fn text_goes_before_iend() {
+        let png = minimal_png();
+        let out = insert_text(&png, &[("Title", "Hello")]).unwrap();
+
+        let text = find_chunk(&out, b"tEXt").unwrap();
+        let iend = find_chunk(&out, b"IEND").unwrap();
+
+        assert!(text < iend);
+        assert_eq!(&out[text + 8..text + 8 + 11], b"Title\0Hello");
+        assert_eq!(&out[iend..], &png[png.len() - 12..]);
+    }
+
+    #[test]
+    fn chunk_crc_matches() {
+        let out = insert_text(&minimal_png(), &[("Title", "Hello")]).unwrap();
+        let text = find_chunk(&out, b"tEXt").unwrap();
+
+        let crc = &out[text + 8 + 11..text + 8 + 11 + 4];
+
+        assert_eq!(
+            crc,
+            &crc32fast::hash(&out[text + 4..text + 8 + 11]).to_be_bytes()
+        );
+    }
+
+    #[test]
+    fn non_latin1_uses_itxt() {
+        let out = insert_text(&minimal_png(), &[("Description", "日本語")]).unwrap();
+
+        assert_eq!(find_chunk(&out, b"tEXt"), None);
+
+        let itxt = find_chunk(&out, b"iTXt").unwrap();
+        let expected: &[u8] = b"Description\0\0\0\0\0\xe6\x97\xa5\xe6\x9c\xac\xe8\xaa\x9e";
+
+        assert_eq!(&out[itxt + 8..itxt + 8 + expected.len()], expected);
+    }
+
+    #[test]
+    fn rejects_non_png() {
+        assert!(insert_text(b"GIF89a", &[("Title", "Hello")]).is_err());
+        assert!(remove_chunks(b"GIF89a", &[b"tIME"]).is_err());
+    }
+
+    #[test]
+    fn removes_only_matching_chunks() {
+        let png = minimal_png();
+
+        let mut timestamped = png[..png.len() - 12].to_vec();
+        write_chunk(&mut timestamped, b"tIME", &[0x07, 0xe3, 1, 1, 0, 0, 0]);
+        timestamped.extend_from_slice(&png[png.len() - 12..]);
+
+        assert_eq!(remove_chunks(&timestamped, &[b"tIME"]).unwrap(), png);
+        assert_eq!(remove_chunks(&png, &[b"tIME"]).unwrap(), png);
+        assert!(remove_chunks(&timestamped[..timestamped.len() - 1], &[b"tIME"]).is_err());
+    }
+}
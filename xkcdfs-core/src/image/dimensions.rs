@@ -0,0 +1,110 @@
+//! Just enough header parsing to find an image's size without decoding it
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const GIF_HEADERS: [&[u8]; 2] = [b"GIF87a", b"GIF89a"];
+
+/// The width and height of a PNG, GIF or JPEG, in pixels
+///
+/// Returns None for anything else, or a header too short to say.
+pub fn dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    if data.starts_with(PNG_SIGNATURE) {
+        if data.len() < 24 {
+            return None;
+        }
+
+        // IHDR always comes first: length, type, then width and height
+        let be = |i: usize| u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+
+        return Some((be(16), be(20)));
+    }
+
+    if GIF_HEADERS.iter().any(|h| data.starts_with(h)) {
+        let le = |i: usize| Some(u32::from(*data.get(i)?) | u32::from(*data.get(i + 1)?) << 8);
+
+        return Some((le(6)?, le(8)?));
+    }
+
+    if data.starts_with(&[0xFF, 0xD8]) {
+        return jpeg_dimensions(data);
+    }
+
+    None
+}
+
+/// Walk the JPEG's markers until a start of frame turns up
+fn jpeg_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let be = |i: usize| Some(u32::from(*data.get(i)?) << 8 | u32::from(*data.get(i + 1)?));
+
+    let mut pos = 2;
+
+    loop {
+        if *data.get(pos)? != 0xFF {
+            return None;
+        }
+
+        let marker = *data.get(pos + 1)?;
+
+        match marker {
+            // Padding before a marker
+            0xFF => {
+                pos += 1;
+                continue;
+            }
+            // Markers without a length
+            0x01 | 0xD0..=0xD7 => {
+                pos += 2;
+                continue;
+            }
+            // Start of frame, except for DHT, JPG and DAC, which share the range
+            0xC0..=0xCF if marker != 0xC4 && marker != 0xC8 && marker != 0xCC => {
+                // Length, precision, height, width
+                return Some((be(pos + 7)?, be(pos + 5)?));
+            }
+            _ => pos += 2 + be(pos + 2)? as usize,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn png_dimensions() {
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend_from_slice(&[0, 0, 0, 13]);
+        png.extend_from_slice(b"IHDR");
+        png.extend_from_slice(&740u32.to_be_bytes());
+        png.extend_from_slice(&1024u32.to_be_bytes());
+
+        assert_eq!(dimensions(&png), Some((740, 1024)));
+        assert_eq!(dimensions(&png[..20]), None);
+    }
+
+    #[test]
+    fn gif_dimensions() {
+        assert_eq!(
+            dimensions(b"GIF89a\x2c\x01\xc8\x00\x80\x00\x00"),
+            Some((300, 200))
+        );
+        assert_eq!(dimensions(b"GIF89a\x2c"), None);
+    }
+
+    #[test]
+    fn jpeg_dimensions() {
+        let jpeg = [
+            0xFF, 0xD8, // Start of image
+            0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00, // An APP0 segment to skip
+            0xFF, 0xC2, 0x00, 0x11, 0x08, 0x01, 0x90, 0x02, 0x58, // Progressive frame
+        ];
+
+        assert_eq!(dimensions(&jpeg), Some((600, 400)));
+        assert_eq!(dimensions(&jpeg[..12]), None);
+    }
+
+    #[test]
+    fn other_data_has_no_dimensions() {
+        assert_eq!(dimensions(b""), None);
+        assert_eq!(dimensions(b"<svg></svg>"), None);
+    }
+}
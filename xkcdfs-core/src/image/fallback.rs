@@ -0,0 +1,259 @@
+//! Font fallback for text the main font can't draw
+//!
+//! Cairo's toy text API draws everything in the one selected font, so any
+//! character that font is missing comes out as a box. Here text is split into
+//! runs, each drawn in the first font that actually has glyphs for it.
+
+use cairo::{Context, FontFace, TextExtents};
+
+/// Fonts to try, in order, for characters the main font doesn't have
+const FALLBACK_FAMILIES: &[&str] = &[
+    "DejaVu Sans",
+    "Noto Sans",
+    "Noto Color Emoji",
+    "Noto Emoji",
+    "Noto Sans CJK JP",
+    "Symbola",
+    "Unifont",
+];
+
+/// Part of a string drawn in a single font
+struct Run<'t> {
+    /// None for the context's own font
+    family: Option<&'static str>,
+    text: &'t str,
+}
+
+/// Whether the context's current font has a real glyph for all of `text`
+fn has_glyphs(ctx: &Context, text: &str) -> bool {
+    let (glyphs, _) = ctx.get_scaled_font().text_to_glyphs(0.0, 0.0, text);
+
+    // Glyph 0 is the "missing glyph" box in every font format
+    !glyphs.is_empty() && glyphs.iter().all(|g| g.index != 0)
+}
+
+/// Characters that modify the one before them, and so need to be drawn in the
+/// same font
+fn is_combining(c: char) -> bool {
+    matches!(c,
+        // Zero-width joiner
+        '\u{200D}'
+        // Variation selectors
+        | '\u{FE00}'..='\u{FE0F}'
+        // Skin tone modifiers
+        | '\u{1F3FB}'..='\u{1F3FF}'
+        // Combining diacritical marks, and the same for symbols
+        | '\u{0300}'..='\u{036F}'
+        | '\u{20D0}'..='\u{20FF}'
+    )
+}
+
+/// Split text into runs that can each be drawn in one font
+fn runs<'t>(ctx: &Context, text: &'t str) -> Vec<Run<'t>> {
+    // Almost all text is fine as-is, so check that first
+    if text.is_empty() || has_glyphs(ctx, text) {
+        return vec![Run { family: None, text }];
+    }
+
+    let face = ctx.get_font_face();
+    let slant = face.toy_get_slant();
+    let weight = face.toy_get_weight();
+
+    let family_for = |cluster: &str| -> Option<&'static str> {
+        if cluster.trim().is_empty() || has_glyphs(ctx, cluster) {
+            return None;
+        }
+
+        let found = FALLBACK_FAMILIES.iter().cloned().find(|family| {
+            ctx.set_font_face(&FontFace::toy_create(family, slant, weight));
+            has_glyphs(ctx, cluster)
+        });
+
+        ctx.set_font_face(&face);
+
+        if found.is_none() {
+            debug!("No font has glyphs for {:?}", cluster);
+        }
+
+        found
+    };
+
+    let mut runs: Vec<Run<'t>> = Vec::new();
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((start, _)) = chars.next() {
+        // Keep combining characters with the character they modify
+        let mut end = text.len();
+        while let Some(&(next, c)) = chars.peek() {
+            if !is_combining(c) {
+                end = next;
+                break;
+            }
+            chars.next();
+        }
+
+        let cluster = &text[start..end];
+        let family = family_for(cluster);
+
+        match runs.last_mut() {
+            Some(run) if run.family == family => {
+                let run_start = run.text.as_ptr() as usize - text.as_ptr() as usize;
+                run.text = &text[run_start..end];
+            }
+            _ => runs.push(Run {
+                family,
+                text: cluster,
+            }),
+        }
+    }
+
+    runs
+}
+
+/// Run `f` with the context's font switched to `family`
+fn with_family<T>(ctx: &Context, family: Option<&str>, f: impl FnOnce() -> T) -> T {
+    let family = match family {
+        Some(f) => f,
+        None => return f(),
+    };
+
+    let face = ctx.get_font_face();
+
+    ctx.set_font_face(&FontFace::toy_create(
+        family,
+        face.toy_get_slant(),
+        face.toy_get_weight(),
+    ));
+
+    let result = f();
+
+    ctx.set_font_face(&face);
+
+    result
+}
+
+/// Like `Context::text_extents`, but measuring with fallback fonts
+pub fn text_extents(ctx: &Context, text: &str) -> TextExtents {
+    let mut combined: Option<TextExtents> = None;
+
+    for run in runs(ctx, text) {
+        let extents = with_family(ctx, run.family, || ctx.text_extents(run.text));
+
+        combined = Some(match combined {
+            None => extents,
+            Some(c) => {
+                // Each run starts where the last one's advance left off
+                let top = c.y_bearing.min(extents.y_bearing + c.y_advance);
+                let bottom =
+                    (c.y_bearing + c.height).max(extents.y_bearing + extents.height + c.y_advance);
+
+                TextExtents {
+                    x_bearing: c.x_bearing,
+                    y_bearing: top,
+                    width: c.x_advance + extents.x_bearing + extents.width - c.x_bearing,
+                    height: bottom - top,
+                    x_advance: c.x_advance + extents.x_advance,
+                    y_advance: c.y_advance + extents.y_advance,
+                }
+            }
+        });
+    }
+
+    combined.unwrap_or_else(|| ctx.text_extents(text))
+}
+
+/// Like `Context::show_text`, but drawing with fallback fonts
+pub fn show_text(ctx: &Context, text: &str) {
+    for run in runs(ctx, text) {
+        with_family(ctx, run.family, || ctx.show_text(run.text));
+    }
+}
+
+/// Characters in `text` that still come out as boxes with fallback fonts
+#[cfg(test)]
+pub fn missing_glyphs(ctx: &Context, text: &str) -> Vec<char> {
+    runs(ctx, text)
+        .iter()
+        .flat_map(|run| {
+            with_family(ctx, run.family, || {
+                run.text
+                    .chars()
+                    .filter(|c| !c.is_whitespace() && !has_glyphs(ctx, &c.to_string()))
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cairo::{Format, ImageSurface};
+
+    fn context() -> Context {
+        context_in(super::super::FONT_FAMILY)
+    }
+
+    fn context_in(family: &str) -> Context {
+        let surface = ImageSurface::create(Format::ARgb32, 10, 10).unwrap();
+        let ctx = Context::new(&surface);
+
+        ctx.select_font_face(family, cairo::FontSlant::Normal, cairo::FontWeight::Normal);
+        ctx.set_font_size(16.0);
+
+        ctx
+    }
+
+    #[test]
+    fn runs_cover_the_whole_text() {
+        let ctx = context();
+
+        for text in &[
+            "",
+            "Plain ASCII alt text",
+            "Emoji 🙂 in the middle",
+            "👍🏽 with a skin tone, ❤️ with a selector",
+            "Mixed scripts: 日本語, Ελληνικά, עברית",
+        ] {
+            let joined: String = runs(&ctx, text).iter().map(|r| r.text).collect();
+
+            assert_eq!(&joined, text);
+        }
+    }
+
+    #[test]
+    fn modifiers_stay_with_their_character() {
+        let ctx = context();
+
+        for run in runs(&ctx, "a👍🏽b❤️c") {
+            let first = run.text.chars().next().unwrap();
+            assert!(
+                !is_combining(first),
+                "Run starts with a modifier: {:?}",
+                run.text
+            );
+        }
+    }
+
+    // DejaVu Serif has no chess pieces, but DejaVu Sans, the first fallback,
+    // does
+    #[test]
+    fn falls_back_for_missing_characters() {
+        let ctx = context_in("DejaVu Serif");
+        assert!(!has_glyphs(&ctx, "♞"));
+
+        let runs = runs(&ctx, "Knight ♞");
+        let runs: Vec<_> = runs.iter().map(|r| (r.family, r.text)).collect();
+
+        assert_eq!(runs, vec![(None, "Knight "), (Some("DejaVu Sans"), "♞")]);
+        assert_eq!(missing_glyphs(&ctx, "Knight ♞"), Vec::<char>::new());
+    }
+
+    #[test]
+    fn fallback_text_has_width() {
+        let ctx = context_in("DejaVu Serif");
+
+        assert!(text_extents(&ctx, "♞").x_advance > 0.0);
+        assert!(text_extents(&ctx, "Knight ♞").width > text_extents(&ctx, "Knight").width);
+    }
+}
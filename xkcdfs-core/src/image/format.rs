@@ -0,0 +1,107 @@
+//! Telling what kind of image some bytes are
+//!
+//! Downloads say what they are in their Content-Type, which is noted with
+//! each raw image. Anything that didn't say, or said something unhelpful like
+//! `application/octet-stream`, goes by its first few bytes instead.
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const GIF_HEADERS: [&[u8]; 2] = [b"GIF87a", b"GIF89a"];
+const JPEG_SIGNATURE: &[u8] = &[0xFF, 0xD8, 0xFF];
+
+/// A kind of image comics come as
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+}
+
+impl ImageFormat {
+    /// The format a Content-Type header names, ignoring any parameters
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let mime = content_type.split(';').next()?.trim().to_ascii_lowercase();
+
+        match mime.as_str() {
+            "image/png" => Some(ImageFormat::Png),
+            "image/jpeg" | "image/jpg" | "image/pjpeg" => Some(ImageFormat::Jpeg),
+            "image/gif" => Some(ImageFormat::Gif),
+            "image/webp" => Some(ImageFormat::Webp),
+            _ => None,
+        }
+    }
+
+    /// The format `data` starts like
+    pub fn sniff(data: &[u8]) -> Option<Self> {
+        if data.starts_with(PNG_SIGNATURE) {
+            Some(ImageFormat::Png)
+        } else if data.starts_with(JPEG_SIGNATURE) {
+            Some(ImageFormat::Jpeg)
+        } else if GIF_HEADERS.iter().any(|h| data.starts_with(h)) {
+            Some(ImageFormat::Gif)
+        } else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+            Some(ImageFormat::Webp)
+        } else {
+            None
+        }
+    }
+
+    /// The format of a download, by its Content-Type if that says, and by its
+    /// bytes if not
+    pub fn detect(content_type: Option<&str>, data: &[u8]) -> Option<Self> {
+        content_type
+            .and_then(Self::from_content_type)
+            .or_else(|| Self::sniff(data))
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "image/png",
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Gif => "image/gif",
+            ImageFormat::Webp => "image/webp",
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_content_types() {
+        assert_eq!(
+            ImageFormat::from_content_type("image/PNG"),
+            Some(ImageFormat::Png)
+        );
+        assert_eq!(
+            ImageFormat::from_content_type("image/jpeg; charset=binary"),
+            Some(ImageFormat::Jpeg)
+        );
+        assert_eq!(ImageFormat::from_content_type("text/html"), None);
+        assert_eq!(ImageFormat::from_content_type(""), None);
+    }
+
+    #[test]
+    fn sniffs_when_the_content_type_is_no_help() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+        assert_eq!(
+            ImageFormat::detect(Some("application/octet-stream"), png),
+            Some(ImageFormat::Png)
+        );
+        assert_eq!(
+            ImageFormat::detect(None, b"GIF89a\x01\0\x01\0"),
+            Some(ImageFormat::Gif)
+        );
+        assert_eq!(
+            ImageFormat::detect(None, b"RIFF\0\0\0\0WEBPVP8 "),
+            Some(ImageFormat::Webp)
+        );
+        assert_eq!(
+            ImageFormat::detect(Some("image/gif"), png),
+            Some(ImageFormat::Gif)
+        );
+        assert_eq!(ImageFormat::detect(None, b"<html>"), None);
+    }
+}
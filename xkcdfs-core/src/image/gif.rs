@@ -0,0 +1,119 @@
+//! Just enough GIF parsing to tell animations apart from still images
+
+const HEADERS: [&[u8]; 2] = [b"GIF87a", b"GIF89a"];
+
+const EXTENSION: u8 = 0x21;
+const IMAGE_DESCRIPTOR: u8 = 0x2C;
+const TRAILER: u8 = 0x3B;
+
+/// Check whether the data is a GIF with more than one frame
+///
+/// Truncated or malformed GIFs are treated as stills, which means they go
+/// through the usual rendering path (and fail there, with a better error).
+pub fn is_animated(data: &[u8]) -> bool {
+    count_frames(data).map(|n| n > 1).unwrap_or(false)
+}
+
+fn count_frames(data: &[u8]) -> Option<usize> {
+    if !HEADERS.iter().any(|h| data.starts_with(h)) {
+        return None;
+    }
+
+    // Logical screen descriptor, possibly followed by a global color table
+    let packed = *data.get(10)?;
+    let mut pos = 13 + color_table_size(packed);
+
+    let mut frames = 0;
+
+    loop {
+        match *data.get(pos)? {
+            EXTENSION => {
+                // Introducer and label, then the data sub-blocks
+                pos = skip_sub_blocks(data, pos + 2)?;
+            }
+            IMAGE_DESCRIPTOR => {
+                frames += 1;
+
+                // Once we've seen two, there's no reason to keep going
+                if frames > 1 {
+                    return Some(frames);
+                }
+
+                let packed = *data.get(pos + 9)?;
+
+                // Descriptor, local color table, and LZW minimum code size,
+                // then the image data sub-blocks
+                pos = skip_sub_blocks(data, pos + 10 + color_table_size(packed) + 1)?;
+            }
+            TRAILER => return Some(frames),
+            _ => return None,
+        }
+    }
+}
+
+/// Size in bytes of the color table described by a packed field, if any
+fn color_table_size(packed: u8) -> usize {
+    if packed & 0x80 != 0 {
+        3 * (1 << ((packed & 0x07) + 1))
+    } else {
+        0
+    }
+}
+
+/// Get the position just past a run of sub-blocks
+fn skip_sub_blocks(data: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *data.get(pos)? as usize;
+
+        pos += 1;
+
+        if len == 0 {
+            return Some(pos);
+        }
+
+        pos += len;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn gif(frames: usize) -> Vec<u8> {
+        let mut data = b"GIF89a".to_vec();
+
+        // 1x1, two-entry global color table
+        data.extend_from_slice(&[1, 0, 1, 0, 0x80, 0, 0]);
+        data.extend_from_slice(&[0, 0, 0, 0xFF, 0xFF, 0xFF]);
+
+        for _ in 0..frames {
+            // Graphic control extension
+            data.extend_from_slice(&[EXTENSION, 0xF9, 4, 0, 10, 0, 0, 0]);
+
+            // Image descriptor and a single block of image data
+            data.extend_from_slice(&[IMAGE_DESCRIPTOR, 0, 0, 0, 0, 1, 0, 1, 0, 0]);
+            data.extend_from_slice(&[2, 2, 0x4C, 0x01, 0]);
+        }
+
+        data.push(TRAILER);
+        data
+    }
+
+    #[test]
+    fn still_gif_is_not_animated() {
+        assert!(!is_animated(&gif(1)));
+    }
+
+    #[test]
+    fn multi_frame_gif_is_animated() {
+        assert!(is_animated(&gif(2)));
+        assert!(is_animated(&gif(10)));
+    }
+
+    #[test]
+    fn other_data_is_not_animated() {
+        assert!(!is_animated(b""));
+        assert!(!is_animated(b"\x89PNG\r\n\x1a\n"));
+        assert!(!is_animated(&gif(2)[..20]));
+    }
+}
@@ -0,0 +1,232 @@
+//! Contact sheets of several rendered comics
+
+use super::{chunks, create_image_surface, downscale, ScaleFilter};
+use cairo::{Context, Format, ImageSurface};
+use std::io::Cursor;
+
+/// Most comics a single montage will hold
+///
+/// Every cell is drawn into one surface, so this also bounds its size.
+pub const MAX_COMICS: usize = 100;
+
+// Each comic is scaled to fit a square cell
+const CELL_SIZE: u32 = 400;
+const CELL_SPACING: f64 = 20.0;
+const MARGIN: f64 = 20.0;
+
+/// Parse a selection of comics, like `1-5, 10 20`
+///
+/// Numbers and ranges can be separated by commas or whitespace. Order is
+/// kept, but comics listed more than once only appear the first time.
+pub fn parse_selection(selection: &str) -> Result<Vec<u32>, String> {
+    let mut nums: Vec<u32> = Vec::new();
+
+    let parse_num = |s: &str| {
+        s.trim()
+            .parse::<u32>()
+            .map_err(|_| format!("{:?} is not a comic number", s.trim()))
+    };
+
+    for item in selection
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+    {
+        let (first, last) = match item.find('-') {
+            Some(i) => (parse_num(&item[..i])?, parse_num(&item[i + 1..])?),
+            None => {
+                let num = parse_num(item)?;
+                (num, num)
+            }
+        };
+
+        if first == 0 || first > last {
+            return Err(format!("{:?} is not a valid range of comics", item));
+        }
+
+        if (last - first) as usize >= MAX_COMICS {
+            return Err(format!("A montage can hold at most {} comics", MAX_COMICS));
+        }
+
+        for num in first..=last {
+            if !nums.contains(&num) {
+                nums.push(num);
+            }
+        }
+
+        if nums.len() > MAX_COMICS {
+            return Err(format!("A montage can hold at most {} comics", MAX_COMICS));
+        }
+    }
+
+    if nums.is_empty() {
+        return Err("No comics selected".to_string());
+    }
+
+    Ok(nums)
+}
+
+/// Columns and rows of the most square grid that holds `count` cells
+pub fn grid(count: usize) -> (usize, usize) {
+    if count == 0 {
+        return (0, 0);
+    }
+
+    let columns = (count as f64).sqrt().ceil() as usize;
+    let rows = count.div_ceil(columns);
+
+    (columns, rows)
+}
+
+/// A montage PNG, and the comics left out of it
+pub struct Montage {
+    pub png: Vec<u8>,
+    /// Comics that couldn't be loaded, so their cells are empty
+    pub missing: Vec<u32>,
+}
+
+impl Montage {
+    /// Whether every comic made it into a cell
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+    }
+}
+
+/// Build a montage PNG from several rendered comics
+///
+/// `tile` is asked for each comic's rendered image in turn, and each one is
+/// scaled down and dropped before the next is loaded. Comics that can't be
+/// loaded leave an empty cell, and are listed in `missing`.
+pub fn montage<F>(nums: &[u32], mut tile: F) -> Result<Montage, String>
+where
+    F: FnMut(u32) -> Option<Vec<u8>>,
+{
+    if nums.is_empty() || nums.len() > MAX_COMICS {
+        return Err(format!(
+            "A montage needs between 1 and {} comics, not {}",
+            MAX_COMICS,
+            nums.len()
+        ));
+    }
+
+    let (columns, rows) = grid(nums.len());
+    let cell = CELL_SIZE as f64;
+
+    let width = 2.0 * MARGIN + columns as f64 * cell + (columns - 1) as f64 * CELL_SPACING;
+    let height = 2.0 * MARGIN + rows as f64 * cell + (rows - 1) as f64 * CELL_SPACING;
+
+    trace!(
+        "Montage of {} comics is {} by {} cells, {} by {} pixels",
+        nums.len(),
+        columns,
+        rows,
+        width,
+        height
+    );
+
+    let surface = ImageSurface::create(Format::ARgb32, width as i32, height as i32)
+        .map_err(|e| e.to_string())?;
+    let cr = Context::new(&surface);
+
+    cr.set_source_rgb(1.0, 1.0, 1.0);
+    cr.paint();
+
+    let mut missing = Vec::new();
+
+    for (index, num) in nums.iter().enumerate() {
+        let image = match tile(*num) {
+            Some(i) => i,
+            None => {
+                warn!("No image for comic {}, leaving its montage cell empty", num);
+                missing.push(*num);
+                continue;
+            }
+        };
+
+        let scaled = match create_image_surface(&mut Cursor::new(&image))
+            .and_then(|s| downscale(&s, CELL_SIZE, CELL_SIZE, ScaleFilter::Box))
+        {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("Could not add comic {} to the montage: {}", num, e);
+                missing.push(*num);
+                continue;
+            }
+        };
+
+        // Center the comic in its cell
+        let cell_x = MARGIN + (index % columns) as f64 * (cell + CELL_SPACING);
+        let cell_y = MARGIN + (index / columns) as f64 * (cell + CELL_SPACING);
+        let x = cell_x + ((cell - scaled.get_width() as f64) / 2.0).floor();
+        let y = cell_y + ((cell - scaled.get_height() as f64) / 2.0).floor();
+
+        cr.set_source_surface(&scaled, x, y);
+        cr.paint();
+    }
+
+    let mut buffer = Vec::new();
+
+    surface
+        .write_to_png(&mut buffer)
+        .map_err(|e| format!("Could not write montage PNG: {:?}", e))?;
+
+    Ok(Montage {
+        png: chunks::remove_chunks(&buffer, chunks::TIMESTAMPS)?,
+        missing,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_numbers_and_ranges() {
+        assert_eq!(parse_selection("1"), Ok(vec![1]));
+        assert_eq!(parse_selection("1-3"), Ok(vec![1, 2, 3]));
+        assert_eq!(parse_selection("10, 2-3 7\n"), Ok(vec![10, 2, 3, 7]));
+        assert_eq!(parse_selection("3,1-4"), Ok(vec![3, 1, 2, 4]));
+    }
+
+    #[test]
+    fn rejects_bad_selections() {
+        assert!(parse_selection("").is_err());
+        assert!(parse_selection(" , ").is_err());
+        assert!(parse_selection("0").is_err());
+        assert!(parse_selection("5-3").is_err());
+        assert!(parse_selection("one").is_err());
+        assert!(parse_selection("1-").is_err());
+        assert!(parse_selection("1-101").is_err());
+        assert!(parse_selection("1-100").is_ok());
+        assert!(parse_selection("1-100, 200").is_err());
+    }
+
+    #[test]
+    fn grid_is_nearly_square() {
+        assert_eq!(grid(0), (0, 0));
+        assert_eq!(grid(1), (1, 1));
+        assert_eq!(grid(2), (2, 1));
+        assert_eq!(grid(4), (2, 2));
+        assert_eq!(grid(5), (3, 2));
+        assert_eq!(grid(10), (4, 3));
+        assert_eq!(grid(100), (10, 10));
+    }
+
+    #[test]
+    fn lists_missing_comics() {
+        let surface = ImageSurface::create(Format::Rgb24, 40, 30).unwrap();
+        let mut png = Vec::new();
+        surface.write_to_png(&mut png).unwrap();
+
+        let complete = montage(&[1, 2], |_| Some(png.clone())).unwrap();
+        assert!(complete.is_complete());
+
+        let partial = montage(&[1, 2, 3], |num| match num {
+            1 => Some(png.clone()),
+            2 => None,
+            _ => Some(b"not an image".to_vec()),
+        })
+        .unwrap();
+        assert!(!partial.is_complete());
+        assert_eq!(partial.missing, vec![2, 3]);
+    }
+}
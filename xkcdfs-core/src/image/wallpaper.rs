@@ -0,0 +1,156 @@
+//! A rendered comic letterboxed onto a screen-sized background
+
+use super::{chunks, create_image_surface, Color, ScaleFilter};
+use cairo::{Context, Format, ImageSurface, SurfacePattern};
+use std::io::Cursor;
+
+/// Largest width or height a wallpaper can be, which is plenty for an 8K
+/// screen and keeps the surface a sensible size
+pub const MAX_SIZE: u32 = 16_384;
+
+/// How much of the screen the comic can cover, in each direction
+const FILL: f64 = 0.9;
+
+/// Most a comic is scaled up, so small comics on big screens don't blur
+const MAX_ZOOM: f64 = 2.0;
+
+/// Everything needed to produce (and cache) one wallpaper of a comic
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WallpaperSpec {
+    pub width: u32,
+    pub height: u32,
+    pub background: Color,
+}
+
+impl Default for WallpaperSpec {
+    fn default() -> Self {
+        Self {
+            width: 1920,
+            height: 1080,
+            background: super::WHITE,
+        }
+    }
+}
+
+impl WallpaperSpec {
+    /// Part of the key wallpapers are cached under, along with the render
+    /// options
+    pub fn cache_key(&self) -> String {
+        format!(
+            "{}x{};background={}",
+            self.width, self.height, self.background
+        )
+    }
+}
+
+/// Parse a screen size like `1920x1080`
+pub fn parse_size(size: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("Invalid size {:?}, expected something like 1920x1080", size);
+
+    let i = size.find('x').ok_or_else(invalid)?;
+    let width: u32 = size[..i].parse().map_err(|_| invalid())?;
+    let height: u32 = size[i + 1..].parse().map_err(|_| invalid())?;
+
+    if width == 0 || height == 0 || width > MAX_SIZE || height > MAX_SIZE {
+        return Err(format!(
+            "Wallpapers must be between 1 and {} pixels each way, not {}",
+            MAX_SIZE, size
+        ));
+    }
+
+    Ok((width, height))
+}
+
+/// How much to scale a `width` by `height` comic, and where its top left
+/// corner goes
+fn placement(spec: WallpaperSpec, width: f64, height: f64) -> (f64, f64, f64) {
+    let scale = (spec.width as f64 * FILL / width)
+        .min(spec.height as f64 * FILL / height)
+        .min(MAX_ZOOM);
+
+    let x = ((spec.width as f64 - width * scale) / 2.0).floor();
+    let y = ((spec.height as f64 - height * scale) / 2.0).floor();
+
+    (scale, x, y)
+}
+
+/// Build a wallpaper PNG from a rendered comic, centered and scaled to fit
+pub fn wallpaper(rendered: &[u8], spec: WallpaperSpec) -> Result<Vec<u8>, String> {
+    let source = create_image_surface(&mut Cursor::new(rendered))?;
+    let (width, height) = (source.get_width(), source.get_height());
+
+    if width <= 0 || height <= 0 {
+        return Err(format!(
+            "Cannot make a wallpaper of an image with dimensions {} by {}",
+            width, height
+        ));
+    }
+
+    let (scale, x, y) = placement(spec, width as f64, height as f64);
+
+    trace!(
+        "Wallpaper of a {} by {} image is {} by {}, scaled by {}",
+        width,
+        height,
+        spec.width,
+        spec.height,
+        scale
+    );
+
+    let surface = ImageSurface::create(Format::Rgb24, spec.width as i32, spec.height as i32)
+        .map_err(|e| e.to_string())?;
+
+    {
+        let cr = Context::new(&surface);
+        let (red, green, blue) = spec.background.rgb();
+
+        cr.set_source_rgb(red, green, blue);
+        cr.paint();
+
+        cr.translate(x, y);
+        cr.scale(scale, scale);
+
+        let pattern = SurfacePattern::create(&source);
+        pattern.set_filter(ScaleFilter::Box.cairo_filter());
+
+        cr.set_source(&pattern);
+        cr.paint();
+    }
+
+    let mut buffer = Vec::new();
+
+    surface
+        .write_to_png(&mut buffer)
+        .map_err(|e| format!("Could not write wallpaper PNG: {:?}", e))?;
+
+    chunks::remove_chunks(&buffer, chunks::TIMESTAMPS)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("1920x1080"), Ok((1920, 1080)));
+        assert_eq!(parse_size("800x600"), Ok((800, 600)));
+        assert!(parse_size("1920").is_err());
+        assert!(parse_size("1920x").is_err());
+        assert!(parse_size("x1080").is_err());
+        assert!(parse_size("0x1080").is_err());
+        assert!(parse_size("100000x1080").is_err());
+        assert!(parse_size("1920 x 1080").is_err());
+    }
+
+    #[test]
+    fn centers_the_comic() {
+        let spec = WallpaperSpec::default();
+
+        // Tall comics fill the height, wide ones the width
+        assert_eq!(placement(spec, 486.0, 972.0), (1.0, 717.0, 54.0));
+        assert_eq!(placement(spec, 3456.0, 100.0), (0.5, 96.0, 515.0));
+
+        // Small ones only go so far
+        assert_eq!(placement(spec, 100.0, 100.0), (2.0, 860.0, 440.0));
+    }
+}
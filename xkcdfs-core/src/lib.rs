@@ -0,0 +1,50 @@
+//! The caching xkcd client and comic renderer behind xkcdfs
+//!
+//! `XkcdClient` fetches comics and their images from xkcd.com, keeping
+//! everything in an SQLite cache so it's there later without a connection.
+//! Comics can come from elsewhere instead by implementing `ComicSource`, and be
+//! kept somewhere else by implementing `CacheStore`. It also renders comics
+//! with their title and alt text, as PNG, PDF, SVG or ASCII art, using the
+//! functions in `image`.
+//!
+//! ```no_run
+//! use xkcdfs_core::{image::RenderOptions, RequestMode, XkcdClient};
+//! use std::ffi::OsStr;
+//! use std::time::Duration;
+//!
+//! let client = XkcdClient::new(
+//!     Duration::from_secs(5),
+//!     OsStr::new("xkcd.db"),
+//!     "my-tool/1.0".to_string(),
+//!     Default::default(),
+//!     false,
+//!     false,
+//!     RenderOptions::default(),
+//!     None,
+//! )
+//! .unwrap();
+//!
+//! let comic = client.request_comic(1, None, RequestMode::Normal).unwrap();
+//! let png = client.request_rendered_image(&comic, None, RequestMode::Normal);
+//! ```
+//!
+//! Messages are logged with the `log` crate. Work on a particular comic is
+//! tagged using `scope`, so a logger can say what each message was about.
+
+#[macro_use]
+extern crate log;
+
+pub mod image;
+pub mod requests;
+pub mod scope;
+pub mod source;
+pub mod stats;
+pub mod store;
+pub mod titles;
+pub mod transcript;
+mod xkcd;
+
+pub use requests::{RequestMode, SqliteStore, XkcdApi, XkcdClient};
+pub use source::ComicSource;
+pub use store::CacheStore;
+pub use xkcd::Comic;
@@ -0,0 +1,297 @@
+use crate::source::ComicSource;
+use crate::Comic;
+use reqwest::header::{HeaderMap, CONTENT_LENGTH, CONTENT_TYPE, USER_AGENT};
+use reqwest::{StatusCode, Url};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::convert::{TryFrom, TryInto};
+use std::str::FromStr;
+use std::time::Duration;
+
+const LATEST_URL: &str = "https://xkcd.com/info.0.json";
+const COMIC_URL: &str = "https://xkcd.com/{num}/info.0.json";
+
+/// The JSON for one comic
+///
+/// xkcd.com almost always sends every field, with the numbers in the date as
+/// strings. Translated mirrors aren't so careful, and a few old comics have
+/// empty dates, so anything that can be done without is optional, numbers can
+/// be either, and fields nobody's heard of are kept aside instead of failing.
+#[derive(Deserialize, Debug)]
+struct ApiComic {
+    num: Number,
+
+    #[serde(default)]
+    day: Option<Number>,
+    #[serde(default)]
+    month: Option<Number>,
+    #[serde(default)]
+    year: Option<Number>,
+
+    #[serde(default)]
+    link: Option<String>,
+    #[serde(default)]
+    news: Option<String>,
+    #[serde(default)]
+    alt: Option<String>,
+
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    safe_title: Option<String>,
+
+    #[serde(default)]
+    transcript: Option<String>,
+    img: String,
+
+    /// Kept as JSON, whatever's in it
+    #[serde(default)]
+    extra_parts: Option<serde_json::Value>,
+
+    /// Anything else
+    #[serde(flatten)]
+    other: BTreeMap<String, serde_json::Value>,
+}
+
+/// A number, which may be written as a string
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Number {
+    Number(i64),
+    String(String),
+}
+
+impl Number {
+    fn parse<T: FromStr + TryFrom<i64>>(self, field: &str) -> Result<T, String> {
+        let invalid = || format!("Invalid {} in comic JSON", field);
+
+        match self {
+            Number::Number(n) => T::try_from(n).map_err(|_| invalid()),
+            Number::String(s) => s.trim().parse().map_err(|_| invalid()),
+        }
+    }
+
+    /// Part of a date, with `default` if it's missing or empty
+    fn date_part(part: Option<Self>, field: &str, default: i32) -> Result<i32, String> {
+        match part {
+            None => Ok(default),
+            Some(Number::String(ref s)) if s.trim().is_empty() => Ok(default),
+            Some(n) => n.parse(field),
+        }
+    }
+}
+
+/// Read a comic from the API's JSON
+///
+/// Error pages (like the one for comic 404) aren't JSON at all, which is an
+/// error like any other.
+pub fn parse_comic(json: &[u8]) -> Result<Comic, String> {
+    serde_json::from_slice::<ApiComic>(json)
+        .map_err(|e| format!("Not a comic's JSON: {}", e))?
+        .try_into()
+}
+
+/// Read a JSON array of comics, each as the API gives them
+pub fn parse_comics(json: &[u8]) -> Result<Vec<Comic>, String> {
+    serde_json::from_slice::<Vec<ApiComic>>(json)
+        .map_err(|e| format!("Not a list of comics' JSON: {}", e))?
+        .into_iter()
+        .map(TryInto::try_into)
+        .collect()
+}
+
+impl TryInto<Comic> for ApiComic {
+    type Error = String;
+
+    fn try_into(self) -> Result<Comic, Self::Error> {
+        fn none_if_empty(s: Option<String>) -> Option<String> {
+            s.filter(|s| !s.is_empty())
+        }
+
+        let num = self.num.parse("num")?;
+
+        if !self.other.is_empty() {
+            let fields: Vec<&str> = self.other.keys().map(String::as_str).collect();
+            debug!("Comic {} has other fields: {}", num, fields.join(", "));
+        }
+
+        // Either title will do for the other
+        let title = none_if_empty(self.title);
+        let safe_title = none_if_empty(self.safe_title);
+
+        Ok(Comic {
+            num,
+
+            // Undated comics go at the start of the epoch
+            day: Number::date_part(self.day, "day", 1)?,
+            month: Number::date_part(self.month, "month", 1)?,
+            year: Number::date_part(self.year, "year", 1970)?,
+
+            link: none_if_empty(self.link),
+            news: none_if_empty(self.news),
+            alt: self.alt.unwrap_or_default(),
+
+            title: title
+                .clone()
+                .or_else(|| safe_title.clone())
+                .unwrap_or_default(),
+            safe_title: safe_title.or(title).unwrap_or_default(),
+            transcript: self.transcript,
+
+            img_url: self.img,
+            img_len: None,
+
+            extra_parts: self.extra_parts.map(|e| e.to_string()),
+        })
+    }
+}
+
+/// The xkcd.com JSON API, and the images it links to
+///
+/// Other webcomics with the same API can be read with `with_urls`.
+#[derive(Clone)]
+pub struct XkcdApi {
+    client: reqwest::Client,
+    user_agent: String,
+    latest_url: String,
+    /// With `{num}` where the comic number goes
+    comic_url: String,
+}
+
+impl XkcdApi {
+    /// `headers` are sent with every request, on top of `user_agent`
+    pub fn new(timeout: Duration, user_agent: String, headers: HeaderMap) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .default_headers(headers)
+            .build()
+            .map_err(|e| format!("Could not set up the HTTP client: {}", e))?;
+
+        Ok(Self {
+            client,
+            user_agent,
+            latest_url: LATEST_URL.to_string(),
+            comic_url: COMIC_URL.to_string(),
+        })
+    }
+
+    /// Get comics from somewhere other than xkcd.com
+    ///
+    /// `comic_url` has `{num}` where the comic number goes.
+    pub fn with_urls(self, latest_url: String, comic_url: String) -> Self {
+        Self {
+            latest_url,
+            comic_url,
+            ..self
+        }
+    }
+}
+
+impl ComicSource for XkcdApi {
+    fn latest(&self) -> Result<Comic, String> {
+        get_comic(&self.client, &self.user_agent, &self.latest_url)
+    }
+
+    fn comic(&self, num: u32) -> Result<Comic, String> {
+        let url = self.comic_url.replace("{num}", &num.to_string());
+
+        get_comic(&self.client, &self.user_agent, &url)
+    }
+
+    fn image(&self, comic: &Comic) -> Result<Vec<u8>, String> {
+        self.image_and_type(comic).map(|(image, _)| image)
+    }
+
+    fn image_and_type(&self, comic: &Comic) -> Result<(Vec<u8>, Option<String>), String> {
+        get_image(&self.client, &self.user_agent, &comic.img_url)
+    }
+
+    fn hires_size(&self, comic: &Comic) -> Result<Option<u64>, String> {
+        match comic.hires_url() {
+            Some(url) => get_size(&self.client, &self.user_agent, &url),
+            None => Ok(None),
+        }
+    }
+
+    fn hires_image(&self, comic: &Comic) -> Result<Vec<u8>, String> {
+        let url = comic
+            .hires_url()
+            .ok_or_else(|| format!("{} has no double-size image", comic))?;
+
+        get_image(&self.client, &self.user_agent, &url).map(|(image, _)| image)
+    }
+}
+
+fn get_comic(client: &reqwest::Client, user_agent: &str, url: &str) -> Result<Comic, String> {
+    let mut buf: Vec<u8> = vec![];
+
+    client
+        .get(url)
+        .header(USER_AGENT, user_agent)
+        .send()
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|e| e.to_string())?
+        .copy_to(&mut buf)
+        .map_err(|e| e.to_string())?;
+
+    let mut comic = parse_comic(&buf)?;
+
+    // Some mirrors link to images relative to the JSON
+    if Url::parse(&comic.img_url).is_err() {
+        comic.img_url = Url::parse(url)
+            .and_then(|u| u.join(&comic.img_url))
+            .map_err(|e| format!("Invalid image URL {:?}: {}", comic.img_url, e))?
+            .into_string();
+    }
+
+    Ok(comic)
+}
+
+/// How big the file at `url` is, without downloading it, or `None` if there's
+/// nothing there
+fn get_size(client: &reqwest::Client, user_agent: &str, url: &str) -> Result<Option<u64>, String> {
+    let response = client
+        .head(url)
+        .header(USER_AGENT, user_agent)
+        .send()
+        .map_err(|e| e.to_string())?;
+
+    match response.status() {
+        StatusCode::NOT_FOUND | StatusCode::GONE => Ok(None),
+        status if status.is_success() => Ok(Some(
+            response
+                .headers()
+                .get(CONTENT_LENGTH)
+                .and_then(|l| l.to_str().ok())
+                .and_then(|l| l.parse().ok())
+                .unwrap_or(0),
+        )),
+        status => Err(status.to_string()),
+    }
+}
+
+/// The image, and its Content-Type
+fn get_image(
+    client: &reqwest::Client,
+    user_agent: &str,
+    url: &str,
+) -> Result<(Vec<u8>, Option<String>), String> {
+    let mut buf: Vec<u8> = vec![];
+
+    let mut response = client
+        .get(url)
+        .header(USER_AGENT, user_agent)
+        .send()
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|e| e.to_string())?;
+
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|t| t.to_str().ok())
+        .map(str::to_string);
+
+    response.copy_to(&mut buf).map_err(|e| e.to_string())?;
+
+    Ok((buf, content_type))
+}
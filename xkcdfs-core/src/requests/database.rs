@@ -0,0 +1,2007 @@
+use rusqlite::blob::Blob;
+use rusqlite::{DatabaseName, OpenFlags, OptionalExtension, ToSql, NO_PARAMS};
+use std::convert::TryInto;
+use std::ffi::{OsStr, OsString};
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::image::ThumbnailSpec;
+use crate::store::{CacheStore, Fetch, HiRes, Session};
+use crate::titles;
+use crate::Comic;
+
+/// The usual `CacheStore`: everything in one SQLite database
+///
+/// Each store has its own connection, and `try_clone` opens another one to the
+/// same file. An in-memory database (`:memory:`) is given a name in SQLite's
+/// `memdb` VFS instead, so its clones see the same database. It lasts as long
+/// as any of them is open, and its connections take turns writing the same way
+/// a file's do, rather than failing with SQLITE_LOCKED like a shared cache's.
+pub struct SqliteStore {
+    conn: rusqlite::Connection,
+    database: OsString,
+}
+
+/// Asks for an in-memory database, as SQLite itself takes it
+const MEMORY: &str = ":memory:";
+
+/// Tells in-memory stores in the same process apart
+static NEXT_MEMORY: AtomicUsize = AtomicUsize::new(0);
+
+/// How long a connection waits for another one to finish writing before giving
+/// up with SQLITE_BUSY
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl SqliteStore {
+    /// Open (or create) the database at `database` and bring its tables up to
+    /// date
+    pub fn open(database: &OsStr) -> Result<Self, String> {
+        let database = if database == MEMORY {
+            OsString::from(format!(
+                "file:/xkcdfs-{}-{}?vfs=memdb",
+                std::process::id(),
+                NEXT_MEMORY.fetch_add(1, Ordering::Relaxed)
+            ))
+        } else {
+            database.to_owned()
+        };
+
+        let conn = connect(&database)?;
+
+        // Once here, so clones only have to connect
+        setup(&conn)
+            .map_err(|e| format!("Could not set up {}: {}", database.to_string_lossy(), e))?;
+
+        Ok(Self { conn, database })
+    }
+}
+
+/// Open a connection to `database`, set up to wait its turn to write
+fn connect(database: &OsStr) -> Result<rusqlite::Connection, String> {
+    let conn = rusqlite::Connection::open_with_flags(
+        database,
+        OpenFlags::default() | OpenFlags::SQLITE_OPEN_URI,
+    )
+    .map_err(|e| format!("Could not open {}: {}", database.to_string_lossy(), e))?;
+
+    conn.busy_timeout(BUSY_TIMEOUT)
+        .map_err(|e| format!("Could not open {}: {}", database.to_string_lossy(), e))?;
+
+    Ok(conn)
+}
+
+impl CacheStore for SqliteStore {
+    fn try_clone(&self) -> Result<Self, String> {
+        Ok(Self {
+            conn: connect(&self.database)?,
+            database: self.database.clone(),
+        })
+    }
+
+    fn comic(&self, num: u32) -> Result<Option<Comic>, String> {
+        get_comic(&self.conn, num).map_err(|e| e.to_string())
+    }
+
+    fn latest_comic(&self) -> Result<Option<Comic>, String> {
+        get_latest_comic(&self.conn).map_err(|e| e.to_string())
+    }
+
+    fn comic_count(&self) -> usize {
+        get_comics_count(&self.conn)
+    }
+
+    fn put_comic(&self, comic: &Comic) -> Result<(), String> {
+        immediately(&self.conn, || {
+            insert_comic(&self.conn, comic)?;
+            insert_title(&self.conn, comic.num, &comic.safe_title)
+        })
+        .map_err(|e| e.to_string())
+    }
+
+    fn each_comic(&self, each: &mut dyn FnMut(Comic)) -> Result<(), String> {
+        for_each_comic(&self.conn, each).map_err(|e| e.to_string())
+    }
+
+    fn raw_image(&self, num: u32) -> Result<Option<Vec<u8>>, String> {
+        get_raw_image(&self.conn, num)
+            .optional()
+            .map_err(|e| e.to_string())
+    }
+
+    fn put_raw_image(&self, num: u32, data: &[u8]) -> Result<(), String> {
+        insert_raw_image(&self.conn, num, data).map_err(|e| e.to_string())
+    }
+
+    fn raw_image_range(
+        &self,
+        num: u32,
+        offset: u64,
+        len: usize,
+    ) -> Result<Option<Vec<u8>>, String> {
+        match open_raw_image(&self.conn, num).map_err(|e| e.to_string())? {
+            Some(mut blob) => read_range(&mut blob, offset, len)
+                .map(Some)
+                .map_err(|e| e.to_string()),
+            None => Ok(None),
+        }
+    }
+
+    fn image_type(&self, num: u32) -> Result<Option<String>, String> {
+        get_image_type(&self.conn, num).map_err(|e| e.to_string())
+    }
+
+    fn put_image_type(&self, num: u32, content_type: &str) -> Result<(), String> {
+        update_image_type(&self.conn, num, content_type).map_err(|e| e.to_string())
+    }
+
+    fn rendered_image(&self, num: u32, render_key: &str) -> Result<Option<Vec<u8>>, String> {
+        get_rendered_image(&self.conn, num, render_key)
+            .optional()
+            .map_err(|e| e.to_string())
+    }
+
+    fn put_rendered_image(&self, num: u32, render_key: &str, data: &[u8]) -> Result<(), String> {
+        insert_rendered_image(&self.conn, num, render_key, data).map_err(|e| e.to_string())
+    }
+
+    fn rendered_image_range(
+        &self,
+        num: u32,
+        render_key: &str,
+        offset: u64,
+        len: usize,
+    ) -> Result<Option<Vec<u8>>, String> {
+        match open_rendered_image(&self.conn, num, render_key).map_err(|e| e.to_string())? {
+            Some(mut blob) => read_range(&mut blob, offset, len)
+                .map(Some)
+                .map_err(|e| e.to_string()),
+            None => Ok(None),
+        }
+    }
+
+    fn thumbnail(&self, num: u32, spec: ThumbnailSpec) -> Result<Option<Vec<u8>>, String> {
+        get_thumbnail(&self.conn, num, spec)
+            .optional()
+            .map_err(|e| e.to_string())
+    }
+
+    fn put_thumbnail(&self, num: u32, spec: ThumbnailSpec, data: &[u8]) -> Result<(), String> {
+        insert_thumbnail(&self.conn, num, spec, data).map_err(|e| e.to_string())
+    }
+
+    fn montage(&self, montage_key: &str) -> Result<Option<Vec<u8>>, String> {
+        get_montage(&self.conn, montage_key)
+            .optional()
+            .map_err(|e| e.to_string())
+    }
+
+    fn put_montage(&self, montage_key: &str, data: &[u8]) -> Result<(), String> {
+        insert_montage(&self.conn, montage_key, data).map_err(|e| e.to_string())
+    }
+
+    fn ascii_art(&self, num: u32, width: u32) -> Result<Option<String>, String> {
+        get_ascii_art(&self.conn, num, width)
+            .optional()
+            .map_err(|e| e.to_string())
+    }
+
+    fn put_ascii_art(&self, num: u32, width: u32, art: &str) -> Result<(), String> {
+        insert_ascii_art(&self.conn, num, width, art).map_err(|e| e.to_string())
+    }
+
+    fn size(&self) -> Result<u64, String> {
+        cache_size(&self.conn).map_err(|e| e.to_string())
+    }
+
+    fn evict(&self, max_size: u64) -> Result<u64, String> {
+        evict(&self.conn, max_size).map_err(|e| e.to_string())
+    }
+
+    fn wallpaper(&self, num: u32, wallpaper_key: &str) -> Result<Option<Vec<u8>>, String> {
+        get_wallpaper(&self.conn, num, wallpaper_key)
+            .optional()
+            .map_err(|e| e.to_string())
+    }
+
+    fn put_wallpaper(&self, num: u32, wallpaper_key: &str, data: &[u8]) -> Result<(), String> {
+        insert_wallpaper(&self.conn, num, wallpaper_key, data).map_err(|e| e.to_string())
+    }
+
+    fn mirrored(&self, num: u32) -> Result<Option<u32>, String> {
+        get_mirrored(&self.conn, num).map_err(|e| e.to_string())
+    }
+
+    fn put_mirrored(&self, num: u32, checksum: u32) -> Result<(), String> {
+        insert_mirrored(&self.conn, num, checksum).map_err(|e| e.to_string())
+    }
+
+    fn remove_mirrored(&self, num: u32) -> Result<(), String> {
+        delete_mirrored(&self.conn, num).map_err(|e| e.to_string())
+    }
+
+    fn hires(&self, num: u32) -> Result<Option<HiRes>, String> {
+        get_hires(&self.conn, num).map_err(|e| e.to_string())
+    }
+
+    fn put_hires(&self, num: u32, hires: HiRes) -> Result<(), String> {
+        insert_hires(&self.conn, num, hires).map_err(|e| e.to_string())
+    }
+
+    fn hires_image(&self, num: u32) -> Result<Option<Vec<u8>>, String> {
+        get_hires_image(&self.conn, num).map_err(|e| e.to_string())
+    }
+
+    fn put_hires_image(&self, num: u32, image: &[u8]) -> Result<(), String> {
+        insert_hires_image(&self.conn, num, image).map_err(|e| e.to_string())
+    }
+
+    fn accessed(&self, num: u32) -> Result<Option<i64>, String> {
+        get_accessed(&self.conn, num).map_err(|e| e.to_string())
+    }
+
+    fn put_accessed(&self, num: u32, at: i64) -> Result<(), String> {
+        insert_accessed(&self.conn, num, at).map_err(|e| e.to_string())
+    }
+
+    fn recently_accessed(&self, limit: u32) -> Result<Vec<u32>, String> {
+        get_recently_accessed(&self.conn, limit).map_err(|e| e.to_string())
+    }
+
+    fn years(&self) -> Result<Vec<i32>, String> {
+        get_years(&self.conn).map_err(|e| e.to_string())
+    }
+
+    fn comics_in_year(&self, year: i32) -> Result<Vec<u32>, String> {
+        get_comics_in_year(&self.conn, year).map_err(|e| e.to_string())
+    }
+
+    fn comics_on(&self, year: i32, month: i32, day: i32) -> Result<Vec<u32>, String> {
+        get_comics_by_date(&self.conn, year, month, day).map_err(|e| e.to_string())
+    }
+
+    fn nums(&self) -> Result<Vec<u32>, String> {
+        get_nums(&self.conn).map_err(|e| e.to_string())
+    }
+
+    fn titles(&self) -> Result<Vec<(u32, String)>, String> {
+        get_titles(&self.conn).map_err(|e| e.to_string())
+    }
+
+    fn titled(&self, name: &str) -> Result<Option<u32>, String> {
+        get_titled(&self.conn, name).map_err(|e| e.to_string())
+    }
+
+    fn put_fetch(&self, fetch: &Fetch) -> Result<(), String> {
+        insert_fetch(&self.conn, fetch).map_err(|e| e.to_string())
+    }
+
+    fn fetches(&self, since: i64) -> Result<Vec<Fetch>, String> {
+        get_fetches(&self.conn, since).map_err(|e| e.to_string())
+    }
+
+    fn put_session(&self, session: &Session) -> Result<(), String> {
+        insert_session(&self.conn, session).map_err(|e| e.to_string())
+    }
+
+    fn sessions(&self) -> Result<Vec<Session>, String> {
+        get_sessions(&self.conn).map_err(|e| e.to_string())
+    }
+
+    fn clear_rendered(&self) -> Result<u64, String> {
+        clear_rendered(&self.conn).map_err(|e| e.to_string())
+    }
+
+    /// Nothing is left only in the write-ahead log afterwards
+    fn sync(&self) -> Result<(), String> {
+        match checkpoint(&self.conn) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err("The database was busy, so the checkpoint didn't finish".to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+pub fn setup(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    info!("Setting up database");
+
+    // Lets every filesystem worker read while another one writes
+    conn.pragma_update(None, "journal_mode", &"WAL")?;
+
+    conn.execute(
+        r"
+        CREATE TABLE IF NOT EXISTS comics (
+            num INTEGER PRIMARY KEY,
+
+            day INTEGER,
+            month INTEGER,
+            year INTEGER,
+
+            link STRING,
+            news STRING,
+            alt STRING,
+
+            title STRING,
+            safe_title STRING,
+            transcript STRING,
+
+            img_url STRING,
+            extra_parts STRING
+        );",
+        NO_PARAMS,
+    )?;
+
+    // For listing comics by when they were published
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS comics_by_year ON comics (year);",
+        NO_PARAMS,
+    )?;
+
+    // Added later, and empty until comics are fetched again
+    if !has_column(conn, "comics", "extra_parts")? {
+        conn.execute(
+            "ALTER TABLE comics ADD COLUMN extra_parts STRING;",
+            NO_PARAMS,
+        )?;
+    }
+
+    // Each comic's name in by_title, given once it's cached. TEXT, so titles
+    // that look like numbers are still looked up as names.
+    conn.execute(
+        "
+        CREATE TABLE IF NOT EXISTS titles (
+            name TEXT PRIMARY KEY,
+            num INTEGER,
+            title STRING
+        );",
+        NO_PARAMS,
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS titles_by_num ON titles (num);",
+        NO_PARAMS,
+    )?;
+
+    // Added later, so comics cached before it get their names here
+    let unnamed = {
+        let mut statement = conn.prepare(
+            "SELECT num, safe_title FROM comics
+            WHERE num NOT IN (SELECT num FROM titles)
+            ORDER BY num;",
+        )?;
+        let rows = statement.query_map(NO_PARAMS, |row| {
+            Ok((
+                row.get::<_, u32>("num")?,
+                row.get::<_, String>("safe_title")?,
+            ))
+        })?;
+
+        rows.collect::<rusqlite::Result<Vec<_>>>()?
+    };
+
+    for (num, title) in unnamed {
+        insert_title(conn, num, &title)?;
+    }
+
+    conn.execute(
+        r"
+        CREATE TABLE IF NOT EXISTS raw_images (
+            num INTEGER PRIMARY KEY,
+            raw_image BLOB,
+            content_type STRING
+        );",
+        NO_PARAMS,
+    )?;
+
+    // Also added later, and told from the images themselves until then
+    if !has_column(conn, "raw_images", "content_type")? {
+        conn.execute(
+            "ALTER TABLE raw_images ADD COLUMN content_type STRING;",
+            NO_PARAMS,
+        )?;
+    }
+
+    // Rendered images used to be keyed by comic number alone. They can always
+    // be rendered again, so drop an old-style table instead of migrating it.
+    if !has_column(conn, "rendered_images", "render_key")? {
+        conn.execute("DROP TABLE IF EXISTS rendered_images;", NO_PARAMS)?;
+    }
+
+    conn.execute(
+        r"
+        CREATE TABLE IF NOT EXISTS rendered_images (
+            num INTEGER,
+            render_key STRING,
+            rendered_image BLOB,
+            PRIMARY KEY (num, render_key)
+        );",
+        NO_PARAMS,
+    )?;
+
+    conn.execute(
+        r"
+        CREATE TABLE IF NOT EXISTS thumbnails (
+            num INTEGER,
+            width INTEGER,
+            filter STRING,
+            thumbnail BLOB,
+            PRIMARY KEY (num, width, filter)
+        );",
+        NO_PARAMS,
+    )?;
+
+    conn.execute(
+        r"
+        CREATE TABLE IF NOT EXISTS ascii_art (
+            num INTEGER,
+            width INTEGER,
+            art STRING,
+            PRIMARY KEY (num, width)
+        );",
+        NO_PARAMS,
+    )?;
+
+    conn.execute(
+        r"
+        CREATE TABLE IF NOT EXISTS montages (
+            montage_key STRING PRIMARY KEY,
+            montage BLOB
+        );",
+        NO_PARAMS,
+    )?;
+
+    conn.execute(
+        r"
+        CREATE TABLE IF NOT EXISTS wallpapers (
+            num INTEGER,
+            wallpaper_key STRING,
+            wallpaper BLOB,
+            PRIMARY KEY (num, wallpaper_key)
+        );",
+        NO_PARAMS,
+    )?;
+
+    // Comics `xkcdfs mirror` has finished with, and their raw images' CRC-32
+    conn.execute(
+        r"
+        CREATE TABLE IF NOT EXISTS mirrored (
+            num INTEGER PRIMARY KEY,
+            checksum INTEGER
+        );",
+        NO_PARAMS,
+    )?;
+
+    // Whether comics have a double-size image, and its size if they do. A
+    // NULL size means they don't.
+    conn.execute(
+        "
+        CREATE TABLE IF NOT EXISTS hires (
+            num INTEGER PRIMARY KEY,
+            size INTEGER
+        );",
+        NO_PARAMS,
+    )?;
+
+    // The double-size images themselves, which only mirroring fetches
+    conn.execute(
+        "
+        CREATE TABLE IF NOT EXISTS hires_images (
+            num INTEGER PRIMARY KEY,
+            hires_image BLOB
+        );",
+        NO_PARAMS,
+    )?;
+
+    // When comics were last read, in seconds since the epoch, with
+    // --track-atime
+    conn.execute(
+        "
+        CREATE TABLE IF NOT EXISTS accessed (
+            num INTEGER PRIMARY KEY,
+            at INTEGER
+        );",
+        NO_PARAMS,
+    )?;
+
+    // Recent network requests, for working out where bandwidth went
+    conn.execute(
+        "
+        CREATE TABLE IF NOT EXISTS fetch_log (
+            id INTEGER PRIMARY KEY,
+            at INTEGER,
+            class STRING,
+            status STRING,
+            millis INTEGER,
+            bytes INTEGER
+        );",
+        NO_PARAMS,
+    )?;
+
+    // A summary of each mount, for operators who'd rather not read the logs
+    conn.execute(
+        "
+        CREATE TABLE IF NOT EXISTS sessions (
+            id INTEGER PRIMARY KEY,
+            started INTEGER,
+            ended INTEGER,
+            operations INTEGER,
+            cache_hits INTEGER,
+            cache_misses INTEGER,
+            downloaded INTEGER,
+            renders INTEGER,
+            errors INTEGER
+        );",
+        NO_PARAMS,
+    )?;
+
+    Ok(())
+}
+
+/// Copy the write-ahead log into the database, and empty it
+///
+/// Returns false if another connection kept it from finishing.
+pub fn checkpoint(conn: &rusqlite::Connection) -> rusqlite::Result<bool> {
+    // The first column is 1 if the checkpoint couldn't finish
+    conn.query_row("PRAGMA wal_checkpoint(TRUNCATE);", NO_PARAMS, |row| {
+        row.get::<_, i64>(0)
+    })
+    .map(|busy| busy == 0)
+}
+
+fn has_column(conn: &rusqlite::Connection, table: &str, column: &str) -> rusqlite::Result<bool> {
+    let mut statement = conn.prepare(&format!("PRAGMA table_info({});", table))?;
+
+    let names = statement.query_map(NO_PARAMS, |row| row.get::<_, String>("name"))?;
+
+    for name in names {
+        if name? == column {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+fn row_to_comic(row: &rusqlite::Row) -> rusqlite::Result<Comic> {
+    Ok(Comic {
+        num: row.get("num")?,
+
+        day: row.get("day")?,
+        month: row.get("month")?,
+        year: row.get("year")?,
+
+        link: row.get("link")?,
+        news: row.get("news")?,
+        alt: row.get("alt")?,
+
+        title: row.get("title")?,
+        safe_title: row.get("safe_title")?,
+        transcript: row.get("transcript")?,
+
+        img_url: row.get("img_url")?,
+        img_len: None,
+
+        extra_parts: row.get("extra_parts")?,
+    })
+}
+
+pub fn get_comics_count(conn: &rusqlite::Connection) -> usize {
+    conn.query_row("SELECT max(num) FROM comics", NO_PARAMS, |row| row.get(0))
+        .unwrap_or(0i64) // Return 0 on SQL error
+        .try_into()
+        .unwrap_or(0) // Return 0 on over (or under?) flow
+}
+
+pub fn get_latest_comic(conn: &rusqlite::Connection) -> rusqlite::Result<Option<Comic>> {
+    trace!("Fetching latest comic from database");
+
+    conn.query_row(
+        "
+            SELECT 
+                num,
+                day,
+                month,
+                year,
+                link,
+                news,
+                alt,
+                title,
+                safe_title,
+                transcript,
+                img_url,
+                extra_parts
+            FROM comics
+            ORDER BY num DESC
+            LIMIT 1;",
+        NO_PARAMS,
+        row_to_comic,
+    )
+    .optional()
+}
+
+/// Every comic in one query, with the length of its raw image if there is one
+///
+/// Rows are handed over as they're read, instead of all at the end.
+pub fn for_each_comic(
+    conn: &rusqlite::Connection,
+    each: &mut dyn FnMut(Comic),
+) -> rusqlite::Result<()> {
+    trace!("Fetching every comic from database");
+
+    let mut statement = conn.prepare(
+        "
+            SELECT 
+                comics.num AS num,
+                day,
+                month,
+                year,
+                link,
+                news,
+                alt,
+                title,
+                safe_title,
+                transcript,
+                img_url,
+                extra_parts,
+                LENGTH(raw_image) AS img_len
+            FROM comics
+            LEFT JOIN raw_images ON raw_images.num = comics.num
+            ORDER BY comics.num;",
+    )?;
+
+    let mut rows = statement.query(NO_PARAMS)?;
+
+    while let Some(row) = rows.next()? {
+        let img_len: Option<i64> = row.get("img_len")?;
+
+        each(Comic {
+            img_len: img_len.map(|l| l as usize),
+            ..row_to_comic(row)?
+        });
+    }
+
+    Ok(())
+}
+
+pub fn get_comic(conn: &rusqlite::Connection, num: u32) -> rusqlite::Result<Option<Comic>> {
+    trace!("Fetching comic {} from database", num);
+
+    let mut statement = conn.prepare(
+        "
+            SELECT 
+                num,
+                day,
+                month,
+                year,
+                link,
+                news,
+                alt,
+                title,
+                safe_title,
+                transcript,
+                img_url,
+                extra_parts
+            FROM comics
+            WHERE num==?;",
+    )?;
+
+    let mut results = match statement.query_map(&[&num], row_to_comic) {
+        Err(e) => {
+            warn!("Database error while retrieving comic {}: {}", num, e);
+            return Err(e);
+        }
+        Ok(r) => r,
+    };
+
+    results.next().transpose()
+}
+
+pub fn insert_comic(conn: &rusqlite::Connection, comic: &Comic) -> rusqlite::Result<()> {
+    let mut statement = conn.prepare(
+        "
+            INSERT OR REPLACE INTO comics (
+                num,
+                day,
+                month,
+                year,
+                link,
+                news,
+                alt,
+                title,
+                safe_title,
+                transcript,
+                img_url,
+                extra_parts
+            ) VALUES (
+                ?,
+                ?,
+                ?,
+                ?,
+                ?,
+                ?,
+                ?,
+                ?,
+                ?,
+                ?,
+                ?,
+                ?
+            );",
+    )?;
+
+    statement.execute(&[
+        &comic.num as &dyn ToSql,
+        &comic.day as &dyn ToSql,
+        &comic.month as &dyn ToSql,
+        &comic.year as &dyn ToSql,
+        &comic.link as &dyn ToSql,
+        &comic.news as &dyn ToSql,
+        &comic.alt as &dyn ToSql,
+        &comic.title as &dyn ToSql,
+        &comic.safe_title as &dyn ToSql,
+        &comic.transcript as &dyn ToSql,
+        &comic.img_url as &dyn ToSql,
+        &comic.extra_parts as &dyn ToSql,
+    ])?;
+
+    Ok(())
+}
+
+pub fn get_raw_image(conn: &rusqlite::Connection, num: u32) -> rusqlite::Result<Vec<u8>> {
+    let mut statement = conn
+        .prepare(
+            "
+            SELECT raw_image FROM raw_images WHERE num=?
+            ;",
+        )
+        .unwrap();
+
+    debug!("Retrieving comic {} raw image", num);
+
+    let data: rusqlite::Result<Vec<u8>> = statement.query_row([num], |r| r.get("raw_image"));
+
+    match data {
+        Ok(ref d) => debug!(
+            "Retrieved {} bytes from cache for comic {} raw image",
+            d.len(),
+            num
+        ),
+        Err(ref e) => debug!(
+            "Could not retrieve raw image from cache for comic {}: {}",
+            num, e
+        ),
+    }
+
+    data
+}
+
+pub fn insert_raw_image(
+    conn: &rusqlite::Connection,
+    num: u32,
+    data: &[u8],
+) -> rusqlite::Result<()> {
+    let mut statement = conn
+        .prepare("INSERT OR REPLACE INTO raw_images (num, raw_image) VALUES (?, ?)")
+        .unwrap();
+
+    debug!(
+        "Storing {} bytes in cache for comic {} raw image",
+        data.len(),
+        num
+    );
+
+    let result = statement.execute(&[&num as &dyn ToSql, &data as &dyn ToSql]);
+
+    result.map(|_| ())
+}
+
+/// Comic `num`'s raw image, to be read a piece at a time
+pub fn open_raw_image(conn: &rusqlite::Connection, num: u32) -> rusqlite::Result<Option<Blob<'_>>> {
+    // The number is the row ID, but opening a missing row is an error that
+    // can't be told apart from others
+    let cached = conn
+        .query_row("SELECT 1 FROM raw_images WHERE num=?;", [num], |_| Ok(()))
+        .optional()?;
+
+    match cached {
+        Some(()) => conn
+            .blob_open(
+                DatabaseName::Main,
+                "raw_images",
+                "raw_image",
+                num.into(),
+                true,
+            )
+            .map(Some),
+        None => Ok(None),
+    }
+}
+
+pub fn get_image_type(conn: &rusqlite::Connection, num: u32) -> rusqlite::Result<Option<String>> {
+    conn.query_row(
+        "SELECT content_type FROM raw_images WHERE num=?;",
+        [num],
+        |r| r.get::<_, Option<String>>("content_type"),
+    )
+    .optional()
+    .map(|t| t.and_then(|t| t))
+}
+
+/// Only for images already cached, so the type goes when the image is evicted
+pub fn update_image_type(
+    conn: &rusqlite::Connection,
+    num: u32,
+    content_type: &str,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE raw_images SET content_type=? WHERE num=?;",
+        &[&content_type as &dyn ToSql, &num as &dyn ToSql],
+    )
+    .map(|_| ())
+}
+
+pub fn get_rendered_image(
+    conn: &rusqlite::Connection,
+    num: u32,
+    render_key: &str,
+) -> rusqlite::Result<Vec<u8>> {
+    debug!("Retrieving comic {} rendered image", num);
+
+    let mut statement = conn
+        .prepare(
+            "
+            SELECT rendered_image FROM rendered_images WHERE num=? AND render_key=?
+            ;",
+        )
+        .unwrap();
+
+    let data: rusqlite::Result<Vec<u8>> = statement
+        .query_row(&[&num as &dyn ToSql, &render_key as &dyn ToSql], |r| {
+            r.get("rendered_image")
+        });
+
+    match data {
+        Ok(ref d) => debug!(
+            "Retrieved {} bytes from cache for comic {} rendered image",
+            d.len(),
+            num
+        ),
+        Err(ref e) => debug!(
+            "Could not retrieve rendered image from cache for comic {}: {}",
+            num, e
+        ),
+    }
+
+    data
+}
+
+/// A rendered image, to be read a piece at a time
+pub fn open_rendered_image<'a>(
+    conn: &'a rusqlite::Connection,
+    num: u32,
+    render_key: &str,
+) -> rusqlite::Result<Option<Blob<'a>>> {
+    let row: Option<i64> = conn
+        .query_row(
+            "SELECT rowid FROM rendered_images WHERE num=? AND render_key=?;",
+            &[&num as &dyn ToSql, &render_key as &dyn ToSql],
+            |r| r.get(0),
+        )
+        .optional()?;
+
+    match row {
+        Some(row) => conn
+            .blob_open(
+                DatabaseName::Main,
+                "rendered_images",
+                "rendered_image",
+                row,
+                true,
+            )
+            .map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Up to `len` bytes of a blob from `offset`, leaving the rest unread
+pub fn read_range(blob: &mut Blob, offset: u64, len: usize) -> std::io::Result<Vec<u8>> {
+    let size = blob.size() as u64;
+    let start = offset.min(size);
+    let end = start.saturating_add(len as u64).min(size);
+    let mut data = vec![0; (end - start) as usize];
+
+    blob.seek(SeekFrom::Start(start))?;
+    blob.read_exact(&mut data)?;
+
+    debug!("Read {} bytes from a cached image at {}", data.len(), start);
+
+    Ok(data)
+}
+
+pub fn insert_rendered_image(
+    conn: &rusqlite::Connection,
+    num: u32,
+    render_key: &str,
+    data: &[u8],
+) -> rusqlite::Result<()> {
+    let mut statement = conn
+        .prepare(
+            "INSERT OR REPLACE INTO rendered_images (num, render_key, rendered_image) VALUES (?, ?, ?)",
+        )
+        .unwrap();
+
+    debug!(
+        "Storing {} bytes in cache for comic {} rendered image",
+        data.len(),
+        num
+    );
+
+    let result = statement.execute(&[
+        &num as &dyn ToSql,
+        &render_key as &dyn ToSql,
+        &data as &dyn ToSql,
+    ]);
+
+    result.map(|_| ())
+}
+
+pub fn get_thumbnail(
+    conn: &rusqlite::Connection,
+    num: u32,
+    spec: ThumbnailSpec,
+) -> rusqlite::Result<Vec<u8>> {
+    debug!("Retrieving comic {} thumbnail at width {}", num, spec.width);
+
+    let mut statement = conn
+        .prepare(
+            "
+            SELECT thumbnail FROM thumbnails WHERE num=? AND width=? AND filter=?
+            ;",
+        )
+        .unwrap();
+
+    let data: rusqlite::Result<Vec<u8>> = statement.query_row(
+        &[
+            &num as &dyn ToSql,
+            &spec.width as &dyn ToSql,
+            &spec.filter.name() as &dyn ToSql,
+        ],
+        |r| r.get("thumbnail"),
+    );
+
+    match data {
+        Ok(ref d) => debug!(
+            "Retrieved {} bytes from cache for comic {} thumbnail",
+            d.len(),
+            num
+        ),
+        Err(ref e) => debug!(
+            "Could not retrieve thumbnail from cache for comic {}: {}",
+            num, e
+        ),
+    }
+
+    data
+}
+
+pub fn insert_thumbnail(
+    conn: &rusqlite::Connection,
+    num: u32,
+    spec: ThumbnailSpec,
+    data: &[u8],
+) -> rusqlite::Result<()> {
+    let mut statement = conn
+        .prepare(
+            "INSERT OR REPLACE INTO thumbnails (num, width, filter, thumbnail) VALUES (?, ?, ?, ?)",
+        )
+        .unwrap();
+
+    debug!(
+        "Storing {} bytes in cache for comic {} thumbnail at width {}",
+        data.len(),
+        num,
+        spec.width
+    );
+
+    let result = statement.execute(&[
+        &num as &dyn ToSql,
+        &spec.width as &dyn ToSql,
+        &spec.filter.name() as &dyn ToSql,
+        &data as &dyn ToSql,
+    ]);
+
+    result.map(|_| ())
+}
+
+pub fn get_montage(conn: &rusqlite::Connection, montage_key: &str) -> rusqlite::Result<Vec<u8>> {
+    debug!("Retrieving montage {}", montage_key);
+
+    let mut statement = conn
+        .prepare(
+            "
+            SELECT montage FROM montages WHERE montage_key=?
+            ;",
+        )
+        .unwrap();
+
+    let data: rusqlite::Result<Vec<u8>> =
+        statement.query_row(&[&montage_key as &dyn ToSql], |r| r.get("montage"));
+
+    match data {
+        Ok(ref d) => debug!("Retrieved {} bytes from cache for montage", d.len()),
+        Err(ref e) => debug!("Could not retrieve montage from cache: {}", e),
+    }
+
+    data
+}
+
+/// Most montages kept, after which the oldest go
+///
+/// Any selection of comics makes a new one, and they can be big.
+const MONTAGE_ROWS: i64 = 16;
+
+pub fn insert_montage(
+    conn: &rusqlite::Connection,
+    montage_key: &str,
+    data: &[u8],
+) -> rusqlite::Result<()> {
+    let mut statement = conn
+        .prepare("INSERT OR REPLACE INTO montages (montage_key, montage) VALUES (?, ?)")
+        .unwrap();
+
+    debug!(
+        "Storing {} bytes in cache for montage {}",
+        data.len(),
+        montage_key
+    );
+
+    statement.execute(&[&montage_key as &dyn ToSql, &data as &dyn ToSql])?;
+
+    // Replacing a montage moves it to the end, so the oldest go first
+    conn.execute(
+        "DELETE FROM montages WHERE rowid <= (SELECT MAX(rowid) FROM montages) - ?;",
+        [MONTAGE_ROWS],
+    )
+    .map(|_| ())
+}
+
+pub fn get_wallpaper(
+    conn: &rusqlite::Connection,
+    num: u32,
+    wallpaper_key: &str,
+) -> rusqlite::Result<Vec<u8>> {
+    debug!("Retrieving comic {} wallpaper {}", num, wallpaper_key);
+
+    let mut statement = conn
+        .prepare(
+            "
+            SELECT wallpaper FROM wallpapers WHERE num=? AND wallpaper_key=?
+            ;",
+        )
+        .unwrap();
+
+    let data: rusqlite::Result<Vec<u8>> = statement
+        .query_row(&[&num as &dyn ToSql, &wallpaper_key as &dyn ToSql], |r| {
+            r.get("wallpaper")
+        });
+
+    match data {
+        Ok(ref d) => debug!("Retrieved {} bytes from cache for wallpaper", d.len()),
+        Err(ref e) => debug!("Could not retrieve wallpaper from cache: {}", e),
+    }
+
+    data
+}
+
+pub fn insert_wallpaper(
+    conn: &rusqlite::Connection,
+    num: u32,
+    wallpaper_key: &str,
+    data: &[u8],
+) -> rusqlite::Result<()> {
+    let mut statement = conn
+        .prepare(
+            "INSERT OR REPLACE INTO wallpapers (num, wallpaper_key, wallpaper) VALUES (?, ?, ?)",
+        )
+        .unwrap();
+
+    debug!(
+        "Storing {} bytes in cache for comic {} wallpaper {}",
+        data.len(),
+        num,
+        wallpaper_key
+    );
+
+    let result = statement.execute(&[
+        &num as &dyn ToSql,
+        &wallpaper_key as &dyn ToSql,
+        &data as &dyn ToSql,
+    ]);
+
+    result.map(|_| ())
+}
+
+pub fn get_ascii_art(
+    conn: &rusqlite::Connection,
+    num: u32,
+    width: u32,
+) -> rusqlite::Result<String> {
+    debug!("Retrieving comic {} ASCII art at width {}", num, width);
+
+    let mut statement = conn
+        .prepare(
+            "
+            SELECT art FROM ascii_art WHERE num=? AND width=?
+            ;",
+        )
+        .unwrap();
+
+    let data: rusqlite::Result<String> = statement
+        .query_row(&[&num as &dyn ToSql, &width as &dyn ToSql], |r| {
+            r.get("art")
+        });
+
+    match data {
+        Ok(ref d) => debug!(
+            "Retrieved {} bytes from cache for comic {} ASCII art",
+            d.len(),
+            num
+        ),
+        Err(ref e) => debug!(
+            "Could not retrieve ASCII art from cache for comic {}: {}",
+            num, e
+        ),
+    }
+
+    data
+}
+
+pub fn insert_ascii_art(
+    conn: &rusqlite::Connection,
+    num: u32,
+    width: u32,
+    art: &str,
+) -> rusqlite::Result<()> {
+    let mut statement = conn
+        .prepare("INSERT OR REPLACE INTO ascii_art (num, width, art) VALUES (?, ?, ?)")
+        .unwrap();
+
+    debug!(
+        "Storing {} bytes in cache for comic {} ASCII art at width {}",
+        art.len(),
+        num,
+        width
+    );
+
+    let result = statement.execute(&[&num as &dyn ToSql, &width as &dyn ToSql, &art as &dyn ToSql]);
+
+    result.map(|_| ())
+}
+
+pub fn get_mirrored(conn: &rusqlite::Connection, num: u32) -> rusqlite::Result<Option<u32>> {
+    conn.query_row("SELECT checksum FROM mirrored WHERE num=?;", [num], |r| {
+        r.get::<_, i64>("checksum")
+    })
+    .optional()
+    .map(|checksum| checksum.map(|c| c as u32))
+}
+
+pub fn insert_mirrored(
+    conn: &rusqlite::Connection,
+    num: u32,
+    checksum: u32,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO mirrored (num, checksum) VALUES (?, ?);",
+        [i64::from(num), i64::from(checksum)],
+    )
+    .map(|_| ())
+}
+
+pub fn delete_mirrored(conn: &rusqlite::Connection, num: u32) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM mirrored WHERE num=?;", [num])
+        .map(|_| ())
+}
+
+pub fn get_hires(conn: &rusqlite::Connection, num: u32) -> rusqlite::Result<Option<HiRes>> {
+    conn.query_row("SELECT size FROM hires WHERE num=?;", [num], |r| {
+        r.get::<_, Option<i64>>("size")
+    })
+    .optional()
+    .map(|size| size.map(|s| s.map_or(HiRes::Missing, |s| HiRes::Available(s as u64))))
+}
+
+pub fn insert_hires(conn: &rusqlite::Connection, num: u32, hires: HiRes) -> rusqlite::Result<()> {
+    let size = match hires {
+        HiRes::Missing => None,
+        HiRes::Available(size) => Some(size as i64),
+    };
+
+    conn.execute(
+        "INSERT OR REPLACE INTO hires (num, size) VALUES (?, ?);",
+        &[&num as &dyn ToSql, &size as &dyn ToSql],
+    )
+    .map(|_| ())
+}
+
+pub fn get_hires_image(conn: &rusqlite::Connection, num: u32) -> rusqlite::Result<Option<Vec<u8>>> {
+    conn.query_row(
+        "SELECT hires_image FROM hires_images WHERE num=?;",
+        [num],
+        |r| r.get("hires_image"),
+    )
+    .optional()
+}
+
+pub fn insert_hires_image(
+    conn: &rusqlite::Connection,
+    num: u32,
+    image: &[u8],
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO hires_images (num, hires_image) VALUES (?, ?);",
+        &[&num as &dyn ToSql, &image as &dyn ToSql],
+    )
+    .map(|_| ())
+}
+
+pub fn get_accessed(conn: &rusqlite::Connection, num: u32) -> rusqlite::Result<Option<i64>> {
+    conn.query_row("SELECT at FROM accessed WHERE num=?;", [num], |r| {
+        r.get::<_, i64>("at")
+    })
+    .optional()
+}
+
+pub fn insert_accessed(conn: &rusqlite::Connection, num: u32, at: i64) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO accessed (num, at) VALUES (?, ?);",
+        [i64::from(num), at],
+    )
+    .map(|_| ())
+}
+
+/// Comics read in the same second are listed highest number first
+pub fn get_recently_accessed(
+    conn: &rusqlite::Connection,
+    limit: u32,
+) -> rusqlite::Result<Vec<u32>> {
+    let mut statement =
+        conn.prepare("SELECT num FROM accessed ORDER BY at DESC, num DESC LIMIT ?;")?;
+
+    let rows = statement.query_map([limit], |row| row.get::<_, u32>("num"))?;
+
+    rows.collect()
+}
+
+pub fn get_years(conn: &rusqlite::Connection) -> rusqlite::Result<Vec<i32>> {
+    let mut statement = conn.prepare("SELECT DISTINCT year FROM comics ORDER BY year;")?;
+
+    let rows = statement.query_map(NO_PARAMS, |row| row.get::<_, i32>("year"))?;
+
+    rows.collect()
+}
+
+pub fn get_comics_in_year(conn: &rusqlite::Connection, year: i32) -> rusqlite::Result<Vec<u32>> {
+    let mut statement = conn.prepare("SELECT num FROM comics WHERE year=? ORDER BY num;")?;
+
+    let rows = statement.query_map([year], |row| row.get::<_, u32>("num"))?;
+
+    rows.collect()
+}
+
+// The year's index narrows it down to a year's worth of comics first
+pub fn get_comics_by_date(
+    conn: &rusqlite::Connection,
+    year: i32,
+    month: i32,
+    day: i32,
+) -> rusqlite::Result<Vec<u32>> {
+    let mut statement =
+        conn.prepare("SELECT num FROM comics WHERE year=? AND month=? AND day=? ORDER BY num;")?;
+
+    let rows = statement.query_map([year, month, day], |row| row.get::<_, u32>("num"))?;
+
+    rows.collect()
+}
+
+pub fn get_nums(conn: &rusqlite::Connection) -> rusqlite::Result<Vec<u32>> {
+    let mut statement = conn.prepare("SELECT num FROM comics ORDER BY num;")?;
+
+    let rows = statement.query_map(NO_PARAMS, |row| row.get::<_, u32>("num"))?;
+
+    rows.collect()
+}
+
+pub fn get_titles(conn: &rusqlite::Connection) -> rusqlite::Result<Vec<(u32, String)>> {
+    let mut statement = conn.prepare("SELECT num, name FROM titles ORDER BY num;")?;
+
+    let rows = statement.query_map(NO_PARAMS, |row| {
+        Ok((row.get::<_, u32>("num")?, row.get("name")?))
+    })?;
+
+    rows.collect()
+}
+
+pub fn get_titled(conn: &rusqlite::Connection, name: &str) -> rusqlite::Result<Option<u32>> {
+    conn.query_row("SELECT num FROM titles WHERE name = ?;", &[name], |row| {
+        row.get(0)
+    })
+    .optional()
+}
+
+/// Give comic `num` its name for `title`, unless it already has it
+///
+/// A comic only loses its name if its title changes. Otherwise it keeps it
+/// however many comics with the same title are cached after it.
+pub fn insert_title(conn: &rusqlite::Connection, num: u32, title: &str) -> rusqlite::Result<()> {
+    let named: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM titles WHERE num = ? AND title = ?;",
+        &[&num as &dyn ToSql, &title as &dyn ToSql],
+        |row| row.get(0),
+    )?;
+
+    if named {
+        return Ok(());
+    }
+
+    conn.execute("DELETE FROM titles WHERE num = ?;", [num])?;
+
+    let name = titles::name_for(num, title, |name| {
+        get_titled(conn, name).map(|n| n.is_some())
+    })?;
+
+    conn.execute(
+        "INSERT INTO titles (name, num, title) VALUES (?, ?, ?);",
+        &[
+            &name as &dyn ToSql,
+            &num as &dyn ToSql,
+            &title as &dyn ToSql,
+        ],
+    )?;
+
+    Ok(())
+}
+
+/// Run `write` in a transaction that holds the write lock from the start, so
+/// what it reads can't change before it writes
+fn immediately<T>(
+    conn: &rusqlite::Connection,
+    write: impl FnOnce() -> rusqlite::Result<T>,
+) -> rusqlite::Result<T> {
+    conn.execute_batch("BEGIN IMMEDIATE;")?;
+
+    match write() {
+        Ok(result) => {
+            conn.execute_batch("COMMIT;")?;
+            Ok(result)
+        }
+        Err(e) => {
+            // Whatever went wrong, nothing's left half-written
+            let _ = conn.execute_batch("ROLLBACK;");
+            Err(e)
+        }
+    }
+}
+
+/// Most network requests kept in the fetch log, after which the oldest go
+const FETCH_LOG_ROWS: i64 = 10_000;
+
+pub fn insert_fetch(conn: &rusqlite::Connection, fetch: &Fetch) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO fetch_log (at, class, status, millis, bytes) VALUES (?, ?, ?, ?, ?);",
+        &[
+            &fetch.at as &dyn ToSql,
+            &fetch.class as &dyn ToSql,
+            &fetch.status as &dyn ToSql,
+            &(fetch.millis as i64) as &dyn ToSql,
+            &fetch.bytes.map(|b| b as i64) as &dyn ToSql,
+        ],
+    )?;
+
+    conn.execute(
+        "DELETE FROM fetch_log WHERE id <= (SELECT MAX(id) FROM fetch_log) - ?;",
+        [FETCH_LOG_ROWS],
+    )
+    .map(|_| ())
+}
+
+pub fn get_fetches(conn: &rusqlite::Connection, since: i64) -> rusqlite::Result<Vec<Fetch>> {
+    let mut statement = conn.prepare(
+        "SELECT at, class, status, millis, bytes FROM fetch_log WHERE at >= ? ORDER BY id;",
+    )?;
+
+    let rows = statement.query_map([since], |row| {
+        Ok(Fetch {
+            at: row.get("at")?,
+            class: row.get("class")?,
+            status: row.get("status")?,
+            millis: row.get::<_, i64>("millis")? as u64,
+            bytes: row.get::<_, Option<i64>>("bytes")?.map(|b| b as u64),
+        })
+    })?;
+
+    rows.collect()
+}
+
+pub fn insert_session(conn: &rusqlite::Connection, session: &Session) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO sessions
+            (started, ended, operations, cache_hits, cache_misses, downloaded, renders, errors)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?);",
+        [
+            session.started,
+            session.ended,
+            session.operations as i64,
+            session.cache_hits as i64,
+            session.cache_misses as i64,
+            session.downloaded as i64,
+            session.renders as i64,
+            session.errors as i64,
+        ],
+    )
+    .map(|_| ())
+}
+
+pub fn get_sessions(conn: &rusqlite::Connection) -> rusqlite::Result<Vec<Session>> {
+    let mut statement = conn.prepare(
+        "SELECT started, ended, operations, cache_hits, cache_misses, downloaded, renders, errors
+            FROM sessions ORDER BY id;",
+    )?;
+
+    let rows = statement.query_map(NO_PARAMS, |row| {
+        let count = |column: &str| row.get::<_, i64>(column).map(|c| c as u64);
+
+        Ok(Session {
+            started: row.get("started")?,
+            ended: row.get("ended")?,
+            operations: count("operations")?,
+            cache_hits: count("cache_hits")?,
+            cache_misses: count("cache_misses")?,
+            downloaded: count("downloaded")?,
+            renders: count("renders")?,
+            errors: count("errors")?,
+        })
+    })?;
+
+    rows.collect()
+}
+
+/// Tables holding cached data, their data columns, and whether they're per
+/// comic, in the order they're evicted from
+///
+/// Everything derived from raw images goes first, since it can be recreated
+/// without the network. Double-size images are only there for mirrors, so
+/// they go before the raw images comics are read from. Comic metadata is
+/// small and is never evicted.
+const EVICTABLE: &[(&str, &str, bool)] = &[
+    ("montages", "montage", false),
+    ("wallpapers", "wallpaper", true),
+    ("rendered_images", "rendered_image", true),
+    ("thumbnails", "thumbnail", true),
+    ("ascii_art", "art", true),
+    ("hires_images", "hires_image", true),
+    ("raw_images", "raw_image", true),
+];
+
+/// Total size of the evictable data in the cache, in bytes
+pub fn cache_size(conn: &rusqlite::Connection) -> rusqlite::Result<u64> {
+    let mut total = 0;
+
+    for (table, column, _) in EVICTABLE {
+        let size: i64 = conn.query_row(
+            &format!("SELECT IFNULL(SUM(LENGTH({})), 0) FROM {}", column, table),
+            NO_PARAMS,
+            |row| row.get(0),
+        )?;
+
+        total += size as u64;
+    }
+
+    Ok(total)
+}
+
+/// Delete cached data until the cache holds at most `max_size` bytes
+///
+/// Within each table, comics read longest ago go first, if reads are being
+/// tracked, and then the oldest entries. Returns how many bytes were freed.
+pub fn evict(conn: &rusqlite::Connection, max_size: u64) -> rusqlite::Result<u64> {
+    let size = cache_size(conn)?;
+    let mut freed = 0;
+
+    // Other connections can shrink the cache while this one works through it,
+    // so more can be freed than there was to begin with
+    let fits = |freed| size.saturating_sub(freed) <= max_size;
+
+    for (table, column, per_comic) in EVICTABLE {
+        if fits(freed) {
+            break;
+        }
+
+        // Comics that have never been read count as read at the epoch
+        let access = if *per_comic {
+            "LEFT JOIN accessed ON accessed.num = t.num ORDER BY IFNULL(accessed.at, 0), t.rowid"
+        } else {
+            "ORDER BY t.rowid"
+        };
+
+        let mut statement = conn
+            .prepare(&format!(
+                "SELECT t.rowid, IFNULL(LENGTH(t.{}), 0) FROM {} AS t {}",
+                column, table, access
+            ))
+            .unwrap();
+
+        let rows = statement.query_map(NO_PARAMS, |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?))
+        })?;
+
+        let mut rowids = Vec::new();
+
+        for row in rows {
+            let (rowid, length) = row?;
+
+            rowids.push(rowid.to_string());
+            freed += length as u64;
+
+            if fits(freed) {
+                break;
+            }
+        }
+
+        if !rowids.is_empty() {
+            let deleted = conn.execute(
+                &format!(
+                    "DELETE FROM {} WHERE rowid IN ({})",
+                    table,
+                    rowids.join(",")
+                ),
+                NO_PARAMS,
+            )?;
+
+            debug!("Evicted {} rows from {}", deleted, table);
+        }
+    }
+
+    if freed > 0 {
+        info!(
+            "Evicted {} bytes from the cache to stay under {} bytes",
+            freed, max_size
+        );
+    }
+
+    Ok(freed)
+}
+
+/// Tables holding rendered images, or images made from them, and their data
+/// columns
+const RENDERED: &[(&str, &str)] = &[
+    ("rendered_images", "rendered_image"),
+    ("montages", "montage"),
+    ("wallpapers", "wallpaper"),
+];
+
+/// Delete every rendered image and everything made from one, returning how
+/// many bytes were freed
+pub fn clear_rendered(conn: &rusqlite::Connection) -> rusqlite::Result<u64> {
+    let mut freed = 0;
+
+    for (table, column) in RENDERED {
+        let size: i64 = conn.query_row(
+            &format!("SELECT IFNULL(SUM(LENGTH({})), 0) FROM {}", column, table),
+            NO_PARAMS,
+            |row| row.get(0),
+        )?;
+        let deleted = conn.execute(&format!("DELETE FROM {}", table), NO_PARAMS)?;
+
+        debug!("Cleared {} rows from {}", deleted, table);
+        freed += size as u64;
+    }
+
+    info!("Cleared {} bytes of rendered images from the cache", freed);
+
+    Ok(freed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn evicts_derived_data_first() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        setup(&conn).unwrap();
+
+        insert_raw_image(&conn, 1, &[0; 100]).unwrap();
+        insert_raw_image(&conn, 2, &[0; 100]).unwrap();
+        insert_rendered_image(&conn, 1, "key", &[0; 50]).unwrap();
+        insert_rendered_image(&conn, 2, "key", &[0; 50]).unwrap();
+
+        assert_eq!(cache_size(&conn).unwrap(), 300);
+        assert_eq!(evict(&conn, 300).unwrap(), 0);
+
+        // Both renders have to go before any raw image does
+        assert_eq!(evict(&conn, 220).unwrap(), 100);
+        assert!(get_rendered_image(&conn, 2, "key").is_err());
+        assert!(get_raw_image(&conn, 1).is_ok());
+
+        // Then the oldest raw image
+        assert_eq!(evict(&conn, 150).unwrap(), 100);
+        assert!(get_raw_image(&conn, 1).is_err());
+        assert!(get_raw_image(&conn, 2).is_ok());
+    }
+
+    #[test]
+    fn lists_comics_with_image_sizes() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        setup(&conn).unwrap();
+
+        for num in &[2, 1, 3] {
+            let comic = Comic {
+                num: *num,
+                day: 1,
+                month: 1,
+                year: 2006,
+                link: None,
+                news: None,
+                alt: String::new(),
+                title: format!("Comic {}", num),
+                safe_title: format!("Comic {}", num),
+                transcript: None,
+                img_url: String::new(),
+                img_len: None,
+                extra_parts: if *num == 3 {
+                    Some(r#"{"post":"Special"}"#.to_string())
+                } else {
+                    None
+                },
+            };
+
+            insert_comic(&conn, &comic).unwrap();
+        }
+
+        insert_raw_image(&conn, 2, &[0; 100]).unwrap();
+
+        let mut comics = Vec::new();
+        for_each_comic(&conn, &mut |c| comics.push((c.num, c.img_len))).unwrap();
+
+        assert_eq!(comics, vec![(1, None), (2, Some(100)), (3, None)]);
+        assert_eq!(get_comic(&conn, 1).unwrap().unwrap().extra_parts, None);
+        assert_eq!(
+            get_comic(&conn, 3).unwrap().unwrap().extra_parts,
+            Some(r#"{"post":"Special"}"#.to_string())
+        );
+    }
+
+    #[test]
+    fn remembers_mirrored_comics() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        setup(&conn).unwrap();
+
+        assert_eq!(get_mirrored(&conn, 614).unwrap(), None);
+
+        // Checksums use all 32 bits
+        insert_mirrored(&conn, 614, 0xDEAD_BEEF).unwrap();
+        assert_eq!(get_mirrored(&conn, 614).unwrap(), Some(0xDEAD_BEEF));
+
+        delete_mirrored(&conn, 614).unwrap();
+        assert_eq!(get_mirrored(&conn, 614).unwrap(), None);
+    }
+
+    #[test]
+    fn keeps_recent_fetches() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        setup(&conn).unwrap();
+
+        let fetch = |at: i64| Fetch {
+            at,
+            class: "image".to_string(),
+            status: "ok".to_string(),
+            millis: 120,
+            bytes: Some(31_337),
+        };
+
+        for at in 0..FETCH_LOG_ROWS + 10 {
+            insert_fetch(&conn, &fetch(at)).unwrap();
+        }
+
+        let fetches = get_fetches(&conn, 0).unwrap();
+        assert_eq!(fetches.len() as i64, FETCH_LOG_ROWS);
+        assert_eq!(fetches[0], fetch(10));
+
+        assert_eq!(
+            get_fetches(&conn, FETCH_LOG_ROWS + 9).unwrap(),
+            vec![fetch(FETCH_LOG_ROWS + 9)]
+        );
+    }
+
+    #[test]
+    fn reads_part_of_an_image() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        setup(&conn).unwrap();
+
+        let image: Vec<u8> = (0..=255).collect();
+        insert_raw_image(&conn, 614, &image).unwrap();
+        insert_rendered_image(&conn, 614, "key", &image[..100]).unwrap();
+
+        let mut raw = open_raw_image(&conn, 614).unwrap().unwrap();
+        assert_eq!(read_range(&mut raw, 10, 5).unwrap(), &image[10..15]);
+        assert_eq!(read_range(&mut raw, 250, 100).unwrap(), &image[250..]);
+        assert_eq!(read_range(&mut raw, 300, 100).unwrap(), &[] as &[u8]);
+
+        let mut rendered = open_rendered_image(&conn, 614, "key").unwrap().unwrap();
+        assert_eq!(read_range(&mut rendered, 90, 20).unwrap(), &image[90..100]);
+
+        assert!(open_raw_image(&conn, 404).unwrap().is_none());
+        assert!(open_rendered_image(&conn, 614, "other").unwrap().is_none());
+    }
+
+    #[test]
+    fn keeps_sessions() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        setup(&conn).unwrap();
+
+        assert_eq!(get_sessions(&conn).unwrap(), vec![]);
+
+        let session = Session {
+            started: 1_248_440_400,
+            ended: 1_248_444_000,
+            operations: 1200,
+            cache_hits: 900,
+            cache_misses: 30,
+            downloaded: 31_337,
+            renders: 12,
+            errors: 1,
+        };
+
+        insert_session(&conn, &session).unwrap();
+        insert_session(&conn, &Session::default()).unwrap();
+
+        assert_eq!(
+            get_sessions(&conn).unwrap(),
+            vec![session, Session::default()]
+        );
+    }
+
+    #[test]
+    fn remembers_hires_images() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        setup(&conn).unwrap();
+
+        assert_eq!(get_hires(&conn, 1).unwrap(), None);
+
+        insert_hires(&conn, 1, HiRes::Missing).unwrap();
+        insert_hires(&conn, 2, HiRes::Available(31_337)).unwrap();
+        assert_eq!(get_hires(&conn, 1).unwrap(), Some(HiRes::Missing));
+        assert_eq!(get_hires(&conn, 2).unwrap(), Some(HiRes::Available(31_337)));
+
+        assert_eq!(get_hires_image(&conn, 2).unwrap(), None);
+        insert_hires_image(&conn, 2, b"2x").unwrap();
+        assert_eq!(get_hires_image(&conn, 2).unwrap(), Some(b"2x".to_vec()));
+        assert_eq!(cache_size(&conn).unwrap(), 2);
+    }
+
+    #[test]
+    fn evicts_least_recently_read_first() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        setup(&conn).unwrap();
+
+        for num in 1..=3 {
+            insert_raw_image(&conn, num, &[0; 100]).unwrap();
+        }
+
+        // Comic 2 has never been read, and comic 1 was read after comic 3
+        insert_accessed(&conn, 1, 2000).unwrap();
+        insert_accessed(&conn, 3, 1000).unwrap();
+        assert_eq!(get_accessed(&conn, 1).unwrap(), Some(2000));
+        assert_eq!(get_accessed(&conn, 2).unwrap(), None);
+
+        assert_eq!(evict(&conn, 200).unwrap(), 100);
+        assert!(get_raw_image(&conn, 2).is_err());
+
+        assert_eq!(evict(&conn, 100).unwrap(), 100);
+        assert!(get_raw_image(&conn, 3).is_err());
+        assert!(get_raw_image(&conn, 1).is_ok());
+    }
+
+    #[test]
+    fn clears_only_rendered_images() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        setup(&conn).unwrap();
+
+        insert_raw_image(&conn, 1, &[0; 100]).unwrap();
+        insert_rendered_image(&conn, 1, "classic", &[0; 200]).unwrap();
+        insert_montage(&conn, "classic;comics=1", &[0; 300]).unwrap();
+
+        assert_eq!(clear_rendered(&conn).unwrap(), 500);
+        assert!(get_rendered_image(&conn, 1, "classic").is_err());
+        assert!(get_montage(&conn, "classic;comics=1").is_err());
+        assert!(get_raw_image(&conn, 1).is_ok());
+
+        assert_eq!(clear_rendered(&conn).unwrap(), 0);
+    }
+
+    #[test]
+    fn keeps_the_newest_montages() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        setup(&conn).unwrap();
+
+        for num in 1..=MONTAGE_ROWS + 4 {
+            insert_montage(&conn, &format!("classic;comics={}", num), &[0; 10]).unwrap();
+        }
+
+        // Replacing the oldest one keeps it
+        insert_montage(&conn, "classic;comics=5", &[1; 10]).unwrap();
+
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM montages", NO_PARAMS, |r| r.get(0))
+            .unwrap();
+        assert!(count <= MONTAGE_ROWS);
+        assert!(get_montage(&conn, "classic;comics=4").is_err());
+        assert_eq!(get_montage(&conn, "classic;comics=5").unwrap(), vec![1; 10]);
+        assert!(get_montage(&conn, &format!("classic;comics={}", MONTAGE_ROWS + 4)).is_ok());
+    }
+
+    #[test]
+    fn lists_recently_read_comics() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        setup(&conn).unwrap();
+
+        assert_eq!(get_recently_accessed(&conn, 10).unwrap(), Vec::<u32>::new());
+
+        insert_accessed(&conn, 614, 1000).unwrap();
+        insert_accessed(&conn, 327, 2000).unwrap();
+        insert_accessed(&conn, 1000, 2000).unwrap();
+        insert_accessed(&conn, 614, 3000).unwrap();
+
+        assert_eq!(
+            get_recently_accessed(&conn, 10).unwrap(),
+            vec![614, 1000, 327]
+        );
+        assert_eq!(get_recently_accessed(&conn, 2).unwrap(), vec![614, 1000]);
+    }
+
+    #[test]
+    fn lists_comics_by_year() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        setup(&conn).unwrap();
+
+        for (num, year) in &[(1500, 2015), (614, 2009), (1, 2006), (613, 2009)] {
+            let comic = Comic {
+                num: *num,
+                day: 1,
+                month: 1,
+                year: *year,
+                link: None,
+                news: None,
+                alt: String::new(),
+                title: format!("Comic {}", num),
+                safe_title: format!("Comic {}", num),
+                transcript: None,
+                img_url: String::new(),
+                img_len: None,
+                extra_parts: None,
+            };
+
+            insert_comic(&conn, &comic).unwrap();
+        }
+
+        assert_eq!(get_years(&conn).unwrap(), vec![2006, 2009, 2015]);
+        assert_eq!(get_comics_in_year(&conn, 2009).unwrap(), vec![613, 614]);
+        assert_eq!(get_comics_in_year(&conn, 2010).unwrap(), Vec::<u32>::new());
+        assert_eq!(
+            get_comics_by_date(&conn, 2009, 1, 1).unwrap(),
+            vec![613, 614]
+        );
+        assert_eq!(
+            get_comics_by_date(&conn, 2009, 1, 2).unwrap(),
+            Vec::<u32>::new()
+        );
+        assert_eq!(get_nums(&conn).unwrap(), vec![1, 613, 614, 1500]);
+
+        // Comics cached before they were named get their names in setup
+        assert_eq!(get_titles(&conn).unwrap(), Vec::new());
+        setup(&conn).unwrap();
+        assert_eq!(
+            get_titles(&conn).unwrap(),
+            vec![
+                (1, "Comic 1".to_string()),
+                (613, "Comic 613".to_string()),
+                (614, "Comic 614".to_string()),
+                (1500, "Comic 1500".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn titles_are_named_once() {
+        let store = SqliteStore::open(OsStr::new(MEMORY)).unwrap();
+        let comic = |num, title: &str| Comic {
+            num,
+            day: 1,
+            month: 1,
+            year: 2006,
+            link: None,
+            news: None,
+            alt: String::new(),
+            title: title.to_string(),
+            safe_title: title.to_string(),
+            transcript: None,
+            img_url: String::new(),
+            img_len: None,
+            extra_parts: None,
+        };
+        let named = |names: &[(u32, &str)]| {
+            Ok(names
+                .iter()
+                .map(|&(num, name)| (num, name.to_string()))
+                .collect::<Vec<_>>())
+        };
+
+        store.put_comic(&comic(5, "Same")).unwrap();
+        store.put_comic(&comic(3, "Same")).unwrap();
+        store.put_comic(&comic(4, "AC/DC")).unwrap();
+        store.put_comic(&comic(1, "1234")).unwrap();
+
+        // Fetching a comic again doesn't rename it, or the one it clashed with
+        store.put_comic(&comic(5, "Same")).unwrap();
+        store.put_comic(&comic(3, "Same")).unwrap();
+
+        assert_eq!(
+            store.titles(),
+            named(&[(1, "1234"), (3, "Same (3)"), (4, "AC_DC"), (5, "Same")])
+        );
+        assert_eq!(store.titled("Same"), Ok(Some(5)));
+        assert_eq!(store.titled("Same (3)"), Ok(Some(3)));
+        assert_eq!(store.titled("AC_DC"), Ok(Some(4)));
+        assert_eq!(store.titled("AC/DC"), Ok(None));
+        assert_eq!(store.titled("1234"), Ok(Some(1)));
+
+        // A comic whose title changes gives its old name up
+        store.put_comic(&comic(5, "Different")).unwrap();
+        assert_eq!(store.titled("Same"), Ok(None));
+        assert_eq!(store.titled("Different"), Ok(Some(5)));
+        assert_eq!(store.titled("Same (3)"), Ok(Some(3)));
+    }
+
+    #[test]
+    fn in_memory_clones_share_a_database() {
+        let store = SqliteStore::open(OsStr::new(MEMORY)).unwrap();
+        let clone = store.try_clone().unwrap();
+        let other = SqliteStore::open(OsStr::new(MEMORY)).unwrap();
+
+        clone.put_raw_image(614, b"woodpecker").unwrap();
+
+        assert_eq!(store.raw_image(614), Ok(Some(b"woodpecker".to_vec())));
+        assert_eq!(other.raw_image(614), Ok(None));
+    }
+
+    #[test]
+    fn clones_wait_for_each_other_to_write() {
+        let store = SqliteStore::open(OsStr::new(MEMORY)).unwrap();
+        let clone = store.try_clone().unwrap();
+
+        store.conn.execute_batch("BEGIN IMMEDIATE;").unwrap();
+
+        let writer = std::thread::spawn(move || clone.put_raw_image(614, b"woodpecker"));
+        std::thread::sleep(Duration::from_millis(100));
+        store.conn.execute_batch("COMMIT;").unwrap();
+
+        assert_eq!(writer.join().unwrap(), Ok(()));
+        assert_eq!(store.raw_image(614), Ok(Some(b"woodpecker".to_vec())));
+    }
+
+    #[test]
+    fn sync_empties_the_log() {
+        let path = std::env::temp_dir().join(format!("xkcdfs-sync-test-{}.db", std::process::id()));
+        let wal = path.with_extension("db-wal");
+
+        let store = SqliteStore::open(path.as_os_str()).unwrap();
+        store.put_raw_image(1, &[0; 100]).unwrap();
+        assert!(std::fs::metadata(&wal).unwrap().len() > 0);
+
+        store.sync().unwrap();
+        assert_eq!(std::fs::metadata(&wal).unwrap().len(), 0);
+        assert_eq!(store.raw_image(1).unwrap(), Some(vec![0; 100]));
+
+        drop(store);
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,1599 @@
+use crate::image::format::ImageFormat;
+use crate::image::wallpaper::WallpaperSpec;
+use crate::image::{RenderOptions, ThumbnailSpec, VectorFormat};
+use crate::scope;
+use crate::source::ComicSource;
+use crate::stats::Stats;
+use crate::store::{CacheStore, Fetch, HiRes};
+use crate::Comic;
+use reqwest::header::HeaderMap;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use time::Timespec;
+
+mod api;
+mod database;
+
+pub use api::{parse_comic, parse_comics, XkcdApi};
+pub use database::SqliteStore;
+
+/// Where a request may look for what it wants
+#[derive(Clone, Debug)]
+pub enum RequestMode {
+    /// Try the cache, then the network, rendering if needed
+    Normal,
+    /// Only use the cache, though rendering is still allowed
+    NoNetwork,
+    /// Skip the cache and go straight to the network
+    BustCache,
+    /// Only use the cache, and don't render anything
+    VeryFast,
+    /// Like Normal, but stop short of rendering anything
+    NoRender,
+}
+
+impl RequestMode {
+    pub fn network(&self) -> bool {
+        match self {
+            Self::Normal => true,
+            Self::NoNetwork => false,
+            Self::BustCache => true,
+            Self::VeryFast => false,
+            Self::NoRender => true,
+        }
+    }
+
+    pub fn cache(&self) -> bool {
+        match self {
+            Self::Normal => true,
+            Self::NoNetwork => true,
+            Self::BustCache => false,
+            Self::VeryFast => true,
+            Self::NoRender => true,
+        }
+    }
+
+    pub fn render(&self) -> bool {
+        match self {
+            Self::Normal => true,
+            Self::NoNetwork => true,
+            Self::BustCache => true,
+            Self::VeryFast => false,
+            Self::NoRender => false,
+        }
+    }
+}
+
+/// Open the database and bring its tables up to date, without touching what's
+/// cached
+///
+/// Returns how many comics are cached.
+pub fn check_database(database: &OsStr) -> Result<usize, String> {
+    Ok(SqliteStore::open(database)?.comic_count())
+}
+
+/// Network requests in `database`'s fetch log from the last `since`, oldest
+/// first
+pub fn fetch_history(database: &OsStr, since: Duration) -> Result<Vec<Fetch>, String> {
+    let store = SqliteStore::open(database)?;
+
+    store.fetches(time::get_time().sec - since.as_secs() as i64)
+}
+
+/// Delete every rendered image in `database`, returning how many bytes were
+/// freed
+pub fn clear_rendered(database: &OsStr) -> Result<u64, String> {
+    SqliteStore::open(database)?.clear_rendered()
+}
+
+/// Move everything in the database's write-ahead log into the database itself
+///
+/// SQLite does this by itself as the log grows and when the last connection
+/// closes. This is for when connections might never be closed, like when the
+/// process is about to die.
+pub fn checkpoint_database(database: &OsStr) -> Result<(), String> {
+    let conn = rusqlite::Connection::open(database).map_err(|e| e.to_string())?;
+
+    match database::checkpoint(&conn) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err("The database was busy".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Copy the database to `to`, even while it's in use
+///
+/// SQLite copies a few pages at a time, letting go of the database in
+/// between, so other connections carry on as usual. Anything written to the
+/// database while it's copied is picked up before it's done.
+pub fn backup_database(database: &OsStr, to: &Path) -> Result<(), String> {
+    let conn = rusqlite::Connection::open(database).map_err(|e| e.to_string())?;
+
+    conn.backup(rusqlite::DatabaseName::Main, to, None)
+        .map_err(|e| e.to_string())
+}
+
+/// Make sure the xkcd API can be reached, returning the latest comic
+pub fn probe_network(
+    timeout: Duration,
+    user_agent: &str,
+    headers: HeaderMap,
+) -> Result<Comic, String> {
+    XkcdApi::new(timeout, user_agent.to_string(), headers)?.latest()
+}
+
+/// Fetches comics from a source (xkcd.com, unless told otherwise), caching
+/// everything in a store (an SQLite database, unless told otherwise)
+///
+/// Each client has its own handle on the store, so it can't be shared between
+/// threads. Use `try_clone` to get one for another thread.
+pub struct XkcdClient<S: ComicSource = XkcdApi, C: CacheStore = SqliteStore> {
+    source: S,
+    store: C,
+
+    keep_animations: bool,
+    /// Serve original images, and never render anything
+    no_render: bool,
+    render_options: RenderOptions,
+    /// Most bytes of images and renders to keep in the cache
+    max_cache_size: Option<u64>,
+    /// Shared with every clone
+    stats: Arc<Stats>,
+}
+
+impl XkcdClient<XkcdApi> {
+    /// Open (or create) the cache at `database`, for comics from xkcd.com
+    ///
+    /// `headers` are sent with every request, on top of `user_agent`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        master_timeout: Duration,
+        database: &OsStr,
+        user_agent: String,
+        headers: HeaderMap,
+        keep_animations: bool,
+        no_render: bool,
+        render_options: RenderOptions,
+        max_cache_size: Option<u64>,
+    ) -> Result<Self, String> {
+        Self::with_source(
+            XkcdApi::new(master_timeout, user_agent, headers)?,
+            database,
+            keep_animations,
+            no_render,
+            render_options,
+            max_cache_size,
+        )
+    }
+}
+
+impl<S: ComicSource> XkcdClient<S> {
+    /// Open (or create) the cache at `database`, for comics from `source`
+    pub fn with_source(
+        source: S,
+        database: &OsStr,
+        keep_animations: bool,
+        no_render: bool,
+        render_options: RenderOptions,
+        max_cache_size: Option<u64>,
+    ) -> Result<Self, String> {
+        Ok(Self::with_store(
+            source,
+            SqliteStore::open(database)?,
+            keep_animations,
+            no_render,
+            render_options,
+            max_cache_size,
+        ))
+    }
+}
+
+impl<S: ComicSource, C: CacheStore> XkcdClient<S, C> {
+    /// Cache comics from `source` in `store`
+    pub fn with_store(
+        source: S,
+        store: C,
+        keep_animations: bool,
+        no_render: bool,
+        render_options: RenderOptions,
+        max_cache_size: Option<u64>,
+    ) -> Self {
+        let new = Self {
+            source,
+            store,
+
+            keep_animations,
+            no_render,
+            render_options,
+            max_cache_size,
+            stats: Arc::new(Stats::default()),
+        };
+
+        // The limit might be lower than last time
+        new.enforce_cache_limit();
+
+        new
+    }
+
+    /// Another client with the same settings, for use on another thread
+    ///
+    /// It gets its own handle on the same store.
+    pub fn try_clone(&self) -> Result<Self, String> {
+        Ok(Self {
+            source: self.source.clone(),
+            store: self.store.try_clone()?,
+
+            keep_animations: self.keep_animations,
+            no_render: self.no_render,
+            render_options: self.render_options.clone(),
+            max_cache_size: self.max_cache_size,
+            stats: Arc::clone(&self.stats),
+        })
+    }
+
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    pub fn store(&self) -> &C {
+        &self.store
+    }
+
+    pub fn stats(&self) -> Arc<Stats> {
+        Arc::clone(&self.stats)
+    }
+
+    /// How many bytes of images and renders are cached
+    pub fn cache_size(&self) -> Result<u64, String> {
+        self.store.size()
+    }
+
+    /// Get everything cached so far into the store's files
+    pub fn sync(&self) -> Result<(), String> {
+        let _op = scope::operation_scope("sync", None);
+
+        self.store.sync().inspect_err(|_| {
+            scope::set_outcome("error");
+        })
+    }
+
+    /// Note that comic `num` was just read, for its atime and for eviction
+    pub fn note_access(&self, num: u32) {
+        let now = time::get_time().sec;
+
+        if let Err(e) = self.to_cache(Some(num), |s| s.put_accessed(num, now)) {
+            warn!("Failed to note that comic {} was read: {}", num, e);
+        }
+    }
+
+    /// When comic `num` was last read, if that's been noted
+    pub fn accessed(&self, num: u32) -> Option<Timespec> {
+        match self.from_cache(Some(num), |s| s.accessed(num)) {
+            Ok(at) => at.map(|sec| Timespec::new(sec, 0)),
+            Err(e) => {
+                warn!("Failed to look up when comic {} was read: {}", num, e);
+                None
+            }
+        }
+    }
+
+    /// Delete every rendered image, so they're rendered again when next read
+    pub fn clear_rendered(&self) -> Result<u64, String> {
+        self.store.clear_rendered()
+    }
+
+    /// The `limit` comics read most recently, most recent first
+    pub fn recently_read(&self, limit: u32) -> Vec<u32> {
+        self.store.recently_accessed(limit).unwrap_or_else(|e| {
+            warn!("Failed to look up recently read comics: {}", e);
+            Vec::new()
+        })
+    }
+
+    /// Every year a cached comic was published in, in order
+    pub fn cached_years(&self) -> Vec<i32> {
+        self.store.years().unwrap_or_else(|e| {
+            warn!(
+                "Failed to look up the years comics were published in: {}",
+                e
+            );
+            Vec::new()
+        })
+    }
+
+    /// The cached comics published in `year`, in order
+    pub fn cached_comics_in_year(&self, year: i32) -> Vec<u32> {
+        self.store.comics_in_year(year).unwrap_or_else(|e| {
+            warn!("Failed to look up the comics published in {}: {}", year, e);
+            Vec::new()
+        })
+    }
+
+    /// The cached comics published on a day, in order
+    pub fn cached_comics_on(&self, year: i32, month: i32, day: i32) -> Vec<u32> {
+        self.store.comics_on(year, month, day).unwrap_or_else(|e| {
+            warn!(
+                "Failed to look up the comics published on {:04}-{:02}-{:02}: {}",
+                year, month, day, e
+            );
+            Vec::new()
+        })
+    }
+
+    /// The latest cached comic's number, without asking the source or
+    /// counting it as a cache hit
+    pub fn cached_latest_num(&self) -> Option<u32> {
+        self.store
+            .latest_comic()
+            .unwrap_or_else(|e| {
+                warn!("Failed to look up the latest cached comic: {}", e);
+                None
+            })
+            .map(|c| c.num)
+    }
+
+    /// Every cached comic's number, in order
+    pub fn cached_nums(&self) -> Vec<u32> {
+        self.store.nums().unwrap_or_else(|e| {
+            warn!("Failed to look up the cached comics: {}", e);
+            Vec::new()
+        })
+    }
+
+    /// Every cached comic's number and name from its title, in order
+    pub fn cached_titles(&self) -> Vec<(u32, String)> {
+        self.store.titles().unwrap_or_else(|e| {
+            warn!("Failed to look up comic titles: {}", e);
+            Vec::new()
+        })
+    }
+
+    /// The cached comic named `name` from its title, if there is one
+    pub fn cached_comic_titled(&self, name: &str) -> Option<u32> {
+        self.store.titled(name).unwrap_or_else(|e| {
+            warn!("Failed to look up the comic titled {:?}: {}", name, e);
+            None
+        })
+    }
+
+    /// Network requests that finished in the last `since`, oldest first
+    pub fn fetches(&self, since: Duration) -> Result<Vec<Fetch>, String> {
+        self.store
+            .fetches(time::get_time().sec - since.as_secs() as i64)
+    }
+
+    /// Note a network request for `class` in the fetch log, with how many
+    /// bytes came back if it worked
+    fn log_fetch(&self, class: &str, started: Instant, result: Result<Option<usize>, &String>) {
+        let fetch = Fetch {
+            at: time::get_time().sec,
+            class: class.to_string(),
+            status: match result {
+                Ok(_) => "ok".to_string(),
+                Err(e) => e.clone(),
+            },
+            millis: started.elapsed().as_millis() as u64,
+            bytes: result.ok().and_then(|b| b).map(|b| b as u64),
+        };
+
+        if let Some(bytes) = fetch.bytes {
+            self.stats.downloaded(bytes);
+        }
+
+        if let Err(e) = self.to_cache(None, |s| s.put_fetch(&fetch)) {
+            warn!("Failed to note a {} request in the fetch log: {}", class, e);
+        }
+    }
+
+    /// Evict from the cache if it's grown past the size limit
+    fn enforce_cache_limit(&self) {
+        if let Some(max_size) = self.max_cache_size {
+            let _op = scope::operation_scope("evict", None);
+
+            if let Err(e) = self.store.evict(max_size) {
+                scope::set_outcome("error");
+                warn!("Failed to evict from the cache: {}", e);
+            }
+        }
+    }
+
+    /// Look something up in the store, timed as a `cache` operation
+    #[allow(clippy::wrong_self_convention)]
+    fn from_cache<T>(
+        &self,
+        comic: Option<u32>,
+        lookup: impl FnOnce(&C) -> Result<Option<T>, String>,
+    ) -> Result<Option<T>, String> {
+        let _op = scope::operation_scope("cache", comic);
+        let result = lookup(&self.store);
+
+        scope::set_outcome(match result {
+            Ok(Some(_)) => "hit",
+            Ok(None) => "miss",
+            Err(_) => "error",
+        });
+
+        result
+    }
+
+    /// Put something in the store, timed as a `store` operation
+    fn to_cache(
+        &self,
+        comic: Option<u32>,
+        put: impl FnOnce(&C) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let _op = scope::operation_scope("store", comic);
+        let result = put(&self.store);
+
+        if result.is_err() {
+            scope::set_outcome("error");
+        }
+
+        result
+    }
+
+    pub fn render_options(&self) -> &RenderOptions {
+        &self.render_options
+    }
+
+    /// Render with different options from now on
+    ///
+    /// Renders made with the old options stay cached under their own key,
+    /// and are evicted like anything else.
+    pub fn set_render_options(&mut self, render_options: RenderOptions) {
+        self.render_options = render_options;
+    }
+
+    pub fn set_max_cache_size(&mut self, max_cache_size: Option<u64>) {
+        self.max_cache_size = max_cache_size;
+        self.enforce_cache_limit();
+    }
+
+    /// Cache an image that was rendered elsewhere
+    pub fn store_rendered_image(&self, num: u32, render_key: &str, image: &[u8]) {
+        if let Err(e) = self.to_cache(Some(num), |s| s.put_rendered_image(num, render_key, image)) {
+            warn!(
+                "Failed to store rendered image for comic {} in the cache: {}",
+                num, e
+            );
+        }
+
+        self.enforce_cache_limit();
+    }
+
+    pub fn rendering_enabled(&self) -> bool {
+        !self.no_render
+    }
+
+    pub fn keep_animations(&self) -> bool {
+        self.keep_animations
+    }
+
+    /// Check whether a comic's original image is an animated GIF
+    ///
+    /// Only GIFs are checked, so this won't hit the network for most comics.
+    pub fn is_animated(&self, comic: &Comic, mode: RequestMode) -> bool {
+        comic.is_gif()
+            && self
+                .request_raw_image(comic, None, mode)
+                .map(|raw| crate::image::gif::is_animated(&raw))
+                .unwrap_or(false)
+    }
+
+    pub fn get_cached_count(&self) -> usize {
+        self.store.comic_count()
+    }
+
+    /// Every comic up to the latest one cached, with `None` for the ones that
+    /// aren't
+    pub fn get_cached_comics(&self) -> impl Iterator<Item = Option<Comic>> + '_ {
+        (1..=self.get_cached_count() as u32)
+            .map(move |num| self.store.comic(num).ok().and_then(|c| c))
+    }
+
+    /// Every cached comic in order, with `img_len` set if its raw image is
+    /// cached too
+    ///
+    /// Unlike `get_cached_comics`, this can get them all at once.
+    pub fn each_cached_comic(&self, each: &mut dyn FnMut(Comic)) -> Result<(), String> {
+        let _op = scope::operation_scope("cache", None);
+
+        self.store.each_comic(each).inspect_err(|_| {
+            scope::set_outcome("error");
+        })
+    }
+
+    pub fn request_latest_comic(
+        &self,
+        _timeout: Option<Duration>,
+        mode: RequestMode,
+    ) -> Option<Comic> {
+        debug!("Latest comic requested");
+
+        if mode.cache() {
+            trace!("Trying the cache for the latest comic");
+
+            match self.from_cache(None, |s| s.latest_comic()) {
+                Ok(Some(c)) => {
+                    self.stats.cache_hit();
+                    return Some(c);
+                }
+                Ok(None) => {
+                    self.stats.cache_miss();
+                    warn!("Could not find latest comic in cache");
+                }
+                Err(e) => error!("Cache error retrieving latest comic: {}", e),
+            }
+        } else {
+            trace!(
+                "Skipping the cache for the latest comic (mode was {:?})",
+                mode
+            );
+        }
+
+        if mode.network() {
+            trace!("Trying the network for the latest comic");
+            let _op = scope::operation_scope("fetch_comic", None);
+            let request = self.stats.network_request();
+            let started = Instant::now();
+            let latest = self.source.latest();
+            self.log_fetch("latest", started, latest.as_ref().map(|_| None));
+
+            match latest {
+                Ok(c) => {
+                    if let Err(e) = self.to_cache(Some(c.num), |s| s.put_comic(&c)) {
+                        warn!("Failed to store latest comic {} in the cache: {}", c.num, e);
+                    }
+
+                    self.request_hires(&c, RequestMode::Normal);
+
+                    return Some(c);
+                }
+                Err(e) => {
+                    request.failed(&e);
+                    scope::set_outcome("error");
+                    warn!("Could not get latest comic on the network: {}", e);
+                }
+            }
+        } else {
+            trace!(
+                "Skipping the network for the latest comic (mode was {:?})",
+                mode
+            );
+        }
+
+        warn!("Could not find latest comic");
+
+        None
+    }
+
+    pub fn request_comic(
+        &self,
+        num: u32,
+        _timeout: Option<Duration>,
+        mode: RequestMode,
+    ) -> Option<Comic> {
+        debug!("Comic {} requested", num);
+
+        if mode.cache() {
+            trace!("Trying the cache for comic {}", num);
+
+            match self.from_cache(Some(num), |s| s.comic(num)) {
+                Ok(Some(c)) => {
+                    self.stats.cache_hit();
+                    return Some(c);
+                }
+                Ok(None) => {
+                    self.stats.cache_miss();
+                    info!("Comic {} not found in cache", num);
+                }
+                Err(e) => error!("Error retreiving {} from cache: {}", num, e),
+            }
+        } else {
+            trace!("Skipping the cache for comic {} (mode was {:?})", num, mode);
+        }
+
+        if mode.network() {
+            trace!("Trying the network for comic {}", num);
+            let _op = scope::operation_scope("fetch_comic", Some(num));
+            let request = self.stats.network_request();
+            let started = Instant::now();
+            let comic = self.source.comic(num);
+            self.log_fetch("comic", started, comic.as_ref().map(|_| None));
+
+            match comic {
+                Ok(c) => {
+                    self.stats.comic_succeeded(num, "comic");
+
+                    if let Err(e) = self.to_cache(Some(num), |s| s.put_comic(&c)) {
+                        warn!("Failed to store comic {} in the cache: {}", num, e);
+                    }
+
+                    // Only asked for the first time, and cached from then on
+                    self.request_hires(&c, RequestMode::Normal);
+
+                    return Some(c);
+                }
+                Err(e) => {
+                    request.failed(&format!("Comic {}: {}", num, e));
+                    self.stats.comic_failed(num, "comic", &e);
+                    scope::set_outcome("error");
+                    debug!("Comic {} not found on network: {}", num, e);
+                }
+            }
+        } else {
+            trace!(
+                "Skipping the network for comic {} (mode was {:?})",
+                num,
+                mode
+            );
+        }
+
+        None
+    }
+
+    /// What kind of image `comic`'s original is
+    ///
+    /// This goes by the Content-Type it was downloaded with, or by the image
+    /// itself if that's not known.
+    pub fn image_format(&self, comic: &Comic, mode: RequestMode) -> Option<ImageFormat> {
+        if mode.cache() {
+            match self.from_cache(Some(comic.num), |s| s.image_type(comic.num)) {
+                Ok(Some(t)) => return ImageFormat::from_content_type(&t),
+                Ok(None) => {}
+                Err(e) => warn!("Error looking up image type {} in cache: {}", comic, e),
+            }
+        }
+
+        self.request_raw_image(comic, None, mode)
+            .and_then(|i| ImageFormat::sniff(&i))
+    }
+
+    /// Whether `comic` has a double-size image, and how big it is
+    ///
+    /// The source is only asked once, and its answer is cached for good.
+    /// Comics whose images aren't PNGs never have one, and the source isn't
+    /// asked about them at all. `None` means it's not known yet.
+    pub fn request_hires(&self, comic: &Comic, mode: RequestMode) -> Option<HiRes> {
+        debug!("Hi-res image {} requested", comic);
+
+        if comic.hires_url().is_none() {
+            return Some(HiRes::Missing);
+        }
+
+        if mode.cache() {
+            match self.from_cache(Some(comic.num), |s| s.hires(comic.num)) {
+                Ok(Some(h)) => return Some(h),
+                Ok(None) => debug!("Hi-res image {} not known in cache", comic),
+                Err(e) => warn!("Error looking up hi-res image {} in cache: {}", comic, e),
+            }
+        }
+
+        if !mode.network() {
+            return None;
+        }
+
+        let _op = scope::operation_scope("fetch_hires", Some(comic.num));
+        let started = Instant::now();
+        let size = self.source.hires_size(comic);
+        self.log_fetch("hires", started, size.as_ref().map(|_| None));
+
+        match size {
+            Ok(size) => {
+                let hires = size.map_or(HiRes::Missing, HiRes::Available);
+
+                if let Err(e) = self.to_cache(Some(comic.num), |s| s.put_hires(comic.num, hires)) {
+                    warn!("Failed to store hi-res image {} in the cache: {}", comic, e);
+                }
+
+                Some(hires)
+            }
+            // Not cached, so it's asked again next time
+            Err(e) => {
+                scope::set_outcome("error");
+                debug!("Could not ask about hi-res image {}: {}", comic, e);
+                None
+            }
+        }
+    }
+
+    /// `comic`'s double-size image, if it has one
+    ///
+    /// Only mirroring asks for these, so they aren't counted as cache hits or
+    /// misses.
+    pub fn request_hires_image(&self, comic: &Comic, mode: RequestMode) -> Option<Vec<u8>> {
+        debug!("Hi-res image {} requested", comic);
+
+        match self.request_hires(comic, mode.clone()) {
+            Some(HiRes::Available(_)) => (),
+            _ => return None,
+        }
+
+        if mode.cache() {
+            match self.from_cache(Some(comic.num), |s| s.hires_image(comic.num)) {
+                Ok(Some(i)) => return Some(i),
+                Ok(None) => debug!("Hi-res image {} not found in cache", comic),
+                Err(e) => warn!("Error looking up hi-res image {} in cache: {}", comic, e),
+            }
+        }
+
+        if !mode.network() {
+            return None;
+        }
+
+        let _op = scope::operation_scope("fetch_hires_image", Some(comic.num));
+        let request = self.stats.network_request();
+        let started = Instant::now();
+        let image = self.source.hires_image(comic);
+        self.log_fetch(
+            "hires_image",
+            started,
+            image.as_ref().map(|i| Some(i.len())),
+        );
+
+        match image {
+            Ok(i) => {
+                if let Err(e) = self.to_cache(Some(comic.num), |s| s.put_hires_image(comic.num, &i))
+                {
+                    warn!("Failed to store hi-res image {} in the cache: {}", comic, e);
+                }
+
+                self.enforce_cache_limit();
+                Some(i)
+            }
+            Err(e) => {
+                request.failed(&format!("Hi-res image {}: {}", comic.num, e));
+                scope::set_outcome("error");
+                warn!("Could not get hi-res image {}: {}", comic, e);
+                None
+            }
+        }
+    }
+
+    pub fn request_raw_image(
+        &self,
+        comic: &Comic,
+        _timeout: Option<Duration>,
+        mode: RequestMode,
+    ) -> Option<Vec<u8>> {
+        debug!("Raw image {} requested", comic);
+
+        if mode.cache() {
+            trace!("Trying the cache for raw image {}", comic);
+
+            if let Ok(Some(i)) = self.from_cache(Some(comic.num), |s| s.raw_image(comic.num)) {
+                self.stats.cache_hit();
+                return Some(i);
+            } else {
+                self.stats.cache_miss();
+                debug!("Raw image {} not found in cache", comic);
+            }
+        } else {
+            trace!(
+                "Skipping the cache for raw image {} (mode was {:?})",
+                comic,
+                mode
+            );
+        }
+
+        if mode.network() {
+            let _op = scope::operation_scope("fetch_image", Some(comic.num));
+            let request = self.stats.network_request();
+            let started = Instant::now();
+            let image = self.source.image_and_type(comic);
+            self.log_fetch("image", started, image.as_ref().map(|(i, _)| Some(i.len())));
+
+            match image {
+                Ok((i, content_type)) => {
+                    self.stats.comic_succeeded(comic.num, "image");
+                    self.to_cache(Some(comic.num), |s| s.put_raw_image(comic.num, &i))
+                        .ok();
+
+                    match ImageFormat::detect(content_type.as_deref(), &i) {
+                        Some(format) => {
+                            self.to_cache(Some(comic.num), |s| {
+                                s.put_image_type(comic.num, format.content_type())
+                            })
+                            .ok();
+                        }
+                        None => warn!(
+                            "Raw image {} is {}, which isn't an image",
+                            comic,
+                            content_type.as_ref().map_or("unlabeled", String::as_str)
+                        ),
+                    }
+
+                    self.enforce_cache_limit();
+                    return Some(i);
+                }
+                Err(e) => {
+                    request.failed(&format!("Image {}: {}", comic.num, e));
+                    self.stats.comic_failed(comic.num, "image", &e);
+                    scope::set_outcome("error");
+                    warn!(
+                        "Could not get raw image {} from URL {}: {}",
+                        comic, comic.img_url, e
+                    );
+                }
+            }
+        }
+
+        None
+    }
+
+    pub fn request_rendered_image(
+        &self,
+        comic: &Comic,
+        timeout: Option<Duration>,
+        mode: RequestMode,
+    ) -> Option<Vec<u8>> {
+        self.request_rendered_image_with(comic, &self.render_options, timeout, mode)
+    }
+
+    /// Like `request_rendered_image`, but rendered with `options` instead of
+    /// the client's own
+    pub fn request_rendered_image_with(
+        &self,
+        comic: &Comic,
+        options: &RenderOptions,
+        timeout: Option<Duration>,
+        mode: RequestMode,
+    ) -> Option<Vec<u8>> {
+        debug!("Rendered image {} requested", comic);
+
+        if self.no_render {
+            debug!("Passing image {} through unrendered", comic);
+
+            return self.request_raw_image(comic, timeout, mode);
+        }
+
+        if self.keep_animations && self.is_animated(comic, mode.clone()) {
+            debug!("Passing animated image {} through unrendered", comic);
+
+            return self.request_raw_image(comic, timeout, mode);
+        }
+
+        if mode.cache() {
+            trace!("Trying the cache for rendered image {}", comic);
+
+            let render_key = options.cache_key();
+
+            if let Ok(Some(image)) = self.from_cache(Some(comic.num), |s| {
+                s.rendered_image(comic.num, &render_key)
+            }) {
+                return Some(image);
+            }
+        } else {
+            trace!("Skipping the cache for rendered image {}", comic);
+        }
+
+        if mode.render() {
+            trace!(
+                "Getting the rendered image for {} with mode {:?}",
+                comic,
+                mode
+            );
+            let raw_image = self.request_raw_image(comic, timeout, mode)?;
+
+            trace!("Rendering image fresh from raw image for {}", comic);
+            let _op = scope::operation_scope("render", Some(comic.num));
+
+            match crate::image::render(comic, &mut std::io::Cursor::new(&raw_image), options) {
+                Ok(image) => {
+                    trace!("Successfully rendered {}", comic);
+                    self.stats.rendered();
+                    self.stats.comic_succeeded(comic.num, "render");
+                    let render_key = options.cache_key();
+
+                    if let Err(e) = self.to_cache(Some(comic.num), |s| {
+                        s.put_rendered_image(comic.num, &render_key, &image)
+                    }) {
+                        warn!(
+                            "Failed to store rendered image for {} in the cache: {}",
+                            comic, e
+                        );
+                    }
+                    self.enforce_cache_limit();
+                    return Some(image);
+                }
+                Err(e) => {
+                    scope::set_outcome("error");
+                    self.stats.comic_failed(comic.num, "render", &e);
+                    warn!("Error rendering {}: {}", comic, e);
+                }
+            }
+        } else {
+            trace!("Skipping the render for rendered image {}", comic);
+        }
+
+        None
+    }
+
+    /// Up to `len` bytes of a comic's original image from `offset`, if it's
+    /// cached
+    ///
+    /// Only that part is read, so a large image read a piece at a time isn't
+    /// read in full for every piece.
+    pub fn cached_raw_image_range(
+        &self,
+        comic: &Comic,
+        offset: u64,
+        len: usize,
+    ) -> Option<Vec<u8>> {
+        let part = self.from_cache(Some(comic.num), |s| {
+            s.raw_image_range(comic.num, offset, len)
+        });
+
+        match part {
+            Ok(Some(part)) => {
+                self.stats.cache_hit();
+                Some(part)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                warn!("Failed to read part of raw image {}: {}", comic, e);
+                None
+            }
+        }
+    }
+
+    /// Up to `len` bytes from `offset` of the image `request_rendered_image`
+    /// would give, if it's cached, like `cached_raw_image_range`
+    pub fn cached_image_range(&self, comic: &Comic, offset: u64, len: usize) -> Option<Vec<u8>> {
+        self.cached_image_range_with(comic, &self.render_options, offset, len)
+    }
+
+    /// Like `cached_image_range`, for the image rendered with `options`
+    pub fn cached_image_range_with(
+        &self,
+        comic: &Comic,
+        options: &RenderOptions,
+        offset: u64,
+        len: usize,
+    ) -> Option<Vec<u8>> {
+        if self.no_render
+            || (self.keep_animations && self.is_animated(comic, RequestMode::VeryFast))
+        {
+            return self.cached_raw_image_range(comic, offset, len);
+        }
+
+        let render_key = options.cache_key();
+        let part = self.from_cache(Some(comic.num), |s| {
+            s.rendered_image_range(comic.num, &render_key, offset, len)
+        });
+
+        part.unwrap_or_else(|e| {
+            warn!("Failed to read part of rendered image {}: {}", comic, e);
+            None
+        })
+    }
+
+    /// Get a comic rendered as a PDF or SVG
+    ///
+    /// These share the rendered image cache, with the format tacked onto the
+    /// render key.
+    pub fn request_vector_image(
+        &self,
+        comic: &Comic,
+        format: VectorFormat,
+        timeout: Option<Duration>,
+        mode: RequestMode,
+    ) -> Option<Vec<u8>> {
+        debug!("{} of {} requested", format.extension(), comic);
+
+        if self.no_render {
+            trace!(
+                "Rendering is disabled, so there's no {}",
+                format.extension()
+            );
+            return None;
+        }
+
+        let render_key = format!(
+            "{};format={}",
+            self.render_options.cache_key(),
+            format.extension()
+        );
+
+        if mode.cache() {
+            trace!("Trying the cache for {} of {}", format.extension(), comic);
+
+            if let Ok(Some(image)) = self.from_cache(Some(comic.num), |s| {
+                s.rendered_image(comic.num, &render_key)
+            }) {
+                return Some(image);
+            }
+        } else {
+            trace!("Skipping the cache for {} of {}", format.extension(), comic);
+        }
+
+        if mode.render() {
+            let raw_image = self.request_raw_image(comic, timeout, mode)?;
+
+            trace!(
+                "Rendering {} fresh from raw image for {}",
+                format.extension(),
+                comic
+            );
+            let _op = scope::operation_scope("render", Some(comic.num));
+
+            match crate::image::render_vector(
+                comic,
+                &mut std::io::Cursor::new(&raw_image),
+                &self.render_options,
+                format,
+            ) {
+                Ok(image) => {
+                    if let Err(e) = self.to_cache(Some(comic.num), |s| {
+                        s.put_rendered_image(comic.num, &render_key, &image)
+                    }) {
+                        warn!(
+                            "Failed to store {} of {} in the cache: {}",
+                            format.extension(),
+                            comic,
+                            e
+                        );
+                    }
+                    self.enforce_cache_limit();
+                    return Some(image);
+                }
+                Err(e) => {
+                    scope::set_outcome("error");
+                    warn!("Error rendering {} of {}: {}", format.extension(), comic, e);
+                }
+            }
+        } else {
+            trace!(
+                "Skipping the render for {} of {}",
+                format.extension(),
+                comic
+            );
+        }
+
+        None
+    }
+
+    /// Get a montage of several rendered comics, in the given order
+    pub fn request_montage(
+        &self,
+        nums: &[u32],
+        timeout: Option<Duration>,
+        mode: RequestMode,
+    ) -> Option<Vec<u8>> {
+        debug!("Montage of {} comics requested", nums.len());
+
+        if self.no_render {
+            trace!("Rendering is disabled, so there's no montage");
+            return None;
+        }
+
+        let comics: Vec<String> = nums.iter().map(u32::to_string).collect();
+        let montage_key = format!(
+            "{};comics={}",
+            self.render_options.cache_key(),
+            comics.join(",")
+        );
+
+        if mode.cache() {
+            trace!("Trying the cache for montage {}", montage_key);
+
+            if let Ok(Some(image)) = self.from_cache(None, |s| s.montage(&montage_key)) {
+                return Some(image);
+            }
+        } else {
+            trace!("Skipping the cache for montage {}", montage_key);
+        }
+
+        if !mode.render() {
+            trace!("Skipping the render for montage {}", montage_key);
+            return None;
+        }
+
+        let montage = crate::image::montage::montage(nums, |num| {
+            let comic = self.request_comic(num, timeout, mode.clone())?;
+            self.request_rendered_image(&comic, timeout, mode.clone())
+        });
+
+        match montage {
+            // Missing comics may load next time, so only whole montages are
+            // kept
+            Ok(montage) => {
+                if montage.is_complete() {
+                    if let Err(e) =
+                        self.to_cache(None, |s| s.put_montage(&montage_key, &montage.png))
+                    {
+                        warn!("Failed to store montage in the cache: {}", e);
+                    }
+                    self.enforce_cache_limit();
+                } else {
+                    debug!(
+                        "Not caching montage {}, comics {:?} are missing",
+                        montage_key, montage.missing
+                    );
+                }
+
+                Some(montage.png)
+            }
+            Err(e) => {
+                warn!("Error building montage: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Get a comic's rendered image centered on a wallpaper
+    pub fn request_wallpaper(
+        &self,
+        comic: &Comic,
+        spec: WallpaperSpec,
+        timeout: Option<Duration>,
+        mode: RequestMode,
+    ) -> Option<Vec<u8>> {
+        debug!(
+            "Wallpaper {} at {}x{} requested",
+            comic, spec.width, spec.height
+        );
+
+        if self.no_render {
+            trace!("Rendering is disabled, so there's no wallpaper");
+            return None;
+        }
+
+        let wallpaper_key = format!("{};{}", self.render_options.cache_key(), spec.cache_key());
+
+        if mode.cache() {
+            trace!("Trying the cache for wallpaper {}", comic);
+
+            if let Ok(Some(image)) =
+                self.from_cache(Some(comic.num), |s| s.wallpaper(comic.num, &wallpaper_key))
+            {
+                return Some(image);
+            }
+        } else {
+            trace!("Skipping the cache for wallpaper {}", comic);
+        }
+
+        if !mode.render() {
+            trace!("Skipping the render for wallpaper {}", comic);
+            return None;
+        }
+
+        let rendered = self.request_rendered_image(comic, timeout, mode)?;
+        let _op = scope::operation_scope("render", Some(comic.num));
+
+        match crate::image::wallpaper::wallpaper(&rendered, spec) {
+            Ok(image) => {
+                if let Err(e) = self.to_cache(Some(comic.num), |s| {
+                    s.put_wallpaper(comic.num, &wallpaper_key, &image)
+                }) {
+                    warn!(
+                        "Failed to store wallpaper for {} in the cache: {}",
+                        comic, e
+                    );
+                }
+                self.enforce_cache_limit();
+                Some(image)
+            }
+            Err(e) => {
+                scope::set_outcome("error");
+                warn!("Error making wallpaper for {}: {}", comic, e);
+                None
+            }
+        }
+    }
+
+    pub fn request_ascii_art(
+        &self,
+        comic: &Comic,
+        width: u32,
+        timeout: Option<Duration>,
+        mode: RequestMode,
+    ) -> Option<String> {
+        debug!("ASCII art {} at width {} requested", comic, width);
+
+        if self.no_render {
+            trace!("Rendering is disabled, so there's no ASCII art");
+            return None;
+        }
+
+        if mode.cache() {
+            trace!("Trying the cache for ASCII art {}", comic);
+
+            if let Ok(Some(art)) =
+                self.from_cache(Some(comic.num), |s| s.ascii_art(comic.num, width))
+            {
+                return Some(art);
+            }
+        } else {
+            trace!("Skipping the cache for ASCII art {}", comic);
+        }
+
+        if mode.render() {
+            let raw_image = self.request_raw_image(comic, timeout, mode)?;
+
+            trace!("Converting ASCII art fresh from raw image for {}", comic);
+            let _op = scope::operation_scope("render", Some(comic.num));
+
+            match crate::image::ascii::render(comic, &mut std::io::Cursor::new(&raw_image), width) {
+                Ok(art) => {
+                    if let Err(e) =
+                        self.to_cache(Some(comic.num), |s| s.put_ascii_art(comic.num, width, &art))
+                    {
+                        warn!(
+                            "Failed to store ASCII art for {} in the cache: {}",
+                            comic, e
+                        );
+                    }
+                    self.enforce_cache_limit();
+                    return Some(art);
+                }
+                Err(e) => {
+                    scope::set_outcome("error");
+                    warn!("Error converting {} to ASCII art: {}", comic, e);
+                }
+            }
+        } else {
+            trace!("Skipping the render for ASCII art {}", comic);
+        }
+
+        None
+    }
+
+    pub fn request_thumbnail(
+        &self,
+        comic: &Comic,
+        spec: ThumbnailSpec,
+        timeout: Option<Duration>,
+        mode: RequestMode,
+    ) -> Option<Vec<u8>> {
+        debug!("Thumbnail {} at width {} requested", comic, spec.width);
+
+        if self.no_render {
+            trace!("Rendering is disabled, so there's no thumbnail");
+            return None;
+        }
+
+        if mode.cache() {
+            trace!("Trying the cache for thumbnail {}", comic);
+
+            if let Ok(Some(image)) =
+                self.from_cache(Some(comic.num), |s| s.thumbnail(comic.num, spec))
+            {
+                return Some(image);
+            }
+        } else {
+            trace!("Skipping the cache for thumbnail {}", comic);
+        }
+
+        if mode.render() {
+            let raw_image = self.request_raw_image(comic, timeout, mode)?;
+
+            trace!("Scaling thumbnail fresh from raw image for {}", comic);
+            let _op = scope::operation_scope("render", Some(comic.num));
+
+            match crate::image::thumbnail(&mut std::io::Cursor::new(&raw_image), spec) {
+                Ok(image) => {
+                    if let Err(e) = self.to_cache(Some(comic.num), |s| {
+                        s.put_thumbnail(comic.num, spec, &image)
+                    }) {
+                        warn!(
+                            "Failed to store thumbnail for {} in the cache: {}",
+                            comic, e
+                        );
+                    }
+                    self.enforce_cache_limit();
+                    return Some(image);
+                }
+                Err(e) => {
+                    scope::set_outcome("error");
+                    warn!("Error scaling thumbnail for {}: {}", comic, e);
+                }
+            }
+        } else {
+            trace!("Skipping the render for thumbnail {}", comic);
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    /// Makes up comics, counting how often it's asked
+    #[derive(Clone, Default)]
+    struct FakeSource {
+        requests: Arc<AtomicUsize>,
+    }
+
+    impl ComicSource for FakeSource {
+        fn latest(&self) -> Result<Comic, String> {
+            self.comic(10)
+        }
+
+        fn comic(&self, num: u32) -> Result<Comic, String> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+
+            if num > 10 {
+                return Err(format!("There is no comic {} yet", num));
+            }
+
+            Ok(Comic {
+                num,
+                day: 1,
+                month: 1,
+                year: 2006,
+                link: None,
+                news: None,
+                alt: format!("Alt text {}", num),
+                title: format!("Comic {}", num),
+                safe_title: format!("Comic {}", num),
+                transcript: None,
+                img_url: format!("https://example.com/{}.png", num),
+                img_len: None,
+                extra_parts: None,
+            })
+        }
+
+        fn image(&self, _comic: &Comic) -> Result<Vec<u8>, String> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+
+            Ok(vec![1, 2, 3])
+        }
+    }
+
+    #[derive(Default)]
+    struct Memory {
+        comics: BTreeMap<u32, Comic>,
+        /// Raw images under `raw/`, and everything derived from them
+        blobs: HashMap<String, Vec<u8>>,
+        art: HashMap<(u32, u32), String>,
+    }
+
+    /// Keeps everything in memory, shared between clones
+    ///
+    /// A broken store fails every operation.
+    #[derive(Clone, Default)]
+    struct MemoryStore {
+        memory: Arc<Mutex<Memory>>,
+        broken: bool,
+    }
+
+    impl MemoryStore {
+        fn with<T>(&self, f: impl FnOnce(&mut Memory) -> T) -> Result<T, String> {
+            if self.broken {
+                return Err("The store is broken".to_string());
+            }
+
+            Ok(f(&mut self.memory.lock().unwrap()))
+        }
+
+        fn blob(&self, key: String) -> Result<Option<Vec<u8>>, String> {
+            self.with(|m| m.blobs.get(&key).cloned())
+        }
+
+        fn put_blob(&self, key: String, data: &[u8]) -> Result<(), String> {
+            self.with(|m| {
+                m.blobs.insert(key, data.to_vec());
+            })
+        }
+    }
+
+    impl CacheStore for MemoryStore {
+        fn try_clone(&self) -> Result<Self, String> {
+            Ok(self.clone())
+        }
+
+        fn comic(&self, num: u32) -> Result<Option<Comic>, String> {
+            self.with(|m| m.comics.get(&num).cloned())
+        }
+
+        fn latest_comic(&self) -> Result<Option<Comic>, String> {
+            self.with(|m| m.comics.values().next_back().cloned())
+        }
+
+        fn comic_count(&self) -> usize {
+            self.with(|m| m.comics.keys().next_back().cloned().unwrap_or(0))
+                .unwrap_or(0) as usize
+        }
+
+        fn put_comic(&self, comic: &Comic) -> Result<(), String> {
+            self.with(|m| {
+                m.comics.insert(comic.num, comic.clone());
+            })
+        }
+
+        fn raw_image(&self, num: u32) -> Result<Option<Vec<u8>>, String> {
+            self.blob(format!("raw/{}", num))
+        }
+
+        fn put_raw_image(&self, num: u32, data: &[u8]) -> Result<(), String> {
+            self.put_blob(format!("raw/{}", num), data)
+        }
+
+        fn rendered_image(&self, num: u32, render_key: &str) -> Result<Option<Vec<u8>>, String> {
+            self.blob(format!("rendered/{}/{}", num, render_key))
+        }
+
+        fn put_rendered_image(
+            &self,
+            num: u32,
+            render_key: &str,
+            data: &[u8],
+        ) -> Result<(), String> {
+            self.put_blob(format!("rendered/{}/{}", num, render_key), data)
+        }
+
+        fn thumbnail(&self, num: u32, spec: ThumbnailSpec) -> Result<Option<Vec<u8>>, String> {
+            self.blob(format!("thumbnail/{}/{:?}", num, spec))
+        }
+
+        fn put_thumbnail(&self, num: u32, spec: ThumbnailSpec, data: &[u8]) -> Result<(), String> {
+            self.put_blob(format!("thumbnail/{}/{:?}", num, spec), data)
+        }
+
+        fn montage(&self, montage_key: &str) -> Result<Option<Vec<u8>>, String> {
+            self.blob(format!("montage/{}", montage_key))
+        }
+
+        fn put_montage(&self, montage_key: &str, data: &[u8]) -> Result<(), String> {
+            self.put_blob(format!("montage/{}", montage_key), data)
+        }
+
+        fn ascii_art(&self, num: u32, width: u32) -> Result<Option<String>, String> {
+            self.with(|m| m.art.get(&(num, width)).cloned())
+        }
+
+        fn put_ascii_art(&self, num: u32, width: u32, art: &str) -> Result<(), String> {
+            self.with(|m| {
+                m.art.insert((num, width), art.to_string());
+            })
+        }
+
+        fn size(&self) -> Result<u64, String> {
+            self.with(|m| {
+                let blobs: usize = m.blobs.values().map(Vec::len).sum();
+                let art: usize = m.art.values().map(String::len).sum();
+
+                (blobs + art) as u64
+            })
+        }
+
+        /// Throws out everything at once, which is all the tests need
+        fn evict(&self, max_size: u64) -> Result<u64, String> {
+            let size = self.size()?;
+
+            if size <= max_size {
+                return Ok(0);
+            }
+
+            self.with(|m| {
+                m.blobs.clear();
+                m.art.clear();
+            })?;
+
+            Ok(size)
+        }
+    }
+
+    fn client(source: FakeSource) -> XkcdClient<FakeSource> {
+        XkcdClient::with_source(
+            source,
+            OsStr::new(":memory:"),
+            false,
+            false,
+            RenderOptions::default(),
+            None,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn caches_what_the_source_returns() {
+        let source = FakeSource::default();
+        let client = client(source.clone());
+
+        let comic = client.request_comic(3, None, RequestMode::Normal).unwrap();
+        assert_eq!(comic.title, "Comic 3");
+        assert_eq!(
+            client.request_raw_image(&comic, None, RequestMode::Normal),
+            Some(vec![1, 2, 3])
+        );
+        assert_eq!(source.requests.load(Ordering::SeqCst), 2);
+
+        // Both come from the cache the second time around
+        assert!(client.request_comic(3, None, RequestMode::Normal).is_some());
+        assert!(client
+            .request_raw_image(&comic, None, RequestMode::Normal)
+            .is_some());
+        assert_eq!(source.requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn source_errors_are_misses() {
+        let client = client(FakeSource::default());
+
+        assert!(client
+            .request_comic(11, None, RequestMode::Normal)
+            .is_none());
+        assert!(client
+            .request_comic(1, None, RequestMode::NoNetwork)
+            .is_none());
+        assert_eq!(client.stats().snapshot().network_failures, 1);
+    }
+
+    fn store_client(source: FakeSource, store: MemoryStore) -> XkcdClient<FakeSource, MemoryStore> {
+        XkcdClient::with_store(source, store, false, false, RenderOptions::default(), None)
+    }
+
+    #[test]
+    fn caches_in_any_store() {
+        let source = FakeSource::default();
+        let store = MemoryStore::default();
+        let client = store_client(source.clone(), store.clone());
+
+        let comic = client.request_comic(3, None, RequestMode::Normal).unwrap();
+        client
+            .request_raw_image(&comic, None, RequestMode::Normal)
+            .unwrap();
+
+        assert_eq!(store.comic(3).unwrap().unwrap().title, "Comic 3");
+        assert_eq!(store.raw_image(3).unwrap(), Some(vec![1, 2, 3]));
+        assert_eq!(client.cache_size(), Ok(3));
+
+        // Clones share the store
+        let clone = client.try_clone().unwrap();
+        assert!(clone
+            .request_comic(3, None, RequestMode::NoNetwork)
+            .is_some());
+        assert_eq!(source.requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn store_errors_are_misses() {
+        let source = FakeSource::default();
+        let store = MemoryStore {
+            broken: true,
+            ..MemoryStore::default()
+        };
+        let client = store_client(source.clone(), store);
+
+        // Everything still works, straight from the source
+        assert!(client.request_comic(3, None, RequestMode::Normal).is_some());
+        assert!(client.request_comic(3, None, RequestMode::Normal).is_some());
+        assert!(client
+            .request_comic(3, None, RequestMode::NoNetwork)
+            .is_none());
+        assert_eq!(source.requests.load(Ordering::SeqCst), 2);
+        assert_eq!(client.get_cached_count(), 0);
+    }
+
+    #[test]
+    fn finds_the_latest_cached_comic() {
+        let client = client(FakeSource::default());
+
+        assert!(client
+            .request_latest_comic(None, RequestMode::NoNetwork)
+            .is_none());
+
+        client.request_comic(2, None, RequestMode::Normal).unwrap();
+        client.request_comic(4, None, RequestMode::Normal).unwrap();
+
+        let latest = client
+            .request_latest_comic(None, RequestMode::NoNetwork)
+            .unwrap();
+        assert_eq!(latest.num, 4);
+
+        let cached: Vec<Option<u32>> = client
+            .get_cached_comics()
+            .map(|c| c.map(|c| c.num))
+            .collect();
+        assert_eq!(cached, vec![None, Some(2), None, Some(4)]);
+    }
+}
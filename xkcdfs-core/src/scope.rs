@@ -0,0 +1,120 @@
+//! What each thread is working on, for tagging log messages
+//!
+//! Nothing here logs anything itself, besides a trace message when an
+//! operation finishes. Whatever logger is set up can ask what the current
+//! thread is doing, and include that with each message.
+//!
+//! Operations are also `tracing` spans, named `operation` with `operation`,
+//! `comic` and `outcome` fields. Operations started while another is running on the
+//! same thread are its children, so a subscriber can work out where the time
+//! went.
+
+use std::cell::Cell;
+use std::time::Instant;
+use tracing::{field, span, Level, Span};
+
+thread_local! {
+    /// The comic this thread is currently working on, if any
+    static COMIC: Cell<Option<u32>> = const { Cell::new(None) };
+
+    /// The operation this thread is working on, and when it started
+    static OPERATION: Cell<Option<(&'static str, Instant)>> = const { Cell::new(None) };
+}
+
+/// Tags messages logged on this thread with a comic number, until dropped
+pub struct ComicScope {
+    previous: Option<u32>,
+}
+
+impl Drop for ComicScope {
+    fn drop(&mut self) {
+        COMIC.with(|c| c.set(self.previous));
+    }
+}
+
+/// Tag messages logged on this thread with `comic`, if there is one
+pub fn comic_scope(comic: Option<u32>) -> ComicScope {
+    ComicScope {
+        previous: COMIC.with(|c| c.replace(comic)),
+    }
+}
+
+/// Tags messages logged on this thread with an operation, until dropped
+///
+/// Dropping it logs how long the operation took, and closes its span.
+pub struct OperationScope {
+    previous: Option<(&'static str, Instant)>,
+    _comic: ComicScope,
+    _span: span::EnteredSpan,
+}
+
+impl Drop for OperationScope {
+    fn drop(&mut self) {
+        if let Some((name, _)) = OPERATION.with(Cell::get) {
+            trace!("Finished {}", name);
+        }
+
+        OPERATION.with(|o| o.set(self.previous));
+    }
+}
+
+/// Tag messages logged on this thread with an operation name and `comic`
+pub fn operation_scope(name: &'static str, comic: Option<u32>) -> OperationScope {
+    let span = span!(
+        Level::DEBUG,
+        "operation",
+        operation = name,
+        comic = field::Empty,
+        outcome = field::Empty
+    );
+
+    if let Some(num) = comic {
+        span.record("comic", num);
+    }
+
+    OperationScope {
+        _comic: comic_scope(comic),
+        previous: OPERATION.with(|o| o.replace(Some((name, Instant::now())))),
+        _span: span.entered(),
+    }
+}
+
+/// Record how the operation this thread is working on turned out, like `hit`,
+/// `miss` or `error`
+///
+/// Operations that don't say are assumed to have gone fine.
+pub fn set_outcome(outcome: &'static str) {
+    Span::current().record("outcome", outcome);
+}
+
+/// The comic this thread is working on, if any
+pub fn current_comic() -> Option<u32> {
+    COMIC.with(Cell::get)
+}
+
+/// The operation this thread is working on, and when it started
+pub fn current_operation() -> Option<(&'static str, Instant)> {
+    OPERATION.with(Cell::get)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn comic_scopes_nest() {
+        {
+            let _outer = comic_scope(Some(1));
+            assert_eq!(current_comic(), Some(1));
+
+            {
+                let _inner = comic_scope(Some(2));
+                assert_eq!(current_comic(), Some(2));
+            }
+
+            assert_eq!(current_comic(), Some(1));
+        }
+
+        assert_eq!(current_comic(), None);
+    }
+}
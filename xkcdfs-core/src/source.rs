@@ -0,0 +1,45 @@
+//! Where comics come from
+//!
+//! `XkcdClient` caches whatever its `ComicSource` hands it. The usual source is
+//! `XkcdApi`, which asks xkcd.com, but anything that can find comics by number
+//! and fetch their images will do: a mirror, a local archive, or a test double
+//! that never touches the network.
+
+use crate::Comic;
+
+/// Somewhere to get comics and their images from
+///
+/// Each clone of a client gets a clone of its source, so sources should be
+/// cheap to clone and fine to use from several threads at once.
+pub trait ComicSource: Clone + Send + 'static {
+    /// The newest comic
+    fn latest(&self) -> Result<Comic, String>;
+
+    /// Comic `num`
+    fn comic(&self, num: u32) -> Result<Comic, String>;
+
+    /// The original image for `comic`, which came from this source
+    fn image(&self, comic: &Comic) -> Result<Vec<u8>, String>;
+
+    /// The original image, with the Content-Type it came with, if any
+    ///
+    /// Sources that can't say leave it to be told from the image itself.
+    fn image_and_type(&self, comic: &Comic) -> Result<(Vec<u8>, Option<String>), String> {
+        self.image(comic).map(|i| (i, None))
+    }
+
+    /// The size of `comic`'s double-size image, or `None` if it hasn't got one
+    ///
+    /// Only asked about comics with a `hires_url`. Sources without double-size
+    /// images never have one.
+    fn hires_size(&self, _comic: &Comic) -> Result<Option<u64>, String> {
+        Ok(None)
+    }
+
+    /// `comic`'s double-size image
+    ///
+    /// Only asked for once `hires_size` has said there is one.
+    fn hires_image(&self, comic: &Comic) -> Result<Vec<u8>, String> {
+        Err(format!("{} has no double-size image", comic))
+    }
+}
@@ -0,0 +1,265 @@
+//! Counters for what a client and everything built on it has been doing
+//!
+//! Every client cloned from the same one shares a set of counters, which are
+//! bumped wherever the work happens. Take a `Snapshot` to read them.
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Counters shared between a client and its clones
+pub struct Stats {
+    started: Instant,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    network_requests: AtomicU64,
+    network_failures: AtomicU64,
+    in_flight: AtomicU64,
+    /// Bytes of images downloaded
+    downloaded: AtomicU64,
+    renders: AtomicU64,
+    /// Filesystem operations answered
+    operations: AtomicU64,
+    /// Comics, images and renders that failed
+    errors: AtomicU64,
+    /// Renders waiting on or running in the render pool
+    renders_pending: AtomicU64,
+    prefetch_remaining: AtomicU64,
+    open_handles: AtomicU64,
+    memory_reserved: AtomicU64,
+    /// Network requests that have failed since the last one that didn't
+    failing: AtomicU64,
+    last_error: Mutex<Option<(Instant, String)>>,
+    /// When the latest comic was last checked for in the background, and what
+    /// it was if that worked
+    last_refresh: Mutex<Option<(Instant, Option<u32>)>>,
+    /// Why each comic's last fetch or render failed, by comic and what failed,
+    /// and when in seconds since the epoch
+    comic_errors: Mutex<HashMap<(u32, &'static str), ComicError>>,
+}
+
+/// When a comic failed, and why
+type ComicError = (i64, String);
+
+/// A network request that's been started, until it's dropped
+pub struct InFlight<'a> {
+    stats: &'a Stats,
+    failed: Cell<bool>,
+}
+
+impl InFlight<'_> {
+    pub fn failed(&self, error: &str) {
+        let stats = self.stats;
+
+        self.failed.set(true);
+        stats.network_failures.fetch_add(1, Ordering::Relaxed);
+        stats.failing.fetch_add(1, Ordering::Relaxed);
+        *stats.last_error.lock().unwrap() = Some((Instant::now(), error.to_string()));
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.stats.in_flight.fetch_sub(1, Ordering::Relaxed);
+
+        if !self.failed.get() {
+            self.stats.failing.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            cache_hits: AtomicU64::new(0),
+            cache_misses: AtomicU64::new(0),
+            network_requests: AtomicU64::new(0),
+            network_failures: AtomicU64::new(0),
+            in_flight: AtomicU64::new(0),
+            downloaded: AtomicU64::new(0),
+            renders: AtomicU64::new(0),
+            operations: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            renders_pending: AtomicU64::new(0),
+            prefetch_remaining: AtomicU64::new(0),
+            open_handles: AtomicU64::new(0),
+            memory_reserved: AtomicU64::new(0),
+            failing: AtomicU64::new(0),
+            last_error: Mutex::new(None),
+            last_refresh: Mutex::new(None),
+            comic_errors: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Stats {
+    pub fn cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn cache_miss(&self) {
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a network request, which is in flight until the result is dropped
+    pub fn network_request(&self) -> InFlight<'_> {
+        self.network_requests.fetch_add(1, Ordering::Relaxed);
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+
+        InFlight {
+            stats: self,
+            failed: Cell::new(false),
+        }
+    }
+
+    /// Whether the last network request failed
+    pub fn offline(&self) -> bool {
+        self.failing.load(Ordering::Relaxed) > 0
+    }
+
+    pub fn downloaded(&self, bytes: u64) {
+        self.downloaded.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn rendered(&self) {
+        self.renders.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count an operation a filesystem answered
+    pub fn served(&self) {
+        self.operations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_renders_pending(&self, count: usize) {
+        self.renders_pending.store(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn set_prefetch_remaining(&self, count: u32) {
+        self.prefetch_remaining
+            .store(u64::from(count), Ordering::Relaxed);
+    }
+
+    pub fn opened(&self) {
+        self.open_handles.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn released(&self) {
+        self.open_handles.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn set_memory_reserved(&self, bytes: u64) {
+        self.memory_reserved.store(bytes, Ordering::Relaxed);
+    }
+
+    /// Note a background check for the latest comic, which found `latest`
+    pub fn refreshed(&self, latest: Option<u32>) {
+        *self.last_refresh.lock().unwrap() = Some((Instant::now(), latest));
+    }
+
+    /// Note that `what` (`comic`, `image` or `render`) failed for comic `num`
+    pub fn comic_failed(&self, num: u32, what: &'static str, error: &str) {
+        let at = time::get_time().sec;
+
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        self.comic_errors
+            .lock()
+            .unwrap()
+            .insert((num, what), (at, error.to_string()));
+    }
+
+    /// Forget that `what` failed for comic `num`, now that it's worked
+    pub fn comic_succeeded(&self, num: u32, what: &'static str) {
+        self.comic_errors.lock().unwrap().remove(&(num, what));
+    }
+
+    /// What's failed for comic `num` and not worked since, as what failed,
+    /// when, and why, oldest first
+    pub fn comic_errors(&self, num: u32) -> Vec<(&'static str, i64, String)> {
+        let mut errors: Vec<_> = self
+            .comic_errors
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|((n, _), _)| *n == num)
+            .map(|((_, what), (at, error))| (*what, *at, error.clone()))
+            .collect();
+
+        errors.sort_by_key(|&(what, at, _)| (at, what));
+
+        errors
+    }
+
+    /// Read every counter
+    ///
+    /// Each is read separately, so they might not quite add up while work is
+    /// going on.
+    pub fn snapshot(&self) -> Snapshot {
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        Snapshot {
+            uptime: self.started.elapsed(),
+            cache_hits: get(&self.cache_hits),
+            cache_misses: get(&self.cache_misses),
+            network_requests: get(&self.network_requests),
+            network_failures: get(&self.network_failures),
+            in_flight: get(&self.in_flight),
+            downloaded: get(&self.downloaded),
+            renders: get(&self.renders),
+            operations: get(&self.operations),
+            errors: get(&self.errors),
+            renders_pending: get(&self.renders_pending),
+            prefetch_remaining: get(&self.prefetch_remaining),
+            open_handles: get(&self.open_handles),
+            memory_reserved: get(&self.memory_reserved),
+            failing: get(&self.failing),
+            last_error: self
+                .last_error
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(|(at, e)| (at.elapsed(), e.clone())),
+            last_refresh: self
+                .last_refresh
+                .lock()
+                .unwrap()
+                .map(|(at, latest)| (at.elapsed(), latest)),
+        }
+    }
+}
+
+/// The counters at one point in time
+#[derive(Clone, Debug, Default)]
+pub struct Snapshot {
+    /// Time since the counters were created
+    pub uptime: Duration,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub network_requests: u64,
+    pub network_failures: u64,
+    /// Network requests that haven't finished
+    pub in_flight: u64,
+    /// Bytes of images downloaded
+    pub downloaded: u64,
+    pub renders: u64,
+    /// Operations answered by a filesystem using the client
+    pub operations: u64,
+    /// Comics, images and renders that failed
+    pub errors: u64,
+    /// Renders waiting on or running in a render pool
+    pub renders_pending: u64,
+    pub prefetch_remaining: u64,
+    /// File handles open on a filesystem using the client
+    pub open_handles: u64,
+    /// Bytes set aside for renders and what they produced
+    pub memory_reserved: u64,
+    /// Network requests that have failed in a row, up to now
+    pub failing: u64,
+    /// How long ago the last network request failed, and why
+    pub last_error: Option<(Duration, String)>,
+    /// How long ago the latest comic was checked for in the background, and
+    /// what it was if that worked
+    pub last_refresh: Option<(Duration, Option<u32>)>,
+}
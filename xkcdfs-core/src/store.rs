@@ -0,0 +1,355 @@
+//! Where comics are kept
+//!
+//! `XkcdClient` keeps everything it fetches or renders in a `CacheStore`, so
+//! it's there next time without the network. The usual store is `SqliteStore`,
+//! a single SQLite database, but anything that can hold comics and blobs by
+//! key will do: plain files, another embedded database, or a test double that
+//! keeps everything in memory.
+
+use crate::image::ThumbnailSpec;
+use crate::titles;
+use crate::Comic;
+use std::collections::BTreeSet;
+
+/// A network request, as noted in the fetch log
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fetch {
+    /// When it finished, in seconds since the epoch
+    pub at: i64,
+    /// What was asked for: `latest`, `comic`, `image`, `hires` or `hires_image`
+    pub class: String,
+    /// `ok`, or why it failed
+    pub status: String,
+    pub millis: u64,
+    /// How much came back, for images
+    pub bytes: Option<u64>,
+}
+
+/// What a mount did, as noted when it's unmounted
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Session {
+    /// When it was mounted and unmounted, in seconds since the epoch
+    pub started: i64,
+    pub ended: i64,
+    /// Filesystem operations answered
+    pub operations: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    /// Bytes of images downloaded
+    pub downloaded: u64,
+    pub renders: u64,
+    /// Comics, images and renders that failed
+    pub errors: u64,
+}
+
+/// Whether a comic has a double-size image, as found by asking for it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HiRes {
+    Missing,
+    /// In bytes, or 0 if the server didn't say
+    Available(u64),
+}
+
+/// Somewhere to cache comics, their images and everything rendered from them
+///
+/// Lookups return `Ok(None)` for anything that isn't cached, saving `Err` for
+/// a store that isn't working. Storing something again replaces it.
+///
+/// Each clone of a client gets its own store from `try_clone`, and uses it
+/// from one thread at a time.
+pub trait CacheStore: Send + 'static {
+    /// Another handle on the same cache, for use on another thread
+    fn try_clone(&self) -> Result<Self, String>
+    where
+        Self: Sized;
+
+    /// Comic `num`
+    fn comic(&self, num: u32) -> Result<Option<Comic>, String>;
+
+    /// The cached comic with the highest number
+    fn latest_comic(&self) -> Result<Option<Comic>, String>;
+
+    /// The highest comic number cached, or 0 if there are none
+    fn comic_count(&self) -> usize;
+
+    fn put_comic(&self, comic: &Comic) -> Result<(), String>;
+
+    /// Go through every cached comic in order, with `img_len` filled in when
+    /// its raw image is cached too
+    ///
+    /// This looks each one up on its own, so stores that can get them all at
+    /// once should.
+    fn each_comic(&self, each: &mut dyn FnMut(Comic)) -> Result<(), String> {
+        for num in 1..=self.comic_count() as u32 {
+            if let Some(comic) = self.comic(num)? {
+                let img_len = self.raw_image(num)?.map(|i| i.len());
+
+                each(Comic { img_len, ..comic });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Comic `num`'s original image
+    fn raw_image(&self, num: u32) -> Result<Option<Vec<u8>>, String>;
+
+    fn put_raw_image(&self, num: u32, data: &[u8]) -> Result<(), String>;
+
+    /// Up to `len` bytes of comic `num`'s original image, from `offset`
+    ///
+    /// Stores that can read part of an image should, since large ones are
+    /// read this way a piece at a time. The rest read all of it and cut it
+    /// down.
+    fn raw_image_range(
+        &self,
+        num: u32,
+        offset: u64,
+        len: usize,
+    ) -> Result<Option<Vec<u8>>, String> {
+        Ok(self
+            .raw_image(num)?
+            .map(|i| range(&i, offset, len).to_vec()))
+    }
+
+    /// The Content-Type of comic `num`'s original image, if it's cached
+    ///
+    /// Stores that don't keep it have it told from the image instead.
+    fn image_type(&self, _num: u32) -> Result<Option<String>, String> {
+        Ok(None)
+    }
+
+    /// Note the Content-Type of a cached original image, which goes when the
+    /// image does
+    fn put_image_type(&self, _num: u32, _content_type: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Comic `num` rendered with the options behind `render_key`
+    fn rendered_image(&self, num: u32, render_key: &str) -> Result<Option<Vec<u8>>, String>;
+
+    fn put_rendered_image(&self, num: u32, render_key: &str, data: &[u8]) -> Result<(), String>;
+
+    /// Up to `len` bytes of a rendered image, from `offset`, like
+    /// `raw_image_range`
+    fn rendered_image_range(
+        &self,
+        num: u32,
+        render_key: &str,
+        offset: u64,
+        len: usize,
+    ) -> Result<Option<Vec<u8>>, String> {
+        Ok(self
+            .rendered_image(num, render_key)?
+            .map(|i| range(&i, offset, len).to_vec()))
+    }
+
+    fn thumbnail(&self, num: u32, spec: ThumbnailSpec) -> Result<Option<Vec<u8>>, String>;
+
+    fn put_thumbnail(&self, num: u32, spec: ThumbnailSpec, data: &[u8]) -> Result<(), String>;
+
+    fn montage(&self, montage_key: &str) -> Result<Option<Vec<u8>>, String>;
+
+    fn put_montage(&self, montage_key: &str, data: &[u8]) -> Result<(), String>;
+
+    fn ascii_art(&self, num: u32, width: u32) -> Result<Option<String>, String>;
+
+    fn put_ascii_art(&self, num: u32, width: u32, art: &str) -> Result<(), String>;
+
+    /// Comic `num` as a wallpaper, made with the options behind
+    /// `wallpaper_key`
+    ///
+    /// Stores that don't keep wallpapers make them again every time.
+    fn wallpaper(&self, _num: u32, _wallpaper_key: &str) -> Result<Option<Vec<u8>>, String> {
+        Ok(None)
+    }
+
+    fn put_wallpaper(&self, _num: u32, _wallpaper_key: &str, _data: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Total size of everything that can be evicted, in bytes
+    ///
+    /// Comic metadata is small and doesn't count.
+    fn size(&self) -> Result<u64, String>;
+
+    /// Delete cached data until the cache holds at most `max_size` bytes,
+    /// returning how many bytes were freed
+    ///
+    /// Whatever can be recreated without the network should go before the
+    /// raw images it came from, and comics read longest ago before others.
+    fn evict(&self, max_size: u64) -> Result<u64, String>;
+
+    /// The checksum comic `num`'s raw image had when `xkcdfs mirror` finished
+    /// with it, if it has
+    ///
+    /// Stores that don't keep track have mirrors start over every time.
+    fn mirrored(&self, _num: u32) -> Result<Option<u32>, String> {
+        Ok(None)
+    }
+
+    fn put_mirrored(&self, _num: u32, _checksum: u32) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn remove_mirrored(&self, _num: u32) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Whether comic `num` has a double-size image, if that's been found out
+    ///
+    /// Stores that don't keep track have it asked for again every time.
+    fn hires(&self, _num: u32) -> Result<Option<HiRes>, String> {
+        Ok(None)
+    }
+
+    fn put_hires(&self, _num: u32, _hires: HiRes) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Comic `num`'s double-size image, if it's been fetched
+    fn hires_image(&self, _num: u32) -> Result<Option<Vec<u8>>, String> {
+        Ok(None)
+    }
+
+    fn put_hires_image(&self, _num: u32, _image: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// When comic `num` was last read, in seconds since the epoch, if that's
+    /// been noted
+    ///
+    /// Stores that don't keep track evict without knowing what's been read.
+    fn accessed(&self, _num: u32) -> Result<Option<i64>, String> {
+        Ok(None)
+    }
+
+    fn put_accessed(&self, _num: u32, _at: i64) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// The `limit` comics read most recently, most recent first
+    fn recently_accessed(&self, _limit: u32) -> Result<Vec<u32>, String> {
+        Ok(Vec::new())
+    }
+
+    /// Every year a cached comic was published in, in order
+    ///
+    /// Stores that can't look this up directly go through every comic.
+    fn years(&self) -> Result<Vec<i32>, String> {
+        let mut years = BTreeSet::new();
+
+        self.each_comic(&mut |comic| {
+            years.insert(comic.year);
+        })?;
+
+        Ok(years.into_iter().collect())
+    }
+
+    /// The cached comics published in `year`, in order
+    fn comics_in_year(&self, year: i32) -> Result<Vec<u32>, String> {
+        let mut nums = Vec::new();
+
+        self.each_comic(&mut |comic| {
+            if comic.year == year {
+                nums.push(comic.num);
+            }
+        })?;
+
+        Ok(nums)
+    }
+
+    /// The cached comics published on a day, in order
+    fn comics_on(&self, year: i32, month: i32, day: i32) -> Result<Vec<u32>, String> {
+        let mut nums = Vec::new();
+
+        self.each_comic(&mut |comic| {
+            if (comic.year, comic.month, comic.day) == (year, month, day) {
+                nums.push(comic.num);
+            }
+        })?;
+
+        Ok(nums)
+    }
+
+    /// Every cached comic's number, in order
+    fn nums(&self) -> Result<Vec<u32>, String> {
+        let mut nums = Vec::new();
+
+        self.each_comic(&mut |comic| nums.push(comic.num))?;
+
+        Ok(nums)
+    }
+
+    /// Every cached comic's number and name from `titles`, in order
+    ///
+    /// Stores should give each comic its name in `put_comic` and keep it, so
+    /// names never change. By default they're worked out every time instead,
+    /// as if comics were cached in order.
+    fn titles(&self) -> Result<Vec<(u32, String)>, String> {
+        let mut titles = Vec::new();
+
+        self.each_comic(&mut |comic| {
+            titles.push((comic.num, comic.safe_title.clone()));
+        })?;
+
+        Ok(titles::names(&titles))
+    }
+
+    /// The cached comic `titles` named `name`
+    fn titled(&self, name: &str) -> Result<Option<u32>, String> {
+        Ok(self
+            .titles()?
+            .into_iter()
+            .find(|(_, n)| n == name)
+            .map(|(num, _)| num))
+    }
+
+    /// Note a network request in the fetch log
+    ///
+    /// Stores can keep as few of these as they like, and forget the oldest.
+    fn put_fetch(&self, _fetch: &Fetch) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Network requests from the fetch log that finished at or after `since`,
+    /// in seconds since the epoch, oldest first
+    fn fetches(&self, _since: i64) -> Result<Vec<Fetch>, String> {
+        Ok(Vec::new())
+    }
+
+    /// Note a mount's summary once it's unmounted
+    fn put_session(&self, _session: &Session) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Every mount's summary, oldest first
+    fn sessions(&self) -> Result<Vec<Session>, String> {
+        Ok(Vec::new())
+    }
+
+    /// Delete every rendered image, and everything made from them, returning
+    /// how many bytes were freed
+    ///
+    /// Raw images and metadata stay, so rendering them again needs no network.
+    /// Stores that can't are left as they are.
+    fn clear_rendered(&self) -> Result<u64, String> {
+        Ok(0)
+    }
+
+    /// Get everything stored so far into the store's own files, so they can
+    /// be copied as they are
+    ///
+    /// Stores that write everything straight away have nothing to do.
+    fn sync(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Up to `len` bytes of `data` from `offset`, or none if it starts past the end
+pub fn range(data: &[u8], offset: u64, len: usize) -> &[u8] {
+    let start = offset.min(data.len() as u64) as usize;
+    let end = start.saturating_add(len).min(data.len());
+
+    &data[start..end]
+}
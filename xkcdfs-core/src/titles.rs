@@ -0,0 +1,174 @@
+//! Filenames made from comic titles, for xkcdfs's `by_title`
+//!
+//! Titles can have anything in them. Slashes and NULs can't be in a filename
+//! at all, a leading dot would hide the file (or make it `.` or `..`), and a
+//! name can only be 255 bytes long, so titles are made safe first. Two comics
+//! can still end up with the same name, so a comic whose name is already
+//! taken has its own number added to it, like `Untitled (1234)`.
+//!
+//! A comic is given its name once, when it's cached, and the store keeps it
+//! and looks it back up. Caching more comics never renames one.
+
+use std::collections::HashSet;
+
+/// Longest a name can be in bytes, leaving room for a number and an extension
+/// in the 255 bytes a filename can have
+const MAX_NAME_LEN: usize = 200;
+
+/// What a title with nothing left after sanitizing is named
+const UNTITLED: &str = "untitled";
+
+/// `title`, made safe to use as a filename, without an extension
+pub fn sanitize(title: &str) -> String {
+    let name: String = title
+        .chars()
+        .map(|c| if c == '/' || c.is_control() { '_' } else { c })
+        .collect();
+    let name = name.trim_start_matches(|c: char| c == '.' || c.is_whitespace());
+
+    // Cut on a character boundary, so what's left is still UTF-8
+    let mut end = name.len().min(MAX_NAME_LEN);
+
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    match name[..end].trim_end() {
+        "" => UNTITLED.to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// The name comic `num` gets for `title`, without an extension
+///
+/// `taken` says whether a name is already another comic's. A title can look
+/// like another comic's name with its number added, so the number is added
+/// until the name is free.
+pub fn name_for<E>(
+    num: u32,
+    title: &str,
+    mut taken: impl FnMut(&str) -> Result<bool, E>,
+) -> Result<String, E> {
+    let mut name = sanitize(title);
+
+    while taken(&name)? {
+        name = format!("{} ({})", name, num);
+    }
+
+    Ok(name)
+}
+
+/// Every comic's name, for stores that work them out instead of keeping them
+///
+/// `titles` are comic numbers and titles in order, and each comic is named as
+/// if they were cached in that order.
+pub fn names(titles: &[(u32, String)]) -> Vec<(u32, String)> {
+    let mut taken = HashSet::new();
+
+    titles
+        .iter()
+        .map(|(num, title)| {
+            let name = name_for(*num, title, |n| Ok::<_, ()>(taken.contains(n))).unwrap();
+
+            taken.insert(name.clone());
+            (*num, name)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn titles(titles: &[(u32, &str)]) -> Vec<(u32, String)> {
+        titles.iter().map(|&(n, t)| (n, t.to_string())).collect()
+    }
+
+    #[test]
+    fn replaces_slashes_and_nuls() {
+        assert_eq!(sanitize("AC/DC"), "AC_DC");
+        assert_eq!(sanitize("a\0b"), "a_b");
+        assert_eq!(sanitize("tab\there\nnewline"), "tab_here_newline");
+        assert_eq!(sanitize("/"), "_");
+    }
+
+    #[test]
+    fn removes_leading_dots() {
+        assert_eq!(sanitize(".status"), "status");
+        assert_eq!(sanitize("..."), UNTITLED);
+        assert_eq!(sanitize(". . . Or Not"), "Or Not");
+        assert_eq!(sanitize("."), UNTITLED);
+        assert_eq!(sanitize(".."), UNTITLED);
+        assert_eq!(sanitize("Wait..."), "Wait...");
+    }
+
+    #[test]
+    fn trims_spaces() {
+        assert_eq!(sanitize("  Barrel - Part 1  "), "Barrel - Part 1");
+        assert_eq!(sanitize(""), UNTITLED);
+        assert_eq!(sanitize("   "), UNTITLED);
+    }
+
+    #[test]
+    fn shortens_long_titles() {
+        let long = "a".repeat(1000);
+        assert_eq!(sanitize(&long), "a".repeat(MAX_NAME_LEN));
+
+        // Never partway through a character
+        let snowmen = "☃".repeat(100);
+        let name = sanitize(&snowmen);
+        assert!(name.len() <= MAX_NAME_LEN);
+        assert!(name.chars().all(|c| c == '☃'));
+        assert_eq!(name.chars().count(), MAX_NAME_LEN / '☃'.len_utf8());
+
+        // Room is left for a number and extension in 255 bytes
+        let titled = titles(&[(1, &long), (123_456, &long)]);
+        for (_, name) in names(&titled) {
+            assert!(name.len() + ".png".len() <= 255);
+        }
+    }
+
+    #[test]
+    fn leaves_other_titles_alone() {
+        assert_eq!(sanitize("Ünïcödé ☃"), "Ünïcödé ☃");
+        assert_eq!(sanitize("Petit Trees (sketch)"), "Petit Trees (sketch)");
+    }
+
+    #[test]
+    fn clashing_names_get_numbers() {
+        let titled = titles(&[
+            (1, "Same"),
+            (2, "Same"),
+            (3, "Same (2)"),
+            (4, "S/ame"),
+            (5, "S_ame"),
+        ]);
+
+        assert_eq!(
+            names(&titled),
+            titles(&[
+                (1, "Same"),
+                (2, "Same (2)"),
+                (3, "Same (2) (3)"),
+                (4, "S_ame"),
+                (5, "S_ame (5)"),
+            ])
+        );
+    }
+
+    #[test]
+    fn clashes_are_numbered_for_the_comic_itself() {
+        let taken =
+            |names: &'static [&'static str]| move |name: &str| Ok::<_, ()>(names.contains(&name));
+
+        assert_eq!(name_for(7, "Same", taken(&[])), Ok("Same".to_string()));
+        assert_eq!(
+            name_for(7, "Same", taken(&["Same"])),
+            Ok("Same (7)".to_string())
+        );
+        assert_eq!(
+            name_for(7, "S/ame", taken(&["S_ame", "S_ame (7)"])),
+            Ok("S_ame (7) (7)".to_string())
+        );
+    }
+}
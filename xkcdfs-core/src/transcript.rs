@@ -0,0 +1,167 @@
+//! Making the API's transcripts readable
+//!
+//! Transcripts come with wiki-ish markup: `[[scene descriptions]]`,
+//! `<<sound effects>>`, and the alt text again at the end in `{{Title text:
+//! ...}}`. `clean` turns the markup into plain text, leaves out the alt text
+//! (it has a file of its own), and writes every speaker's label the same way,
+//! like `Cueball: Hi.`
+
+/// Longest a speaker's label can be, so a sentence with a colon in it isn't
+/// taken for one
+const MAX_LABEL_LEN: usize = 30;
+
+/// Most words in a speaker's label, like `Man in hat`
+const MAX_LABEL_WORDS: usize = 3;
+
+/// Notes starting with these are the alt text again
+const ALT_NOTES: &[&str] = &["title text", "title-text", "alt text", "alt-text", "alt:"];
+
+/// A transcript as plain text, ending with a newline unless it's empty
+pub fn clean(transcript: &str) -> String {
+    let text = notes(transcript)
+        .replace("[[", "[")
+        .replace("]]", "]")
+        .replace("<<", "*")
+        .replace(">>", "*");
+
+    let mut lines: Vec<String> = Vec::new();
+
+    for line in text.lines() {
+        let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+
+        // Only one blank line in a row, and none at the start
+        if line.is_empty() {
+            if lines.last().is_some_and(|l| !l.is_empty()) {
+                lines.push(line);
+            }
+        } else {
+            lines.push(speaker(&line));
+        }
+    }
+
+    while lines.last().is_some_and(String::is_empty) {
+        lines.pop();
+    }
+
+    let mut cleaned = lines.join("\n");
+
+    if !cleaned.is_empty() {
+        cleaned.push('\n');
+    }
+
+    cleaned
+}
+
+/// `{{notes}}` in parentheses, apart from the alt text, which is left out
+fn notes(transcript: &str) -> String {
+    let mut text = String::new();
+    let mut rest = transcript;
+
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start..].find("}}") {
+            Some(e) => start + e,
+            None => break,
+        };
+
+        text.push_str(&rest[..start]);
+
+        let note = rest[start + 2..end].trim();
+        let lower = note.to_lowercase();
+
+        if !ALT_NOTES.iter().any(|a| lower.starts_with(a)) {
+            text.push('(');
+            text.push_str(note);
+            text.push(')');
+        }
+
+        rest = &rest[end + 2..];
+    }
+
+    text.push_str(rest);
+
+    text
+}
+
+/// A line with its speaker's label written like `Man: Hi`, if it has one
+///
+/// Labels start with a letter and are a few words at most, so scene
+/// descriptions and times like `12:30` are left alone.
+fn speaker(line: &str) -> String {
+    let colon = match line.find(':') {
+        Some(c) if c <= MAX_LABEL_LEN => c,
+        _ => return line.to_string(),
+    };
+
+    let label = line[..colon].trim();
+    let is_label = label.starts_with(char::is_alphabetic)
+        && label.split_whitespace().count() <= MAX_LABEL_WORDS
+        && label
+            .chars()
+            .all(|c| c.is_alphanumeric() || " #'.-&".contains(c));
+
+    if !is_label {
+        return line.to_string();
+    }
+
+    let mut chars = label.chars();
+    let first = chars.next().map(char::to_uppercase);
+    let speech = line[colon + 1..].trim();
+
+    format!(
+        "{}{}: {}",
+        first.map(|f| f.collect::<String>()).unwrap_or_default(),
+        chars.as_str(),
+        speech
+    )
+    .trim_end()
+    .to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cleans_markup() {
+        let transcript = "[[A man with a beret and a woman are standing on a boardwalk.]]\n\
+                          man :A woodpecker!\n\
+                          <<Pop pop pop>>\n\n\n\
+                          Woman:   Yup.\n\
+                          {{Title text: If you don't have an extension cord I can get that too.}}";
+
+        assert_eq!(
+            clean(transcript),
+            "[A man with a beret and a woman are standing on a boardwalk.]\n\
+             Man: A woodpecker!\n\
+             *Pop pop pop*\n\
+             \n\
+             Woman: Yup.\n"
+        );
+    }
+
+    #[test]
+    fn keeps_other_notes() {
+        assert_eq!(
+            clean("{{Caption: Later}}\nCueball: Hi."),
+            "(Caption: Later)\nCueball: Hi.\n"
+        );
+        assert_eq!(clean("{{alt: Hidden}}"), "");
+        assert_eq!(clean(""), "");
+    }
+
+    #[test]
+    fn only_labels_speakers() {
+        assert_eq!(speaker("Person #1: Hi"), "Person #1: Hi");
+        assert_eq!(speaker("black hat guy: Hi"), "Black hat guy: Hi");
+        assert_eq!(speaker("Man:"), "Man:");
+        assert_eq!(speaker("12:30 and still going"), "12:30 and still going");
+        assert_eq!(
+            speaker("[A sign reads: Keep out]"),
+            "[A sign reads: Keep out]"
+        );
+        assert_eq!(
+            speaker("It was a long day and then: nothing"),
+            "It was a long day and then: nothing"
+        );
+    }
+}
@@ -0,0 +1,202 @@
+use crate::transcript;
+use time::{Timespec, Tm};
+
+/// Where explainxkcd has a page for each comic, by number
+const EXPLAIN_URL: &str = "https://www.explainxkcd.com/wiki/index.php/";
+
+/// A comic's metadata, as given by the xkcd API
+#[derive(Clone, Debug)]
+pub struct Comic {
+    pub num: u32,
+
+    pub day: i32,
+    pub month: i32,
+    pub year: i32,
+
+    pub link: Option<String>,
+    pub news: Option<String>,
+    pub alt: String,
+
+    pub title: String,
+    pub safe_title: String,
+    pub transcript: Option<String>,
+
+    pub img_url: String,
+
+    pub img_len: Option<usize>,
+
+    /// Headers, text and links some special comics have in their JSON besides
+    /// the image, as the JSON they came in
+    pub extra_parts: Option<String>,
+}
+
+impl Comic {
+    pub fn time(&self) -> Timespec {
+        Tm {
+            tm_sec: 0,
+            tm_min: 0,
+            tm_hour: 12,
+            tm_mday: self.day,
+            tm_mon: self.month - 1,
+            tm_year: self.year - 1900,
+            tm_wday: 0,
+            tm_yday: 0,
+            tm_isdst: 0,
+            tm_utcoff: 0,
+            tm_nsec: 0,
+        }
+        .to_timespec()
+    }
+
+    pub fn isodate(&self) -> String {
+        format!("{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+
+    /// The transcript, with its markup cleaned up unless `raw`
+    pub fn transcript_text(&self, raw: bool) -> Option<String> {
+        self.transcript
+            .as_ref()
+            .map(|t| if raw { t.clone() } else { transcript::clean(t) })
+    }
+
+    /// The title, date, transcript and alt text together as plain text, so a
+    /// screen reader can get through the whole comic in one go
+    ///
+    /// Comics without a transcript point to explainxkcd, which usually has one.
+    /// `raw` keeps the transcript's markup, as with `transcript_text`.
+    pub fn described(&self, raw: bool) -> String {
+        let transcript = match self.transcript_text(raw) {
+            Some(ref t) if !t.trim().is_empty() => t.trim().to_string(),
+            _ => format!(
+                "There's no transcript yet. explainxkcd might have one, at {}{}",
+                EXPLAIN_URL, self.num
+            ),
+        };
+
+        format!(
+            "Comic {}: {}\nPublished {}\n\nTranscript:\n{}\n\nAlt text:\n{}\n",
+            self.num,
+            self.title,
+            self.isodate(),
+            transcript,
+            self.alt
+        )
+    }
+
+    /// Where a double-size version of the image would be, for PNGs
+    ///
+    /// xkcd only has them for some comics, so there may be nothing there.
+    pub fn hires_url(&self) -> Option<String> {
+        let url = self.img_url.to_ascii_lowercase();
+
+        if url.ends_with(".png") && !url.ends_with("_2x.png") {
+            Some(format!(
+                "{}_2x.png",
+                &self.img_url[..self.img_url.len() - ".png".len()]
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Whether the original image is (probably) a GIF, judging by its URL
+    pub fn is_gif(&self) -> bool {
+        self.img_url.to_ascii_lowercase().ends_with(".gif")
+    }
+
+    /// Extension for the original image, judging by its URL
+    pub fn image_extension(&self) -> &'static str {
+        let url = self.img_url.to_ascii_lowercase();
+
+        if url.ends_with(".gif") {
+            "gif"
+        } else if url.ends_with(".jpg") || url.ends_with(".jpeg") {
+            "jpg"
+        } else {
+            "png"
+        }
+    }
+}
+
+impl std::fmt::Display for Comic {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "#{} ({})", self.num, self.safe_title)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn describes_comics() {
+        let comic = Comic {
+            num: 614,
+            day: 24,
+            month: 7,
+            year: 2009,
+            link: None,
+            news: None,
+            alt: "If you don't have an extension cord I can get that too.".to_string(),
+            title: "Woodpecker".to_string(),
+            safe_title: "Woodpecker".to_string(),
+            transcript: Some(
+                "[[A man with a beret and a woman are standing on a boardwalk.]]\n".to_string(),
+            ),
+            img_url: String::new(),
+            img_len: None,
+            extra_parts: None,
+        };
+
+        assert_eq!(
+            comic.described(true),
+            "Comic 614: Woodpecker\nPublished 2009-07-24\n\n\
+             Transcript:\n[[A man with a beret and a woman are standing on a boardwalk.]]\n\n\
+             Alt text:\nIf you don't have an extension cord I can get that too.\n"
+        );
+        assert!(comic.described(false).contains(
+            "Transcript:\n[A man with a beret and a woman are standing on a boardwalk.]\n\n"
+        ));
+
+        let comic = Comic {
+            transcript: Some(String::new()),
+            ..comic
+        };
+
+        assert!(comic.described(false).contains(
+            "explainxkcd might have one, at https://www.explainxkcd.com/wiki/index.php/614\n"
+        ));
+    }
+
+    #[test]
+    fn finds_hires_urls() {
+        let comic = |img_url: &str| Comic {
+            num: 614,
+            day: 24,
+            month: 7,
+            year: 2009,
+            link: None,
+            news: None,
+            alt: String::new(),
+            title: String::new(),
+            safe_title: String::new(),
+            transcript: None,
+            img_url: img_url.to_string(),
+            img_len: None,
+            extra_parts: None,
+        };
+
+        assert_eq!(
+            comic("https://imgs.xkcd.com/comics/woodpecker.PNG").hires_url(),
+            Some("https://imgs.xkcd.com/comics/woodpecker_2x.png".to_string())
+        );
+        assert_eq!(
+            comic("https://imgs.xkcd.com/comics/a_2x.png").hires_url(),
+            None
+        );
+        assert_eq!(
+            comic("https://imgs.xkcd.com/comics/barrel.jpg").hires_url(),
+            None
+        );
+    }
+}
@@ -0,0 +1,414 @@
+//! `XkcdClient` against a local stand-in for xkcd.com
+//!
+//! The server answers with the JSON and images in `tests/fixtures`, and can
+//! be told to fail, go missing or stall for any path.
+
+use reqwest::header::HeaderMap;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tiny_http::{Response, Server};
+use xkcdfs_core::{image::RenderOptions, RequestMode::*, XkcdApi, XkcdClient};
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+/// What the server sends for a path
+#[derive(Clone)]
+struct Reply {
+    status: u16,
+    body: Vec<u8>,
+    delay: Option<Duration>,
+}
+
+impl Reply {
+    fn ok(body: Vec<u8>) -> Self {
+        Self {
+            status: 200,
+            body,
+            delay: None,
+        }
+    }
+
+    fn status(status: u16) -> Self {
+        Self {
+            status,
+            body: b"Nope".to_vec(),
+            delay: None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Routes {
+    replies: HashMap<String, Reply>,
+    hits: HashMap<String, usize>,
+}
+
+struct MockServer {
+    base: String,
+    routes: Arc<Mutex<Routes>>,
+}
+
+impl MockServer {
+    /// A server with comics 1 and 2, 2 being the latest
+    fn start() -> Self {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", server.server_addr());
+        let routes = Arc::new(Mutex::new(Routes::default()));
+
+        let thread_routes = Arc::clone(&routes);
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let reply = {
+                    let mut routes = thread_routes.lock().unwrap();
+                    *routes.hits.entry(request.url().to_string()).or_insert(0) += 1;
+                    routes.replies.get(request.url()).cloned()
+                };
+
+                let reply = reply.unwrap_or_else(|| Reply::status(404));
+
+                // Stalls happen on their own thread, so other requests go on
+                thread::spawn(move || {
+                    if let Some(delay) = reply.delay {
+                        thread::sleep(delay);
+                    }
+
+                    request
+                        .respond(Response::from_data(reply.body).with_status_code(reply.status))
+                        .ok();
+                });
+            }
+        });
+
+        let server = Self { base, routes };
+
+        server.set("/info.0.json", Reply::ok(server.fixture("info.0.json")));
+        server.set("/1/info.0.json", Reply::ok(server.fixture("1.info.0.json")));
+        server.set("/2/info.0.json", Reply::ok(server.fixture("info.0.json")));
+
+        for image in &["barrel_cropped_(1).jpg", "tree_cropped_(1).jpg"] {
+            server.set(
+                &format!("/comics/{}", image),
+                Reply::ok(server.fixture("progressive.jpg")),
+            );
+        }
+
+        server
+    }
+
+    /// A file from `tests/fixtures`, pointed at this server
+    fn fixture(&self, name: &str) -> Vec<u8> {
+        let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+        let data = std::fs::read(path).unwrap();
+
+        if name.ends_with(".json") {
+            String::from_utf8(data)
+                .unwrap()
+                .replace("{base}", &self.base)
+                .into_bytes()
+        } else {
+            data
+        }
+    }
+
+    fn set(&self, path: &str, reply: Reply) {
+        self.routes
+            .lock()
+            .unwrap()
+            .replies
+            .insert(path.to_string(), reply);
+    }
+
+    /// How many times `path` has been asked for
+    fn hits(&self, path: &str) -> usize {
+        *self.routes.lock().unwrap().hits.get(path).unwrap_or(&0)
+    }
+
+    /// A client with an empty cache, passing images through unrendered
+    fn client(&self) -> XkcdClient {
+        self.client_with(true)
+    }
+
+    /// A client with an empty cache that renders comics
+    fn rendering_client(&self) -> XkcdClient {
+        self.client_with(false)
+    }
+
+    fn client_with(&self, no_render: bool) -> XkcdClient {
+        let api = XkcdApi::new(TIMEOUT, "xkcdfs-test".to_string(), HeaderMap::new())
+            .unwrap()
+            .with_urls(
+                format!("{}/info.0.json", self.base),
+                format!("{}/{{num}}/info.0.json", self.base),
+            );
+
+        XkcdClient::with_source(
+            api,
+            OsStr::new(":memory:"),
+            false,
+            no_render,
+            RenderOptions::default(),
+            None,
+        )
+        .unwrap()
+    }
+}
+
+#[test]
+fn fetches_comics_and_images() {
+    let server = MockServer::start();
+    let client = server.client();
+
+    let latest = client.request_latest_comic(None, BustCache).unwrap();
+    assert_eq!(latest.num, 2);
+    assert_eq!(latest.title, "Petit Trees (sketch)");
+
+    let comic = client.request_comic(1, None, Normal).unwrap();
+    assert_eq!(comic.alt, "Don't we all.");
+    assert_eq!(comic.isodate(), "2006-01-01");
+    assert_eq!(comic.link, None);
+
+    let image = client.request_raw_image(&comic, None, Normal).unwrap();
+    assert_eq!(image, server.fixture("progressive.jpg"));
+
+    // Without rendering, the original is what's shown
+    assert_eq!(
+        client.request_rendered_image(&comic, None, Normal),
+        Some(image)
+    );
+}
+
+#[test]
+fn populates_the_cache() {
+    let server = MockServer::start();
+    let client = server.client();
+    assert_eq!(client.cached_latest_num(), None);
+
+    let comic = client.request_comic(1, None, Normal).unwrap();
+    client.request_raw_image(&comic, None, Normal).unwrap();
+
+    assert_eq!(server.hits("/1/info.0.json"), 1);
+    assert_eq!(server.hits("/comics/barrel_cropped_(1).jpg"), 1);
+
+    // Everything is there without the network now
+    let cached = client.request_comic(1, None, NoNetwork).unwrap();
+    assert_eq!(cached.title, comic.title);
+    assert!(client.request_raw_image(&cached, None, NoNetwork).is_some());
+    assert!(client.request_comic(1, None, Normal).is_some());
+
+    assert_eq!(server.hits("/1/info.0.json"), 1);
+    assert_eq!(server.hits("/comics/barrel_cropped_(1).jpg"), 1);
+    assert_eq!(client.get_cached_count(), 1);
+    assert_eq!(client.cached_latest_num(), Some(1));
+}
+
+#[test]
+fn bust_cache_goes_to_the_network() {
+    let server = MockServer::start();
+    let client = server.client();
+
+    client.request_comic(1, None, Normal).unwrap();
+
+    // The comic changes upstream, which a normal request doesn't notice
+    let changed = String::from_utf8(server.fixture("1.info.0.json"))
+        .unwrap()
+        .replace("Don't we all.", "Changed");
+    server.set("/1/info.0.json", Reply::ok(changed.into_bytes()));
+
+    assert_eq!(
+        client.request_comic(1, None, Normal).unwrap().alt,
+        "Don't we all."
+    );
+    assert_eq!(
+        client.request_comic(1, None, BustCache).unwrap().alt,
+        "Changed"
+    );
+    assert_eq!(
+        client.request_comic(1, None, Normal).unwrap().alt,
+        "Changed"
+    );
+    assert_eq!(server.hits("/1/info.0.json"), 2);
+}
+
+#[test]
+fn missing_comics_and_images() {
+    let server = MockServer::start();
+    let client = server.client();
+
+    assert!(client.request_comic(3, None, Normal).is_none());
+    assert_eq!(server.hits("/3/info.0.json"), 1);
+
+    let comic = client.request_comic(1, None, Normal).unwrap();
+    server.set("/comics/barrel_cropped_(1).jpg", Reply::status(404));
+
+    // An error page isn't an image, so it shouldn't be cached as one
+    assert!(client.request_raw_image(&comic, None, Normal).is_none());
+    assert!(client.request_raw_image(&comic, None, NoNetwork).is_none());
+    assert_eq!(client.stats().snapshot().network_failures, 2);
+}
+
+#[test]
+fn partial_montages_are_not_cached() {
+    let server = MockServer::start();
+    let client = server.rendering_client();
+    let tree = "/comics/tree_cropped_(1).jpg";
+
+    // Comic 2's cell is left empty, so it's tried again next time
+    server.set(tree, Reply::status(404));
+    assert!(client.request_montage(&[1, 2], None, Normal).is_some());
+    assert!(client.request_montage(&[1, 2], None, VeryFast).is_none());
+
+    server.set(tree, Reply::ok(server.fixture("progressive.jpg")));
+    assert!(client.request_montage(&[1, 2], None, Normal).is_some());
+    assert!(client.request_montage(&[1, 2], None, VeryFast).is_some());
+}
+
+#[test]
+fn slow_responses_time_out() {
+    let server = MockServer::start();
+    let client = server.client();
+
+    server.set(
+        "/1/info.0.json",
+        Reply {
+            delay: Some(TIMEOUT * 4),
+            ..Reply::ok(server.fixture("1.info.0.json"))
+        },
+    );
+
+    let start = std::time::Instant::now();
+    assert!(client.request_comic(1, None, Normal).is_none());
+    assert!(start.elapsed() < TIMEOUT * 4);
+    assert_eq!(client.stats().snapshot().network_failures, 1);
+}
+
+#[test]
+fn failures_are_retried_next_time() {
+    let server = MockServer::start();
+    let client = server.client();
+
+    server.set("/1/info.0.json", Reply::status(503));
+    assert!(client.request_comic(1, None, Normal).is_none());
+
+    // Nothing about the failure sticks around
+    server.set("/1/info.0.json", Reply::ok(server.fixture("1.info.0.json")));
+    assert_eq!(client.request_comic(1, None, Normal).unwrap().num, 1);
+    assert_eq!(server.hits("/1/info.0.json"), 2);
+}
+
+#[test]
+fn reads_translated_mirrors() {
+    let server = MockServer::start();
+    let client = server.client();
+
+    // Numbers as numbers, fields left out, and an image relative to the JSON
+    server.set(
+        "/1/info.0.json",
+        Reply::ok(server.fixture("translated.info.0.json")),
+    );
+
+    let comic = client.request_comic(1, None, Normal).unwrap();
+    assert_eq!(comic.isodate(), "2006-01-01");
+    assert_eq!(comic.safe_title, "Бочка — часть 1");
+    assert_eq!(comic.transcript, None);
+    assert_eq!(
+        comic.img_url,
+        format!("{}/comics/barrel_cropped_(1).jpg", server.base)
+    );
+
+    let image = client.request_raw_image(&comic, None, Normal).unwrap();
+    assert_eq!(image, server.fixture("progressive.jpg"));
+}
+
+#[test]
+fn tolerates_odd_comics() {
+    let server = MockServer::start();
+    let client = server.client();
+
+    // Empty date strings and title, extra parts, and fields nobody expected
+    server.set(
+        "/1/info.0.json",
+        Reply::ok(server.fixture("undated.info.0.json")),
+    );
+
+    let comic = client.request_comic(1, None, Normal).unwrap();
+    assert_eq!(comic.isodate(), "1970-01-01");
+    assert_eq!(comic.title, "Barrel - Part 1");
+    assert_eq!(comic.transcript, Some(String::new()));
+    assert_eq!(
+        comic.extra_parts.as_deref(),
+        Some(r#"{"headerextra":"","post":"","pre":""}"#)
+    );
+
+    let cached = client.request_comic(1, None, NoNetwork).unwrap();
+    assert_eq!(cached.extra_parts, comic.extra_parts);
+}
+
+#[test]
+fn comic_404_is_missing() {
+    let server = MockServer::start();
+    let client = server.client();
+
+    // xkcd.com answers with an HTML page, and sometimes mirrors do with a 200
+    let page = server.fixture("404.html");
+    server.set(
+        "/404/info.0.json",
+        Reply {
+            status: 404,
+            ..Reply::ok(page.clone())
+        },
+    );
+    assert!(client.request_comic(404, None, Normal).is_none());
+
+    server.set("/404/info.0.json", Reply::ok(page.clone()));
+    assert!(client.request_comic(404, None, Normal).is_none());
+
+    assert!(xkcdfs_core::requests::parse_comic(&page)
+        .unwrap_err()
+        .starts_with("Not a comic's JSON"));
+    assert!(client.request_comic(404, None, NoNetwork).is_none());
+}
+
+#[test]
+fn asks_about_hires_images_once() {
+    use xkcdfs_core::store::HiRes;
+
+    let server = MockServer::start();
+    let client = server.client();
+
+    let png = String::from_utf8(server.fixture("1.info.0.json"))
+        .unwrap()
+        .replace(".jpg", ".png");
+    server.set("/1/info.0.json", Reply::ok(png.into_bytes()));
+    server.set(
+        "/comics/barrel_cropped_(1)_2x.png",
+        Reply::ok(server.fixture("progressive.jpg")),
+    );
+
+    // Asked about when the comic is first fetched
+    let comic = client.request_comic(1, None, Normal).unwrap();
+    assert_eq!(server.hits("/comics/barrel_cropped_(1)_2x.png"), 1);
+
+    match client.request_hires(&comic, NoNetwork) {
+        Some(HiRes::Available(_)) => {}
+        other => panic!("Expected a hi-res image, got {:?}", other),
+    }
+    assert_eq!(server.hits("/comics/barrel_cropped_(1)_2x.png"), 1);
+
+    // The image itself is only fetched when asked for, and then cached
+    assert_eq!(client.request_hires_image(&comic, NoNetwork), None);
+    let image = client.request_hires_image(&comic, Normal).unwrap();
+    assert_eq!(image, server.fixture("progressive.jpg"));
+    assert_eq!(server.hits("/comics/barrel_cropped_(1)_2x.png"), 2);
+    assert_eq!(client.request_hires_image(&comic, NoNetwork), Some(image));
+
+    // Comic 2 is a JPEG, so there's nothing to ask
+    let comic = client.request_comic(2, None, Normal).unwrap();
+    assert_eq!(
+        client.request_hires(&comic, NoNetwork),
+        Some(HiRes::Missing)
+    );
+    assert_eq!(client.request_hires_image(&comic, Normal), None);
+}
@@ -0,0 +1,230 @@
+//! Rendered comics compared against golden images in `tests/golden`
+//!
+//! Text is drawn with whatever fonts are installed, so small differences are
+//! allowed: a render matches if it's the same size and almost every pixel is
+//! close to the golden one.
+//!
+//! A missing golden image is a failure. To record them again after a
+//! deliberate change to the renderer, or to record a new one, run
+//!
+//! ```text
+//! XKCDFS_BLESS=1 cargo test -p xkcdfs-core --test render
+//! ```
+//!
+//! and look over the new images before committing them.
+
+use cairo::{Context, Format, ImageSurface};
+use std::io::Cursor;
+use std::path::PathBuf;
+use xkcdfs_core::{image::RenderOptions, Comic};
+
+/// How far apart two channels can be before a pixel counts as different
+const CHANNEL_TOLERANCE: u8 = 64;
+
+/// The share of pixels that can be different
+const PIXEL_TOLERANCE: f64 = 0.01;
+
+fn comic(title: &str, alt: &str) -> Comic {
+    Comic {
+        num: 1,
+        day: 1,
+        month: 1,
+        year: 2006,
+        link: None,
+        news: None,
+        alt: alt.to_string(),
+        title: title.to_string(),
+        safe_title: title.to_string(),
+        transcript: None,
+        img_url: "https://imgs.xkcd.com/comics/test.png".to_string(),
+        img_len: None,
+        extra_parts: None,
+    }
+}
+
+/// A small line drawing, like most comics
+fn png_fixture() -> Vec<u8> {
+    let surface = ImageSurface::create(Format::Rgb24, 160, 120).unwrap();
+    let cr = Context::new(&surface);
+
+    cr.set_source_rgb(1.0, 1.0, 1.0);
+    cr.paint();
+
+    cr.set_source_rgb(0.0, 0.0, 0.0);
+    cr.set_line_width(2.0);
+    cr.arc(50.0, 30.0, 12.0, 0.0, 2.0 * std::f64::consts::PI);
+    cr.move_to(50.0, 42.0);
+    cr.line_to(50.0, 80.0);
+    cr.line_to(35.0, 110.0);
+    cr.move_to(50.0, 80.0);
+    cr.line_to(65.0, 110.0);
+    cr.move_to(30.0, 55.0);
+    cr.line_to(70.0, 55.0);
+    cr.stroke();
+
+    let mut png = Vec::new();
+    surface.write_to_png(&mut png).unwrap();
+    png
+}
+
+fn jpeg_fixture() -> Vec<u8> {
+    std::fs::read(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/grayscale.jpg"
+    ))
+    .unwrap()
+}
+
+fn surface(png: &[u8]) -> ImageSurface {
+    ImageSurface::create_from_png(&mut Cursor::new(png)).unwrap()
+}
+
+/// Why `actual` doesn't match `expected`, if it doesn't
+fn difference(expected: &[u8], actual: &[u8]) -> Option<String> {
+    let mut expected = surface(expected);
+    let mut actual = surface(actual);
+
+    let size = |s: &ImageSurface| (s.get_width(), s.get_height());
+
+    if size(&expected) != size(&actual) {
+        return Some(format!(
+            "size is {:?}, expected {:?}",
+            size(&actual),
+            size(&expected)
+        ));
+    }
+
+    let (width, height) = size(&actual);
+    let (expected_stride, actual_stride) =
+        (expected.get_stride() as usize, actual.get_stride() as usize);
+    let expected = expected.get_data().unwrap();
+    let actual = actual.get_data().unwrap();
+
+    let mut different = 0;
+
+    for y in 0..height as usize {
+        for x in 0..width as usize {
+            let a = &expected[y * expected_stride + x * 4..][..4];
+            let b = &actual[y * actual_stride + x * 4..][..4];
+
+            if a.iter()
+                .zip(b)
+                .any(|(a, b)| (*a as i16 - *b as i16).abs() > CHANNEL_TOLERANCE as i16)
+            {
+                different += 1;
+            }
+        }
+    }
+
+    let share = different as f64 / (width as f64 * height as f64);
+
+    if share > PIXEL_TOLERANCE {
+        Some(format!("{:.2}% of pixels are different", share * 100.0))
+    } else {
+        None
+    }
+}
+
+/// Render `comic` and compare it with `tests/golden/<name>.png`
+fn check(name: &str, comic: &Comic, image: &[u8]) {
+    let rendered =
+        xkcdfs_core::image::render(comic, &mut Cursor::new(image), &RenderOptions::default())
+            .unwrap();
+
+    let golden: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "golden"]
+        .iter()
+        .collect::<PathBuf>()
+        .join(format!("{}.png", name));
+
+    if std::env::var_os("XKCDFS_BLESS").is_some() {
+        std::fs::create_dir_all(golden.parent().unwrap()).unwrap();
+        std::fs::write(&golden, &rendered).unwrap();
+        eprintln!("Recorded {}", golden.display());
+        return;
+    }
+
+    let expected = std::fs::read(&golden).unwrap_or_else(|e| {
+        panic!(
+            "Couldn't read {}: {}. Run with XKCDFS_BLESS=1 to record it",
+            golden.display(),
+            e
+        )
+    });
+
+    if let Some(why) = difference(&expected, &rendered) {
+        let actual = std::env::temp_dir().join(format!("xkcdfs-golden-{}.png", name));
+        std::fs::write(&actual, &rendered).unwrap();
+
+        panic!(
+            "{} doesn't match {}: {}. The render is at {}",
+            name,
+            golden.display(),
+            why,
+            actual.display()
+        );
+    }
+}
+
+#[test]
+fn png() {
+    check(
+        "png",
+        &comic("Barrel - Part 1", "Don't we all."),
+        &png_fixture(),
+    );
+}
+
+#[test]
+fn grayscale_jpeg() {
+    check(
+        "grayscale_jpeg",
+        &comic(
+            "Petit Trees (sketch)",
+            "'Petit' being a reference to Le Petit Prince",
+        ),
+        &jpeg_fixture(),
+    );
+}
+
+#[test]
+fn long_alt_text() {
+    let alt = "This alt text goes on for quite a while, the way the best ones do. ".repeat(12)
+        + "It ends with a word that's too long to fit on any line: "
+        + &"Supercalifragilistic".repeat(6);
+
+    check(
+        "long_alt_text",
+        &comic("Long Alt Text", &alt),
+        &png_fixture(),
+    );
+}
+
+#[test]
+fn unicode() {
+    check(
+        "unicode",
+        &comic(
+            "Ünïcödé ☃",
+            "Ελληνικά, кириллица, français and “curly quotes” — all in one",
+        ),
+        &png_fixture(),
+    );
+}
+
+#[test]
+fn differences_are_noticed() {
+    let render = |image: &[u8]| {
+        xkcdfs_core::image::render(
+            &comic("Title", "Alt"),
+            &mut Cursor::new(image),
+            &RenderOptions::default(),
+        )
+        .unwrap()
+    };
+
+    let png = render(&png_fixture());
+    let jpeg = render(&jpeg_fixture());
+
+    assert_eq!(difference(&png, &png), None);
+    assert!(difference(&png, &jpeg).is_some());
+}